@@ -38,6 +38,15 @@ impl Aabb {
         Self::new(a.x.union(&b.x), a.y.union(&b.y), a.z.union(&b.z))
     }
 
+    /// Get the center point of the box.
+    pub fn center(&self) -> DPoint3 {
+        DPoint3::new(
+            0.5 * (self.x.min + self.x.max),
+            0.5 * (self.y.min + self.y.max),
+            0.5 * (self.z.min + self.z.max),
+        )
+    }
+
     /// Return the axis-specified interval according to the index.
     pub const fn axis_interval(&self, axis: Axis) -> Interval {
         match axis {
@@ -49,6 +58,11 @@ impl Aabb {
 
     /// Check if ray intersects with AABB.
     pub fn intersect(&self, r: &Ray, ray_t: Interval) -> bool {
+        self.entry_distance(r, ray_t).is_some()
+    }
+
+    /// Get the distance where ray enters AABB within `ray_t`, `None` if they don't intersect.
+    pub fn entry_distance(&self, r: &Ray, ray_t: Interval) -> Option<f64> {
         let mut bounds = ray_t;
 
         // Check intersection with three pairs of planes
@@ -63,10 +77,16 @@ impl Aabb {
             bounds.min = bounds.min.max(t0);
             bounds.max = bounds.max.min(t1);
             if bounds.max <= bounds.min {
-                return false;
+                return None;
             }
         }
-        true
+        Some(bounds.min)
+    }
+
+    /// Get the surface area of AABB.
+    pub fn surface_area(&self) -> f64 {
+        let (x, y, z) = (self.x.size(), self.y.size(), self.z.size());
+        2.0 * (x * y + y * z + z * x)
     }
 
     /// Ensure no side is narrower than delta, padding if necessary
@@ -0,0 +1,286 @@
+use std::{io, path::Path};
+
+use exr::prelude::*;
+
+use crate::{
+    buffer::Buffer,
+    color::{self, Color},
+    openexr::RenderMetadata,
+};
+
+/// The accumulated samples of a render, i.e. the sum and the number of samples of each pixel,
+/// which can be saved as EXR and merged with the renders of other runs or machines into an
+/// image of less noise.
+/// Besides the sums, the count-weighted sum of squared luminance of iteration rounds and the
+/// number of rounds are kept, so that the noise estimate of `Buffer::relative_error` survives
+/// the round trip and the merge.
+pub struct Accumulation {
+    /// The width of image.
+    width: u32,
+
+    /// The height of image.
+    height: u32,
+
+    /// The sum of the sample colors of each pixel in (y * width + x) order.
+    sums: Vec<Color>,
+
+    /// The number of samples of each pixel.
+    counts: Vec<u32>,
+
+    /// The sum of the squared luminance of each iteration round weighted by its number of
+    /// samples.
+    squares: Vec<f64>,
+
+    /// The number of iteration rounds of each pixel.
+    rounds: Vec<u32>,
+
+    /// The named AOVs whose values are summed like colors.
+    aovs: Vec<(String, Vec<Color>)>,
+
+    /// The attributes of the EXR file, e.g. the metadata of the render it is loaded from.
+    attributes: LayerAttributes,
+}
+
+impl Accumulation {
+    /// Collect the accumulated samples of `buffer`.
+    pub fn from_buffer(buffer: &Buffer) -> Self {
+        let (width, height) = buffer.dimensions();
+        let pixels = (width * height) as usize;
+        let mut acc = Self {
+            width,
+            height,
+            sums: Vec::with_capacity(pixels),
+            counts: Vec::with_capacity(pixels),
+            squares: Vec::with_capacity(pixels),
+            rounds: Vec::with_capacity(pixels),
+            aovs: Vec::new(),
+            attributes: LayerAttributes::default(),
+        };
+        for y in 0..height {
+            for x in 0..width {
+                let rounds = buffer.rounds(x, y);
+                acc.sums
+                    .push(rounds.iter().map(|(c, n)| *c * *n as f64).sum());
+                acc.counts.push(rounds.iter().map(|(_, n)| n).sum());
+                acc.squares.push(
+                    rounds
+                        .iter()
+                        .map(|(c, n)| *n as f64 * color::luminance(*c).powi(2))
+                        .sum(),
+                );
+                acc.rounds.push(rounds.len() as u32);
+            }
+        }
+        for name in buffer.aov_names() {
+            let Some(colors) = buffer.aov_colors(name) else {
+                continue;
+            };
+            let sums = colors
+                .iter()
+                .zip(&acc.counts)
+                .map(|(c, n)| *c * *n as f64)
+                .collect();
+            acc.aovs.push((name.to_string(), sums));
+        }
+        acc
+    }
+
+    /// Get the width and height of image.
+    pub const fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Get the total number of samples of all pixels.
+    pub fn total_samples(&self) -> u64 {
+        self.counts.iter().map(|&n| n as u64).sum()
+    }
+
+    /// Get the average number of samples per pixel.
+    pub fn samples_per_pixel(&self) -> f64 {
+        self.total_samples() as f64 / self.counts.len().max(1) as f64
+    }
+
+    /// Add the samples of `other` which renders the same image with different random numbers.
+    /// The AOVs missing in either are dropped, and the attributes of `self` are kept.
+    pub fn merge(&mut self, other: &Self) -> io::Result<()> {
+        if self.dimensions() != other.dimensions() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Mismatched image size {:?} and {:?}",
+                    self.dimensions(),
+                    other.dimensions()
+                ),
+            ));
+        }
+        for i in 0..self.sums.len() {
+            self.sums[i] += other.sums[i];
+            self.counts[i] += other.counts[i];
+            self.squares[i] += other.squares[i];
+            self.rounds[i] += other.rounds[i];
+        }
+        self.aovs.retain_mut(|(name, sums)| {
+            let Some((_, others)) = other.aovs.iter().find(|(n, _)| n == name) else {
+                return false;
+            };
+            for (sum, other) in sums.iter_mut().zip(others) {
+                *sum += *other;
+            }
+            true
+        });
+        Ok(())
+    }
+
+    /// Convert the accumulated samples into a buffer, e.g. to continue rendering or to save
+    /// the image. Each pixel gets two rounds whose spread reproduces the noise estimate of the
+    /// original rounds, or a single round if there are too few rounds to estimate it.
+    pub fn buffer(&self) -> Buffer {
+        let mut buffer = Buffer::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = (y * self.width + x) as usize;
+                let (count, rounds) = (self.counts[i], self.rounds[i]);
+                if count == 0 {
+                    continue;
+                }
+                let mean = self.sums[i] / count as f64;
+                if rounds < 2 || count < 2 {
+                    buffer.add_round(x, y, mean, count);
+                } else {
+                    // The weighted squared deviation of round luminance from the mean, which is
+                    // split between two rounds whose weighted deviations cancel out. The
+                    // luminance of gray offsets equals the offset.
+                    let luminance = color::luminance(mean);
+                    let deviation = (self.squares[i] - count as f64 * luminance * luminance)
+                        .max(0.0)
+                        / (rounds - 1) as f64;
+                    let (n1, n2) = (count / 2, count - count / 2);
+                    let a1 = (deviation * n2 as f64 / (n1 as f64 * count as f64)).sqrt();
+                    let a2 = -a1 * n1 as f64 / n2 as f64;
+                    buffer.add_round(x, y, mean + Color::splat(a1), n1);
+                    buffer.add_round(x, y, mean + Color::splat(a2), n2);
+                }
+                for (name, sums) in &self.aovs {
+                    buffer.add_aov_round(name, x, y, sums[i] / count as f64, count);
+                }
+            }
+        }
+        buffer
+    }
+
+    /// Save the accumulation as an EXR file with the 32-bit float sums in `sum.R`, `sum.G`,
+    /// `sum.B` and `<aov>.sum.R` etc., the 32-bit unsigned `count` and `rounds`, and the float
+    /// `squares` channels. The metadata if any replaces the attributes, and the number of samples
+    /// per pixel is updated.
+    pub fn save<P: AsRef<Path>>(
+        &self,
+        metadata: Option<&RenderMetadata>,
+        path: P,
+    ) -> io::Result<()> {
+        let mut channels = SmallVec::new();
+        let mut add_sums = |prefix: &str, sums: &[Color]| {
+            for (i, channel) in ["R", "G", "B"].iter().enumerate() {
+                let samples = sums.iter().map(|c| c[i] as f32).collect();
+                channels.push(AnyChannel::new(
+                    format!("{prefix}sum.{channel}").as_str(),
+                    FlatSamples::F32(samples),
+                ));
+            }
+        };
+        add_sums("", &self.sums);
+        for (name, sums) in &self.aovs {
+            add_sums(&format!("{name}."), sums);
+        }
+        channels.push(AnyChannel::new(
+            "count",
+            FlatSamples::U32(self.counts.clone()),
+        ));
+        channels.push(AnyChannel::new(
+            "rounds",
+            FlatSamples::U32(self.rounds.clone()),
+        ));
+        channels.push(AnyChannel::new(
+            "squares",
+            FlatSamples::F32(self.squares.iter().map(|&s| s as f32).collect()),
+        ));
+
+        let mut attributes = self.attributes.clone();
+        if let Some(metadata) = metadata {
+            metadata.write_to(&mut attributes);
+        }
+        // The number of samples changes with merges.
+        attributes.other.insert(
+            Text::from("samplesPerPixel"),
+            AttributeValue::F32(self.samples_per_pixel() as f32),
+        );
+        let layer = Layer::new(
+            (self.width as usize, self.height as usize),
+            attributes,
+            Encoding::FAST_LOSSLESS,
+            AnyChannels::sort(channels),
+        );
+        Image::from_layer(layer)
+            .write()
+            .to_file(path)
+            .map_err(|err| io::Error::other(err.to_string()))
+    }
+
+    /// Load an accumulation saved by `save`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let image = read_first_flat_layer_from_file(path)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        let layer = image.layer_data;
+        let channels = &layer.channel_data.list;
+        let find = |name: &str| {
+            channels
+                .iter()
+                .find(|c| c.name.to_string() == name)
+                .map(|c| &c.sample_data)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Missing channel {name} of accumulation"),
+                    )
+                })
+        };
+        let floats = |name: &str| -> io::Result<Vec<f64>> {
+            Ok(find(name)?.values_as_f32().map(|v| v as f64).collect())
+        };
+        let integers = |name: &str| -> io::Result<Vec<u32>> {
+            match find(name)? {
+                FlatSamples::U32(values) => Ok(values.clone()),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Channel {name} of accumulation isn't unsigned integer"),
+                )),
+            }
+        };
+        let sums = |prefix: &str| -> io::Result<Vec<Color>> {
+            let [r, g, b] =
+                ["R", "G", "B"].map(|channel| floats(&format!("{prefix}sum.{channel}")));
+            Ok(r?
+                .into_iter()
+                .zip(g?)
+                .zip(b?)
+                .map(|((r, g), b)| Color::new(r, g, b))
+                .collect())
+        };
+        let mut aovs = Vec::new();
+        for channel in channels {
+            let name = channel.name.to_string();
+            if let Some(aov) = name.strip_suffix(".sum.R") {
+                aovs.push((aov.to_string(), sums(&format!("{aov}."))?));
+            }
+        }
+        Ok(Self {
+            width: layer.size.width() as u32,
+            height: layer.size.height() as u32,
+            sums: sums("")?,
+            counts: integers("count")?,
+            squares: floats("squares")?,
+            rounds: integers("rounds")?,
+            aovs,
+            attributes: layer.attributes,
+        })
+    }
+}
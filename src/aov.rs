@@ -0,0 +1,130 @@
+use glam::DVec3;
+
+use crate::{color::Color, math::DPoint3};
+
+/// The name of depth AOV, see `Renderer::depth_aov`. It is written as the `Z` channel of EXR.
+pub const DEPTH_AOV: &str = "depth";
+
+/// The name of world position AOV, see `Renderer::position_aov`.
+pub const POSITION_AOV: &str = "position";
+
+/// The name of world normal AOV, see `Renderer::normal_albedo_aovs`.
+pub const NORMAL_AOV: &str = "normal";
+
+/// The name of albedo AOV, see `Renderer::normal_albedo_aovs`.
+pub const ALBEDO_AOV: &str = "albedo";
+
+/// The name of motion vector AOV, see `Renderer::motion_aov`.
+pub const MOTION_AOV: &str = "motion";
+
+/// The geometry of the first hit of a camera path which the AOVs are taken from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FirstHit {
+    /// The position in world space.
+    pub p: DPoint3,
+
+    /// The normal in world space which faces to the camera.
+    pub normal: DVec3,
+
+    /// The albedo of material, see `Bsdf::albedo`. Zero unless the albedo AOV is enabled.
+    pub albedo: Color,
+
+    /// The displacement of the point over the shutter interval, see `HitRecord::velocity`.
+    pub velocity: DVec3,
+}
+
+/// The encoding of the planar depth of the first hit along the view direction. The depth is
+/// clamped to [near, far], and the rays escaping to the background are at `far`. The planes
+/// must be ordered and not negative, and `near` of the inverse depth positive, see `is_valid`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DepthEncoding {
+    /// The depth in world units.
+    Linear { far: f64 },
+
+    /// The depth mapped from [near, far] to [0, 1] linearly.
+    Normalized { near: f64, far: f64 },
+
+    /// The reciprocal of depth mapped from [1 / far, 1 / near] to [0, 1], i.e. 1 at near and 0
+    /// at far, which keeps the precision of near objects like the reversed z-buffer.
+    Inverse { near: f64, far: f64 },
+}
+
+impl DepthEncoding {
+    /// Whether the planes can encode the depth, i.e. 0 ≤ far for linear depth, 0 ≤ near < far
+    /// for normalized depth and 0 < near < far for inverse depth.
+    pub const fn is_valid(&self) -> bool {
+        match *self {
+            Self::Linear { far } => far >= 0.0,
+            Self::Normalized { near, far } => near >= 0.0 && near < far && far.is_finite(),
+            Self::Inverse { near, far } => near > 0.0 && near < far,
+        }
+    }
+
+    /// Encode the depth of the first hit, `None` for the rays escaping to the background. The
+    /// invalid planes give meaningless values instead of panicking in the render threads.
+    pub fn encode(&self, depth: Option<f64>) -> f64 {
+        match *self {
+            Self::Linear { far } => depth.unwrap_or(far).min(far).max(0.0),
+            Self::Normalized { near, far } => {
+                let depth = depth.unwrap_or(far).min(far).max(near);
+                (depth - near) / (far - near)
+            }
+            Self::Inverse { near, far } => {
+                let depth = depth.unwrap_or(far).min(far).max(near);
+                (1.0 / depth - 1.0 / far) / (1.0 / near - 1.0 / far)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_encodings_map_planes() {
+        let linear = DepthEncoding::Linear { far: 10.0 };
+        assert_eq!(linear.encode(Some(4.0)), 4.0);
+        assert_eq!(linear.encode(None), 10.0);
+        let normalized = DepthEncoding::Normalized {
+            near: 2.0,
+            far: 10.0,
+        };
+        assert_eq!(normalized.encode(Some(6.0)), 0.5);
+        assert_eq!(normalized.encode(Some(1.0)), 0.0);
+        let inverse = DepthEncoding::Inverse {
+            near: 1.0,
+            far: 4.0,
+        };
+        assert_eq!(inverse.encode(Some(1.0)), 1.0);
+        assert_eq!(inverse.encode(Some(2.0)), 1.0 / 3.0);
+        assert_eq!(inverse.encode(None), 0.0);
+        // The invalid planes don't panic.
+        DepthEncoding::Linear { far: -1.0 }.encode(Some(1.0));
+        DepthEncoding::Normalized {
+            near: 2.0,
+            far: 1.0,
+        }
+        .encode(Some(1.5));
+    }
+
+    #[test]
+    fn depth_planes_are_validated() {
+        assert!(DepthEncoding::Linear { far: 0.0 }.is_valid());
+        assert!(!DepthEncoding::Linear { far: -1.0 }.is_valid());
+        assert!(!DepthEncoding::Linear { far: f64::NAN }.is_valid());
+        for (near, far, valid) in [
+            (1.0, 2.0, true),
+            (2.0, 1.0, false),
+            (1.0, 1.0, false),
+            (-1.0, 1.0, false),
+            (0.0, 1.0, true),
+        ] {
+            assert_eq!(DepthEncoding::Normalized { near, far }.is_valid(), valid);
+            assert_eq!(
+                DepthEncoding::Inverse { near, far }.is_valid(),
+                valid && near > 0.0
+            );
+        }
+    }
+}
@@ -0,0 +1,382 @@
+use glam::{DVec2, DVec3};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use rayon::prelude::*;
+
+use crate::{
+    buffer::Buffer,
+    color::Color,
+    interval::Interval,
+    math::{DPoint3, Ray},
+    onb::ONB,
+    renderer::Renderer,
+    sampler, sampling,
+    shape::Hittable,
+};
+
+/// The quantity baked into each texel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BakeMode {
+    /// The fraction of the cosine-weighted hemisphere which isn't occluded within `distance`,
+    /// stored as grey.
+    AmbientOcclusion { distance: f64 },
+
+    /// The irradiance of direct and indirect light on the surface, which gives the outgoing
+    /// radiance by multiplying albedo / π.
+    Irradiance,
+
+    /// The world space normals of the scene projected onto the mesh, see `Baker::cage`. The
+    /// normals are encoded to [0, 1] by n * 0.5 + 0.5, save the texture without gamma
+    /// correction, e.g. `buffer.image_with(color::quantize)`.
+    WorldNormal,
+
+    /// The normals projected like `WorldNormal` in the tangent space of the mesh, whose x and y
+    /// follow u and v of the texture, i.e. OpenGL convention.
+    TangentNormal,
+
+    /// The mean curvature of the projected surface measured at `radius` around the texel, which
+    /// is mapped from [-1 / radius, 1 / radius] to [0, 1] as grey, i.e. convex edges are bright
+    /// and concave creases are dark. Save it without gamma correction like `WorldNormal`.
+    Curvature { radius: f64 },
+}
+
+/// The frame of the surface of mesh at a texel.
+#[derive(Debug, Clone, Copy)]
+struct SurfaceFrame {
+    p: DPoint3,
+    normal: DVec3,
+    tangent: DVec3,
+    bitangent: DVec3,
+}
+
+/// The surface whose UV layout is baked, given as triangles with the texture coordinates of
+/// their vertices.
+pub struct BakeMesh {
+    /// The vertices of triangles in counter-clockwise order seen from the front.
+    triangles: Vec<[DPoint3; 3]>,
+
+    /// The texture coordinates of the vertices of each triangle.
+    uvs: Vec<[(f64, f64); 3]>,
+
+    /// The normals of the vertices of each triangle, `None` for the flat normals.
+    normals: Option<Vec<[DVec3; 3]>>,
+}
+
+impl BakeMesh {
+    /// Create a bake mesh from triangles and the texture coordinates of their vertices.
+    pub fn new(triangles: Vec<[DPoint3; 3]>, uvs: Vec<[(f64, f64); 3]>) -> Self {
+        assert_eq!(uvs.len(), triangles.len(), "one uv per vertex is required");
+        Self {
+            triangles,
+            uvs,
+            normals: None,
+        }
+    }
+
+    /// Set the normals of the vertices of each triangle which are interpolated over the faces.
+    pub fn normals(mut self, normals: Vec<[DVec3; 3]>) -> Self {
+        assert_eq!(
+            normals.len(),
+            self.triangles.len(),
+            "one normal per vertex is required"
+        );
+        self.normals = Some(normals);
+        self
+    }
+
+    /// Get the frame of the point with barycentric coordinates (b1, b2) on the triangle `i`,
+    /// whose tangent and bitangent follow the directions of u and v, `None` for degenerate
+    /// triangles.
+    fn frame(&self, i: usize, b1: f64, b2: f64) -> Option<SurfaceFrame> {
+        let [a, b, c] = self.triangles[i];
+        let b0 = 1.0 - b1 - b2;
+        let normal = match &self.normals {
+            Some(normals) => {
+                let [n0, n1, n2] = normals[i];
+                b0 * n0 + b1 * n1 + b2 * n2
+            }
+            None => (b - a).cross(c - a),
+        }
+        .try_normalize()?;
+        // Solve the edges as the combinations of the derivatives w.r.t. u and v.
+        let [uv0, uv1, uv2] = self.uvs[i];
+        let (e1, e2) = (b - a, c - a);
+        let (du1, dv1) = (uv1.0 - uv0.0, uv1.1 - uv0.1);
+        let (du2, dv2) = (uv2.0 - uv0.0, uv2.1 - uv0.1);
+        let det = du1 * dv2 - du2 * dv1;
+        let dpdu = (e1 * dv2 - e2 * dv1) / det;
+        let dpdv = (e2 * du1 - e1 * du2) / det;
+        let tangent = (dpdu - dpdu.dot(normal) * normal).try_normalize()?;
+        // Mirrored uv layouts flip the bitangent.
+        let bitangent = normal.cross(tangent) * dpdv.dot(normal.cross(tangent)).signum();
+        Some(SurfaceFrame {
+            p: b0 * a + b1 * b + b2 * c,
+            normal,
+            tangent,
+            bitangent,
+        })
+    }
+}
+
+/// Bake the lighting of the scene on the surface of a mesh into a texture by path tracing each
+/// texel, e.g. ambient occlusion maps and lightmaps of real-time engines.
+pub struct Baker {
+    /// The width of texture.
+    width: u32,
+
+    /// The height of texture.
+    height: u32,
+
+    /// The number of samples of each texel.
+    num_samples: u32,
+
+    /// The quantity to bake.
+    mode: BakeMode,
+
+    /// The number of texels which the baked islands are extended by, so that the bilinear
+    /// filtering and mipmaps of texture don't bleed the empty texels into the seams.
+    padding: u32,
+
+    /// The distance along the normal of mesh within which the scene is projected onto it,
+    /// i.e. the rays are cast from the cage offset by the distance towards the other side.
+    cage: f64,
+}
+
+impl Baker {
+    /// Create a baker of ambient occlusion into a texture of `width` and `height`.
+    pub const fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            num_samples: 64,
+            mode: BakeMode::AmbientOcclusion { distance: 1.0 },
+            padding: 2,
+            cage: 0.1,
+        }
+    }
+
+    /// Set the number of samples of each texel.
+    pub const fn samples(mut self, n: u32) -> Self {
+        self.num_samples = n;
+        self
+    }
+
+    /// Set the quantity to bake.
+    pub const fn mode(mut self, mode: BakeMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Set the number of texels which the baked islands are extended by.
+    pub const fn padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Set the distance of the cage to project the scene onto the mesh, e.g. the distance
+    /// between the high-poly scene and low-poly mesh of normal maps.
+    pub const fn cage(mut self, cage: f64) -> Self {
+        self.cage = cage;
+        self
+    }
+
+    /// Bake the scene of `renderer` on the surface of `mesh`. The projected modes, e.g. normal
+    /// maps, expect the high-poly geometry in the scene without the mesh itself. The texel (x, y) covers the uv
+    /// around ((x + 0.5) / width, 1 - (y + 0.5) / height), i.e. v points up in the texture.
+    /// The texels outside the UV layout are black unless they are filled by the padding.
+    pub fn bake(&self, renderer: &Renderer, mesh: &BakeMesh) -> Buffer {
+        let texels = self.rasterize(mesh);
+        let colors: Vec<Option<Color>> = renderer.install(|| {
+            texels
+                .par_iter()
+                .enumerate()
+                .map(|(index, texel)| {
+                    let (x, y) = (index as u32 % self.width, index as u32 / self.width);
+                    let mut rng = StdRng::seed_from_u64(sampler::seed(x, y, 0));
+                    texel.map(|frame| self.bake_texel(renderer, &frame, &mut rng))
+                })
+                .collect()
+        });
+        let mut buffer = Buffer::new(self.width, self.height);
+        let colors = dilate(colors, self.width, self.height, self.padding);
+        buffer.add_samples(colors, self.num_samples);
+        buffer
+    }
+
+    /// Get the surface point of the center of each texel covered by the UV layout.
+    fn rasterize(&self, mesh: &BakeMesh) -> Vec<Option<SurfaceFrame>> {
+        let (width, height) = (self.width as f64, self.height as f64);
+        let mut texels = vec![None; (self.width * self.height) as usize];
+        for (i, uvs) in mesh.uvs.iter().enumerate() {
+            // The vertices in the pixel coordinates of texture.
+            let [p0, p1, p2] = uvs.map(|(u, v)| DVec2::new(u * width, (1.0 - v) * height));
+            let area = (p1 - p0).perp_dot(p2 - p0);
+            if area == 0.0 {
+                continue;
+            }
+            let min = p0.min(p1).min(p2);
+            let max = p0.max(p1).max(p2);
+            let x_range = (min.x - 0.5).ceil().max(0.0) as u32
+                ..=(max.x - 0.5).floor().min(width - 1.0) as u32;
+            let y_range = (min.y - 0.5).ceil().max(0.0) as u32
+                ..=(max.y - 0.5).floor().min(height - 1.0) as u32;
+            for y in y_range {
+                for x in x_range.clone() {
+                    let center = DVec2::new(x as f64 + 0.5, y as f64 + 0.5);
+                    let b1 = (center - p0).perp_dot(p2 - p0) / area;
+                    let b2 = (p1 - p0).perp_dot(center - p0) / area;
+                    if b1 < 0.0 || b2 < 0.0 || b1 + b2 > 1.0 {
+                        continue;
+                    }
+                    if let Some(frame) = mesh.frame(i, b1, b2) {
+                        texels[(y * self.width + x) as usize] = Some(frame);
+                    }
+                }
+            }
+        }
+        texels
+    }
+
+    /// Get the baked quantity at the surface point of texel, the average of samples for the
+    /// sampled modes.
+    fn bake_texel(&self, renderer: &Renderer, frame: &SurfaceFrame, rng: &mut StdRng) -> Color {
+        let SurfaceFrame { p, normal, .. } = *frame;
+        let encode = |n: DVec3| n * 0.5 + 0.5;
+        match self.mode {
+            BakeMode::AmbientOcclusion { distance } => self.average(|| {
+                let dir = ONB::new(normal).transform(sampling::cosine_hemisphere(rng.random()));
+                let epsilon = renderer.ray_epsilon * renderer.scene_scale;
+                let ray = Ray::new(p, dir, 0.0);
+                let occluded = renderer
+                    .intersect(&ray, Interval::new(epsilon, distance))
+                    .is_some();
+                Color::splat(if occluded { 0.0 } else { 1.0 })
+            }),
+            BakeMode::Irradiance => self.average(|| {
+                let irradiance = renderer.irradiance(p, normal, 0.0, rng);
+                // Drop the non-finite values like the samples of pixels.
+                if irradiance.is_finite() {
+                    irradiance
+                } else {
+                    Color::ZERO
+                }
+            }),
+            BakeMode::WorldNormal => {
+                let (_, n) = self.project(renderer, frame, p).unwrap_or((p, normal));
+                encode(n)
+            }
+            BakeMode::TangentNormal => {
+                let (_, n) = self.project(renderer, frame, p).unwrap_or((p, normal));
+                encode(DVec3::new(
+                    n.dot(frame.tangent),
+                    n.dot(frame.bitangent),
+                    n.dot(normal),
+                ))
+            }
+            BakeMode::Curvature { radius } => {
+                Color::splat(0.5 + 0.5 * self.curvature(renderer, frame, radius))
+            }
+        }
+    }
+
+    /// Get the average of `num_samples` samples.
+    fn average<F>(&self, mut sample: F) -> Color
+    where
+        F: FnMut() -> Color,
+    {
+        let sum: Color = (0..self.num_samples).map(|_| sample()).sum();
+        sum / self.num_samples.max(1) as f64
+    }
+
+    /// Project the scene onto the point `p` on the tangent plane of `frame` by the ray from the
+    /// cage towards the other side, and get the first hit with its outward normal.
+    fn project(
+        &self,
+        renderer: &Renderer,
+        frame: &SurfaceFrame,
+        p: DPoint3,
+    ) -> Option<(DPoint3, DVec3)> {
+        let ray = Ray::new(p + self.cage * frame.normal, -frame.normal, 0.0);
+        let rec = renderer.intersect(&ray, Interval::new(0.0, 2.0 * self.cage))?;
+        let normal = if rec.front_face {
+            rec.normal
+        } else {
+            -rec.normal
+        };
+        Some((rec.p, normal))
+    }
+
+    /// Estimate the mean curvature of the projected surface times `radius` clamped to [-1, 1],
+    /// from the change of normals between the center and four points at `radius` around it.
+    fn curvature(&self, renderer: &Renderer, frame: &SurfaceFrame, radius: f64) -> f64 {
+        let Some((q, n)) = self.project(renderer, frame, frame.p) else {
+            return 0.0;
+        };
+        let (mut sum, mut count) = (0.0, 0);
+        for offset in [
+            frame.tangent,
+            -frame.tangent,
+            frame.bitangent,
+            -frame.bitangent,
+        ] {
+            let Some((qi, ni)) = self.project(renderer, frame, frame.p + radius * offset) else {
+                continue;
+            };
+            let dp = qi - q;
+            let length_squared = dp.length_squared();
+            if length_squared > 0.0 {
+                // The normal curvature along dp, positive for convex surfaces.
+                sum += (ni - n).dot(dp) / length_squared;
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return 0.0;
+        }
+        (sum / count as f64 * radius).clamp(-1.0, 1.0)
+    }
+}
+
+/// Extend the baked texels into the empty neighbors by `padding` texels, each empty texel gets
+/// the average of its baked neighbors. The texels which stay empty are black.
+fn dilate(mut colors: Vec<Option<Color>>, width: u32, height: u32, padding: u32) -> Vec<Color> {
+    for _ in 0..padding {
+        let mut next = colors.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let index = (y * width + x) as usize;
+                if colors[index].is_some() {
+                    continue;
+                }
+                let mut sum = Color::ZERO;
+                let mut count = 0;
+                for (dx, dy) in [
+                    (-1, 0),
+                    (1, 0),
+                    (0, -1),
+                    (0, 1),
+                    (-1, -1),
+                    (1, -1),
+                    (-1, 1),
+                    (1, 1),
+                ] {
+                    let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                    if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                        continue;
+                    }
+                    if let Some(color) = colors[(ny * width as i64 + nx) as usize] {
+                        sum += color;
+                        count += 1;
+                    }
+                }
+                if count > 0 {
+                    next[index] = Some(sum / count as f64);
+                }
+            }
+        }
+        colors = next;
+    }
+    colors
+        .into_iter()
+        .map(|c| c.unwrap_or(Color::ZERO))
+        .collect()
+}
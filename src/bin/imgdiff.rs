@@ -0,0 +1,38 @@
+use std::{env, process};
+
+use simple_rpt::imgdiff::{self, ErrorMetrics};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 || args.len() > 4 {
+        eprintln!("Usage: imgdiff <test image> <reference image> [FLIP heatmap output]");
+        process::exit(2);
+    }
+    let open = |path: &str| {
+        image::open(path)
+            .unwrap_or_else(|err| {
+                eprintln!("Failed to open {path}: {err}");
+                process::exit(1);
+            })
+            .to_rgb8()
+    };
+    let (test, reference) = (open(&args[1]), open(&args[2]));
+    if test.dimensions() != reference.dimensions() {
+        eprintln!("Images have different sizes.");
+        process::exit(1);
+    }
+
+    let metrics = ErrorMetrics::new(&test, &reference);
+    println!("RMSE:   {:.6}", metrics.rmse);
+    println!("relMSE: {:.6}", metrics.rel_mse);
+    println!("FLIP:   {:.6}", metrics.flip);
+
+    if let Some(output) = args.get(3) {
+        let (width, height) = test.dimensions();
+        let flip = imgdiff::flip(&test, &reference, imgdiff::DEFAULT_PPD);
+        if let Err(err) = imgdiff::heatmap(&flip, width, height, 1.0).save(output) {
+            eprintln!("Failed to save {output}: {err}");
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::{env, io, path::Path, process};
+
+use simple_rpt::{
+    lightpath,
+    renderer::Renderer,
+    scene::load::{SceneLoader, progress_bar},
+};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 6 || args.len() > 8 {
+        eprintln!(
+            "Usage: inspect <scene.toml> <width> <height> <x> <y> [samples] [paths output (.obj/.ply)]"
+        );
+        process::exit(2);
+    }
+    let number = |index: usize| {
+        args[index].parse::<u32>().unwrap_or_else(|err| {
+            eprintln!("Invalid number {}: {err}", args[index]);
+            process::exit(2);
+        })
+    };
+    let (width, height, x, y) = (number(2), number(3), number(4), number(5));
+    let samples = if args.len() > 6 { number(6) } else { 4 };
+    if x >= width || y >= height {
+        eprintln!("Pixel {x} {y} is outside the {width}x{height} image.");
+        process::exit(2);
+    }
+
+    let loader = SceneLoader::new().on_progress(progress_bar());
+    let (scene, cam) = loader.load(Path::new(&args[1])).unwrap_or_else(|err| {
+        eprintln!("Failed to load {}: {err}", args[1]);
+        process::exit(1);
+    });
+    let renderer = Renderer::new(cam, scene.build_bvh())
+        .width(width)
+        .height(height);
+    let paths = renderer.record_paths(x, y, samples, 0);
+    if let Err(err) = lightpath::write_report(&paths, io::stdout().lock()) {
+        eprintln!("Failed to write report: {err}");
+        process::exit(1);
+    }
+
+    if let Some(output) = args.get(7)
+        && let Err(err) = lightpath::save(&paths, output)
+    {
+        eprintln!("Failed to save {output}: {err}");
+        process::exit(1);
+    }
+}
@@ -0,0 +1,46 @@
+use std::{env, process};
+
+use simple_rpt::{accumulation::Accumulation, post::PostProcess, writer::ImageWriter};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 4 {
+        eprintln!("Usage: merge <merged accumulation> <output image> <accumulation>...");
+        process::exit(2);
+    }
+    // The renders of the same scene from different runs or machines are summed, so that each
+    // of them is weighted by its number of samples.
+    let mut merged: Option<Accumulation> = None;
+    for path in &args[3..] {
+        let acc = Accumulation::load(path).unwrap_or_else(|err| {
+            eprintln!("Failed to load {path}: {err}");
+            process::exit(1);
+        });
+        println!("{path}: {:.1} spp", acc.samples_per_pixel());
+        match &mut merged {
+            Some(merged) => merged.merge(&acc).unwrap_or_else(|err| {
+                eprintln!("Failed to merge {path}: {err}");
+                process::exit(1);
+            }),
+            None => merged = Some(acc),
+        }
+    }
+    let merged = merged.expect("No accumulation to merge.");
+    if let Err(err) = merged.save(None, &args[1]) {
+        eprintln!("Failed to save {}: {err}", args[1]);
+        process::exit(1);
+    }
+
+    let buffer = merged.buffer();
+    println!("Merged: {:.1} spp", merged.samples_per_pixel());
+    if let Some(noise) = buffer.noise_estimate() {
+        println!("Noise:  {noise:.6}");
+    }
+    // The HDR outputs keep the linear radiance, other formats are tone mapped.
+    let result = ImageWriter::from_path(&args[2])
+        .and_then(|writer| writer.write(&buffer, &PostProcess::new(), None, &args[2]));
+    if let Err(err) = result {
+        eprintln!("Failed to save {}: {err}", args[2]);
+        process::exit(1);
+    }
+}
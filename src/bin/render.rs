@@ -0,0 +1,145 @@
+use std::{env, io, path::Path, process, time::Instant};
+
+use simple_rpt::{
+    accumulation::Accumulation,
+    camera::Camera,
+    compare,
+    openexr::RenderMetadata,
+    post::PostProcess,
+    renderer::Renderer,
+    scene::load::{SceneLoader, progress_bar},
+    watch::{self, WatchEvent},
+    writer::ImageWriter,
+};
+
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+    // The accumulated samples are saved besides the image to be merged with other renders.
+    let accumulation = args
+        .iter()
+        .position(|arg| arg == "--accumulation")
+        .filter(|&i| i + 1 < args.len())
+        .map(|i| args.drain(i..i + 2).nth(1).unwrap());
+    // The scene file is watched and the preview is rendered again whenever it changes.
+    let watching = args
+        .iter()
+        .position(|arg| arg == "--watch")
+        .map(|i| args.remove(i))
+        .is_some();
+    if args.len() < 6 || args.len() > 7 {
+        eprintln!(
+            "Usage: render <scene.toml> <output> <width> <height> <samples> [camera name | --contact-sheet] [--accumulation <output.exr>] [--watch]"
+        );
+        process::exit(2);
+    }
+    let number = |index: usize| {
+        args[index].parse::<u32>().unwrap_or_else(|err| {
+            eprintln!("Invalid number {}: {err}", args[index]);
+            process::exit(2);
+        })
+    };
+    let (width, height, samples) = (number(3), number(4), number(5));
+    let output = &args[2];
+
+    if watching {
+        let camera = args.get(6).map(String::as_str);
+        if camera == Some("--contact-sheet") || accumulation.is_some() {
+            eprintln!("--watch only renders the preview of one camera");
+            process::exit(2);
+        }
+        let writer = ImageWriter::from_path(output).unwrap_or_else(|err| {
+            eprintln!("Failed to save {output}: {err}");
+            process::exit(2);
+        });
+        let result = watch::watch(
+            Path::new(&args[1]),
+            samples,
+            |path| {
+                let (scene, cam) = SceneLoader::new().load(path)?;
+                let cameras = cameras(cam, &scene.cameras);
+                let selected = select_camera(&cameras, camera)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+                Ok(
+                    Renderer::new(cameras[selected].1.clone(), scene.build_bvh())
+                        .width(width)
+                        .height(height),
+                )
+            },
+            |event| match event {
+                WatchEvent::Pass(spp, buffer) => {
+                    match writer.write(buffer, &PostProcess::default(), None, output) {
+                        Ok(()) => eprintln!("Saved the preview of {spp} spp to {output}"),
+                        Err(err) => eprintln!("Failed to save {output}: {err}"),
+                    }
+                }
+                WatchEvent::Error(err) => eprintln!("Failed to load {}: {err}", args[1]),
+            },
+        );
+        if let Err(err) = result {
+            eprintln!("Failed to watch {}: {err}", args[1]);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let loader = SceneLoader::new().on_progress(progress_bar());
+    let (scene, cam) = loader.load(Path::new(&args[1])).unwrap_or_else(|err| {
+        eprintln!("Failed to load {}: {err}", args[1]);
+        process::exit(1);
+    });
+    let cameras = cameras(cam, &scene.cameras);
+    let selected = match args.get(6).map(String::as_str) {
+        None | Some("--contact-sheet") => 0,
+        name => select_camera(&cameras, name).unwrap_or_else(|err| {
+            eprintln!("{err}");
+            process::exit(2);
+        }),
+    };
+    let mut renderer = Renderer::new(cameras[selected].1.clone(), scene.build_bvh())
+        .width(width)
+        .height(height)
+        .num_samples(samples);
+
+    let result = if args.get(6).is_some_and(|arg| arg == "--contact-sheet") {
+        compare::render_contact_sheet(&mut renderer, &cameras)
+            .save(output)
+            .map_err(io::Error::other)
+    } else if let Some(path) = &accumulation {
+        let start = Instant::now();
+        let buffer = renderer.render_buffer();
+        let metadata = RenderMetadata::new(&renderer, &buffer).render_time(start.elapsed());
+        if let Err(err) = Accumulation::from_buffer(&buffer).save(Some(&metadata), path) {
+            eprintln!("Failed to save {path}: {err}");
+            process::exit(1);
+        }
+        ImageWriter::from_path(output)
+            .and_then(|writer| writer.write(&buffer, &renderer.post, Some(&metadata), output))
+    } else {
+        renderer.render_to_file(output)
+    };
+    if let Err(err) = result {
+        eprintln!("Failed to save {output}: {err}");
+        process::exit(1);
+    }
+}
+
+/// Get the default camera first, followed by the named cameras of the scene file.
+fn cameras(cam: Camera, named: &[(String, Camera)]) -> Vec<(String, Camera)> {
+    let mut cameras = vec![("default".to_string(), cam)];
+    cameras.extend(named.iter().cloned());
+    cameras
+}
+
+/// Find the camera of `name`, or the default one without name.
+fn select_camera(cameras: &[(String, Camera)], name: Option<&str>) -> Result<usize, String> {
+    let Some(name) = name else {
+        return Ok(0);
+    };
+    cameras.iter().position(|(n, _)| n == name).ok_or_else(|| {
+        let names: Vec<&str> = cameras.iter().map(|(n, _)| n.as_str()).collect();
+        format!(
+            "Unknown camera {name}, the cameras are: {}",
+            names.join(", ")
+        )
+    })
+}
@@ -1,6 +1,11 @@
 use image::{ImageBuffer, RgbImage};
 
-use crate::color::{Color, color_bytes};
+use crate::color::{self, Color, color_bytes};
+use crate::filter::Filter;
+use crate::imgdiff;
+
+/// The average color and the number of samples of one iteration round.
+type Round = (Color, u32);
 
 /// A buffer to store the result of path tracing.
 pub struct Buffer {
@@ -10,8 +15,12 @@ pub struct Buffer {
     height: u32,
     /// The sample colors of image.
     /// The first index: the location of pixel in (y * width + x)
-    /// The second index: the color of different iteration rounds.
-    samples: Vec<Vec<Color>>,
+    /// The second index: the average color and the number of samples of different iteration
+    /// rounds, the rounds are weighted by their number of samples.
+    samples: Vec<Vec<Round>>,
+    /// The named arbitrary output variables (AOVs) of image, which are stored in the same
+    /// layout as `samples`.
+    aovs: Vec<(String, Vec<Vec<Round>>)>,
 }
 
 impl Buffer {
@@ -21,30 +30,72 @@ impl Buffer {
             width,
             height,
             samples: vec![vec![]; (width * height) as usize],
+            aovs: Vec::new(),
         }
     }
 
-    /// Push a color of new iteration round into the buffer.
+    /// Push a color of new iteration round which contains one sample into the buffer.
     pub fn add_sample(&mut self, x: u32, y: u32, color: Color) {
         assert!(x < self.width && y < self.height, "Invalid pixel location!");
         let index = (y * self.width + x) as usize;
-        self.samples[index].push(color);
+        self.samples[index].push((color, 1));
+    }
+
+    /// Push a color of new iteration round which is the average of `count` samples into the
+    /// buffer, e.g. the history of previous frame, see `FrameHistory`.
+    pub fn add_round(&mut self, x: u32, y: u32, color: Color, count: u32) {
+        assert!(x < self.width && y < self.height, "Invalid pixel location!");
+        let index = (y * self.width + x) as usize;
+        self.samples[index].push((color, count));
     }
 
-    /// Extend a list of colors into the buffer.
-    pub fn add_samples(&mut self, colors: Vec<Color>) {
+    /// Extend a list of colors, each of which is the average of `count` samples, into the buffer.
+    pub fn add_samples(&mut self, colors: Vec<Color>, count: u32) {
         for (index, color) in colors.iter().enumerate() {
-            self.samples[index].push(*color);
+            self.samples[index].push((*color, count));
         }
     }
 
+    /// Get the total number of samples of a pixel in all iteration rounds.
+    pub fn sample_count(&self, x: u32, y: u32) -> u32 {
+        let index = (y * self.width + x) as usize;
+        self.samples[index].iter().map(|(_, count)| count).sum()
+    }
+
+    /// Get the average color and the number of samples of each iteration round of a pixel.
+    pub fn rounds(&self, x: u32, y: u32) -> &[(Color, u32)] {
+        &self.samples[(y * self.width + x) as usize]
+    }
+
+    /// Get the width and height of image.
+    pub const fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Get the average colors of all pixels in (y * width + x) order.
+    pub fn colors(&self) -> Vec<Color> {
+        self.samples
+            .iter()
+            .map(|rounds| weighted_average(rounds))
+            .collect()
+    }
+
     /// Transite the buffer into rgb image.
     pub fn image(&self) -> RgbImage {
+        self.image_with(color_bytes)
+    }
+
+    /// Transite the buffer into rgb image whose pixels are converted from colors by `pixel`,
+    /// e.g. `PostProcess::pixel`.
+    pub fn image_with<F>(&self, pixel: F) -> RgbImage
+    where
+        F: Fn(Color) -> [u8; 3],
+    {
         let mut buf = Vec::new();
         for y in 0..self.height {
             for x in 0..self.width {
                 let color = self.get_color(x, y);
-                let [r, g, b] = color_bytes(color);
+                let [r, g, b] = pixel(color);
                 buf.push(r);
                 buf.push(g);
                 buf.push(b);
@@ -56,8 +107,272 @@ impl Buffer {
     /// Get the average color in iteration rounds color.
     pub fn get_color(&self, x: u32, y: u32) -> Color {
         let index = (y * self.width + x) as usize;
-        let color: Color = self.samples[index].iter().sum();
-        let count = self.samples[index].len();
-        color / count as f64
+        weighted_average(&self.samples[index])
+    }
+
+    /// Estimate the relative error of a pixel from the spread of its iteration rounds, which is
+    /// the standard error of the mean luminance divided by the mean. At least two rounds are
+    /// required, otherwise `None` is returned.
+    pub fn relative_error(&self, x: u32, y: u32) -> Option<f64> {
+        let rounds = &self.samples[(y * self.width + x) as usize];
+        if rounds.len() < 2 {
+            return None;
+        }
+        let total: u32 = rounds.iter().map(|(_, n)| n).sum();
+        let mean = color::luminance(weighted_average(rounds));
+        // The variance of one sample from the variance of round averages weighted by counts.
+        let variance = rounds
+            .iter()
+            .map(|(c, n)| *n as f64 * (color::luminance(*c) - mean).powi(2))
+            .sum::<f64>()
+            / (rounds.len() - 1) as f64;
+        // Offset the mean to avoid the division by zero of black pixels.
+        Some((variance / total as f64).sqrt() / (mean + 1e-3))
+    }
+
+    /// Estimate the average relative error over all pixels, see `relative_error`.
+    pub fn noise_estimate(&self) -> Option<f64> {
+        let mut sum = 0.0;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                sum += self.relative_error(x, y)?;
+            }
+        }
+        Some(sum / (self.width * self.height) as f64)
+    }
+
+    /// Extend a list of colors, each of which is the average of `count` samples, into the named
+    /// AOV of the buffer.
+    pub fn add_aov_samples(&mut self, name: &str, colors: Vec<Color>, count: u32) {
+        let index = self.aov_index(name);
+        for (samples, color) in self.aovs[index].1.iter_mut().zip(colors) {
+            samples.push((color, count));
+        }
+    }
+
+    /// Push a value of new iteration round which is the average of `count` samples into the
+    /// named AOV of a pixel.
+    pub fn add_aov_round(&mut self, name: &str, x: u32, y: u32, color: Color, count: u32) {
+        assert!(x < self.width && y < self.height, "Invalid pixel location!");
+        let index = self.aov_index(name);
+        self.aovs[index].1[(y * self.width + x) as usize].push((color, count));
+    }
+
+    /// Get the index of the named AOV, which is created if it doesn't exist.
+    fn aov_index(&mut self, name: &str) -> usize {
+        match self.aovs.iter().position(|(n, _)| n == name) {
+            Some(index) => index,
+            None => {
+                let samples = vec![vec![]; (self.width * self.height) as usize];
+                self.aovs.push((name.to_string(), samples));
+                self.aovs.len() - 1
+            }
+        }
+    }
+
+    /// Get the names of all AOVs in the buffer.
+    pub fn aov_names(&self) -> Vec<&str> {
+        self.aovs.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Get the average value of the named AOV in iteration rounds.
+    pub fn get_aov_color(&self, name: &str, x: u32, y: u32) -> Option<Color> {
+        let (_, samples) = self.aovs.iter().find(|(n, _)| n == name)?;
+        let index = (y * self.width + x) as usize;
+        Some(weighted_average(&samples[index]))
+    }
+
+    /// Get the average values of the named AOV of all pixels in (y * width + x) order.
+    pub fn aov_colors(&self, name: &str) -> Option<Vec<Color>> {
+        let (_, samples) = self.aovs.iter().find(|(n, _)| n == name)?;
+        Some(
+            samples
+                .iter()
+                .map(|rounds| weighted_average(rounds))
+                .collect(),
+        )
+    }
+
+    /// Visualize the red channel of the named AOV as a heatmap from 0 to `max`, e.g. path
+    /// length AOV.
+    pub fn aov_heatmap(&self, name: &str, max: f64) -> Option<RgbImage> {
+        let mut values = Vec::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                values.push(self.get_aov_color(name, x, y)?.x);
+            }
+        }
+        Some(imgdiff::heatmap(&values, self.width, self.height, max))
+    }
+
+    /// Transite the named AOV of the buffer into rgb image.
+    pub fn aov_image(&self, name: &str) -> Option<RgbImage> {
+        let mut buf = Vec::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                buf.extend(color_bytes(self.get_aov_color(name, x, y)?));
+            }
+        }
+        ImageBuffer::from_raw(self.width, self.height, buf)
+    }
+
+    /// Upscale the buffer to `width` x `height` pixels for draft renders, see
+    /// `Renderer::draft`. The colors are interpolated by the Catmull-Rom spline, whose negative
+    /// lobes sharpen the edges blurred by the low resolution, and the AOVs take the nearest
+    /// pixel so that depths and normals aren't blended across edges. Each pixel keeps the number
+    /// of samples of its nearest pixel in a single round.
+    pub fn upscale(&self, width: u32, height: u32) -> Buffer {
+        let (sx, sy) = (
+            self.width as f64 / width as f64,
+            self.height as f64 / height as f64,
+        );
+        let nearest = |x: u32, y: u32| {
+            let nx = (((x as f64 + 0.5) * sx) as u32).min(self.width - 1);
+            let ny = (((y as f64 + 0.5) * sy) as u32).min(self.height - 1);
+            (ny * self.width + nx) as usize
+        };
+        let colors = self.colors();
+        let texel = |x: i64, y: i64| {
+            let x = x.clamp(0, self.width as i64 - 1);
+            let y = y.clamp(0, self.height as i64 - 1);
+            colors[(y * self.width as i64 + x) as usize]
+        };
+        let mut upscaled = Buffer::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                // The position in the pixels of source, whose centers are at half integers.
+                let fx = (x as f64 + 0.5) * sx - 0.5;
+                let fy = (y as f64 + 0.5) * sy - 0.5;
+                let (x0, y0) = (fx.floor() as i64, fy.floor() as i64);
+                let (wx, wy) = (catmull_rom(fx - fx.floor()), catmull_rom(fy - fy.floor()));
+                let mut color = Color::ZERO;
+                for (j, wy) in wy.iter().enumerate() {
+                    for (i, wx) in wx.iter().enumerate() {
+                        color += wx * wy * texel(x0 + i as i64 - 1, y0 + j as i64 - 1);
+                    }
+                }
+                let index = nearest(x, y);
+                let count = self.samples[index].iter().map(|(_, n)| n).sum();
+                upscaled.samples[(y * width + x) as usize].push((color.max(Color::ZERO), count));
+            }
+        }
+        for (name, samples) in &self.aovs {
+            let samples = (0..width * height)
+                .map(|index| samples[nearest(index % width, index / width)].clone())
+                .collect();
+            upscaled.aovs.push((name.clone(), samples));
+        }
+        upscaled
+    }
+}
+
+/// Get the weights of the Catmull-Rom spline for the four samples around a position at `t` in
+/// [0, 1) between the middle two, which sum to one.
+fn catmull_rom(t: f64) -> [f64; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+    [
+        (-t3 + 2.0 * t2 - t) / 2.0,
+        (3.0 * t3 - 5.0 * t2 + 2.0) / 2.0,
+        (-3.0 * t3 + 4.0 * t2 + t) / 2.0,
+        (t3 - t2) / 2.0,
+    ]
+}
+
+/// Get the average of colors weighted by their number of samples.
+fn weighted_average(rounds: &[Round]) -> Color {
+    let color: Color = rounds.iter().map(|(c, n)| *c * *n as f64).sum();
+    let count: u32 = rounds.iter().map(|(_, n)| n).sum();
+    // The pixel is black before any round, e.g. the first pass is cancelled.
+    if count == 0 {
+        return Color::ZERO;
+    }
+    color / count as f64
+}
+
+/// A frame buffer which accumulates samples splatted to the neighboring pixels with the weights
+/// of reconstruction filter.
+pub struct SplatBuffer {
+    /// The width of image.
+    width: u32,
+    /// The height of image.
+    height: u32,
+    /// The weighted sum of sample colors of each pixel.
+    colors: Vec<Color>,
+    /// The weighted sum of sample AOVs, the first index is the AOV and the second is the pixel.
+    aovs: Vec<Vec<Color>>,
+    /// The sum of weights of each pixel.
+    weights: Vec<f64>,
+}
+
+impl SplatBuffer {
+    /// Create a empty splat buffer with width, height and the number of AOVs.
+    pub fn new(width: u32, height: u32, num_aovs: usize) -> Self {
+        let size = (width * height) as usize;
+        Self {
+            width,
+            height,
+            colors: vec![Color::ZERO; size],
+            aovs: vec![vec![Color::ZERO; size]; num_aovs],
+            weights: vec![0.0; size],
+        }
+    }
+
+    /// Splat the sample at film position (x, y) in pixels to all pixels covered by the filter.
+    pub fn splat(&mut self, x: f64, y: f64, color: Color, aovs: &[Color], filter: &Filter) {
+        // The pixel (i, j) is centered at (i + 0.5, j + 0.5).
+        let radius = filter.radius();
+        let x_min = (x - 0.5 - radius).ceil().max(0.0) as u32;
+        let y_min = (y - 0.5 - radius).ceil().max(0.0) as u32;
+        let x_max = ((x - 0.5 + radius).floor() as i64).min(self.width as i64 - 1);
+        let y_max = ((y - 0.5 + radius).floor() as i64).min(self.height as i64 - 1);
+        for j in y_min as i64..=y_max {
+            for i in x_min as i64..=x_max {
+                let weight = filter.eval(i as f64 + 0.5 - x, j as f64 + 0.5 - y);
+                if weight == 0.0 {
+                    continue;
+                }
+                let index = (j * self.width as i64 + i) as usize;
+                self.colors[index] += weight * color;
+                self.weights[index] += weight;
+                for (aov, value) in self.aovs.iter_mut().zip(aovs) {
+                    aov[index] += weight * *value;
+                }
+            }
+        }
+    }
+
+    /// Add all samples of `other` into the buffer.
+    pub fn merge(mut self, other: Self) -> Self {
+        for (a, b) in self.colors.iter_mut().zip(other.colors) {
+            *a += b;
+        }
+        for (a, b) in self.weights.iter_mut().zip(other.weights) {
+            *a += b;
+        }
+        for (aov_a, aov_b) in self.aovs.iter_mut().zip(other.aovs) {
+            for (a, b) in aov_a.iter_mut().zip(aov_b) {
+                *a += b;
+            }
+        }
+        self
+    }
+
+    /// Get the filtered colors and AOVs of all pixels by normalizing the weighted sums.
+    pub fn resolve(&self) -> (Vec<Color>, Vec<Vec<Color>>) {
+        let normalize = |sums: &[Color]| -> Vec<Color> {
+            sums.iter()
+                .zip(&self.weights)
+                .map(|(sum, &weight)| {
+                    // Filters with negative lobes may cancel out the weights.
+                    if weight.abs() > 1e-8 {
+                        *sum / weight
+                    } else {
+                        Color::ZERO
+                    }
+                })
+                .collect()
+        };
+        let aovs = self.aovs.iter().map(|aov| normalize(aov)).collect();
+        (normalize(&self.colors), aovs)
     }
 }
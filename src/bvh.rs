@@ -6,10 +6,56 @@ use crate::math::{Axis, Ray};
 use crate::object::Object;
 use crate::shape::{Bounded, HitRecord, Hittable};
 
-/// A node in the Bounding Volume Hierarchy. Used to accelerate ray intersection: O(n) -> O(log_n)
+pub mod cache;
+pub mod packet;
+pub mod wide;
+
+/// The counters of work in BVH traversal of one ray.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct TraversalStats {
+    /// The number of visited nodes, including leaves.
+    pub node_visits: u32,
+
+    /// The number of intersection tests of primitives.
+    pub primitive_tests: u32,
+}
+
+/// The counter of `TraversalStats` to visualize.
+#[derive(Clone, Copy)]
+pub enum TraversalMetric {
+    NodeVisits,
+    PrimitiveTests,
+}
+
+impl TraversalStats {
+    /// Get the value of counter.
+    pub const fn get(&self, metric: TraversalMetric) -> u32 {
+        match metric {
+            TraversalMetric::NodeVisits => self.node_visits,
+            TraversalMetric::PrimitiveTests => self.primitive_tests,
+        }
+    }
+}
+
+/// The default maximal number of objects in one leaf of BVH.
+pub const DEFAULT_LEAF_SIZE: usize = 2;
+
+/// The Bounding Volume Hierarchy. Used to accelerate ray intersection: O(n) -> O(log_n)
+pub struct Bvh {
+    /// The objects ordered by the leaves, each leaf refers to a range of them.
+    pub objects: Vec<Object>,
+
+    /// The root node of the hierarchy.
+    pub root: BvhNode,
+}
+
+/// A node in the Bounding Volume Hierarchy.
 pub enum BvhNode {
     Leaf {
-        object: Object,
+        /// The index of the first object of leaf in `Bvh::objects`.
+        start: u32,
+        /// The number of objects in leaf.
+        count: u32,
         bbox: Aabb,
     },
     Node {
@@ -19,11 +65,81 @@ pub enum BvhNode {
     },
 }
 
+impl Bvh {
+    /// Build BVH from list of objects, whose leaves contain at most `leaf_size` objects.
+    pub fn build(objects: Vec<Object>, leaf_size: usize) -> Self {
+        let mut objects = objects;
+        let root = BvhNode::build_from_slice(&mut objects, 0, leaf_size.max(1));
+        Self { objects, root }
+    }
+
+    /// Get closest intersection of ray and count the work of traversal into `stats`.
+    pub fn intersect_with_stats(
+        &self,
+        r: &Ray,
+        ray_t: Interval,
+        stats: &mut TraversalStats,
+    ) -> Option<HitRecord> {
+        self.intersect_node(&self.root, r, ray_t, stats)
+    }
+
+    fn intersect_node(
+        &self,
+        node: &BvhNode,
+        r: &Ray,
+        ray_t: Interval,
+        stats: &mut TraversalStats,
+    ) -> Option<HitRecord> {
+        stats.node_visits += 1;
+        match node {
+            BvhNode::Leaf { start, count, bbox } => {
+                if !bbox.intersect(r, ray_t) {
+                    return None;
+                }
+                let mut rec = None;
+                let mut closest_so_far = ray_t.max;
+                for obj in &self.objects[*start as usize..(start + count) as usize] {
+                    stats.primitive_tests += 1;
+                    if let Some(obj_rec) =
+                        obj.intersect(r, Interval::new(ray_t.min, closest_so_far))
+                    {
+                        closest_so_far = obj_rec.t;
+                        rec = Some(obj_rec);
+                    }
+                }
+                rec
+            }
+            BvhNode::Node { left, right, bbox } => {
+                if !bbox.intersect(r, ray_t) {
+                    return None;
+                }
+
+                let hit_left = self.intersect_node(left, r, ray_t, stats);
+                let t_max = hit_left.as_ref().map_or(ray_t.max, |rec| rec.t);
+                let hit_right =
+                    self.intersect_node(right, r, Interval::new(ray_t.min, t_max), stats);
+
+                hit_right.or(hit_left)
+            }
+        }
+    }
+}
+
 impl BvhNode {
-    /// Build BVH from list of objects.
-    pub fn build(objects: Vec<Object>) -> Self {
-        let mut objs = objects;
-        Self::build_from_slice(&mut objs)
+    /// Get bounding box of this node.
+    pub const fn bbox(&self) -> Aabb {
+        match self {
+            Self::Leaf { bbox, .. } => *bbox,
+            Self::Node { bbox, .. } => *bbox,
+        }
+    }
+
+    /// Get the number of nodes in the subtree of this node, including itself.
+    pub fn count(&self) -> usize {
+        match self {
+            Self::Leaf { .. } => 1,
+            Self::Node { left, right, .. } => 1 + left.count() + right.count(),
+        }
     }
 
     /// Compare the min value of AABB in given axis index.
@@ -36,84 +152,57 @@ impl BvhNode {
             .unwrap_or(Ordering::Equal)
     }
 
-    /// Build BVH from slice of objects.
-    fn build_from_slice(objects: &mut [Object]) -> Self {
+    /// Build BVH from slice of objects which starts at index `start` of all objects.
+    fn build_from_slice(objects: &mut [Object], start: usize, leaf_size: usize) -> Self {
         // Compute the aabb of all objects (the biggest aabb).
         // Then, sort objects and split into two halves (according to longest axis).
-        let (first, rest) = objects.split_first().unwrap();
+        let (first, rest) = objects
+            .split_first()
+            .expect("BVH build called with empty object list");
         let mut bbox = first.bbox();
         for obj in rest {
             bbox = Aabb::surrounding_box(&bbox, &obj.bbox());
         }
+        if objects.len() <= leaf_size {
+            return Self::Leaf {
+                start: start as u32,
+                count: objects.len() as u32,
+                bbox,
+            };
+        }
+
         let axis = bbox.longest_axis();
         objects.sort_by(|a, b| {
             let box_a = a.bbox();
             let box_b = b.bbox();
             Self::box_compare(box_a, box_b, axis)
         });
-
-        match objects.len() {
-            0 => panic!("BVH build called with empty object list"),
-            1 => {
-                let obj = objects[0].clone();
-                let bbox = obj.bbox();
-                Self::Leaf { object: obj, bbox }
-            }
-            2 => {
-                let (left_objs, right_objs) = objects.split_at_mut(1);
-                let left_node = Box::new(Self::build_from_slice(left_objs));
-                let right_node = Box::new(Self::build_from_slice(right_objs));
-                Self::Node {
-                    left: left_node,
-                    right: right_node,
-                    bbox,
-                }
-            }
-            _ => {
-                let mid = objects.len() / 2;
-                let (left_objs, right_objs) = objects.split_at_mut(mid);
-                let left_node = Box::new(Self::build_from_slice(left_objs));
-                let right_node = Box::new(Self::build_from_slice(right_objs));
-                Self::Node {
-                    left: left_node,
-                    right: right_node,
-                    bbox,
-                }
-            }
+        let mid = objects.len() / 2;
+        let (left_objs, right_objs) = objects.split_at_mut(mid);
+        Self::Node {
+            left: Box::new(Self::build_from_slice(left_objs, start, leaf_size)),
+            right: Box::new(Self::build_from_slice(right_objs, start + mid, leaf_size)),
+            bbox,
         }
     }
 }
 
-impl Hittable for BvhNode {
+impl Hittable for Bvh {
     fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        match self {
-            Self::Leaf { object, bbox } => {
-                if !bbox.intersect(r, ray_t) {
-                    return None;
-                }
-                object.intersect(r, ray_t)
-            }
-            Self::Node { left, right, bbox } => {
-                if !bbox.intersect(r, ray_t) {
-                    return None;
-                }
-
-                let hit_left = left.intersect(r, ray_t);
-                let t_max = hit_left.as_ref().map_or(ray_t.max, |rec| rec.t);
-                let hit_right = right.intersect(r, Interval::new(ray_t.min, t_max));
-
-                hit_right.or(hit_left)
-            }
-        }
+        self.intersect_with_stats(r, ray_t, &mut TraversalStats::default())
     }
 }
 
-impl Bounded for BvhNode {
-    /// Get bounding box of this node.
+impl Bounded for Bvh {
+    /// Get bounding box of the root.
     fn bbox(&self) -> Aabb {
-        match self {
-            Self::Leaf { bbox, .. } => *bbox,
-            Self::Node { bbox, .. } => *bbox,
-        }
+        self.root.bbox()
+    }
+
+    /// Get the bytes of the nodes and objects, whose shapes are shared with the scene.
+    fn memory(&self) -> usize {
+        size_of::<Self>()
+            + self.objects.capacity() * size_of::<Object>()
+            + (self.root.count() - 1) * size_of::<BvhNode>()
     }
 }
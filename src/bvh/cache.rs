@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::interval::Interval;
+use crate::object::Object;
+use crate::shape::Bounded;
+
+use super::{Bvh, BvhNode};
+
+/// The magic bytes at the beginning of cache file.
+const MAGIC: &[u8; 4] = b"BVHC";
+/// The version of cache format, which is increased whenever the layout changes.
+const VERSION: u32 = 2;
+
+/// Hash the bounding boxes of objects in order with FNV-1a. The BVH only depends on the boxes,
+/// so the cache stays valid when materials or lights are changed.
+pub fn content_hash(objects: &[Object]) -> u64 {
+    let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+    let mut write = |bytes: &[u8]| {
+        for &byte in bytes {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01B3);
+        }
+    };
+    write(&(objects.len() as u64).to_le_bytes());
+    for object in objects {
+        let bbox = object.bbox();
+        for interval in [bbox.x, bbox.y, bbox.z] {
+            write(&interval.min.to_le_bytes());
+            write(&interval.max.to_le_bytes());
+        }
+    }
+    hash
+}
+
+/// Save the structure of `bvh` built from `objects` to the binary cache file at `path`. The
+/// order of objects in BVH is stored as indices into `objects`.
+pub fn save(bvh: &Bvh, objects: &[Object], path: &Path) -> io::Result<()> {
+    // Objects are identified by their shape and material, clones of the same object are
+    // interchangeable and take the indices in order.
+    let mut indices: HashMap<_, Vec<u32>> = HashMap::new();
+    for (i, object) in objects.iter().enumerate().rev() {
+        indices
+            .entry(object_key(object))
+            .or_default()
+            .push(i as u32);
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&content_hash(objects).to_le_bytes())?;
+    writer.write_all(&(bvh.objects.len() as u32).to_le_bytes())?;
+    for object in &bvh.objects {
+        let index = indices
+            .get_mut(&object_key(object))
+            .and_then(|indices| indices.pop())
+            .ok_or_else(|| invalid_data("the BVH contains an unknown object"))?;
+        writer.write_all(&index.to_le_bytes())?;
+    }
+    write_node(&bvh.root, &mut writer)?;
+    writer.flush()
+}
+
+/// Load the BVH of `objects` from the cache file at `path`. An error of kind `InvalidData` is
+/// returned if the file is corrupted or was saved for different objects.
+pub fn load(path: &Path, objects: &[Object]) -> io::Result<Bvh> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC || read_u32(&mut reader)? != VERSION {
+        return Err(invalid_data("not a BVH cache of this version"));
+    }
+    if read_u64(&mut reader)? != content_hash(objects) {
+        return Err(invalid_data("the BVH cache is outdated"));
+    }
+    let count = read_u32(&mut reader)? as usize;
+    let mut ordered = Vec::with_capacity(count.min(objects.len()));
+    for _ in 0..count {
+        let index = read_u32(&mut reader)? as usize;
+        let object = objects
+            .get(index)
+            .ok_or_else(|| invalid_data("object index out of range"))?;
+        ordered.push(object.clone());
+    }
+    let root = read_node(&mut reader, ordered.len())?;
+    Ok(Bvh {
+        objects: ordered,
+        root,
+    })
+}
+
+/// The identity of object in cache.
+fn object_key(object: &Object) -> (usize, usize) {
+    (
+        Arc::as_ptr(&object.shape) as *const () as usize,
+        Arc::as_ptr(&object.material) as *const () as usize,
+    )
+}
+
+/// Write the node in pre-order: a tag byte, the bounding box, and then the range of objects
+/// for leaves or the children for inner nodes.
+fn write_node<W: Write>(node: &BvhNode, writer: &mut W) -> io::Result<()> {
+    match node {
+        BvhNode::Leaf { start, count, bbox } => {
+            writer.write_all(&[0])?;
+            write_bbox(bbox, writer)?;
+            writer.write_all(&start.to_le_bytes())?;
+            writer.write_all(&count.to_le_bytes())
+        }
+        BvhNode::Node { left, right, bbox } => {
+            writer.write_all(&[1])?;
+            write_bbox(bbox, writer)?;
+            write_node(left, writer)?;
+            write_node(right, writer)
+        }
+    }
+}
+
+/// Read a node written by `write_node`, whose leaves refer to `num_objects` objects.
+fn read_node<R: Read>(reader: &mut R, num_objects: usize) -> io::Result<BvhNode> {
+    let mut tag = [0];
+    reader.read_exact(&mut tag)?;
+    let bbox = read_bbox(reader)?;
+    match tag[0] {
+        0 => {
+            let start = read_u32(reader)?;
+            let count = read_u32(reader)?;
+            if start as usize + count as usize > num_objects {
+                return Err(invalid_data("object range out of range"));
+            }
+            Ok(BvhNode::Leaf { start, count, bbox })
+        }
+        1 => Ok(BvhNode::Node {
+            left: Box::new(read_node(reader, num_objects)?),
+            right: Box::new(read_node(reader, num_objects)?),
+            bbox,
+        }),
+        _ => Err(invalid_data("invalid BVH node tag")),
+    }
+}
+
+fn write_bbox<W: Write>(bbox: &Aabb, writer: &mut W) -> io::Result<()> {
+    for interval in [bbox.x, bbox.y, bbox.z] {
+        writer.write_all(&interval.min.to_le_bytes())?;
+        writer.write_all(&interval.max.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_bbox<R: Read>(reader: &mut R) -> io::Result<Aabb> {
+    let mut read_interval = || -> io::Result<Interval> {
+        let min = read_f64(reader)?;
+        let max = read_f64(reader)?;
+        Ok(Interval { min, max })
+    };
+    Ok(Aabb::new(
+        read_interval()?,
+        read_interval()?,
+        read_interval()?,
+    ))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64<R: Read>(reader: &mut R) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,118 @@
+use glam::DVec3;
+
+use crate::aabb::Aabb;
+use crate::interval::Interval;
+use crate::math::{DPoint3, Ray};
+use crate::shape::{HitRecord, Hittable};
+
+use super::{Bvh, BvhNode};
+
+/// The side planes of a pyramid from a common origin which bounds a packet of coherent rays.
+pub struct Frustum {
+    /// The apex of the pyramid, which is the origin of all rays.
+    origin: DPoint3,
+
+    /// The inward normals of the side planes.
+    normals: [DVec3; 4],
+}
+
+impl Frustum {
+    /// Create the frustum from the origin and the directions through its four corners in order
+    /// around the pyramid.
+    pub fn new(origin: DPoint3, corners: [DVec3; 4]) -> Self {
+        let center: DVec3 = corners.iter().sum();
+        let normals = [0, 1, 2, 3].map(|i| {
+            let n = corners[i].cross(corners[(i + 1) % 4]);
+            // Orient the normal towards the inside whichever the winding of corners is.
+            if n.dot(center) < 0.0 { -n } else { n }
+        });
+        Self { origin, normals }
+    }
+
+    /// Check conservatively whether the box overlaps the frustum, i.e. the box is not entirely
+    /// outside of any side plane.
+    pub fn overlaps(&self, bbox: &Aabb) -> bool {
+        self.normals.iter().all(|n| {
+            // The corner of box which is the farthest along the normal.
+            let corner = DVec3::new(
+                if n.x > 0.0 { bbox.x.max } else { bbox.x.min },
+                if n.y > 0.0 { bbox.y.max } else { bbox.y.min },
+                if n.z > 0.0 { bbox.z.max } else { bbox.z.min },
+            );
+            n.dot(corner - self.origin) >= 0.0
+        })
+    }
+}
+
+impl Bvh {
+    /// Get closest intersections of a packet of rays, which are traversed together. The nodes
+    /// outside of `frustum` are culled for the whole packet, so the frustum must bound all rays.
+    pub fn intersect_packet(
+        &self,
+        rays: &[Ray],
+        frustum: Option<&Frustum>,
+        ray_t: Interval,
+    ) -> Vec<Option<HitRecord>> {
+        let mut hits = vec![None; rays.len()];
+        let mut t_max = vec![ray_t.max; rays.len()];
+        self.intersect_packet_node(&self.root, rays, frustum, ray_t.min, &mut t_max, &mut hits);
+        hits
+    }
+
+    fn intersect_packet_node(
+        &self,
+        node: &BvhNode,
+        rays: &[Ray],
+        frustum: Option<&Frustum>,
+        t_min: f64,
+        t_max: &mut [f64],
+        hits: &mut [Option<HitRecord>],
+    ) {
+        let bbox = node.bbox();
+        if frustum.is_some_and(|frustum| !frustum.overlaps(&bbox)) {
+            return;
+        }
+        // Find the first ray which hits the box, the rays before it can skip the subtree.
+        let Some(first) = rays
+            .iter()
+            .zip(t_max.iter())
+            .position(|(r, &t)| bbox.intersect(r, Interval::new(t_min, t)))
+        else {
+            return;
+        };
+
+        match node {
+            BvhNode::Leaf { start, count, .. } => {
+                let objects = &self.objects[*start as usize..(start + count) as usize];
+                for (i, r) in rays.iter().enumerate().skip(first) {
+                    for obj in objects {
+                        if let Some(rec) = obj.intersect(r, Interval::new(t_min, t_max[i])) {
+                            t_max[i] = rec.t;
+                            hits[i] = Some(rec);
+                        }
+                    }
+                }
+            }
+            BvhNode::Node { left, right, .. } => {
+                let (rays, t_max, hits) = (&rays[first..], &mut t_max[first..], &mut hits[first..]);
+                // Visit the nearer child first along the rays, so that the farther one is
+                // more likely to be skipped.
+                let center = |bbox: Aabb| {
+                    DVec3::new(
+                        bbox.x.min + bbox.x.max,
+                        bbox.y.min + bbox.y.max,
+                        bbox.z.min + bbox.z.max,
+                    )
+                };
+                let (near, far) =
+                    if (center(right.bbox()) - center(left.bbox())).dot(rays[0].dir) < 0.0 {
+                        (right, left)
+                    } else {
+                        (left, right)
+                    };
+                self.intersect_packet_node(near, rays, frustum, t_min, t_max, hits);
+                self.intersect_packet_node(far, rays, frustum, t_min, t_max, hits);
+            }
+        }
+    }
+}
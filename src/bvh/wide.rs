@@ -0,0 +1,232 @@
+use glam::DVec3;
+
+use crate::aabb::Aabb;
+use crate::interval::Interval;
+use crate::math::Ray;
+use crate::object::Object;
+use crate::shape::{Bounded, HitRecord, Hittable};
+use crate::simd::BoxBatch;
+
+use super::{Bvh, BvhNode};
+
+/// The flag of child reference which points to a leaf rather than a node.
+const LEAF: u32 = 1 << 31;
+/// The child reference of unused slots.
+const EMPTY: u32 = u32::MAX;
+/// The capacity of traversal stack, which is enough for the depth of median split BVH.
+const STACK_SIZE: usize = 256;
+
+/// A BVH with `N` children per node, which is converted from the binary BVH by collapsing the
+/// inner nodes. The child bounds are quantized to 8 bits relative to the parent bounds, so that
+/// less memory is fetched per visited node.
+pub struct WideBvh<const N: usize> {
+    /// The inner nodes, the root is the first one unless the BVH is a single leaf.
+    nodes: Vec<WideNode<N>>,
+
+    /// The objects referred by leaves.
+    objects: Vec<Object>,
+
+    /// The start and end indices of objects in each leaf.
+    leaves: Vec<(u32, u32)>,
+
+    /// The reference to the root.
+    root: u32,
+
+    /// The bounding box of the whole BVH.
+    bbox: Aabb,
+}
+
+/// The BVH with 4 children per node.
+pub type Bvh4 = WideBvh<4>;
+
+/// The BVH with 8 children per node.
+pub type Bvh8 = WideBvh<8>;
+
+struct WideNode<const N: usize> {
+    /// The minimal corner of the node bounds.
+    origin: DVec3,
+
+    /// The size of one quantization step in each axis.
+    scale: DVec3,
+
+    /// The quantized min and max of children bounds in x, y, z order.
+    bounds: [[u8; 6]; N],
+
+    /// The references to children, `LEAF` is set for leaves and unused slots are `EMPTY`.
+    children: [u32; N],
+}
+
+impl<const N: usize> WideBvh<N> {
+    /// Convert the binary BVH. The inner child with the largest surface area is opened until a
+    /// node has `N` children.
+    pub fn from_bvh(bvh: &Bvh) -> Self {
+        const { assert!(N >= 2 && N <= 8, "the width of BVH should be in [2, 8]") };
+        let mut wide = Self {
+            nodes: Vec::new(),
+            objects: bvh.objects.clone(),
+            leaves: Vec::new(),
+            root: EMPTY,
+            bbox: bvh.root.bbox(),
+        };
+        wide.root = wide.collapse(&bvh.root);
+        wide
+    }
+
+    /// Convert the subtree and return the reference to it.
+    fn collapse(&mut self, node: &BvhNode) -> u32 {
+        let (left, right, bbox) = match node {
+            BvhNode::Leaf { start, count, .. } => {
+                self.leaves.push((*start, start + count));
+                return (self.leaves.len() - 1) as u32 | LEAF;
+            }
+            BvhNode::Node { left, right, bbox } => (left, right, *bbox),
+        };
+
+        let mut children: Vec<&BvhNode> = vec![left, right];
+        while children.len() < N {
+            let largest = children
+                .iter()
+                .enumerate()
+                .filter(|(_, child)| matches!(child, BvhNode::Node { .. }))
+                .max_by(|(_, a), (_, b)| {
+                    a.bbox().surface_area().total_cmp(&b.bbox().surface_area())
+                })
+                .map(|(i, _)| i);
+            let Some(i) = largest else {
+                break;
+            };
+            if let BvhNode::Node { left, right, .. } = children.swap_remove(i) {
+                children.push(left);
+                children.push(right);
+            }
+        }
+
+        // Reserve the slot so that parents precede their children.
+        let index = self.nodes.len();
+        let (origin, scale) = quantization(&bbox);
+        self.nodes.push(WideNode {
+            origin,
+            scale,
+            bounds: [[0; 6]; N],
+            children: [EMPTY; N],
+        });
+        for (slot, child) in children.into_iter().enumerate() {
+            let reference = self.collapse(child);
+            let node = &mut self.nodes[index];
+            node.bounds[slot] = quantize(origin, scale, &child.bbox());
+            node.children[slot] = reference;
+        }
+        index as u32
+    }
+}
+
+impl<const N: usize> WideNode<N> {
+    /// Get the conservative bounding boxes of all children, the unused slots are empty.
+    fn child_boxes(&self) -> BoxBatch<N> {
+        let mut boxes = BoxBatch::default();
+        for (slot, q) in self.bounds.iter().enumerate() {
+            if self.children[slot] == EMPTY {
+                break;
+            }
+            let min = self.origin + DVec3::new(q[0] as f64, q[2] as f64, q[4] as f64) * self.scale;
+            let max = self.origin + DVec3::new(q[1] as f64, q[3] as f64, q[5] as f64) * self.scale;
+            boxes.set(slot, min, max);
+        }
+        boxes
+    }
+}
+
+/// Get the origin and step size to quantize the children in bounds `bbox`.
+fn quantization(bbox: &Aabb) -> (DVec3, DVec3) {
+    let origin = DVec3::new(bbox.x.min, bbox.y.min, bbox.z.min);
+    let size = DVec3::new(bbox.x.size(), bbox.y.size(), bbox.z.size());
+    // Flat bounds still need a non-zero step to represent the children.
+    (origin, (size / 255.0).max(DVec3::splat(f64::MIN_POSITIVE)))
+}
+
+/// Quantize the child bounds, rounding outward so that the decoded box contains the child.
+fn quantize(origin: DVec3, scale: DVec3, bbox: &Aabb) -> [u8; 6] {
+    let mut q = [0; 6];
+    for (axis, interval) in [bbox.x, bbox.y, bbox.z].iter().enumerate() {
+        let decode = |q: u8| origin[axis] + q as f64 * scale[axis];
+        let mut lo = ((interval.min - origin[axis]) / scale[axis])
+            .floor()
+            .clamp(0.0, 255.0) as u8;
+        while lo > 0 && decode(lo) > interval.min {
+            lo -= 1;
+        }
+        let mut hi = ((interval.max - origin[axis]) / scale[axis])
+            .ceil()
+            .clamp(0.0, 255.0) as u8;
+        while hi < 255 && decode(hi) < interval.max {
+            hi += 1;
+        }
+        q[2 * axis] = lo;
+        q[2 * axis + 1] = hi;
+    }
+    q
+}
+
+impl<const N: usize> Hittable for WideBvh<N> {
+    fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let t_entry = self.bbox.entry_distance(r, ray_t)?;
+        let inv_dir = 1.0 / r.dir;
+        let mut closest = None;
+        let mut t_max = ray_t.max;
+
+        // The children are pushed from far to near, so the nearest one is visited first.
+        let mut stack = [(EMPTY, 0.0); STACK_SIZE];
+        stack[0] = (self.root, t_entry);
+        let mut len = 1;
+        while len > 0 {
+            len -= 1;
+            let (reference, t_entry) = stack[len];
+            // The closest hit so far is in front of the whole subtree.
+            if t_entry >= t_max {
+                continue;
+            }
+            if reference & LEAF != 0 {
+                let (start, end) = self.leaves[(reference & !LEAF) as usize];
+                for object in &self.objects[start as usize..end as usize] {
+                    if let Some(rec) = object.intersect(r, Interval::new(ray_t.min, t_max)) {
+                        t_max = rec.t;
+                        closest = Some(rec);
+                    }
+                }
+                continue;
+            }
+
+            let node = &self.nodes[reference as usize];
+            let t_interval = Interval::new(ray_t.min, t_max);
+            let entries = node
+                .child_boxes()
+                .entry_distances(r.ori, inv_dir, t_interval);
+            let mut hits = [(EMPTY, 0.0); N];
+            let mut count = 0;
+            for (&child, &t) in node.children.iter().zip(&entries) {
+                if t.is_finite() {
+                    hits[count] = (child, t);
+                    count += 1;
+                }
+            }
+            hits[..count].sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+            stack[len..len + count].copy_from_slice(&hits[..count]);
+            len += count;
+        }
+        closest
+    }
+}
+
+impl<const N: usize> Bounded for WideBvh<N> {
+    fn bbox(&self) -> Aabb {
+        self.bbox
+    }
+
+    /// Get the bytes of the nodes and objects, whose shapes are shared with the scene.
+    fn memory(&self) -> usize {
+        size_of::<Self>()
+            + self.nodes.capacity() * size_of::<WideNode<N>>()
+            + self.objects.capacity() * size_of::<Object>()
+            + self.leaves.capacity() * size_of::<(u32, u32)>()
+    }
+}
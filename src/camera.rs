@@ -1,10 +1,14 @@
 use glam::DVec3;
+use rand::Rng;
 use rand::rngs::StdRng;
-use rand_distr::{Distribution, UnitDisc};
 
-use crate::math::{DPoint3, Ray, random};
+use crate::math::{DPoint3, Ray};
+use crate::sampling;
+
+pub mod controller;
 
 #[allow(non_snake_case)]
+#[derive(Clone)]
 pub struct Camera {
     /// The original point of camera.
     pub origin: DPoint3,
@@ -78,14 +82,89 @@ impl Camera {
     /// Get the ray from aperture to pixel plane.
     /// The pixel plane uses coordinate (i, j) which ranged between [0, 1).
     pub fn get_ray(&self, i: f64, j: f64, rng: &mut StdRng) -> Ray {
-        let [x, y]: [f64; 2] = UnitDisc.sample(rng);
+        let (x, y) = sampling::concentric_disk(rng.random());
         let mut lens_offset = self.lens_radius * DVec3::new(x, y, 0.0);
         lens_offset = self.c_x * lens_offset.x + self.c_y * lens_offset.y;
-        let dir = self.upper_left + i * self.u + j * self.v - self.origin - lens_offset;
-        let shutter_time = random();
+        let dir = self.film_point(i, j) - self.origin - lens_offset;
+        let shutter_time = rng.random();
         Ray::new(self.origin + lens_offset, dir.normalize(), shutter_time)
     }
 
+    /// Get the point on the pixel plane at coordinate (i, j), see `get_ray`.
+    pub fn film_point(&self, i: f64, j: f64) -> DPoint3 {
+        self.upper_left + i * self.u + j * self.v
+    }
+
+    /// Get the distance from the origin to the plane in focus, where the pixel plane lies.
+    pub fn focus_distance(&self) -> f64 {
+        (self.origin - self.film_point(0.5, 0.5)).dot(self.c_x.cross(self.c_y))
+    }
+
+    /// Set the diameter of the lens, whose rays start on a disk to blur the points off the
+    /// plane in focus. The zero aperture is a pinhole camera with everything in focus.
+    pub const fn aperture(mut self, aperture: f64) -> Self {
+        self.lens_radius = aperture.abs() / 2.0;
+        self
+    }
+
+    /// Move the plane in focus to `distance` from the origin keeping the field of view.
+    /// Non-positive distances are ignored.
+    pub fn refocus(mut self, distance: f64) -> Self {
+        if distance > 0.0 {
+            let scale = distance / self.focus_distance();
+            let c_z = self.c_x.cross(self.c_y);
+            self.viewport_width *= scale;
+            self.viewport_height *= scale;
+            self.u *= scale;
+            self.v *= scale;
+            self.upper_left = self.origin - self.u / 2.0 - self.v / 2.0 - c_z * distance;
+        }
+        self
+    }
+
+    /// Move the plane in focus through `point` keeping the field of view, so that the depth of
+    /// field needn't be measured by hand. Points behind the camera are ignored.
+    pub fn focus_on(self, point: DPoint3) -> Self {
+        let depth = self.depth(point);
+        self.refocus(depth)
+    }
+
+    /// Get the planar depth of point `p` along the view direction, i.e. the distance from the
+    /// plane of camera through the origin.
+    pub fn depth(&self, p: DPoint3) -> f64 {
+        (self.origin - p).dot(self.c_x.cross(self.c_y))
+    }
+
+    /// Get the coordinate (i, j) on the pixel plane where point `p` is projected, see
+    /// `get_ray`. `None` if the point is behind the camera.
+    pub fn project(&self, p: DPoint3) -> Option<(f64, f64)> {
+        self.project_dir(p - self.origin)
+    }
+
+    /// Get the coordinate (i, j) on the pixel plane where the direction `dir` from the camera
+    /// is projected, e.g. the background at infinity. `None` if it points behind the camera.
+    pub fn project_dir(&self, dir: DVec3) -> Option<(f64, f64)> {
+        let c_z = self.c_x.cross(self.c_y);
+        let focal_length = self.focus_distance();
+        let depth = -dir.dot(c_z);
+        if depth <= 0.0 {
+            return None;
+        }
+        let offset = self.origin + dir * (focal_length / depth) - self.upper_left;
+        Some((
+            offset.dot(self.u) / self.u.length_squared(),
+            offset.dot(self.v) / self.v.length_squared(),
+        ))
+    }
+
+    /// Get the fraction of image height covered by the projection of a sphere, which is
+    /// approximated by its angular size relative to the vertical field of view.
+    pub fn projected_size(&self, center: DPoint3, radius: f64) -> f64 {
+        let focal_length = self.focus_distance();
+        let distance = (center - self.origin).length().max(radius);
+        radius / distance * focal_length / (0.5 * self.viewport_height)
+    }
+
     /// Get how much width for one pixel.
     pub fn pixel_delta_u(&self, image_width: u32) -> DVec3 {
         self.viewport_width * self.c_x / image_width as f64
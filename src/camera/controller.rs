@@ -0,0 +1,159 @@
+use std::f64;
+
+use glam::DVec3;
+
+use crate::{camera::Camera, math::DPoint3, onb::ONB};
+
+/// The largest pitch angle which keeps the view direction away from the up vector.
+const MAX_PITCH: f64 = f64::consts::FRAC_PI_2 - 1e-3;
+
+/// One input event of the preview window which moves the camera.
+#[derive(Clone, Copy)]
+pub enum CameraInput {
+    /// Fly the camera and its target by the multiples of move speed along the view direction,
+    /// right and up vectors.
+    Move { forward: f64, right: f64, up: f64 },
+
+    /// Orbit the camera around its target by the mouse drag in pixels.
+    Orbit { dx: f64, dy: f64 },
+
+    /// Move the camera toward its target by the steps of mouse wheel.
+    Zoom(f64),
+}
+
+impl CameraInput {
+    /// Map the WASD keys, and Q/E for down/up, to move events.
+    pub fn from_key(key: char) -> Option<Self> {
+        let (forward, right, up) = match key.to_ascii_lowercase() {
+            'w' => (1.0, 0.0, 0.0),
+            's' => (-1.0, 0.0, 0.0),
+            'd' => (0.0, 1.0, 0.0),
+            'a' => (0.0, -1.0, 0.0),
+            'e' => (0.0, 0.0, 1.0),
+            'q' => (0.0, 0.0, -1.0),
+            _ => return None,
+        };
+        Some(Self::Move { forward, right, up })
+    }
+}
+
+/// The orbit camera of interactive preview, which keeps the parameters of `Camera::new` and
+/// rebuilds the camera after each input.
+pub struct CameraController {
+    /// The point which the camera looks to and orbits around.
+    pub target: DPoint3,
+
+    /// The distance from the camera to its target.
+    pub distance: f64,
+
+    /// The azimuth angle of the camera around the up vector in radians.
+    pub yaw: f64,
+
+    /// The elevation angle of the camera above the target in radians.
+    pub pitch: f64,
+
+    /// The view up vector.
+    pub vup: DVec3,
+
+    /// Vertical field-of-view in degrees.
+    pub vfov: f64,
+
+    /// The aspect ratio of image.
+    pub aspect_ratio: f64,
+
+    /// The aperture of lens.
+    pub aperture: f64,
+
+    /// The focal length of lens.
+    pub focal_length: f64,
+
+    /// The distance of one move step.
+    pub move_speed: f64,
+
+    /// The angle in radians of orbiting for one pixel of mouse drag.
+    pub rotate_speed: f64,
+
+    /// The ratio of distance kept after one step of zooming in.
+    pub zoom_speed: f64,
+}
+
+impl CameraController {
+    /// Create a controller with the same parameters as `Camera::new`.
+    pub fn new(
+        look_from: DPoint3,
+        look_to: DPoint3,
+        vup: DVec3,
+        vfov: f64,
+        aspect_ratio: f64,
+        aperture: f64,
+        focal_length: f64,
+    ) -> Self {
+        let offset = look_from - look_to;
+        let distance = offset.length();
+        let dir = offset / distance;
+        let local = ONB::new(vup).to_local(dir);
+        let yaw = local.y.atan2(local.x);
+        let pitch = dir.dot(vup.normalize()).clamp(-1.0, 1.0).asin();
+        Self {
+            target: look_to,
+            distance,
+            yaw,
+            pitch: pitch.clamp(-MAX_PITCH, MAX_PITCH),
+            vup,
+            vfov,
+            aspect_ratio,
+            aperture,
+            focal_length,
+            move_speed: 0.1 * distance,
+            rotate_speed: 0.005,
+            zoom_speed: 0.9,
+        }
+    }
+
+    /// Get the position of the camera.
+    pub fn look_from(&self) -> DPoint3 {
+        let local = DVec3::new(
+            self.pitch.cos() * self.yaw.cos(),
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+        );
+        self.target + self.distance * ONB::new(self.vup).transform(local)
+    }
+
+    /// Apply an input event and return whether the camera is changed, in which case the
+    /// accumulated samples are outdated and the preview should restart from a new buffer.
+    pub fn input(&mut self, input: CameraInput) -> bool {
+        match input {
+            CameraInput::Move { forward, right, up } => {
+                let view = (self.target - self.look_from()).normalize();
+                let right_dir = view.cross(self.vup).normalize();
+                let up_dir = right_dir.cross(view);
+                let offset = self.move_speed * (forward * view + right * right_dir + up * up_dir);
+                self.target += offset;
+                offset != DVec3::ZERO
+            }
+            CameraInput::Orbit { dx, dy } => {
+                self.yaw += dx * self.rotate_speed;
+                self.pitch = (self.pitch + dy * self.rotate_speed).clamp(-MAX_PITCH, MAX_PITCH);
+                dx != 0.0 || dy != 0.0
+            }
+            CameraInput::Zoom(steps) => {
+                self.distance *= self.zoom_speed.powf(steps);
+                steps != 0.0
+            }
+        }
+    }
+
+    /// Build the camera of current state.
+    pub fn camera(&self) -> Camera {
+        Camera::new(
+            self.look_from(),
+            self.target,
+            self.vup,
+            self.vfov,
+            self.aspect_ratio,
+            self.aperture,
+            self.focal_length,
+        )
+    }
+}
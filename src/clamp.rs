@@ -0,0 +1,78 @@
+use crate::color::Color;
+
+/// How a contribution over the limit of `IndirectClamp` is reduced.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ClampMode {
+    /// Each channel is clamped to the limit separately, which shifts the hue of bright colors
+    /// towards white.
+    PerChannel,
+
+    /// The color is scaled until its largest channel is at the limit, which keeps the hue.
+    MaxComponent,
+}
+
+/// The limits of the indirect light brought by a bounce, which trade a small bias for much lower
+/// variance, e.g. the fireflies of caustics. The bounces from diffuse and specular surfaces are
+/// limited separately, so the sharp reflections can be kept bright while the noisy diffuse
+/// interreflections are clamped hard.
+#[derive(Clone, Copy)]
+pub struct IndirectClamp {
+    /// The limit of the light bounced by diffuse and rough glossy surfaces.
+    pub diffuse: f64,
+
+    /// The limit of the light bounced by specular and smooth glossy surfaces.
+    pub specular: f64,
+
+    /// How the light over the limit is reduced.
+    pub mode: ClampMode,
+}
+
+impl Default for IndirectClamp {
+    fn default() -> Self {
+        Self::new(100.0)
+    }
+}
+
+impl IndirectClamp {
+    /// Clamp each channel of the light of all bounces to `limit`.
+    pub const fn new(limit: f64) -> Self {
+        Self {
+            diffuse: limit,
+            specular: limit,
+            mode: ClampMode::PerChannel,
+        }
+    }
+
+    /// Set the limits of the diffuse and specular bounces.
+    pub const fn limits(mut self, diffuse: f64, specular: f64) -> Self {
+        self.diffuse = diffuse;
+        self.specular = specular;
+        self
+    }
+
+    /// Set how the light over the limit is reduced.
+    pub const fn mode(mut self, mode: ClampMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Clamp the indirect light `color` of a bounce from a specular or diffuse surface.
+    pub fn clamp(&self, color: Color, specular: bool) -> Color {
+        let limit = if specular {
+            self.specular
+        } else {
+            self.diffuse
+        };
+        match self.mode {
+            ClampMode::PerChannel => color.min(Color::splat(limit)),
+            ClampMode::MaxComponent => {
+                let max = color.max_element();
+                if max > limit {
+                    color * (limit / max)
+                } else {
+                    color
+                }
+            }
+        }
+    }
+}
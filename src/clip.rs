@@ -0,0 +1,101 @@
+use std::sync::Arc;
+
+use glam::DVec3;
+
+use crate::{
+    interval::Interval,
+    material::Bsdf,
+    math::{DPoint3, Ray},
+    shape::HitRecord,
+};
+
+/// A plane which cuts away the geometry in front of it, e.g. the cutaways of architectural
+/// renders and looking into interiors, see `Scene::with_section`.
+#[derive(Clone)]
+pub struct SectionPlane {
+    /// A point on the plane.
+    pub point: DPoint3,
+
+    /// The unit normal of the plane, which points to the side that is cut away.
+    pub normal: DVec3,
+
+    /// The material of the caps which close the cut of solids, `None` to leave them open.
+    pub cap: Option<Arc<dyn Bsdf>>,
+}
+
+impl SectionPlane {
+    /// Create a plane through `point` which cuts away the side that `normal` points to.
+    pub fn new(point: DPoint3, normal: DVec3) -> Self {
+        Self {
+            point,
+            normal: normal.normalize(),
+            cap: None,
+        }
+    }
+
+    /// Set the material of the caps, e.g. a flat bright color to highlight the cut.
+    pub fn cap<M>(mut self, material: M) -> Self
+    where
+        M: Bsdf + 'static,
+    {
+        self.cap = Some(Arc::new(material));
+        self
+    }
+
+    /// Whether `p` is on the side which is cut away.
+    pub fn clips(&self, p: DPoint3) -> bool {
+        (p - self.point).dot(self.normal) > 0.0
+    }
+}
+
+/// The part of `ray_t` along the ray which is kept by all `planes`, and the index of the plane
+/// through which the ray enters the part if it starts clipped. The kept space is convex, so the
+/// part is a single interval, `None` if it is empty.
+pub fn kept_interval(
+    planes: &[SectionPlane],
+    r: &Ray,
+    ray_t: Interval,
+) -> Option<(Interval, Option<usize>)> {
+    let mut kept = ray_t;
+    let mut entry = None;
+    for (i, plane) in planes.iter().enumerate() {
+        let offset = (r.ori - plane.point).dot(plane.normal);
+        let speed = r.dir.dot(plane.normal);
+        if speed == 0.0 {
+            if offset > 0.0 {
+                return None;
+            }
+            continue;
+        }
+        // The ray crosses the plane at `t`, and is kept before it if it moves towards the cut
+        // away side, otherwise after it.
+        let t = -offset / speed;
+        if speed > 0.0 {
+            kept.max = kept.max.min(t);
+        } else if t > kept.min {
+            kept.min = t;
+            entry = Some(i);
+        }
+    }
+    (kept.min < kept.max).then_some((kept, entry))
+}
+
+/// Get the cap of `plane` where the ray enters the kept space at `t`, if the closest hit `rec`
+/// after it shows that the ray is inside a solid, i.e. it hits the back face.
+pub fn cap_hit(plane: &SectionPlane, r: &Ray, t: f64, rec: &HitRecord) -> Option<HitRecord> {
+    let material = plane.cap.clone()?;
+    if rec.front_face {
+        return None;
+    }
+    let mut cap = HitRecord {
+        p: r.at(t),
+        t,
+        material: Some(material),
+        light_group: rec.light_group.clone(),
+        instance_seed: rec.instance_seed,
+        object_id: rec.object_id,
+        ..Default::default()
+    };
+    cap.set_face_normal(r, plane.normal);
+    Some(cap)
+}
@@ -6,7 +6,7 @@ pub type Color = DVec3;
 // gamma correct power coefficient
 const SRGB_GAMMA: f64 = 2.2;
 
-// type annotation needed for const 
+// type annotation needed for const
 pub const BLACK: Color = Color::ZERO;
 pub const GREY: Color = Color::splat(0.5);
 pub const WHITE: Color = Color::splat(1.0);
@@ -14,11 +14,33 @@ pub const RED: Color = Color::new(0.65, 0.05, 0.05);
 pub const GREEN: Color = Color::new(0.12, 0.45, 0.15);
 pub const BLUE: Color = Color::new(0.2, 0.4, 0.9);
 
+/// Get the relative luminance of a linear rgb color.
+pub fn luminance(color: Color) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
 /// Convert pixel rgb values from [0, 1) to [0, 255] with gamma correct.
 pub fn color_bytes(color: Color) -> [u8; 3] {
-    [
-        (256.0 * color.x.clamp(0.0, 0.999).powf(1.0 / SRGB_GAMMA)) as u8,
-        (256.0 * color.y.clamp(0.0, 0.999).powf(1.0 / SRGB_GAMMA)) as u8,
-        (256.0 * color.z.clamp(0.0, 0.999).powf(1.0 / SRGB_GAMMA)) as u8,
-    ]
+    quantize(gamma_encode(color))
+}
+
+/// Gamma correct the linear rgb values which are clamped to [0, 1).
+pub fn gamma_encode(color: Color) -> Color {
+    color
+        .clamp(Color::ZERO, Color::splat(0.999))
+        .powf(1.0 / SRGB_GAMMA)
+}
+
+/// Convert the gamma corrected rgb values from [0, 1) to [0, 255].
+pub fn quantize(color: Color) -> [u8; 3] {
+    (256.0 * color.clamp(Color::ZERO, Color::splat(0.999)))
+        .to_array()
+        .map(|c| c as u8)
+}
+
+/// Convert pixel rgb values from [0, 255] with gamma correct back to linear [0, 1], which is the
+/// inverse of `color_bytes`.
+pub fn color_from_bytes(bytes: [u8; 3]) -> Color {
+    let [r, g, b] = bytes.map(|c| (c as f64 / 255.0).powf(SRGB_GAMMA));
+    Color::new(r, g, b)
 }
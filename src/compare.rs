@@ -0,0 +1,97 @@
+use image::{Rgb, RgbImage};
+
+use crate::{annotate, camera::Camera, renderer::Renderer};
+
+/// The width of the divider between two halves in pixels.
+const DIVIDER_WIDTH: u32 = 2;
+/// The margin between labels and the border of image in pixels.
+const MARGIN: u32 = 4;
+/// The gap between the thumbnails of contact sheet in pixels.
+const GAP: u32 = 4;
+
+/// Combine the left half of `a` and the right half of `b` into one split-screen image, which is
+/// separated by a vertical divider and labeled at the top of each side.
+pub fn split_screen(a: &RgbImage, b: &RgbImage, label_a: &str, label_b: &str) -> RgbImage {
+    assert!(
+        a.dimensions() == b.dimensions(),
+        "Images to compare must have the same size!"
+    );
+    let (width, height) = a.dimensions();
+    let half = width / 2;
+    let mut image = RgbImage::from_fn(width, height, |x, y| {
+        if x < half {
+            *a.get_pixel(x, y)
+        } else {
+            *b.get_pixel(x, y)
+        }
+    });
+    annotate::fill_rect(
+        &mut image,
+        half.saturating_sub(DIVIDER_WIDTH / 2),
+        0,
+        DIVIDER_WIDTH,
+        height,
+        Rgb([255, 255, 255]),
+    );
+
+    // Scale labels with the image so that they are legible in large renders.
+    let scale = (height / 240).max(1);
+    annotate::draw_label(&mut image, MARGIN, MARGIN, label_a, scale);
+    let (label_width, _) = annotate::label_size(label_b, scale);
+    let x = width
+        .saturating_sub(label_width + MARGIN)
+        .max(half + MARGIN);
+    annotate::draw_label(&mut image, x, MARGIN, label_b, scale);
+    image
+}
+
+/// Render the same scene with two renderers which differ in settings or materials, and combine
+/// them into a split-screen image, see `split_screen`.
+pub fn render_split(a: &Renderer, b: &Renderer, label_a: &str, label_b: &str) -> RgbImage {
+    split_screen(&a.render(), &b.render(), label_a, label_b)
+}
+
+/// Arrange the labeled thumbnails of the same size into a grid of about square shape on a black
+/// sheet, e.g. the shots of all cameras to choose from. Each label is drawn at the top left of
+/// its thumbnail.
+pub fn contact_sheet(thumbnails: &[(&str, RgbImage)]) -> RgbImage {
+    let Some((_, first)) = thumbnails.first() else {
+        return RgbImage::new(0, 0);
+    };
+    let (width, height) = first.dimensions();
+    assert!(
+        thumbnails
+            .iter()
+            .all(|(_, t)| t.dimensions() == (width, height)),
+        "Thumbnails must have the same size!"
+    );
+    let count = thumbnails.len() as u32;
+    let columns = (count as f64).sqrt().ceil() as u32;
+    let rows = count.div_ceil(columns);
+    let mut sheet = RgbImage::new(columns * (width + GAP) + GAP, rows * (height + GAP) + GAP);
+    let scale = (height / 240).max(1);
+    for (i, (label, thumbnail)) in thumbnails.iter().enumerate() {
+        let x = GAP + (i as u32 % columns) * (width + GAP);
+        let y = GAP + (i as u32 / columns) * (height + GAP);
+        for (tx, ty, pixel) in thumbnail.enumerate_pixels() {
+            sheet.put_pixel(x + tx, y + ty, *pixel);
+        }
+        annotate::draw_label(&mut sheet, x + MARGIN, y + MARGIN, label, scale);
+    }
+    sheet
+}
+
+/// Render a thumbnail of the size of `renderer` from each named camera, and arrange them into a
+/// contact sheet, see `contact_sheet`. The camera of `renderer` is restored afterwards.
+pub fn render_contact_sheet(renderer: &mut Renderer, cameras: &[(String, Camera)]) -> RgbImage {
+    let original = renderer.cam.clone();
+    let thumbnails: Vec<(&str, RgbImage)> = cameras
+        .iter()
+        .map(|(name, cam)| {
+            renderer.cam = cam.clone();
+            (name.as_str(), renderer.render())
+        })
+        .collect();
+    renderer.cam = original;
+    contact_sheet(&thumbnails)
+}
@@ -0,0 +1,100 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use crate::{buffer::Buffer, color::Color, imgdiff};
+
+/// The error of the image at one sample milestone.
+#[derive(Clone, Copy, Debug)]
+pub struct ConvergencePoint {
+    /// The number of samples per pixel.
+    pub samples: u32,
+
+    /// The time since the recorder is created in seconds.
+    pub seconds: f64,
+
+    /// Root mean squared error against the reference, if any.
+    pub rmse: Option<f64>,
+
+    /// Relative mean squared error against the reference, if any.
+    pub rel_mse: Option<f64>,
+
+    /// The estimated relative error from the variance of passes, see `Buffer::noise_estimate`.
+    pub noise: Option<f64>,
+}
+
+/// A recorder of image error at sample milestones, which is fed by the callback of
+/// `Renderer::progressive_render` or `Renderer::iterative_render` and exported as CSV to plot
+/// the convergence of samplers and integrators.
+pub struct Convergence {
+    /// The time when the recorder is created.
+    start: Instant,
+
+    /// The linear colors of reference image, see `Buffer::colors`.
+    reference: Option<Vec<Color>>,
+
+    /// The recorded milestones.
+    points: Vec<ConvergencePoint>,
+}
+
+impl Convergence {
+    /// Create a recorder which measures the error against `reference`. Without reference, only
+    /// the variance based estimate is recorded.
+    pub fn new(reference: Option<Vec<Color>>) -> Self {
+        Self {
+            start: Instant::now(),
+            reference,
+            points: Vec::new(),
+        }
+    }
+
+    /// Record the error of `buffer` with `samples` samples per pixel.
+    pub fn record(&mut self, samples: u32, buffer: &Buffer) {
+        let colors = buffer.colors();
+        let reference = self.reference.as_deref();
+        self.points.push(ConvergencePoint {
+            samples,
+            seconds: self.start.elapsed().as_secs_f64(),
+            rmse: reference.map(|r| imgdiff::rmse(&colors, r)),
+            rel_mse: reference.map(|r| imgdiff::rel_mse(&colors, r)),
+            noise: buffer.noise_estimate(),
+        });
+    }
+
+    /// Get the recorded milestones.
+    pub fn points(&self) -> &[ConvergencePoint] {
+        &self.points
+    }
+
+    /// Write the milestones as CSV with header, the missing values are left empty.
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let field = |value: Option<f64>| value.map_or_else(String::new, |v| v.to_string());
+        writeln!(writer, "samples,seconds,rmse,rel_mse,noise")?;
+        for point in &self.points {
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                point.samples,
+                point.seconds,
+                field(point.rmse),
+                field(point.rel_mse),
+                field(point.noise)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Save the milestones as CSV file.
+    pub fn save_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_csv(&mut writer)?;
+        writer.flush()
+    }
+}
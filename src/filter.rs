@@ -0,0 +1,104 @@
+use std::f64;
+
+/// Reconstruction filters which weight the contribution of a sample to the pixels around it.
+/// All filters are separable: w(x, y) = f(x) * f(y), where x and y are the offsets from the
+/// pixel center in pixels.
+/// References:
+/// https://pbr-book.org/3ed-2018/Sampling_and_Reconstruction/Image_Reconstruction
+#[derive(Clone, Copy)]
+pub enum Filter {
+    /// Box filter with radius, the box of radius 0.5 only contributes to the pixel itself.
+    Box(f64),
+
+    /// Tent (triangle) filter with radius.
+    Tent(f64),
+
+    /// Gaussian filter with radius and falloff rate alpha.
+    Gaussian { radius: f64, alpha: f64 },
+
+    /// Blackman-Harris window with radius.
+    BlackmanHarris(f64),
+
+    /// Mitchell-Netravali filter with radius and parameters B and C, which has negative lobes.
+    Mitchell { radius: f64, b: f64, c: f64 },
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::Box(0.5)
+    }
+}
+
+impl Filter {
+    /// Gaussian filter with commonly used radius 1.5 and alpha 2.0.
+    pub const fn gaussian() -> Self {
+        Self::Gaussian {
+            radius: 1.5,
+            alpha: 2.0,
+        }
+    }
+
+    /// Mitchell-Netravali filter with recommended B = C = 1/3 and radius 2.0.
+    pub const fn mitchell() -> Self {
+        Self::Mitchell {
+            radius: 2.0,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        }
+    }
+
+    /// Get the radius of filter in pixels, beyond which the weight is zero.
+    pub const fn radius(&self) -> f64 {
+        match *self {
+            Self::Box(radius)
+            | Self::Tent(radius)
+            | Self::Gaussian { radius, .. }
+            | Self::BlackmanHarris(radius)
+            | Self::Mitchell { radius, .. } => radius,
+        }
+    }
+
+    /// Get the weight of the sample which has offset (x, y) from the pixel center.
+    pub fn eval(&self, x: f64, y: f64) -> f64 {
+        self.eval_1d(x) * self.eval_1d(y)
+    }
+
+    /// Get the weight of one dimension.
+    fn eval_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        let radius = self.radius();
+        if x > radius {
+            return 0.0;
+        }
+        match *self {
+            Self::Box(_) => 1.0,
+            Self::Tent(_) => radius - x,
+            Self::Gaussian { alpha, .. } => {
+                ((-alpha * x * x).exp() - (-alpha * radius * radius).exp()).max(0.0)
+            }
+            Self::BlackmanHarris(_) => {
+                // The window is defined on [0, 1] and centered at 0.5.
+                let t = 0.5 + x / (2.0 * radius);
+                let tau = f64::consts::TAU;
+                0.35875 - 0.48829 * (tau * t).cos() + 0.14128 * (2.0 * tau * t).cos()
+                    - 0.01168 * (3.0 * tau * t).cos()
+            }
+            Self::Mitchell { b, c, .. } => {
+                // The polynomial is defined on [0, 2].
+                let x = 2.0 * x / radius;
+                let (x2, x3) = (x * x, x * x * x);
+                let w = if x < 1.0 {
+                    (12.0 - 9.0 * b - 6.0 * c) * x3
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x2
+                        + (6.0 - 2.0 * b)
+                } else {
+                    (-b - 6.0 * c) * x3
+                        + (6.0 * b + 30.0 * c) * x2
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c)
+                };
+                w / 6.0
+            }
+        }
+    }
+}
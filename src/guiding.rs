@@ -0,0 +1,405 @@
+use std::{
+    f64::consts::{PI, TAU},
+    sync::{
+        RwLock,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+
+use glam::DVec3;
+use rand::{Rng, rngs::StdRng};
+
+use crate::{aabb::Aabb, math::DPoint3};
+
+/// The maximal radiance estimate to record. The fireflies of paths sampled with tiny PDF would
+/// otherwise dominate, or even overflow, the learned distribution, which only affects noise.
+const MAX_ESTIMATE: f64 = 1e4;
+
+/// A float which is accumulated by all render threads.
+#[derive(Default)]
+struct AtomicF64(AtomicU64);
+
+impl AtomicF64 {
+    fn new(value: f64) -> Self {
+        Self(AtomicU64::new(value.to_bits()))
+    }
+
+    fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn add(&self, value: f64) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + value).to_bits())
+            });
+    }
+}
+
+impl Clone for AtomicF64 {
+    fn clone(&self) -> Self {
+        Self::new(self.get())
+    }
+}
+
+/// Map a direction to the unit square by the equal-area cylindrical projection.
+fn to_square(dir: DVec3) -> (f64, f64) {
+    let phi = dir.y.atan2(dir.x);
+    let phi = if phi < 0.0 { phi + TAU } else { phi };
+    ((dir.z.clamp(-1.0, 1.0) + 1.0) * 0.5, phi / TAU)
+}
+
+/// Map a point of the unit square to the direction, which is the inverse of `to_square`.
+fn from_square(x: f64, y: f64) -> DVec3 {
+    let cos_theta = 2.0 * x - 1.0;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = TAU * y;
+    DVec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+/// Get the quadrant of a point in the unit square and the point scaled to the quadrant.
+fn quadrant(x: f64, y: f64) -> (usize, f64, f64) {
+    let (ix, iy) = ((x >= 0.5) as usize, (y >= 0.5) as usize);
+    let scale = |v: f64, i: usize| (2.0 * v - i as f64).clamp(0.0, 1.0);
+    (ix + 2 * iy, scale(x, ix), scale(y, iy))
+}
+
+/// A node of the directional quadtree with the energy of its four quadrants.
+#[derive(Clone, Default)]
+struct QuadNode {
+    sums: [AtomicF64; 4],
+
+    /// The node index of each quadrant, zero for leaves as the root is never a child.
+    children: [u32; 4],
+}
+
+impl QuadNode {
+    fn total(&self) -> f64 {
+        self.sums.iter().map(AtomicF64::get).sum()
+    }
+}
+
+/// The quadtree of the incident radiance over the sphere of directions, whose leaves are
+/// finer where more energy comes from.
+pub struct DTree {
+    nodes: Vec<QuadNode>,
+    samples: AtomicU64,
+}
+
+impl Clone for DTree {
+    fn clone(&self) -> Self {
+        Self {
+            nodes: self.nodes.clone(),
+            samples: AtomicU64::new(self.samples.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl DTree {
+    fn new() -> Self {
+        Self {
+            nodes: vec![QuadNode::default()],
+            samples: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the total energy of the tree.
+    fn total(&self) -> f64 {
+        self.nodes[0].total()
+    }
+
+    /// Add the radiance estimate `value` coming from `dir`.
+    fn record(&self, dir: DVec3, value: f64) {
+        self.samples.fetch_add(1, Ordering::Relaxed);
+        if !(value > 0.0 && value.is_finite()) {
+            return;
+        }
+        let (mut x, mut y) = to_square(dir);
+        let mut node = 0;
+        loop {
+            let (c, cx, cy) = quadrant(x, y);
+            self.nodes[node].sums[c].add(value);
+            match self.nodes[node].children[c] {
+                0 => break,
+                child => (node, x, y) = (child as usize, cx, cy),
+            }
+        }
+    }
+
+    /// Get the PDF of sampling `dir` over solid angle.
+    pub fn pdf(&self, dir: DVec3) -> f64 {
+        let (mut x, mut y) = to_square(dir);
+        let mut density = 1.0;
+        let mut node = 0;
+        loop {
+            let total = self.nodes[node].total();
+            if total <= 0.0 {
+                return 0.0;
+            }
+            let (c, cx, cy) = quadrant(x, y);
+            density *= 4.0 * self.nodes[node].sums[c].get() / total;
+            match self.nodes[node].children[c] {
+                0 => break,
+                child => (node, x, y) = (child as usize, cx, cy),
+            }
+        }
+        density / (4.0 * PI)
+    }
+
+    /// Sample a direction proportional to the energy of the leaves.
+    pub fn sample(&self, rng: &mut StdRng) -> DVec3 {
+        let (mut x, mut y, mut size) = (0.0, 0.0, 1.0);
+        let mut node = 0;
+        loop {
+            let sums = self.nodes[node].sums.each_ref().map(AtomicF64::get);
+            let mut u = rng.random::<f64>() * sums.iter().sum::<f64>();
+            let mut c = 0;
+            while c < 3 && (u >= sums[c] || sums[c] <= 0.0) {
+                u -= sums[c];
+                c += 1;
+            }
+            size *= 0.5;
+            x += (c % 2) as f64 * size;
+            y += (c / 2) as f64 * size;
+            match self.nodes[node].children[c] {
+                0 => break,
+                child => node = child as usize,
+            }
+        }
+        let (u, v): (f64, f64) = rng.random();
+        from_square(x + u * size, y + v * size)
+    }
+
+    /// Get an empty tree whose quadrants are subdivided where their fraction of the total
+    /// energy exceeds `threshold`, and merged elsewhere. The energy of the new quadrants of
+    /// leaves is assumed to be uniform.
+    fn refined(&self, threshold: f64, max_depth: u32) -> Self {
+        let total = self.total();
+        let mut nodes = vec![QuadNode::default()];
+        // The node of this tree if any, the node of refined tree, its depth and energy.
+        let mut stack = vec![(Some(0), 0, 1, total)];
+        while let Some((old, new, depth, energy)) = stack.pop() {
+            for c in 0..4 {
+                let (child_energy, old_child) = match old {
+                    Some(old) => {
+                        let node: &QuadNode = &self.nodes[old];
+                        let child = (node.children[c] != 0).then_some(node.children[c] as usize);
+                        (node.sums[c].get(), child)
+                    }
+                    None => (energy / 4.0, None),
+                };
+                if total > 0.0 && child_energy / total > threshold && depth < max_depth {
+                    nodes[new].children[c] = nodes.len() as u32;
+                    stack.push((old_child, nodes.len(), depth + 1, child_energy));
+                    nodes.push(QuadNode::default());
+                }
+            }
+        }
+        Self {
+            nodes,
+            samples: AtomicU64::new(0),
+        }
+    }
+}
+
+/// A node of the spatial binary tree, whose leaves own the directional distributions.
+#[derive(Clone)]
+struct SpatialNode {
+    /// The axis along which the node is split into halves.
+    axis: usize,
+
+    children: Option<[usize; 2]>,
+
+    /// The distribution learned in previous iteration which is sampled.
+    sampling: DTree,
+
+    /// The distribution being learned in current iteration.
+    building: DTree,
+}
+
+/// The spatial-directional tree, i.e. the binary tree over the scene bounds whose leaves are
+/// quadtrees over directions.
+struct SdTree {
+    min: DVec3,
+    extent: DVec3,
+    nodes: Vec<SpatialNode>,
+}
+
+impl SdTree {
+    fn new(bounds: Aabb) -> Self {
+        let min = DVec3::new(bounds.x.min, bounds.y.min, bounds.z.min);
+        let max = DVec3::new(bounds.x.max, bounds.y.max, bounds.z.max);
+        Self {
+            min,
+            extent: (max - min).max(DVec3::splat(f64::EPSILON)),
+            nodes: vec![SpatialNode {
+                axis: 0,
+                children: None,
+                sampling: DTree::new(),
+                building: DTree::new(),
+            }],
+        }
+    }
+
+    /// Get the leaf which contains `p`.
+    fn leaf(&self, p: DPoint3) -> &SpatialNode {
+        let mut local = ((p - self.min) / self.extent).clamp(DVec3::ZERO, DVec3::ONE);
+        let mut node = &self.nodes[0];
+        while let Some(children) = node.children {
+            let axis = node.axis;
+            let half = (local[axis] >= 0.5) as usize;
+            local[axis] = 2.0 * local[axis] - half as f64;
+            node = &self.nodes[children[half]];
+        }
+        node
+    }
+
+    /// Split the leaves with more than `max_samples` samples in halves, and start the next
+    /// iteration from the refined distributions.
+    fn refine(&mut self, max_samples: f64, threshold: f64, max_depth: u32) {
+        let mut i = 0;
+        while i < self.nodes.len() {
+            let node = &mut self.nodes[i];
+            let samples = node.building.samples.load(Ordering::Relaxed);
+            if node.children.is_none() && samples as f64 > max_samples {
+                let mut child = node.clone();
+                child.axis = (node.axis + 1) % 3;
+                child.building.samples = AtomicU64::new(samples / 2);
+                let first = self.nodes.len();
+                self.nodes[i].children = Some([first, first + 1]);
+                self.nodes.push(child.clone());
+                self.nodes.push(child);
+            }
+            i += 1;
+        }
+        for node in self.nodes.iter_mut().filter(|node| node.children.is_none()) {
+            let building = node.building.refined(threshold, max_depth);
+            node.sampling = std::mem::replace(&mut node.building, building);
+        }
+    }
+}
+
+/// The path guiding which learns the incident radiance of the scene in training passes, and
+/// samples the bounces from the mixture of BSDF and the learned distribution.
+/// References:
+/// Müller et al. 2017, Practical Path Guiding for Efficient Light-Transport Simulation
+pub struct PathGuide {
+    /// The number of training passes before rendering, the pass `i` traces 2^i samples per
+    /// pixel and the distribution is refined after each of them.
+    pub training_passes: u32,
+
+    /// The probability to sample the BSDF instead of the learned distribution. It should be
+    /// positive as the directions never recorded in training are only sampled from the BSDF.
+    pub bsdf_fraction: f64,
+
+    /// The factor of the number of samples in a spatial leaf to split it, which grows by the
+    /// square root of the samples of the pass.
+    pub spatial_threshold: f64,
+
+    /// The fraction of energy above which a directional quadrant is subdivided.
+    pub energy_threshold: f64,
+
+    /// The maximal depth of directional quadtrees.
+    pub max_depth: u32,
+
+    tree: RwLock<Option<SdTree>>,
+    recording: AtomicBool,
+}
+
+impl Default for PathGuide {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PathGuide {
+    /// Create path guiding with 5 training passes and the parameters of Müller et al.
+    pub fn new() -> Self {
+        Self {
+            training_passes: 5,
+            bsdf_fraction: 0.5,
+            spatial_threshold: 12000.0,
+            energy_threshold: 0.01,
+            max_depth: 20,
+            tree: RwLock::new(None),
+            recording: AtomicBool::new(false),
+        }
+    }
+
+    /// Set the number of training passes before rendering.
+    pub fn training_passes(mut self, n: u32) -> Self {
+        self.training_passes = n;
+        self
+    }
+
+    /// Set the probability to sample the BSDF instead of the learned distribution.
+    pub fn bsdf_fraction(mut self, fraction: f64) -> Self {
+        self.bsdf_fraction = fraction.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Set the factor of the number of samples in a spatial leaf to split it.
+    pub fn spatial_threshold(mut self, threshold: f64) -> Self {
+        self.spatial_threshold = threshold;
+        self
+    }
+
+    /// Set the fraction of energy above which a directional quadrant is subdivided.
+    pub fn energy_threshold(mut self, threshold: f64) -> Self {
+        self.energy_threshold = threshold;
+        self
+    }
+
+    /// Set the maximal depth of directional quadtrees.
+    pub fn max_depth(mut self, depth: u32) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Forget the learned distribution and start learning over the scene `bounds`.
+    pub fn reset(&self, bounds: Aabb) {
+        *self.tree.write().unwrap() = Some(SdTree::new(bounds));
+    }
+
+    /// Set whether the radiance of paths is recorded, which is only enabled in training.
+    pub fn set_recording(&self, recording: bool) {
+        self.recording.store(recording, Ordering::Relaxed);
+    }
+
+    /// Record the radiance estimate, i.e. the incident radiance divided by the PDF of
+    /// direction, which comes to `p` from `dir`.
+    pub fn record(&self, p: DPoint3, dir: DVec3, value: f64) {
+        if !self.recording.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Some(tree) = &*self.tree.read().unwrap() {
+            tree.leaf(p)
+                .building
+                .record(dir, value.clamp(0.0, MAX_ESTIMATE));
+        }
+    }
+
+    /// Call `f` with the learned distribution at `p`, `None` before the first refinement or
+    /// where no radiance has been recorded.
+    pub fn with_distribution<R, F>(&self, p: DPoint3, f: F) -> R
+    where
+        F: FnOnce(Option<&DTree>) -> R,
+    {
+        let tree = self.tree.read().unwrap();
+        let dtree = tree
+            .as_ref()
+            .map(|tree| &tree.leaf(p).sampling)
+            .filter(|dtree| dtree.total() > 0.0);
+        f(dtree)
+    }
+
+    /// Refine the distribution after the training pass `pass`, and sample the distribution
+    /// learned in it from then on.
+    pub fn refine(&self, pass: u32) {
+        if let Some(tree) = &mut *self.tree.write().unwrap() {
+            let max_samples = self.spatial_threshold * 2f64.powi(pass as i32).sqrt();
+            tree.refine(max_samples, self.energy_threshold, self.max_depth);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+/// A handle to control and query a render from another thread. Clones of the handle share the
+/// same render, so one clone is given to `Renderer` and the others are kept by the host.
+#[derive(Clone, Default)]
+pub struct RenderHandle {
+    inner: Arc<Shared>,
+}
+
+/// The state shared by all clones of a handle.
+#[derive(Default)]
+struct Shared {
+    /// Whether the render has been cancelled.
+    cancelled: AtomicBool,
+
+    /// The number of finished pixel samples.
+    done: AtomicU64,
+
+    /// The number of pixel samples of the whole render.
+    total: AtomicU64,
+
+    /// The timing and pause state.
+    timer: Mutex<Timer>,
+
+    /// Notified when the render is resumed or cancelled.
+    resumed: Condvar,
+}
+
+/// The timing and pause state of a render.
+#[derive(Default)]
+struct Timer {
+    /// The time when the render started.
+    start: Option<Instant>,
+
+    /// The time when the render was paused, `None` if running.
+    paused_at: Option<Instant>,
+
+    /// The total duration of finished pauses.
+    paused_total: Duration,
+}
+
+impl Timer {
+    /// The time spent on rendering, excluding pauses.
+    fn active(&self) -> Duration {
+        let Some(start) = self.start else {
+            return Duration::ZERO;
+        };
+        let end = self.paused_at.unwrap_or_else(Instant::now);
+        end.duration_since(start).saturating_sub(self.paused_total)
+    }
+}
+
+impl RenderHandle {
+    /// Create a handle of a render which is not started.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the render. Render threads stop after their current tile, the unfinished pass is
+    /// discarded and the buffer of finished passes is returned.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        // Wake up the threads blocked by pause so that they can exit.
+        let _timer = self.inner.timer.lock().unwrap();
+        self.inner.resumed.notify_all();
+    }
+
+    /// Whether the render has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Pause the render. Render threads block after their current tile until `resume`.
+    pub fn pause(&self) {
+        let mut timer = self.inner.timer.lock().unwrap();
+        if timer.paused_at.is_none() {
+            timer.paused_at = Some(Instant::now());
+        }
+    }
+
+    /// Resume the paused render.
+    pub fn resume(&self) {
+        let mut timer = self.inner.timer.lock().unwrap();
+        if let Some(paused_at) = timer.paused_at.take() {
+            timer.paused_total += paused_at.elapsed();
+        }
+        self.inner.resumed.notify_all();
+    }
+
+    /// Whether the render is paused.
+    pub fn is_paused(&self) -> bool {
+        self.inner.timer.lock().unwrap().paused_at.is_some()
+    }
+
+    /// Get the finished fraction of the render in [0, 1].
+    pub fn progress(&self) -> f64 {
+        let total = self.inner.total.load(Ordering::SeqCst);
+        if total == 0 {
+            return 0.0;
+        }
+        (self.inner.done.load(Ordering::SeqCst) as f64 / total as f64).min(1.0)
+    }
+
+    /// Get the time spent on rendering, excluding pauses.
+    pub fn elapsed(&self) -> Duration {
+        self.inner.timer.lock().unwrap().active()
+    }
+
+    /// Estimate the remaining time of the render from the speed so far, `None` before any
+    /// progress. Renders with stopping criteria may finish earlier.
+    pub fn eta(&self) -> Option<Duration> {
+        let progress = self.progress();
+        if progress <= 0.0 {
+            return None;
+        }
+        Some(self.elapsed().mul_f64((1.0 - progress) / progress))
+    }
+
+    /// Reset the progress of the handle for a new render of `total` pixel samples. A cancelled
+    /// handle stays cancelled.
+    pub(crate) fn start(&self, total: u64) {
+        self.inner.done.store(0, Ordering::SeqCst);
+        self.inner.total.store(total, Ordering::SeqCst);
+        let mut timer = self.inner.timer.lock().unwrap();
+        timer.start = Some(Instant::now());
+        timer.paused_total = Duration::ZERO;
+        if timer.paused_at.is_some() {
+            timer.paused_at = Some(Instant::now());
+        }
+    }
+
+    /// Record `n` finished pixel samples.
+    pub(crate) fn advance(&self, n: u64) {
+        self.inner.done.fetch_add(n, Ordering::SeqCst);
+    }
+
+    /// Block the current thread while the render is paused, and return whether the render
+    /// should go on.
+    pub(crate) fn proceed(&self) -> bool {
+        let timer = self.inner.timer.lock().unwrap();
+        let _timer = self
+            .inner
+            .resumed
+            .wait_while(timer, |timer| {
+                timer.paused_at.is_some() && !self.is_cancelled()
+            })
+            .unwrap();
+        !self.is_cancelled()
+    }
+}
@@ -1,6 +1,7 @@
 use std::f64;
 
 use glam::DVec3;
+use rayon::prelude::*;
 
 use crate::color::Color;
 
@@ -11,13 +12,86 @@ pub struct HdrImage {
     height: u32,
     /// The pixel colors of the image.
     buf: Vec<Color>,
+    /// The file which the image is loaded from.
+    path: Option<String>,
 }
 
 impl HdrImage {
     /// Create a `HdrImage` using flatten image array `buf` with its width and height.
     pub fn new(width: u32, height: u32, buf: Vec<Color>) -> Self {
         assert!(width * height == buf.len() as u32);
-        Self { width, height, buf }
+        Self {
+            width,
+            height,
+            buf,
+            path: None,
+        }
+    }
+
+    /// Create a `HdrImage` from flatten RGB array `rgb` with its width and height, which is
+    /// downscaled by averaging the blocks of `factor` x `factor` pixels. The full resolution
+    /// image isn't converted, so the peak memory of loading a large image stays low.
+    pub fn from_rgb(width: u32, height: u32, rgb: &[f32], factor: u32) -> Self {
+        assert!((width * height * 3) as usize == rgb.len());
+        let factor = factor.max(1);
+        let (w, h) = (width.div_ceil(factor), height.div_ceil(factor));
+        // The pixels are converted in parallel, which takes a while for large panoramas.
+        let buf = (0..w * h)
+            .into_par_iter()
+            .map(|index| {
+                let (x, y) = (index % w, index / w);
+                let (x_end, y_end) = (
+                    ((x + 1) * factor).min(width),
+                    ((y + 1) * factor).min(height),
+                );
+                let mut sum = Color::ZERO;
+                for yy in y * factor..y_end {
+                    for xx in x * factor..x_end {
+                        let p = &rgb[3 * (yy * width + xx) as usize..][..3];
+                        sum += Color::new(p[0] as f64, p[1] as f64, p[2] as f64);
+                    }
+                }
+                sum / ((x_end - x * factor) * (y_end - y * factor)) as f64
+            })
+            .collect();
+        Self::new(w, h, buf)
+    }
+
+    /// Get the smallest factor by which an image of `width` x `height` is downscaled to at most
+    /// `max_pixels`, see `from_rgb`. The image keeps at least 2 x 2 pixels for the bilinear
+    /// sampling, which may be more than `max_pixels`.
+    pub fn downscale_factor(width: u32, height: u32, max_pixels: usize) -> u32 {
+        let pixels =
+            |factor: u32| width.div_ceil(factor) as usize * height.div_ceil(factor) as usize;
+        let mut factor = 1;
+        while pixels(factor) > max_pixels
+            && width.div_ceil(factor + 1) >= 2
+            && height.div_ceil(factor + 1) >= 2
+        {
+            factor += 1;
+        }
+        factor
+    }
+
+    /// Get the bytes of an image of `width` x `height`.
+    pub const fn memory_of(width: u32, height: u32) -> usize {
+        size_of::<Self>() + width as usize * height as usize * size_of::<Color>()
+    }
+
+    /// Get the bytes of the image.
+    pub fn memory(&self) -> usize {
+        size_of::<Self>() + self.buf.capacity() * size_of::<Color>()
+    }
+
+    /// Set the file which the image is loaded from, so that scene files can refer to it.
+    pub fn with_path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    /// Get the file which the image is loaded from.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
     }
 
     /// Sample the background of this image in camera's field of view.
@@ -41,7 +115,6 @@ impl HdrImage {
         let color11 = self.buf[((y0 + 1) * self.width + (x0 + 1)) as usize];
         let color0 = color00.lerp(color01, dx);
         let color1 = color10.lerp(color11, dx);
-        let color = color0.lerp(color1, dy);
-        color
+        color0.lerp(color1, dy)
     }
 }
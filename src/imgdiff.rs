@@ -0,0 +1,357 @@
+use std::f64;
+
+use glam::{DMat3, DVec3};
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+
+use crate::color::{Color, color_from_bytes};
+
+/// The default pixels per degree of visual angle in FLIP, which is a 0.7 m wide 4K monitor
+/// viewed at 0.7 m.
+pub const DEFAULT_PPD: f64 = 67.0;
+
+/// The linear sRGB to CIE XYZ (D65) matrix.
+const RGB_TO_XYZ: DMat3 = DMat3::from_cols(
+    DVec3::new(0.4124564, 0.2126729, 0.0193339),
+    DVec3::new(0.3575761, 0.7151522, 0.1191920),
+    DVec3::new(0.1804375, 0.0721750, 0.9503041),
+);
+/// The CIE XYZ (D65) to linear sRGB matrix.
+const XYZ_TO_RGB: DMat3 = DMat3::from_cols(
+    DVec3::new(3.2404542, -0.9692660, 0.0556434),
+    DVec3::new(-1.5371385, 1.8760108, -0.2040259),
+    DVec3::new(-0.4985314, 0.0415560, 1.0572252),
+);
+
+/// The exponent of color difference in FLIP.
+const QC: f64 = 0.7;
+/// The exponent of feature difference in FLIP.
+const QF: f64 = 0.5;
+/// The fraction of the maximal color difference below which the errors are compressed.
+const PC: f64 = 0.4;
+/// The error which the compressed color difference is mapped to.
+const PT: f64 = 0.95;
+/// The width in degrees of the features detected by FLIP.
+const FEATURE_WIDTH: f64 = 0.082;
+
+/// The parameters (a1, b1, a2, b2) of contrast sensitivity functions of achromatic, red-green
+/// and blue-yellow channels in FLIP.
+const CSF: [[f64; 4]; 3] = [
+    [1.0, 0.0047, 0.0, 1e-5],
+    [1.0, 0.0053, 0.0, 1e-5],
+    [34.1, 0.04, 13.5, 0.025],
+];
+
+/// The error metrics between a test image and its reference.
+#[derive(Clone, Copy, Debug)]
+pub struct ErrorMetrics {
+    /// Root mean squared error of linear colors.
+    pub rmse: f64,
+
+    /// Mean squared error relative to the squared reference value.
+    pub rel_mse: f64,
+
+    /// Mean FLIP error in [0, 1].
+    pub flip: f64,
+}
+
+impl ErrorMetrics {
+    /// Compute all metrics between two images of the same size.
+    pub fn new(test: &RgbImage, reference: &RgbImage) -> Self {
+        let (test_linear, reference_linear) = (linear_colors(test), linear_colors(reference));
+        let flip_map = flip(test, reference, DEFAULT_PPD);
+        Self {
+            rmse: rmse(&test_linear, &reference_linear),
+            rel_mse: rel_mse(&test_linear, &reference_linear),
+            flip: flip_map.iter().sum::<f64>() / flip_map.len() as f64,
+        }
+    }
+}
+
+/// Decode the pixels of image into linear colors.
+pub fn linear_colors(image: &RgbImage) -> Vec<Color> {
+    image.pixels().map(|p| color_from_bytes(p.0)).collect()
+}
+
+/// Get the root mean squared error of linear colors, averaged over channels.
+pub fn rmse(test: &[Color], reference: &[Color]) -> f64 {
+    assert_eq!(
+        test.len(),
+        reference.len(),
+        "Images must have the same size!"
+    );
+    let sum: f64 = test
+        .iter()
+        .zip(reference)
+        .map(|(t, r)| (*t - *r).length_squared() / 3.0)
+        .sum();
+    (sum / test.len() as f64).sqrt()
+}
+
+/// Get the mean squared error relative to the squared reference, averaged over channels. The
+/// small offset avoids the division by zero of black pixels.
+pub fn rel_mse(test: &[Color], reference: &[Color]) -> f64 {
+    assert_eq!(
+        test.len(),
+        reference.len(),
+        "Images must have the same size!"
+    );
+    let sum: f64 = test
+        .iter()
+        .zip(reference)
+        .map(|(t, r)| {
+            let d = *t - *r;
+            (d * d / (*r * *r + 0.01)).element_sum() / 3.0
+        })
+        .sum();
+    sum / test.len() as f64
+}
+
+/// Compute the per-pixel LDR-FLIP error map in [0, 1] between two images, where `ppd` is the
+/// number of pixels per degree of visual angle.
+/// References:
+/// https://research.nvidia.com/publication/2020-07_flip-difference-evaluator-alternating-images
+pub fn flip(test: &RgbImage, reference: &RgbImage, ppd: f64) -> Vec<f64> {
+    assert!(
+        test.dimensions() == reference.dimensions(),
+        "Images must have the same size!"
+    );
+    let (width, height) = test.dimensions();
+    let (test, reference) = (linear_colors(test), linear_colors(reference));
+    let color_error = color_difference(&test, &reference, width, height, ppd);
+    let feature_error = feature_difference(&test, &reference, width, height, ppd);
+    color_error
+        .iter()
+        .zip(feature_error)
+        .map(|(c, f)| c.powf(1.0 - f))
+        .collect()
+}
+
+/// Convert linear rgb to YCxCz opponent space, which is the linearized CIELab.
+fn ycxcz(rgb: Color) -> DVec3 {
+    let xyz = RGB_TO_XYZ * rgb / white();
+    DVec3::new(
+        116.0 * xyz.y - 16.0,
+        500.0 * (xyz.x - xyz.y),
+        200.0 * (xyz.y - xyz.z),
+    )
+}
+
+/// Convert YCxCz back to linear rgb.
+fn ycxcz_to_rgb(c: DVec3) -> Color {
+    let y = (c.x + 16.0) / 116.0;
+    let xyz = DVec3::new(c.y / 500.0 + y, y, y - c.z / 200.0) * white();
+    XYZ_TO_RGB * xyz
+}
+
+/// The XYZ coordinates of reference white, which is linear rgb (1, 1, 1).
+fn white() -> DVec3 {
+    RGB_TO_XYZ * DVec3::ONE
+}
+
+/// Convert linear rgb to CIELab.
+fn lab(rgb: Color) -> DVec3 {
+    let f = |t: f64| {
+        let delta: f64 = 6.0 / 29.0;
+        if t > delta.powi(3) {
+            t.cbrt()
+        } else {
+            t / (3.0 * delta * delta) + 4.0 / 29.0
+        }
+    };
+    let xyz = RGB_TO_XYZ * rgb / white();
+    let (fx, fy, fz) = (f(xyz.x), f(xyz.y), f(xyz.z));
+    DVec3::new(116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+/// Apply Hunt effect to CIELab, where chroma is scaled by lightness.
+fn hunt(lab: DVec3) -> DVec3 {
+    DVec3::new(lab.x, 0.01 * lab.x * lab.y, 0.01 * lab.x * lab.z)
+}
+
+/// The HyAB distance between two colors in CIELab.
+fn hyab(a: DVec3, b: DVec3) -> f64 {
+    let d = a - b;
+    d.x.abs() + (d.y * d.y + d.z * d.z).sqrt()
+}
+
+/// The color pipeline of FLIP: filter the images by contrast sensitivity functions, then map
+/// the perceptual color difference to [0, 1].
+fn color_difference(
+    test: &[Color],
+    reference: &[Color],
+    width: u32,
+    height: u32,
+    ppd: f64,
+) -> Vec<f64> {
+    let kernels = csf_kernels(ppd);
+    let filter = |image: &[Color]| -> Vec<Color> {
+        let channels: Vec<Vec<f64>> = (0..3)
+            .map(|c| {
+                let channel: Vec<f64> = image.iter().map(|rgb| ycxcz(*rgb)[c]).collect();
+                convolve(&channel, width, height, &kernels[c])
+            })
+            .collect();
+        (0..image.len())
+            .map(|i| {
+                let c = DVec3::new(channels[0][i], channels[1][i], channels[2][i]);
+                ycxcz_to_rgb(c).clamp(DVec3::ZERO, DVec3::ONE)
+            })
+            .collect()
+    };
+    let (test, reference) = (filter(test), filter(reference));
+    let c_max = hyab(hunt(lab(DVec3::Y)), hunt(lab(DVec3::Z))).powf(QC);
+    test.iter()
+        .zip(&reference)
+        .map(|(t, r)| {
+            let error = hyab(hunt(lab(*t)), hunt(lab(*r))).powf(QC);
+            // Compress the small errors and expand the large ones.
+            if error < PC * c_max {
+                PT / (PC * c_max) * error
+            } else {
+                PT + (error - PC * c_max) / (c_max - PC * c_max) * (1.0 - PT)
+            }
+        })
+        .collect()
+}
+
+/// The feature pipeline of FLIP: the difference of edges and points detected on luminance.
+fn feature_difference(
+    test: &[Color],
+    reference: &[Color],
+    width: u32,
+    height: u32,
+    ppd: f64,
+) -> Vec<f64> {
+    let sigma = 0.5 * FEATURE_WIDTH * ppd;
+    let radius = (3.0 * sigma).ceil() as i64;
+    let gaussian = |x: f64, y: f64| (-(x * x + y * y) / (2.0 * sigma * sigma)).exp();
+    let edge_x = normalized_kernel(radius, |x, y| -x * gaussian(x, y));
+    let point_x = normalized_kernel(radius, |x, y| {
+        (x * x / (sigma * sigma) - 1.0) * gaussian(x, y)
+    });
+    let edge_y = transpose(&edge_x);
+    let point_y = transpose(&point_x);
+    let features = |image: &[Color]| {
+        let y: Vec<f64> = image.iter().map(|c| (ycxcz(*c).x + 16.0) / 116.0).collect();
+        let magnitude = |kx: &Kernel, ky: &Kernel| -> Vec<f64> {
+            let gx = convolve(&y, width, height, kx);
+            let gy = convolve(&y, width, height, ky);
+            gx.iter().zip(gy).map(|(x, y)| x.hypot(y)).collect()
+        };
+        (magnitude(&edge_x, &edge_y), magnitude(&point_x, &point_y))
+    };
+    let (test_edges, test_points) = features(test);
+    let (reference_edges, reference_points) = features(reference);
+    (0..test.len())
+        .map(|i| {
+            let edge = (test_edges[i] - reference_edges[i]).abs();
+            let point = (test_points[i] - reference_points[i]).abs();
+            (edge.max(point) / f64::consts::SQRT_2).powf(QF)
+        })
+        .collect()
+}
+
+/// A square convolution kernel with radius and row-major weights.
+struct Kernel {
+    /// The radius of kernel in pixels.
+    radius: i64,
+
+    /// The weights of (2 * radius + 1)^2 pixels.
+    weights: Vec<f64>,
+}
+
+impl Kernel {
+    /// Build the kernel by evaluating `f` at the offsets (x, y) in pixels.
+    fn new<F: Fn(f64, f64) -> f64>(radius: i64, f: F) -> Self {
+        let weights = (-radius..=radius)
+            .flat_map(|y| (-radius..=radius).map(move |x| (x, y)))
+            .map(|(x, y)| f(x as f64, y as f64))
+            .collect();
+        Self { radius, weights }
+    }
+}
+
+/// Build the kernels of contrast sensitivity functions, which are sums of Gaussians in degrees.
+fn csf_kernels(ppd: f64) -> Vec<Kernel> {
+    let b_max = CSF.iter().flat_map(|p| [p[1], p[3]]).fold(0.0, f64::max);
+    let radius = (3.0 * (b_max / (2.0 * f64::consts::PI.powi(2))).sqrt() * ppd).ceil() as i64;
+    CSF.iter()
+        .map(|&[a1, b1, a2, b2]| {
+            let gaussian = |a: f64, b: f64, d2: f64| {
+                a * (f64::consts::PI / b).sqrt() * (-f64::consts::PI.powi(2) * d2 / b).exp()
+            };
+            let mut kernel = Kernel::new(radius, |x, y| {
+                let d2 = (x * x + y * y) / (ppd * ppd);
+                gaussian(a1, b1, d2) + gaussian(a2, b2, d2)
+            });
+            let sum: f64 = kernel.weights.iter().sum();
+            kernel.weights.iter_mut().for_each(|w| *w /= sum);
+            kernel
+        })
+        .collect()
+}
+
+/// Build the derivative kernel whose positive weights sum to 1 and negative weights sum to -1.
+fn normalized_kernel<F: Fn(f64, f64) -> f64>(radius: i64, f: F) -> Kernel {
+    let mut kernel = Kernel::new(radius, f);
+    let positive: f64 = kernel.weights.iter().filter(|w| **w > 0.0).sum();
+    let negative: f64 = -kernel.weights.iter().filter(|w| **w < 0.0).sum::<f64>();
+    for w in kernel.weights.iter_mut() {
+        *w /= if *w > 0.0 { positive } else { negative };
+    }
+    kernel
+}
+
+/// Swap the x and y axes of kernel.
+fn transpose(kernel: &Kernel) -> Kernel {
+    let size = (2 * kernel.radius + 1) as usize;
+    Kernel {
+        radius: kernel.radius,
+        weights: (0..size * size)
+            .map(|i| kernel.weights[(i % size) * size + i / size])
+            .collect(),
+    }
+}
+
+/// Convolve the single channel image with kernel, the border pixels are repeated.
+fn convolve(image: &[f64], width: u32, height: u32, kernel: &Kernel) -> Vec<f64> {
+    let (w, h, r) = (width as i64, height as i64, kernel.radius);
+    let size = 2 * r + 1;
+    (0..h)
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            (0..w).map(move |x| {
+                let mut sum = 0.0;
+                for ky in -r..=r {
+                    let sy = (y + ky).clamp(0, h - 1);
+                    for kx in -r..=r {
+                        let sx = (x + kx).clamp(0, w - 1);
+                        let weight = kernel.weights[((ky + r) * size + kx + r) as usize];
+                        sum += weight * image[(sy * w + sx) as usize];
+                    }
+                }
+                sum
+            })
+        })
+        .collect()
+}
+
+/// Visualize the error map in [0, `max`] with the magma color map, from black for no error to
+/// light yellow for `max`.
+pub fn heatmap(errors: &[f64], width: u32, height: u32, max: f64) -> RgbImage {
+    const MAGMA: [[f64; 3]; 5] = [
+        [0.0, 0.0, 0.016],
+        [0.317, 0.071, 0.486],
+        [0.716, 0.215, 0.475],
+        [0.986, 0.533, 0.382],
+        [0.988, 0.991, 0.749],
+    ];
+    RgbImage::from_fn(width, height, |x, y| {
+        let t = (errors[(y * width + x) as usize] / max).clamp(0.0, 1.0) * 4.0;
+        let i = (t as usize).min(3);
+        let (a, b) = (DVec3::from(MAGMA[i]), DVec3::from(MAGMA[i + 1]));
+        let c = a.lerp(b, t - i as f64);
+        Rgb([c.x, c.y, c.z].map(|v| (255.0 * v).round() as u8))
+    })
+}
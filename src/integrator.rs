@@ -0,0 +1,16 @@
+use std::any::Any;
+
+use rand::rngs::StdRng;
+
+use crate::{color::Color, math::Ray, renderer::Renderer};
+
+/// The estimator of radiance along camera rays. Implement it to replace the built-in path
+/// tracer of `Renderer` with custom light transport, e.g. a debug view or ambient occlusion,
+/// see `Scene::integrator`. The built-in tracer is still reachable by `Renderer::trace_ray`.
+///
+/// The AOVs are left empty for the samples of custom integrators, and the primary rays aren't
+/// traced as packets.
+pub trait Integrator: Send + Sync + Any {
+    /// Estimate the radiance arriving at the camera along the primary `ray` of `renderer`.
+    fn radiance(&self, renderer: &Renderer, ray: &Ray, rng: &mut StdRng) -> Color;
+}
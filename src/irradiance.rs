@@ -0,0 +1,402 @@
+use std::{
+    collections::HashMap,
+    f64::consts::{FRAC_PI_2, PI, TAU},
+    sync::RwLock,
+};
+
+use glam::{DVec3, I64Vec3};
+use rand::{Rng, rngs::StdRng};
+
+use crate::{color::Color, math::DPoint3, onb::ONB};
+
+/// The tolerance of the test which rejects the records in front of the shading point, relative
+/// to the radius of records.
+const FRONT_TOLERANCE: f64 = 0.05;
+
+/// The smallest estimated error of interpolation, which bounds the weight of a record looked up
+/// at its own position and normal.
+const MIN_ERROR: f64 = 1e-6;
+
+/// An irradiance sample of the cache with its gradients, see `IrradianceCache`.
+#[derive(Clone, Debug)]
+pub struct IrradianceRecord {
+    /// The position of the record.
+    pub p: DPoint3,
+
+    /// The normal of the surface at the record.
+    pub normal: DVec3,
+
+    /// The irradiance of the hemisphere around the normal.
+    pub irradiance: Color,
+
+    /// The harmonic mean distance to the surfaces seen from the record, which scales the area
+    /// where the record is valid.
+    pub radius: f64,
+
+    /// The gradient of each color channel of irradiance w.r.t. the rotation of normal.
+    pub rotational: [DVec3; 3],
+
+    /// The gradient of each color channel of irradiance w.r.t. the translation of position.
+    pub translational: [DVec3; 3],
+}
+
+impl IrradianceRecord {
+    /// Estimate the irradiance and its gradients at `p` from about `num_rays` rays of the
+    /// stratified cosine-weighted hemisphere around `normal`. `trace` returns the incident
+    /// radiance along a direction and the distance to the hit surface, infinity if the ray
+    /// escapes.
+    /// References:
+    /// Ward and Heckbert 1992, Irradiance Gradients
+    pub fn estimate<F>(
+        p: DPoint3,
+        normal: DVec3,
+        num_rays: u32,
+        rng: &mut StdRng,
+        mut trace: F,
+    ) -> Self
+    where
+        F: FnMut(DVec3, &mut StdRng) -> (Color, f64),
+    {
+        let onb = ONB::new(normal);
+        // M strata of polar angle and N ≈ πM strata of azimuth, indexed by `k * m + j`.
+        let m = ((num_rays as f64 / PI).sqrt().round() as usize).max(1);
+        let n = (num_rays as usize / m).max(1);
+        let mut radiance = vec![Color::ZERO; m * n];
+        let mut distance = vec![f64::INFINITY; m * n];
+        let mut rotational = [DVec3::ZERO; 3];
+        for k in 0..n {
+            for j in 0..m {
+                let (u1, u2): (f64, f64) = rng.random();
+                let sin2_theta = (j as f64 + u1) / m as f64;
+                let (sin_theta, cos_theta) = (sin2_theta.sqrt(), (1.0 - sin2_theta).sqrt());
+                let phi = TAU * (k as f64 + u2) / n as f64;
+                let local = DVec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                let (l, r) = trace(onb.transform(local), rng);
+                let l = if l.is_finite() { l } else { Color::ZERO };
+                (radiance[k * m + j], distance[k * m + j]) = (l, r);
+                // The irradiance changes as the normal tilts towards the azimuth.
+                let v = onb.transform(DVec3::new(-phi.sin(), phi.cos(), 0.0));
+                let tan_theta = sin_theta / cos_theta.max(1e-6);
+                for (gradient, l) in rotational.iter_mut().zip(l.to_array()) {
+                    *gradient -= v * tan_theta * l;
+                }
+            }
+        }
+        let scale = PI / (m * n) as f64;
+        let irradiance = radiance.iter().sum::<Color>() * scale;
+        let rotational = rotational.map(|gradient| gradient * scale);
+
+        // The translational gradient from the changes of radiance across the boundaries of
+        // strata, which are occluded by the closer surface of the neighboring strata.
+        let mut translational = [DVec3::ZERO; 3];
+        let mut add = |dir: DVec3, coefficient: f64, delta: Color| {
+            for (gradient, delta) in translational.iter_mut().zip(delta.to_array()) {
+                *gradient += dir * coefficient * delta;
+            }
+        };
+        for k in 0..n {
+            let prev = (k + n - 1) % n;
+            let phi_center = TAU * (k as f64 + 0.5) / n as f64;
+            let phi_boundary = TAU * k as f64 / n as f64 + FRAC_PI_2;
+            let u = onb.transform(DVec3::new(phi_center.cos(), phi_center.sin(), 0.0));
+            let v = onb.transform(DVec3::new(phi_boundary.cos(), phi_boundary.sin(), 0.0));
+            for j in 0..m {
+                let (lo, hi) = (j as f64 / m as f64, (j + 1) as f64 / m as f64);
+                if j > 0 {
+                    let r = distance[k * m + j].min(distance[k * m + j - 1]);
+                    let coefficient = TAU / n as f64 * lo.sqrt() * (1.0 - lo) / r;
+                    add(
+                        u,
+                        coefficient,
+                        radiance[k * m + j] - radiance[k * m + j - 1],
+                    );
+                }
+                let r = distance[k * m + j].min(distance[prev * m + j]);
+                let coefficient = (hi.sqrt() - lo.sqrt()) / r;
+                add(v, coefficient, radiance[k * m + j] - radiance[prev * m + j]);
+            }
+        }
+
+        let inverse_sum: f64 = distance.iter().map(|r| r.recip()).sum();
+        Self {
+            p,
+            normal,
+            irradiance,
+            radius: (m * n) as f64 / inverse_sum,
+            rotational,
+            translational,
+        }
+    }
+
+    /// Get the weight of the record at `p` with `normal`, which is the reciprocal of the
+    /// estimated error of interpolation, see `MIN_ERROR`.
+    fn weight(&self, p: DPoint3, normal: DVec3) -> f64 {
+        let error =
+            p.distance(self.p) / self.radius + (1.0 - normal.dot(self.normal)).max(0.0).sqrt();
+        error.max(MIN_ERROR).recip()
+    }
+
+    /// Get the irradiance extrapolated to `p` with `normal` by the gradients.
+    fn extrapolate(&self, p: DPoint3, normal: DVec3) -> Color {
+        let rotation = self.normal.cross(normal);
+        let translation = p - self.p;
+        let delta = Color::from_array(std::array::from_fn(|c| {
+            rotation.dot(self.rotational[c]) + translation.dot(self.translational[c])
+        }));
+        (self.irradiance + delta).max(Color::ZERO)
+    }
+}
+
+/// The records of the cache and the uniform grid to find them.
+#[derive(Default)]
+struct CacheGrid {
+    records: Vec<IrradianceRecord>,
+    cells: HashMap<[i64; 3], Vec<usize>>,
+}
+
+/// The irradiance cache which interpolates the diffuse indirect light between sparse records,
+/// which are placed densely near the other surfaces and sparsely in open areas. It trades the
+/// noise of indirect light for the smooth low-frequency bias controlled by `accuracy` and the
+/// spacing of records. The records are computed lazily and shared by all render threads.
+/// References:
+/// Ward et al. 1988, A Ray Tracing Solution for Diffuse Interreflection
+/// Křivánek et al. 2006, Making Radiance and Irradiance Caching Practical: Adaptive Caching
+/// and Neighbor Clamping
+pub struct IrradianceCache {
+    /// The maximal error of interpolation, smaller values place more records with less bias.
+    pub accuracy: f64,
+
+    /// The number of hemisphere rays to compute a record.
+    pub num_rays: u32,
+
+    /// The minimal radius of records relative to the scene scale, which limits the density of
+    /// records in corners.
+    pub min_spacing: f64,
+
+    /// The maximal radius of records relative to the scene scale, which limits the
+    /// interpolation in open areas.
+    pub max_spacing: f64,
+
+    grid: RwLock<CacheGrid>,
+}
+
+impl Default for IrradianceCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IrradianceCache {
+    /// Create an empty cache with the accuracy of 0.3 and 512 rays per record.
+    pub fn new() -> Self {
+        Self {
+            accuracy: 0.3,
+            num_rays: 512,
+            min_spacing: 0.05,
+            max_spacing: 0.5,
+            grid: RwLock::default(),
+        }
+    }
+
+    /// Set the maximal error of interpolation.
+    pub fn accuracy(mut self, accuracy: f64) -> Self {
+        self.accuracy = accuracy;
+        self
+    }
+
+    /// Set the number of hemisphere rays to compute a record.
+    pub fn num_rays(mut self, n: u32) -> Self {
+        self.num_rays = n;
+        self
+    }
+
+    /// Set the minimal and maximal radius of records relative to the scene scale.
+    pub fn spacing(mut self, min: f64, max: f64) -> Self {
+        self.min_spacing = min;
+        self.max_spacing = max;
+        self
+    }
+
+    /// Get the number of records in the cache.
+    pub fn len(&self) -> usize {
+        self.grid.read().unwrap().records.len()
+    }
+
+    /// Whether the cache has no record.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all records, e.g. after the scene is changed.
+    pub fn clear(&self) {
+        *self.grid.write().unwrap() = CacheGrid::default();
+    }
+
+    /// Get the size of grid cells, which bounds the area of any record within two cells per
+    /// axis.
+    fn cell_size(&self, scale: f64) -> f64 {
+        self.accuracy * self.max_spacing * scale
+    }
+
+    /// Interpolate the irradiance at `p` with `normal` from the records, `None` if no record is
+    /// accurate enough there. `scale` is the scene scale which the spacing is relative to.
+    pub fn lookup(&self, p: DPoint3, normal: DVec3, scale: f64) -> Option<Color> {
+        let size = self.cell_size(scale);
+        let grid = self.grid.read().unwrap();
+        let cell = (p / size).floor().as_i64vec3().to_array();
+        let (mut sum, mut total) = (Color::ZERO, 0.0);
+        for &i in grid.cells.get(&cell)? {
+            let record = &grid.records[i];
+            let weight = record.weight(p, normal);
+            // Skip the records in front of the point, which may be occluded from it.
+            let front = (p - record.p).dot((normal + record.normal) * 0.5);
+            if weight * self.accuracy <= 1.0 || front < -FRONT_TOLERANCE * record.radius {
+                continue;
+            }
+            sum += weight * record.extrapolate(p, normal);
+            total += weight;
+        }
+        (total > 0.0).then(|| sum / total)
+    }
+
+    /// Add a record to the cache. Its radius is limited by the translational gradient, the
+    /// spacing and the radii of the neighboring records, so that the records change smoothly
+    /// even if some of them miss the small nearby surfaces.
+    pub fn insert(&self, mut record: IrradianceRecord, scale: f64) {
+        for (gradient, e) in record
+            .translational
+            .iter()
+            .zip(record.irradiance.to_array())
+        {
+            let length = gradient.length();
+            if length > 0.0 {
+                record.radius = record.radius.min(e / length);
+            }
+        }
+        let (min, max) = (self.min_spacing * scale, self.max_spacing * scale);
+        record.radius = record.radius.clamp(min, max);
+
+        let size = self.cell_size(scale);
+        let mut grid = self.grid.write().unwrap();
+        let center = (record.p / size).floor().as_i64vec3();
+        let mut neighbors = Vec::new();
+        for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let cell = (center + I64Vec3::new(dx, dy, dz)).to_array();
+                    neighbors.extend(grid.cells.get(&cell).into_iter().flatten().copied());
+                }
+            }
+        }
+        neighbors.sort_unstable();
+        neighbors.dedup();
+        for &i in &neighbors {
+            let neighbor = &grid.records[i];
+            record.radius = record
+                .radius
+                .min(neighbor.radius + record.p.distance(neighbor.p))
+                .max(min);
+        }
+        for &i in &neighbors {
+            let neighbor = &mut grid.records[i];
+            let limit = record.radius + record.p.distance(neighbor.p);
+            neighbor.radius = neighbor.radius.min(limit).max(min);
+        }
+
+        let extent = DVec3::splat(self.accuracy * record.radius);
+        let lo = ((record.p - extent) / size).floor().as_i64vec3();
+        let hi = ((record.p + extent) / size).floor().as_i64vec3();
+        let index = grid.records.len();
+        for z in lo.z..=hi.z {
+            for y in lo.y..=hi.y {
+                for x in lo.x..=hi.x {
+                    grid.cells.entry([x, y, z]).or_default().push(index);
+                }
+            }
+        }
+        grid.records.push(record);
+    }
+
+    /// Get the irradiance at `p` with `normal` from the cache, a new record is estimated by
+    /// `trace` and inserted if the cached ones aren't accurate enough, see
+    /// `IrradianceRecord::estimate`.
+    pub fn irradiance<F>(
+        &self,
+        p: DPoint3,
+        normal: DVec3,
+        scale: f64,
+        rng: &mut StdRng,
+        trace: F,
+    ) -> Color
+    where
+        F: FnMut(DVec3, &mut StdRng) -> (Color, f64),
+    {
+        if let Some(irradiance) = self.lookup(p, normal, scale) {
+            return irradiance;
+        }
+        let record = IrradianceRecord::estimate(p, normal, self.num_rays, rng, trace);
+        let irradiance = record.irradiance;
+        self.insert(record, scale);
+        irradiance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    /// Get the record of `irradiance` without gradients at `p` on the floor.
+    fn record(p: DPoint3, irradiance: f64, radius: f64) -> IrradianceRecord {
+        IrradianceRecord {
+            p,
+            normal: DVec3::Y,
+            irradiance: Color::splat(irradiance),
+            radius,
+            rotational: [DVec3::ZERO; 3],
+            translational: [DVec3::ZERO; 3],
+        }
+    }
+
+    #[test]
+    fn lookup_at_a_record_returns_it() {
+        let cache = IrradianceCache::new();
+        let p = DVec3::new(0.2, 0.0, 0.3);
+        cache.insert(record(p, 2.0, 0.4), 1.0);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.lookup(p, DVec3::Y, 1.0), Some(Color::splat(2.0)));
+        assert_eq!(cache.lookup(p, -DVec3::Y, 1.0), None);
+        assert_eq!(cache.lookup(p + DVec3::X * 5.0, DVec3::Y, 1.0), None);
+    }
+
+    #[test]
+    fn lookup_interpolates_between_records() {
+        let cache = IrradianceCache::new().accuracy(0.5);
+        let (a, b) = (DVec3::ZERO, DVec3::new(0.1, 0.0, 0.0));
+        cache.insert(record(a, 1.0, 0.5), 1.0);
+        cache.insert(record(b, 3.0, 0.5), 1.0);
+        let middle = cache.lookup((a + b) / 2.0, DVec3::Y, 1.0).unwrap();
+        assert!((middle.x - 2.0).abs() < 1e-9, "{middle}");
+        let near_a = cache.lookup(a + DVec3::X * 0.01, DVec3::Y, 1.0).unwrap();
+        assert!(near_a.x > 1.0 && near_a.x < 2.0, "{near_a}");
+        cache.clear();
+        assert!(cache.is_empty() && cache.lookup(a, DVec3::Y, 1.0).is_none());
+    }
+
+    #[test]
+    fn estimate_of_uniform_light_is_pi_radiance() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let record = IrradianceRecord::estimate(DVec3::ZERO, DVec3::Y, 256, &mut rng, |_, _| {
+            (Color::splat(0.5), 2.0)
+        });
+        assert!(
+            (record.irradiance - Color::splat(0.5 * PI))
+                .abs()
+                .max_element()
+                < 1e-9
+        );
+        assert!((record.radius - 2.0).abs() < 1e-9);
+        assert!(record.translational.iter().all(|g| g.length() < 1e-9));
+    }
+}
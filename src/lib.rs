@@ -1,15 +1,48 @@
 pub mod aabb;
+pub mod accumulation;
+pub mod annotate;
+pub mod aov;
+pub mod bake;
 pub mod buffer;
 pub mod bvh;
 pub mod camera;
+pub mod clamp;
+pub mod clip;
 pub mod color;
+pub mod compare;
+pub mod convergence;
+pub mod filter;
+pub mod guiding;
+pub mod handle;
 pub mod image;
+pub mod imgdiff;
+pub mod integrator;
 pub mod interval;
+pub mod irradiance;
 pub mod light;
+pub mod lightcull;
+pub mod lightpath;
 pub mod material;
 pub mod math;
+pub mod memory;
+pub mod netpbm;
+pub mod obj;
 pub mod object;
 pub mod onb;
+pub mod openexr;
+pub mod post;
+pub mod registry;
 pub mod renderer;
+pub mod sampler;
+pub mod sampling;
 pub mod scene;
+pub mod scenegen;
+pub mod shadow;
 pub mod shape;
+pub mod simd;
+pub mod temporal;
+pub mod texture;
+pub mod tile;
+pub mod watch;
+pub mod watchdog;
+pub mod writer;
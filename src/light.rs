@@ -1,9 +1,63 @@
-use std::f64;
+use std::{f64, sync::Arc};
 
 use glam::DVec3;
 use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
 
-use crate::{color::Color, object::Object};
+use crate::{
+    color::{self, Color},
+    interval::Interval,
+    material::{Bsdf, Material},
+    math::Ray,
+    object::{Object, Sides},
+    shape::{HitRecord, Sampleable},
+};
+
+/// The non-physical attenuation of point lights over distance for artistic control. The
+/// default is the physical inverse-square law.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Falloff {
+    /// The intensity attenuates 1/r^exponent for distance r.
+    pub exponent: f64,
+
+    /// The distance within which the intensity no longer increases, which avoids the
+    /// singularity of surfaces close to the light.
+    pub near: f64,
+
+    /// The distance beyond which the light has no effect, the intensity is smoothly faded to
+    /// zero towards it. `None` means unlimited.
+    pub far: Option<f64>,
+}
+
+impl Falloff {
+    /// The physical inverse-square falloff.
+    pub const INVERSE_SQUARE: Self = Self {
+        exponent: 2.0,
+        near: 0.0,
+        far: None,
+    };
+
+    /// Get the ratio of intensity at distance `r`.
+    pub fn attenuation(&self, r: f64) -> f64 {
+        let window = self.far.map_or(1.0, |far| {
+            // Fade smoothly to zero, (1 - (r / far)^4)^2.
+            (1.0 - (r / far).powi(4)).max(0.0).powi(2)
+        });
+        window / r.max(self.near).powf(self.exponent)
+    }
+
+    /// Whether the falloff is the physical inverse-square law.
+    pub fn is_inverse_square(&self) -> bool {
+        *self == Self::INVERSE_SQUARE
+    }
+}
+
+impl Default for Falloff {
+    fn default() -> Self {
+        Self::INVERSE_SQUARE
+    }
+}
 
 pub enum Light {
     /// Ambient light with color
@@ -12,14 +66,77 @@ pub enum Light {
     /// Directional light with color and direction vector
     Directional(Color, DVec3),
 
-    /// Point light with color and location in world coordinate.
-    Point(Color, DVec3),
+    /// Point light with color, location in world coordinate and falloff over distance.
+    Point(Color, DVec3, Falloff),
 
-    /// Light from invisible, emissive object
-    Object(Object),
+    /// Area light with the shape and its emissive material, whose emission is evaluated at
+    /// the sampled points like the emitter seen by rays, e.g. textured, one-sided or shaped by
+    /// an angular profile. The shape is only sampled towards the shading points, add it with
+    /// `Scene::with_visible_light` to make it also visible to rays.
+    Area(Arc<dyn Sampleable>, Arc<Material>),
 }
 
 impl Light {
+    /// Create an area light from the shape, color and intensity, which emits uniformly from
+    /// both sides of the surface.
+    pub fn area<T>(shape: T, color: Color, intensity: f64) -> Self
+    where
+        T: Sampleable + 'static,
+    {
+        Self::area_material(shape, Material::light(color, intensity))
+    }
+
+    /// Create an area light from the shape and the emissive material, e.g.
+    /// `Material::light(color, intensity).one_sided(true)`.
+    pub fn area_material<T>(shape: T, material: Material) -> Self
+    where
+        T: Sampleable + 'static,
+    {
+        Self::Area(Arc::new(shape), Arc::new(material))
+    }
+
+    /// Get the emissive object of the same shape and radiance as the area light, which makes
+    /// the light visible. `None` for the other lights.
+    pub fn emitter(&self) -> Option<Object> {
+        match self {
+            Light::Area(shape, material) => Some(Object {
+                shape: shape.clone(),
+                material: material.clone(),
+                light_group: None,
+                material_name: None,
+                seed: 0,
+                id: 0,
+                name: None,
+                sides: Sides::TwoSided,
+                emitter: true,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Get the total power emitted by the light in luminance, e.g. to select lights by their
+    /// contribution. Directional lights hit the cross section of the scene bounding sphere of
+    /// `scene_radius`, and ambient lights have no power since they are never selected.
+    pub fn power(&self, scene_radius: f64) -> f64 {
+        match self {
+            Light::Ambient(_) => 0.0,
+            Light::Directional(color, _) => {
+                color::luminance(*color) * f64::consts::PI * scene_radius * scene_radius
+            }
+            Light::Point(color, _, _) => color::luminance(*color) * 4.0 * f64::consts::PI,
+            // The Lambertian emission from one or both sides of the surface, regardless of the
+            // emission texture and profile.
+            Light::Area(shape, material) => {
+                let sides = if material.one_sided { 1.0 } else { 2.0 };
+                color::luminance(material.color)
+                    * material.emittance
+                    * shape.area()
+                    * sides
+                    * f64::consts::PI
+            }
+        }
+    }
+
     /// Illuminates a point.
     /// Returning the intensity, direction from `pos` to the light and distance from `pos` to the light in micro time.
     pub fn illuminate(
@@ -37,29 +154,99 @@ impl Light {
                 -*dir,
                 f64::INFINITY,
             ),
-            Light::Point(color, loc) => {
+            Light::Point(color, loc, falloff) => {
                 let disp = loc - pos;
                 let len = disp.length();
                 (
-                    // The point light source attenuates 1/r^2 for displacement r.
-                    *color / (len * len),
+                    // The point light source attenuates 1/r^2 for displacement r by default.
+                    *color * falloff.attenuation(len),
                     disp / len,
                     len,
                 )
             }
-            Light::Object(object) => {
-                let (p, n, pdf) = object.shape.sample(pos, rng, shutter_time);
+            Light::Area(shape, material) => {
+                let (p, n, pdf) = shape.sample(pos, rng, shutter_time);
                 let disp = p - pos;
                 let len = disp.length();
-                // Only consider the light if it's facing the point.
-                let cosine = (-disp.dot(n)).max(0.0) / len;
+                let dir = disp / len;
+                // The emission towards `pos` of the sampled point as seen by a ray, whose record
+                // has the texture coordinates of the shape, e.g. for the emission textures of
+                // screens. The record is only made up from the sample if the ray misses it by
+                // rounding.
+                let ray = Ray::new(pos, dir, shutter_time);
+                let rec = shape
+                    .intersect(&ray, Interval::new(len * (1.0 - 1e-6), len * (1.0 + 1e-6)))
+                    .unwrap_or_else(|| {
+                        let mut rec = HitRecord {
+                            p,
+                            t: len,
+                            ..Default::default()
+                        };
+                        rec.set_face_normal(&ray, n);
+                        rec
+                    });
+                let radiance = material.emitted(&rec, -dir);
+                // Convert the PDF over surface area to solid angle.
+                let cosine = disp.dot(n).abs() / len;
                 let surface_area = cosine / (len * len);
-                (
-                    object.material.emittance * object.material.color * surface_area / pdf,
-                    disp / len,
-                    len,
-                )
+                (radiance * surface_area / pdf, dir, len)
             }
         }
     }
 }
+
+/// How the lights are picked for the direct lighting of each shading point.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LightSelection {
+    /// Sample every light, whose cost grows with the number of lights.
+    #[default]
+    All,
+
+    /// Sample one light picked with the probability proportional to its power, see
+    /// `LightDistribution`. Ambient lights are always sampled, and all lights are sampled if
+    /// the distribution isn't built or the lights have no finite power.
+    Power,
+}
+
+/// The discrete distribution of lights proportional to their power, which is built by
+/// `Scene::build_lights`.
+#[derive(Clone, Debug, Default)]
+pub struct LightDistribution {
+    /// The cumulative distribution over lights, whose last entry is one. Empty if the lights
+    /// have no power.
+    cdf: Vec<f64>,
+}
+
+impl LightDistribution {
+    /// Create the distribution from the power of each light.
+    pub fn new(powers: &[f64]) -> Self {
+        let total: f64 = powers.iter().map(|p| p.max(0.0)).sum();
+        if !(total > 0.0 && total.is_finite()) {
+            return Self::default();
+        }
+        let mut sum = 0.0;
+        let cdf = powers
+            .iter()
+            .map(|p| {
+                sum += p.max(0.0);
+                sum / total
+            })
+            .collect();
+        Self { cdf }
+    }
+
+    /// Pick a light by `u` in [0, 1) and return its index and its probability, `None` if the
+    /// lights have no power.
+    pub fn sample(&self, u: f64) -> Option<(usize, f64)> {
+        let last = self.cdf.len().checked_sub(1)?;
+        let i = self.cdf.partition_point(|&c| c <= u).min(last);
+        Some((i, self.pmf(i)))
+    }
+
+    /// Get the probability to pick the light `i`.
+    pub fn pmf(&self, i: usize) -> f64 {
+        let below = if i == 0 { 0.0 } else { self.cdf[i - 1] };
+        self.cdf.get(i).map_or(0.0, |c| c - below)
+    }
+}
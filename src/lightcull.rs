@@ -0,0 +1,218 @@
+use std::any::Any;
+
+use glam::DVec3;
+
+use crate::{
+    aabb::Aabb,
+    bvh::{Bvh, BvhNode},
+    interval::Interval,
+    light::Light,
+    math::DPoint3,
+    object::Object,
+    shape::quad::Quad,
+};
+
+/// The depth of BVH nodes down to which the light lists are kept, the deeper nodes share the
+/// list of their ancestor at this depth.
+const MAX_DEPTH: u32 = 10;
+
+/// The lists of lights which can possibly reach the region of each BVH node, so that the
+/// direct lighting of huge scenes doesn't consider the lights on the other side of walls or
+/// out of range, see `Renderer::light_culling`.
+/// A light is culled from a region if its falloff range ends before the region, or a single
+/// quad blocks all segments between the region and the light, which is conservative since
+/// shadow rays are blocked by any surface.
+pub struct LightCulling {
+    /// The root of the tree of regions which follows the BVH.
+    root: Region,
+}
+
+/// A region of the BVH node and the lights which can reach it.
+struct Region {
+    /// The bounds of the node, padded by the margin of ray origins.
+    bbox: Aabb,
+
+    /// The indices of the lights which can reach the region.
+    lights: Vec<u32>,
+
+    /// The regions of the children, `None` for leaves and nodes below `MAX_DEPTH`.
+    children: Option<Box<[Region; 2]>>,
+}
+
+impl LightCulling {
+    /// Build the light lists of the nodes of `bvh`, whose bounds and the lights are padded by
+    /// `margin`, i.e. the minimal distance of shadow ray intersections. The quads among
+    /// `occluders` can block lights.
+    pub fn build(bvh: &Bvh, lights: &[Light], occluders: &[Object], margin: f64) -> Self {
+        let quads: Vec<&Quad> = occluders
+            .iter()
+            .filter_map(|obj| {
+                let any: &dyn Any = obj.shape.as_ref();
+                any.downcast_ref::<Quad>()
+            })
+            .collect();
+        let all = (0..lights.len() as u32).collect();
+        let root = Region::build(&bvh.root, lights, &quads, margin, all, 0);
+        Self { root }
+    }
+
+    /// Get the indices of the lights which can reach the point `p`, `None` if the point is out
+    /// of the scene, i.e. all lights can.
+    pub fn lights_at(&self, p: DPoint3) -> Option<&[u32]> {
+        let mut region = &self.root;
+        if !contains(&region.bbox, p) {
+            return None;
+        }
+        while let Some(child) = region
+            .children
+            .as_ref()
+            .and_then(|children| children.iter().find(|c| contains(&c.bbox, p)))
+        {
+            region = child;
+        }
+        Some(&region.lights)
+    }
+
+    /// Get the average number of lights per region of the deepest level, e.g. to report the
+    /// effect of culling.
+    pub fn average_lights(&self) -> f64 {
+        let (mut sum, mut count) = (0, 0);
+        let mut stack = vec![&self.root];
+        while let Some(region) = stack.pop() {
+            match &region.children {
+                Some(children) => stack.extend(children.iter()),
+                None => {
+                    sum += region.lights.len();
+                    count += 1;
+                }
+            }
+        }
+        sum as f64 / count as f64
+    }
+}
+
+impl Region {
+    /// Build the region of `node` at `depth`, whose lights are the ones of `parent` which can
+    /// reach it.
+    fn build(
+        node: &BvhNode,
+        lights: &[Light],
+        quads: &[&Quad],
+        margin: f64,
+        parent: Vec<u32>,
+        depth: u32,
+    ) -> Self {
+        let bbox = padded(node.bbox(), margin);
+        let lights_here: Vec<u32> = parent
+            .into_iter()
+            .filter(|&i| reaches(&lights[i as usize], &bbox, quads, margin))
+            .collect();
+        let children = match node {
+            BvhNode::Node { left, right, .. } if depth < MAX_DEPTH => {
+                let child = |node: &BvhNode| {
+                    Region::build(node, lights, quads, margin, lights_here.clone(), depth + 1)
+                };
+                Some(Box::new([child(left), child(right)]))
+            }
+            _ => None,
+        };
+        Self {
+            bbox,
+            lights: lights_here,
+            children,
+        }
+    }
+}
+
+/// Whether the light can possibly reach some point in `bbox`. The sources of light are padded
+/// by `margin`, since shadow rays stop short of them.
+fn reaches(light: &Light, bbox: &Aabb, quads: &[&Quad], margin: f64) -> bool {
+    let region = corners(bbox);
+    match light {
+        Light::Ambient(_) => true,
+        Light::Directional(_, dir) => {
+            // The rays from the region towards the light, which are approximated by long
+            // segments, leave through the quad.
+            let far: Vec<DVec3> = region.iter().map(|c| *c - *dir * 1e9).collect();
+            !quads.iter().any(|quad| blocks(quad, &region, &far))
+        }
+        Light::Point(_, p, falloff) => {
+            let in_range = falloff.far.is_none_or(|far| distance(bbox, *p) < far);
+            let source = corners(&padded(Aabb::from_points(*p, *p), margin));
+            in_range && !quads.iter().any(|quad| blocks(quad, &region, &source))
+        }
+        Light::Area(shape, _) => {
+            let source = corners(&padded(shape.bbox(), margin));
+            !quads.iter().any(|quad| blocks(quad, &region, &source))
+        }
+    }
+}
+
+/// Whether `quad` blocks all segments between the convex hulls of `a` and `b`. The hulls must
+/// be strictly on the opposite sides of its plane, then the crossings of all segments lie in
+/// the convex hull of the crossings of the segments between their points, which must be inside
+/// the quad.
+fn blocks(quad: &Quad, a: &[DPoint3], b: &[DPoint3]) -> bool {
+    // The quad must overlap the bounds of the hulls to block them, which rejects most quads
+    // quickly.
+    let (min, max) = a
+        .iter()
+        .chain(b)
+        .fold((a[0], a[0]), |(min, max), p| (min.min(*p), max.max(*p)));
+    let overlaps = |axis: &Interval, min: f64, max: f64| axis.min <= max && min <= axis.max;
+    if !(overlaps(&quad.aabb.x, min.x, max.x)
+        && overlaps(&quad.aabb.y, min.y, max.y)
+        && overlaps(&quad.aabb.z, min.z, max.z))
+    {
+        return false;
+    }
+    let side = |p: &DPoint3| (*p - quad.origin).dot(quad.normal);
+    let (sa, sb): (Vec<f64>, Vec<f64>) =
+        (a.iter().map(side).collect(), b.iter().map(side).collect());
+    let separated = (sa.iter().all(|&s| s > 0.0) && sb.iter().all(|&s| s < 0.0))
+        || (sa.iter().all(|&s| s < 0.0) && sb.iter().all(|&s| s > 0.0));
+    if !separated {
+        return false;
+    }
+    a.iter().zip(&sa).all(|(pa, &da)| {
+        b.iter().zip(&sb).all(|(pb, &db)| {
+            let crossing = *pa + (*pb - *pa) * (da / (da - db));
+            let q = crossing - quad.origin;
+            let alpha = quad.w.dot(q.cross(quad.v));
+            let beta = quad.w.dot(quad.u.cross(q));
+            (0.0..=1.0).contains(&alpha) && (0.0..=1.0).contains(&beta)
+        })
+    })
+}
+
+/// Pad the bounding box by `margin` on all sides.
+fn padded(mut bbox: Aabb, margin: f64) -> Aabb {
+    for axis in [&mut bbox.x, &mut bbox.y, &mut bbox.z] {
+        axis.extend(margin);
+    }
+    bbox
+}
+
+/// Get the eight corners of the bounding box.
+fn corners(bbox: &Aabb) -> Vec<DPoint3> {
+    let mut corners = Vec::with_capacity(8);
+    for x in [bbox.x.min, bbox.x.max] {
+        for y in [bbox.y.min, bbox.y.max] {
+            for z in [bbox.z.min, bbox.z.max] {
+                corners.push(DVec3::new(x, y, z));
+            }
+        }
+    }
+    corners
+}
+
+/// Get the distance from `p` to the closest point of the bounding box.
+fn distance(bbox: &Aabb, p: DPoint3) -> f64 {
+    let gap = |axis: &Interval, x: f64| (axis.min - x).max(x - axis.max).max(0.0);
+    DVec3::new(gap(&bbox.x, p.x), gap(&bbox.y, p.y), gap(&bbox.z, p.z)).length()
+}
+
+/// Whether the bounding box contains `p`.
+fn contains(bbox: &Aabb, p: DPoint3) -> bool {
+    bbox.x.contains(p.x) && bbox.y.contains(p.y) && bbox.z.contains(p.z)
+}
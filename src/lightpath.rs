@@ -0,0 +1,340 @@
+use std::{
+    any::Any,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use glam::DVec3;
+
+use crate::{
+    color::Color,
+    material::{Bsdf, Material, merl::MerlBrdf},
+    math::DPoint3,
+};
+
+/// The event which happens at a vertex of a recorded light path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathEvent {
+    /// The origin of the camera ray.
+    Camera,
+
+    /// The path is scattered by a rough BSDF, whose lights are also sampled directly.
+    Scatter,
+
+    /// The path is reflected or refracted by a delta BSDF.
+    Specular,
+
+    /// The BSDF gives no direction to continue, e.g. a light source or an absorbing surface.
+    Absorb,
+
+    /// The path is terminated by Russian roulette.
+    Roulette,
+
+    /// The indirect light is interpolated by the irradiance cache instead of continuing the
+    /// path.
+    Cached,
+
+    /// The path is terminated by the maximum number of bounces.
+    MaxDepth,
+
+    /// The ray leaves the scene towards the background. The vertex is placed at a finite
+    /// distance along the ray.
+    Escape,
+}
+
+impl PathEvent {
+    /// Get the display color of the event in exported files.
+    const fn rgb(self) -> [u8; 3] {
+        match self {
+            Self::Camera => [255, 255, 255],
+            Self::Scatter => [64, 160, 255],
+            Self::Specular => [0, 255, 255],
+            Self::Absorb => [255, 64, 64],
+            Self::Roulette => [255, 0, 255],
+            Self::Cached => [128, 255, 128],
+            Self::MaxDepth => [255, 160, 0],
+            Self::Escape => [255, 255, 0],
+        }
+    }
+}
+
+/// One vertex of a recorded light path.
+#[derive(Clone, Debug)]
+pub struct PathVertex {
+    /// The position of the vertex in world space.
+    pub p: DPoint3,
+
+    /// The shading normal at the vertex, `None` for the camera and escaped rays.
+    pub normal: Option<DVec3>,
+
+    /// What happens to the path at the vertex.
+    pub event: PathEvent,
+
+    /// The product of BSDF weights from the camera to the vertex.
+    pub throughput: Color,
+
+    /// The radiance emitted by the surface at the vertex.
+    pub emitted: Color,
+
+    /// The index of the hit object in `Scene::objects`.
+    pub object: Option<usize>,
+
+    /// The description of the material at the vertex after regularization.
+    pub material: Option<String>,
+
+    /// The lights sampled directly at the vertex, in the order of `Scene::lights`.
+    pub lights: Vec<LightRecord>,
+
+    /// The direction sampled from the BSDF to continue the path.
+    pub bounce: Option<BounceRecord>,
+}
+
+/// The direct light sample of a light at a path vertex.
+#[derive(Clone, Debug)]
+pub struct LightRecord {
+    /// The index of the light in `Scene::lights`.
+    pub light: usize,
+
+    /// The radiance reflected towards the previous vertex, before multiplying the throughput.
+    pub radiance: Color,
+
+    /// Whether the shadow ray is blocked.
+    pub occluded: bool,
+}
+
+/// The BSDF sample which continues the path at a vertex.
+#[derive(Clone, Debug)]
+pub struct BounceRecord {
+    /// The direction towards the next vertex.
+    pub l: DVec3,
+
+    /// The PDF of sampling `l`, see `BsdfSample::pdf`.
+    pub pdf: f64,
+
+    /// The BSDF value between `l` and the view direction, `None` for delta lobes.
+    pub f: Option<Color>,
+
+    /// The weight f * |n • l| / pdf which multiplies the throughput.
+    pub weight: Color,
+}
+
+/// A camera path recorded for debugging, see `Renderer::record_paths`.
+#[derive(Clone, Debug)]
+pub struct LightPath {
+    /// The pixel whose sample starts the path.
+    pub pixel: (u32, u32),
+
+    /// The vertices from the camera to the end of path.
+    pub vertices: Vec<PathVertex>,
+
+    /// The radiance which the path contributes to the pixel.
+    pub color: Color,
+}
+
+/// Write the paths as a Wavefront OBJ polyline set. The vertex colors follow the positions
+/// (a common extension) to show the events, and each path is a group named by its index and
+/// color.
+pub fn write_obj<W: Write>(paths: &[LightPath], mut writer: W) -> io::Result<()> {
+    let mut first = 1;
+    for (i, path) in paths.iter().enumerate() {
+        writeln!(writer, "g path{i}")?;
+        writeln!(
+            writer,
+            "# pixel {} {} color {} {} {}",
+            path.pixel.0, path.pixel.1, path.color.x, path.color.y, path.color.z
+        )?;
+        for vertex in &path.vertices {
+            let [r, g, b] = vertex.event.rgb().map(|c| c as f64 / 255.0);
+            writeln!(
+                writer,
+                "v {} {} {} {r} {g} {b}",
+                vertex.p.x, vertex.p.y, vertex.p.z
+            )?;
+        }
+        if path.vertices.len() >= 2 {
+            write!(writer, "l")?;
+            for index in first..first + path.vertices.len() {
+                write!(writer, " {index}")?;
+            }
+            writeln!(writer)?;
+        }
+        first += path.vertices.len();
+    }
+    Ok(())
+}
+
+/// Write the paths as an ASCII PLY file with an edge between successive vertices of each
+/// path. The vertices carry the color and the index of their events, see `PathEvent`, and the
+/// index of their path.
+pub fn write_ply<W: Write>(paths: &[LightPath], mut writer: W) -> io::Result<()> {
+    let num_vertices: usize = paths.iter().map(|path| path.vertices.len()).sum();
+    let num_edges: usize = paths
+        .iter()
+        .map(|path| path.vertices.len().saturating_sub(1))
+        .sum();
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format ascii 1.0")?;
+    writeln!(writer, "element vertex {num_vertices}")?;
+    for property in ["float x", "float y", "float z"] {
+        writeln!(writer, "property {property}")?;
+    }
+    for property in ["red", "green", "blue", "event"] {
+        writeln!(writer, "property uchar {property}")?;
+    }
+    writeln!(writer, "property uint path")?;
+    writeln!(writer, "element edge {num_edges}")?;
+    writeln!(writer, "property int vertex1")?;
+    writeln!(writer, "property int vertex2")?;
+    writeln!(writer, "end_header")?;
+    for (i, path) in paths.iter().enumerate() {
+        for vertex in &path.vertices {
+            let [r, g, b] = vertex.event.rgb();
+            writeln!(
+                writer,
+                "{} {} {} {r} {g} {b} {} {}",
+                vertex.p.x, vertex.p.y, vertex.p.z, vertex.event as u8, i as u32
+            )?;
+        }
+    }
+    let mut first = 0;
+    for path in paths {
+        for index in first + 1..first + path.vertices.len() {
+            writeln!(writer, "{} {index}", index - 1)?;
+        }
+        first += path.vertices.len();
+    }
+    Ok(())
+}
+
+/// Describe the BSDF by its type and parameters.
+pub fn describe_material(bsdf: &dyn Bsdf) -> String {
+    let any: &dyn Any = bsdf;
+    if let Some(m) = any.downcast_ref::<Material>() {
+        format!(
+            "uber color=({:.4}, {:.4}, {:.4}) roughness={:.4} metallic={:.4} index={:.4} \
+             emittance={:.4} transparent={}",
+            m.color.x,
+            m.color.y,
+            m.color.z,
+            m.roughness,
+            m.metallic,
+            m.index,
+            m.emittance,
+            m.transparent
+        )
+    } else if let Some(merl) = any.downcast_ref::<MerlBrdf>() {
+        let path = merl.path().map(|path| path.display().to_string());
+        format!("merl {}", path.as_deref().unwrap_or("(in memory)"))
+    } else {
+        "unknown".to_string()
+    }
+}
+
+/// Write the paths of a pixel as a human readable report which lists each bounce with its
+/// object, material, direct light samples and BSDF sample, e.g. to find out where the
+/// radiance of a firefly comes from or why a pixel is black.
+pub fn write_report<W: Write>(paths: &[LightPath], mut writer: W) -> io::Result<()> {
+    let vec3 = |v: DVec3| format!("({:.4}, {:.4}, {:.4})", v.x, v.y, v.z);
+    let mean = paths.iter().map(|path| path.color).sum::<Color>() / paths.len().max(1) as f64;
+    if let Some(path) = paths.first() {
+        writeln!(writer, "pixel {} {}", path.pixel.0, path.pixel.1)?;
+    }
+    writeln!(writer, "paths: {}", paths.len())?;
+    writeln!(writer, "mean: {}", vec3(mean))?;
+    for (i, path) in paths.iter().enumerate() {
+        writeln!(writer)?;
+        writeln!(writer, "path {i}: color {}", vec3(path.color))?;
+        for (depth, vertex) in path.vertices.iter().enumerate() {
+            write!(
+                writer,
+                "  [{depth}] {:?} p={}",
+                vertex.event,
+                vec3(vertex.p)
+            )?;
+            if let Some(object) = vertex.object {
+                write!(writer, " object={object}")?;
+            }
+            writeln!(writer)?;
+            if let Some(normal) = vertex.normal {
+                writeln!(writer, "      normal: {}", vec3(normal))?;
+            }
+            if let Some(material) = &vertex.material {
+                writeln!(writer, "      material: {material}")?;
+            }
+            writeln!(writer, "      throughput: {}", vec3(vertex.throughput))?;
+            if vertex.emitted != Color::ZERO {
+                writeln!(writer, "      emitted: {}", vec3(vertex.emitted))?;
+            }
+            for light in &vertex.lights {
+                if light.occluded {
+                    writeln!(writer, "      light {}: occluded", light.light)?;
+                } else {
+                    writeln!(
+                        writer,
+                        "      light {}: radiance {}",
+                        light.light,
+                        vec3(light.radiance)
+                    )?;
+                }
+            }
+            if let Some(bounce) = &vertex.bounce {
+                let f = bounce.f.map_or_else(|| "delta".to_string(), vec3);
+                writeln!(
+                    writer,
+                    "      bsdf: l={} f={f} pdf={:.4} weight={}",
+                    vec3(bounce.l),
+                    bounce.pdf,
+                    vec3(bounce.weight)
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Save the paths as OBJ or PLY file by the extension of `path`.
+pub fn save<P: AsRef<Path>>(paths: &[LightPath], path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut writer = BufWriter::new(File::create(path)?);
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("ply") => write_ply(paths, &mut writer)?,
+        _ => write_obj(paths, &mut writer)?,
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ply_keeps_the_index_of_many_paths() {
+        let vertex = |x: f64| PathVertex {
+            p: DVec3::new(x, 0.0, 0.0),
+            normal: None,
+            event: PathEvent::Scatter,
+            throughput: Color::ONE,
+            emitted: Color::ZERO,
+            object: None,
+            material: None,
+            lights: Vec::new(),
+            bounce: None,
+        };
+        let paths: Vec<_> = (0..300)
+            .map(|i| LightPath {
+                pixel: (0, 0),
+                vertices: vec![vertex(i as f64), vertex(i as f64 + 0.5)],
+                color: Color::ZERO,
+            })
+            .collect();
+        let mut ply = Vec::new();
+        write_ply(&paths, &mut ply).unwrap();
+        let ply = String::from_utf8(ply).unwrap();
+        assert!(ply.contains("property uint path\n"));
+        let (_, body) = ply.split_once("end_header\n").unwrap();
+        let rows: Vec<&str> = body.lines().take(600).collect();
+        assert_eq!(rows[599].rsplit(' ').next(), Some("299"));
+        assert_eq!(rows[512].rsplit(' ').next(), Some("256"));
+    }
+}
@@ -1,15 +1,25 @@
-use std::f64;
+use std::{any::Any, borrow::Cow, f64, sync::Arc};
 
 use glam::{DVec3, FloatExt};
 use rand::{Rng, rngs::StdRng};
-use rand_distr::{Distribution, UnitCircle};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     color::{self, Color},
-    math::vec::random_cosine_weight_on_hemisphere,
+    material::normalmap::NormalMap,
     onb::ONB,
+    sampler, sampling,
+    shape::HitRecord,
+    texture::Texture,
 };
 
+pub mod carpaint;
+pub mod hair;
+pub mod library;
+pub mod merl;
+pub mod normalmap;
+pub mod wireframe;
+
 /// Normal Distribution Functions for microfacet distribution.
 pub mod ndf {
     use std::f64;
@@ -57,6 +67,20 @@ pub mod fresnel {
         let f0 = DVec3::splat(f0).lerp(color, metallic);
         (1.0 - f0).mul_add(DVec3::splat((1.0 - h_dot_v).powi(5)), f0)
     }
+
+    /// Exact Fresnel reflectance of unpolarized light between two dielectrics, where `eta` is the
+    /// ratio of refractive indices η_t / η_i. Returns 1.0 on total internal reflection.
+    pub fn dielectric(cos_i: f64, eta: f64) -> f64 {
+        let cos_i = cos_i.abs();
+        let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+        if sin2_t >= 1.0 {
+            return 1.0;
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+        let r_s = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+        let r_p = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+        (r_s * r_s + r_p * r_p) / 2.0
+    }
 }
 
 /// Geometry function for microfacet shadowing.
@@ -85,14 +109,221 @@ pub mod gf {
     }
 }
 
+/// Bidirectional Scattering Distribution Function of a surface. Implement it to plug custom
+/// materials into `Object`.
+///
+/// Parameters:
+/// - rec: The intersection record whose normal vector faces to the incident ray.
+/// - l: The direction from the intersection point towards light.
+/// - v: The direction from the intersection point towards view.
+pub trait Bsdf: Send + Sync + Any {
+    /// Evaluate the distribution of scattering between `l` and `v`.
+    fn eval(&self, rec: &HitRecord, l: DVec3, v: DVec3) -> Color;
+
+    /// Sample the direction towards light.
+    fn sample(&self, rec: &HitRecord, v: DVec3, rng: &mut StdRng) -> Option<BsdfSample>;
+
+    /// Get the PDF of sampling the direction towards light `l`.
+    fn pdf(&self, rec: &HitRecord, l: DVec3, v: DVec3) -> f64;
+
+    /// Whether all lobes of the BSDF are delta distributions, e.g. perfect mirror and smooth
+    /// glass, which makes next event estimation useless.
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    /// Get the roughness of the BSDF, `None` if the BSDF has no notion of roughness.
+    fn roughness(&self) -> Option<f64> {
+        None
+    }
+
+    /// Get a copy of the BSDF whose roughness is raised to at least `min_roughness`, `None` if
+    /// the BSDF is already rough enough or can't be regularized.
+    fn regularized(&self, _min_roughness: f64) -> Option<Arc<dyn Bsdf>> {
+        None
+    }
+
+    /// Get the albedo of the BSDF towards `v` if it's close enough to a Lambertian reflector,
+    /// whose indirect light can be interpolated by `IrradianceCache`. `None` by default.
+    fn diffuse_albedo(&self, _rec: &HitRecord, _v: DVec3) -> Option<Color> {
+        None
+    }
+
+    /// Get the base color of the surface at the intersection, e.g. for the albedo AOV of
+    /// denoisers and relighting. `None` by default.
+    fn albedo(&self, _rec: &HitRecord) -> Option<Color> {
+        None
+    }
+
+    /// Get the radiance emitted from the intersection towards `v`, i.e. the origin of the
+    /// incident ray. The `u`, `v`, `p`, `normal` and `front_face` of `rec` are used to look up
+    /// textured, angular and one-sided emission.
+    fn emitted(&self, _rec: &HitRecord, _v: DVec3) -> Color {
+        color::BLACK
+    }
+}
+
+/// The direction towards light sampled from BSDF.
+pub struct BsdfSample {
+    /// The direction from the intersection point towards light.
+    pub l: DVec3,
+
+    /// The PDF of sampling `l`. For a delta lobe, it's the discrete probability of the lobe.
+    pub pdf: f64,
+
+    /// The weight of sample which equals to f * |n • l| / pdf.
+    pub weight: Color,
+
+    /// Whether `l` is sampled from a delta lobe, which can't be evaluated by `Bsdf::eval`.
+    pub delta: bool,
+}
+
+impl BsdfSample {
+    /// Create a sample from the non-delta lobes of `bsdf`, the weight is evaluated by `f`.
+    pub fn new(l: DVec3, pdf: f64, f: Color, n: DVec3) -> Self {
+        Self {
+            l,
+            pdf,
+            weight: f * n.dot(l).abs() / pdf,
+            delta: false,
+        }
+    }
+
+    /// Create a sample from a delta lobe with its discrete probability.
+    pub fn delta(l: DVec3, pdf: f64, weight: Color) -> Self {
+        Self {
+            l,
+            pdf,
+            weight,
+            delta: true,
+        }
+    }
+}
+
+/// The α of microfacet distribution below which the microfacet lobes of `Material` become
+/// delta distributions, see `RoughnessMapping`.
+pub const DELTA_ROUGHNESS: f64 = 1e-3;
+
+/// The roughness from which opaque dielectric `Material` is treated as Lambertian by the
+/// irradiance cache.
+pub const DIFFUSE_ROUGHNESS: f64 = 0.8;
+
+/// The strategy to choose between the specular lobe and the diffuse lobe when sampling opaque
+/// `Material`. It only changes the noise, not the expected value. Transparent materials always
+/// choose by the exact Fresnel reflectance of the sampled microfacet.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum LobeSelection {
+    /// Fresnel reflectance at normal incidence, raised to at least the floor probability.
+    FresnelFloor(f64),
+
+    /// Balance by the estimated albedo of each lobe at the viewing angle.
+    Albedo,
+
+    /// Fixed probability of specular lobe.
+    Fixed(f64),
+
+    /// Schlick's Fresnel reflectance at the viewing angle, the rest of the light enters the
+    /// diffuse lobe unless metallic.
+    Fresnel,
+}
+
+impl Default for LobeSelection {
+    fn default() -> Self {
+        Self::FresnelFloor(0.2)
+    }
+}
+
+/// The convention which maps the roughness of `Material` to the α of microfacet distribution.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoughnessMapping {
+    /// α = roughness², the perceptually linear convention of Disney and glTF materials.
+    #[default]
+    Squared,
+
+    /// α = roughness, the legacy convention for the scenes tuned by earlier versions.
+    Linear,
+}
+
+impl RoughnessMapping {
+    /// Get the α of microfacet distribution of `roughness`.
+    pub fn alpha(self, roughness: f64) -> f64 {
+        match self {
+            Self::Squared => roughness * roughness,
+            Self::Linear => roughness,
+        }
+    }
+
+    /// Get the roughness whose α is `alpha`, the inverse of `alpha`.
+    pub fn roughness(self, alpha: f64) -> f64 {
+        match self {
+            Self::Squared => alpha.sqrt(),
+            Self::Linear => alpha,
+        }
+    }
+}
+
+/// The angular distribution of the radiance emitted by `Material`, as the ratio to the radiance
+/// along the normal over the angle θ to the normal, like the candela tables of IES profiles.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmissionProfile {
+    /// The same radiance in all directions.
+    #[default]
+    Lambertian,
+
+    /// The radiance falls off by cos^n θ, e.g. the narrowing beams of spotlights and the dimming
+    /// of screens viewed at an angle.
+    CosinePower(f64),
+
+    /// The ratios at evenly spaced angles from 0° to 90°, which are linearly interpolated, e.g.
+    /// the lamps whose shades cut off the light at some angle.
+    Curve(Vec<f64>),
+}
+
+impl EmissionProfile {
+    /// Get the ratio of radiance at the angle whose cosine to the normal is `cos`.
+    pub fn radiance(&self, cos: f64) -> f64 {
+        match self {
+            Self::Lambertian => 1.0,
+            Self::CosinePower(n) => cos.clamp(0.0, 1.0).powf(*n),
+            Self::Curve(ratios) => match ratios.as_slice() {
+                [] => 1.0,
+                [ratio] => *ratio,
+                _ => {
+                    let x = cos.clamp(0.0, 1.0).acos() / f64::consts::FRAC_PI_2
+                        * (ratios.len() - 1) as f64;
+                    let i = (x as usize).min(ratios.len() - 2);
+                    ratios[i].lerp(ratios[i + 1], x - i as f64)
+                }
+            },
+        }
+    }
+}
+
+/// Rotate the hue of a color by `turns` around the grey axis of RGB space, which keeps the
+/// average of channels unless a negative channel is clamped.
+fn rotate_hue(color: Color, turns: f64) -> Color {
+    let axis = Color::ONE.normalize();
+    let (sin, cos) = (turns * f64::consts::TAU).sin_cos();
+    // Rodrigues' rotation formula.
+    let rotated = color * cos + axis.cross(color) * sin + axis * axis.dot(color) * (1.0 - cos);
+    rotated.max(Color::ZERO)
+}
+
+/// The uber-material which combines diffuse, metallic and transmission lobes of microfacet model.
 #[derive(Clone)]
 pub struct Material {
     /// The base color of the material. Values between (0,0,0) and (1,1,1).
     pub color: Color,
 
-    /// The roughness of the material. Values will be automatically clamped between 0.01 and 1.0.
+    /// The roughness of the material. Values will be automatically clamped between 0.0 and 1.0,
+    /// and the values whose α is below `DELTA_ROUGHNESS` are perfectly smooth.
     pub roughness: f64,
 
+    /// The convention which maps `roughness` to the α of microfacet distribution.
+    pub roughness_mapping: RoughnessMapping,
+
     /// The metallic property of the material. Values between 0.0 and 1.0.
     pub metallic: f64,
 
@@ -104,6 +335,42 @@ pub struct Material {
 
     /// Whether the material is transparent.
     pub transparent: bool,
+
+    /// The texture which overrides the base color at the intersection.
+    pub texture: Option<Arc<dyn Texture>>,
+
+    /// The texture of emitted color which overrides the base color for emission, e.g. screens
+    /// and neon signs.
+    pub emission: Option<Arc<dyn Texture>>,
+
+    /// The tangent space normal map which perturbs the shading normal, whose variance under
+    /// the pixel footprint raises the roughness, see `NormalMap`.
+    pub normal_map: Option<Arc<NormalMap>>,
+
+    /// Whether the material only emits light from the front face.
+    pub one_sided: bool,
+
+    /// The angular distribution of the emitted radiance, e.g. screens and shaded lamps.
+    pub emission_profile: EmissionProfile,
+
+    /// The strategy to choose the lobe when sampling.
+    pub lobe_selection: LobeSelection,
+
+    /// The maximal rotation of the hue of base color in turns, which is random per object by
+    /// its seed, see `Object::seed`.
+    pub hue_jitter: f64,
+
+    /// The maximal change of roughness, which is random per object like `hue_jitter`. The
+    /// perfectly smooth materials are left smooth.
+    pub roughness_jitter: f64,
+
+    /// The color of the sheen lobe on top of opaque material, e.g. velvet and fabric whose
+    /// fibers glow at grazing angles. Black for no sheen.
+    pub sheen: Color,
+
+    /// The roughness of the sheen lobe in [0, 1], the rougher the wider the sheen spreads from
+    /// grazing angles.
+    pub sheen_roughness: f64,
 }
 
 impl Material {
@@ -111,14 +378,24 @@ impl Material {
     pub fn base(index: f64, roughness: f64) -> Self {
         Self {
             color: color::WHITE,
-            // Clamp roughness to avoid numerical issues in NDF.
-            // roughness should be at least 0.01 to avoid glass sphere to be too dark.
-            roughness: roughness.clamp(1e-2, 1.0),
+            // Tiny roughness is handled as delta distribution, so no lower bound is needed.
+            roughness: roughness.clamp(0.0, 1.0),
+            roughness_mapping: RoughnessMapping::default(),
             metallic: 0.0,
             // Avoid index of exactly 1.0 to prevent numerical issues in refraction calculations.
             index: index + 1e-6,
             emittance: 0.0,
             transparent: false,
+            texture: None,
+            emission: None,
+            normal_map: None,
+            one_sided: false,
+            emission_profile: EmissionProfile::default(),
+            lobe_selection: LobeSelection::default(),
+            hue_jitter: 0.0,
+            roughness_jitter: 0.0,
+            sheen: color::BLACK,
+            sheen_roughness: 0.5,
         }
     }
 
@@ -172,6 +449,119 @@ impl Material {
             ..Self::base(index, roughness)
         }
     }
+
+    /// Water with the index of refraction 1.333, slightly tinted towards blue-green and smooth
+    /// enough to mirror the sky, e.g. the surface of `Ocean`.
+    pub fn water() -> Self {
+        Self::transparent(Color::new(0.85, 0.97, 0.95), 1.333, 0.02)
+    }
+}
+
+impl Material {
+    /// Set the texture which overrides the base color.
+    pub fn texture<T>(mut self, texture: T) -> Self
+    where
+        T: Texture + 'static,
+    {
+        self.texture = Some(Arc::new(texture));
+        self
+    }
+
+    /// Set the texture of emitted color which overrides the base color for emission.
+    pub fn emission<T>(mut self, texture: T) -> Self
+    where
+        T: Texture + 'static,
+    {
+        self.emission = Some(Arc::new(texture));
+        self
+    }
+
+    /// Set the tangent space normal map which perturbs the shading normal.
+    pub fn normal_map(mut self, normal_map: NormalMap) -> Self {
+        self.normal_map = Some(Arc::new(normal_map));
+        self
+    }
+
+    /// Set whether the material only emits light from the front face.
+    pub fn one_sided(mut self, one_sided: bool) -> Self {
+        self.one_sided = one_sided;
+        self
+    }
+
+    /// Set the angular distribution of the emitted radiance.
+    pub fn emission_profile(mut self, profile: EmissionProfile) -> Self {
+        self.emission_profile = profile;
+        self
+    }
+
+    /// Set the strategy to choose the lobe when sampling.
+    pub fn lobe_selection(mut self, lobe_selection: LobeSelection) -> Self {
+        self.lobe_selection = lobe_selection;
+        self
+    }
+
+    /// Set the convention which maps the roughness to the α of microfacet distribution, e.g.
+    /// `RoughnessMapping::Linear` for the scenes tuned by earlier versions.
+    pub fn roughness_mapping(mut self, mapping: RoughnessMapping) -> Self {
+        self.roughness_mapping = mapping;
+        self
+    }
+
+    /// Set the color and the roughness of the sheen lobe on top of opaque material.
+    pub fn sheen(mut self, color: Color, roughness: f64) -> Self {
+        self.sheen = color;
+        self.sheen_roughness = roughness.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Set the maximal rotation of hue in turns and the maximal change of roughness, which are
+    /// random per object.
+    pub fn jitter(mut self, hue: f64, roughness: f64) -> Self {
+        self.hue_jitter = hue;
+        self.roughness_jitter = roughness;
+        self
+    }
+
+    /// Get the material at the intersection, in which the base color is looked up from texture
+    /// and the jitter of the object is applied.
+    pub fn resolve(&self, rec: &HitRecord) -> Cow<'_, Self> {
+        let jittered = self.hue_jitter != 0.0 || self.roughness_jitter != 0.0;
+        if self.texture.is_none() && !jittered {
+            return Cow::Borrowed(self);
+        }
+        let mut material = self.clone();
+        if let Some(texture) = &self.texture {
+            material.color = texture.value(rec);
+        }
+        if jittered {
+            let random = |dim| 2.0 * sampler::hash_unit(rec.instance_seed, dim) - 1.0;
+            material.color = rotate_hue(material.color, self.hue_jitter * random(0));
+            if !self.is_smooth() {
+                let min = self.roughness_mapping.roughness(DELTA_ROUGHNESS);
+                material.roughness =
+                    (self.roughness + self.roughness_jitter * random(1)).clamp(min, 1.0);
+            }
+        }
+        Cow::Owned(material)
+    }
+
+    /// Get the material at the intersection like `resolve` and the shading normal, which is
+    /// perturbed by the normal map. The variance of the normal map under the pixel footprint is
+    /// added to the squared α, so that the highlights don't sparkle at a distance.
+    pub fn shade(&self, rec: &HitRecord) -> (Cow<'_, Self>, DVec3) {
+        let material = self.resolve(rec);
+        let Some(normal_map) = &self.normal_map else {
+            return (material, rec.normal);
+        };
+        let (n, variance) = normal_map.shade(rec);
+        if variance <= 0.0 {
+            return (material, n);
+        }
+        let mut material = material.into_owned();
+        let alpha = (material.alpha().powi(2) + variance).sqrt();
+        material.roughness = material.roughness_mapping.roughness(alpha).min(1.0);
+        (Cow::Owned(material), n)
+    }
 }
 
 impl Material {
@@ -187,9 +577,23 @@ impl Material {
     ///
     /// Returning the function describes the distribution of scattering.
     pub fn bsdf(&self, l: DVec3, v: DVec3, n: DVec3, front_face: bool) -> DVec3 {
+        if self.transparent {
+            return self.dielectric_bsdf(l, v, n, front_face);
+        }
+        if self.is_smooth() {
+            // Only the diffuse lobe can be evaluated, the specular lobe is a delta distribution.
+            if n.dot(l).is_sign_positive() != n.dot(v).is_sign_positive() {
+                return DVec3::ZERO;
+            }
+            let f = fresnel::schlick(self.index, self.color, self.metallic, n.dot(v).abs());
+            let diffuse = (1.0 - f) * (1.0 - self.metallic) * self.color * f64::consts::FRAC_1_PI;
+            return diffuse + self.sheen_bsdf(l, v, n);
+        }
+
         // normal distribution function
-        let ndf = |nh| ndf::beckmann(self.roughness, nh);
-        let gf = |n, l, v, _h| gf::smith_schlick_ggx(self.roughness, n, l, v);
+        let alpha = self.alpha();
+        let ndf = |nh| ndf::beckmann(alpha, nh);
+        let gf = |n, l, v, _h| gf::smith_schlick_ggx(alpha, n, l, v);
         // let gf = |n: DVec3, l, v, h| {
         //     let n_dot_h = n.dot(h);
         //     let h_dot_v = h.dot(v);
@@ -200,71 +604,42 @@ impl Material {
 
         let n_dot_l = n.dot(l);
         let n_dot_v = n.dot(v);
-        let l_outside = n_dot_l.is_sign_positive();
-        let v_outside = n_dot_v.is_sign_positive();
-
-        if l_outside == v_outside {
-            // Reflection
-
-            // For reflection, h is the half vector between l and v
-            let h = (l + v).normalize();
-            let n_dot_h = n.dot(h);
-            let h_dot_v = v.dot(h);
-
-            // 1. normal distribution function
-            let d = ndf(n_dot_h);
-            // 2. fresnel function
-            let f = if !l_outside && (1.0 - n_dot_v * n_dot_v).sqrt() * self.index > 1.0 {
-                DVec3::splat(1.0)
-            } else {
-                fresnel::schlick(self.index, self.color, self.metallic, h_dot_v)
-            };
-            // 3. geometry function
-            let g = gf(n, l, v, h);
-
-            // BRDF
-            // Cook-Torrance = DFG / (4(n • l)(n • v))
-            // Lambert = (1 - F) * c / π
-            let specular = d * f * g / (4.0 * n_dot_v * n_dot_l);
-            if self.transparent {
-                specular
-            } else {
-                let diffuse = (1.0 - f) * self.color * f64::consts::FRAC_1_PI;
-                specular + diffuse
-            }
-        } else {
-            // Transmission
+        if n_dot_l.is_sign_positive() != n_dot_v.is_sign_positive() {
+            // Opaque material doesn't transmit light.
+            return DVec3::ZERO;
+        }
 
-            // Ratio of refractive indices, η_i / η_o
-            let eta_t = if front_face {
-                self.index
-            } else {
-                1.0 / self.index
-            };
+        // For reflection, h is the half vector between l and v
+        let h = (l + v).normalize();
+        let n_dot_h = n.dot(h);
+        let h_dot_v = v.dot(h);
 
-            // For refraction, h is -(η_i * l + η_o * v).normalize()
-            let h = -(l * eta_t + v).normalize();
+        // 1. normal distribution function
+        let d = ndf(n_dot_h);
+        // 2. fresnel function
+        let f = fresnel::schlick(self.index, self.color, self.metallic, h_dot_v);
+        // 3. geometry function
+        let g = gf(n, l, v, h);
 
-            let n_dot_h = n.dot(h);
-            let h_dot_v = v.dot(h);
-            let h_dot_l = l.dot(h);
+        // BRDF
+        // Cook-Torrance = DFG / (4(n • l)(n • v))
+        // Lambert = (1 - F) * (1 - metallic) * c / π
+        let specular = d * f * g / (4.0 * n_dot_v * n_dot_l);
+        let diffuse = (1.0 - f) * (1.0 - self.metallic) * self.color * f64::consts::FRAC_1_PI;
+        specular + diffuse + self.sheen_bsdf(l, v, n)
+    }
 
-            // 1. normal distribution function
-            let d = ndf(n_dot_h);
-            // 2. fresnel function
-            let f = fresnel::schlick(self.index, self.color, self.metallic, h_dot_v.abs());
-            // 3. geometry function
-            let g = gf(n, l, v, h);
+    /// Get the α of microfacet distribution, see `RoughnessMapping`.
+    pub fn alpha(&self) -> f64 {
+        self.roughness_mapping.alpha(self.roughness)
+    }
 
-            // BTDF
-            // Cook-Torrance = |l • h|/|n • l| * |v • h|/|n • v| * (1 - F)DG / (η_i / η_o * (h • l) + (h • v))^2
-            let btdf = (h_dot_l * h_dot_v / (n_dot_l * n_dot_v)).abs()
-                * ((1.0 - f) * d * g / (eta_t * h_dot_l + h_dot_v).powi(2));
-            btdf * self.color
-        }
+    /// Whether the microfacet lobes are perfectly smooth delta distributions.
+    fn is_smooth(&self) -> bool {
+        self.alpha() < DELTA_ROUGHNESS
     }
 
-    /// Get the incident ray and the PDF according to the given normal vector and light towards view.
+    /// Sample the incident ray according to the given normal vector and light towards view.
     /// References:
     /// https://agraphicsguynotes.com/posts/sample_microfacet_brdf/
     pub fn scatter(
@@ -273,88 +648,465 @@ impl Material {
         n: DVec3,
         v: DVec3,
         front_face: bool,
-    ) -> Option<(DVec3, f64)> {
-        let m2 = self.roughness * self.roughness;
+    ) -> Option<BsdfSample> {
+        if self.is_smooth() {
+            return Some(self.smooth_scatter(rng, n, v, front_face));
+        }
         let world_onb = ONB::new(n);
-        // front_face equals to -v.dot(n).is_sign_negative() which implemented in `shape.rs`.
-        let eta_t = if front_face {
-            self.index
+        let l = if self.transparent {
+            self.dielectric_scatter(rng, &world_onb, v, front_face)?
+        } else if rng.random_bool(self.specular_probability(n, v)) {
+            // specular
+            let h = self.sample_beckmann(rng, &world_onb);
+            -v.reflect(h)
         } else {
-            1.0 / self.index
+            // diffuse
+            world_onb.transform(sampling::cosine_hemisphere(rng.random()))
         };
 
-        // Estimate specular contribution using Fresnel.
+        // The microfacets may reflect below the surface, which is absorbed.
+        let pdf = self.scatter_pdf(l, v, n, front_face);
+        let f = self.bsdf(l, v, n, front_face);
+        (pdf > 0.0).then(|| BsdfSample::new(l, pdf, f, n))
+    }
+
+    /// Sample the perfect mirror and smooth glass, and the diffuse lobe of opaque material.
+    fn smooth_scatter(&self, rng: &mut StdRng, n: DVec3, v: DVec3, front_face: bool) -> BsdfSample {
+        let cos_v = n.dot(v).abs();
+        let reflected = -v.reflect(n);
+        if self.transparent {
+            // Reflect with probability F or refract otherwise, the weights are the ratio of
+            // Fresnel term to probability.
+            let eta = self.eta(front_face);
+            let f = fresnel::dielectric(cos_v, eta);
+            if rng.random_bool(f) {
+                return BsdfSample::delta(reflected, f, color::WHITE);
+            }
+            let k = 1.0 + (cos_v * cos_v - 1.0) / (eta * eta);
+            let l = (cos_v / eta - k.max(0.0).sqrt()) * n - v / eta;
+            return BsdfSample::delta(l, 1.0 - f, self.color / (eta * eta));
+        }
+
+        let p = self.specular_probability(n, v);
+        if rng.random_bool(p) {
+            let f = fresnel::schlick(self.index, self.color, self.metallic, cos_v);
+            BsdfSample::delta(reflected, p, f / p)
+        } else {
+            let l = ONB::new(n).transform(sampling::cosine_hemisphere(rng.random()));
+            let pdf = self.scatter_pdf(l, v, n, front_face);
+            BsdfSample::new(l, pdf, self.bsdf(l, v, n, front_face), n)
+        }
+    }
+
+    /// Sample the microfacet normal from Beckmann distribution.
+    fn sample_beckmann(&self, rng: &mut StdRng, world_onb: &ONB) -> DVec3 {
+        world_onb.transform(sampling::beckmann(rng.random(), self.alpha()))
+    }
+
+    /// Get the PDF of sampling the microfacet normal `h` in `sample_beckmann`, which equals to
+    /// D(h) * |n • h|.
+    fn beckmann_pdf(&self, n: DVec3, h: DVec3) -> f64 {
+        sampling::beckmann_pdf(self.alpha(), n.dot(h).abs())
+    }
+
+    /// Get the probability of sampling the specular lobe in `scatter`.
+    fn specular_probability(&self, n: DVec3, v: DVec3) -> f64 {
+        // Estimate specular reflectance at normal incidence using Fresnel.
         let f0 = ((self.index - 1.0) / (self.index + 1.0)).powi(2);
-        let f = f0.lerp(self.color.element_sum() / 3.0, self.metallic);
-
-        // Raise the specular probability to at least 0.2, but only if there is a specular component.
-        // If F0 is closely 0 and not metallic, we shouldn't force specular sampling.
-        let f = if f > 1e-3 { 0.2.lerp(1.0, f) } else { f };
-
-        // Probability Integral Transform
-        let beckmann = |rng: &mut StdRng| {
-            // θ = arctan √(-m^2 ln U)
-            let theta = (-m2 * rng.random::<f64>().ln()).sqrt().atan();
-            let (sin_t, cos_t) = theta.sin_cos();
-            let [x, y]: [f64; 2] = UnitCircle.sample(rng);
-            let h = DVec3::new(x * sin_t, y * sin_t, cos_t);
-            world_onb.transform(h)
+        let f0 = f0.lerp(self.color.element_sum() / 3.0, self.metallic);
+
+        let p = match self.lobe_selection {
+            LobeSelection::FresnelFloor(floor) => {
+                // Raise the specular probability to at least the floor, but only if there is a
+                // specular component. If F0 is closely 0 and not metallic, we shouldn't force
+                // specular sampling.
+                if f0 > 1e-3 { floor.lerp(1.0, f0) } else { f0 }
+            }
+            LobeSelection::Albedo if f0 > 1e-3 => {
+                // The other lobe is diffuse which is tinted by color.
+                let f = self.reflectance(n, v);
+                let other = (1.0 - f) * (1.0 - self.metallic) * self.color.element_sum() / 3.0;
+                f / (f + other)
+            }
+            LobeSelection::Fresnel if f0 > 1e-3 => {
+                // The light which isn't reflected enters the diffuse lobe, which metals lack.
+                let f = self.reflectance(n, v);
+                f + (1.0 - f) * self.metallic
+            }
+            LobeSelection::Albedo | LobeSelection::Fresnel => f0,
+            LobeSelection::Fixed(p) => p.clamp(0.0, 1.0),
         };
+        // The sheen lobe is sampled by the cosine weighted hemisphere of the diffuse lobe, which
+        // must be left a chance even on metals.
+        if self.has_sheen() { p.min(0.8) } else { p }
+    }
 
-        let beckmann_pdf = |h: DVec3| {
-            // p = 1 / (π m^2 cos^3 θ) * e^(-tan^2(θ) / m^2)
-            let cos_t = n.dot(h).abs();
-            let sin_t = (1.0 - cos_t.powi(2)).sqrt();
-            (f64::consts::PI * m2 * cos_t.powi(3)).recip() * (-(sin_t / cos_t).powi(2) / m2).exp()
+    /// Get the Fresnel reflectance of the specular lobe at the viewing angle, averaged over the
+    /// channels.
+    fn reflectance(&self, n: DVec3, v: DVec3) -> f64 {
+        fresnel::schlick(self.index, self.color, self.metallic, n.dot(v).abs()).element_sum() / 3.0
+    }
+
+    /// Get the PDF of sampling the direction towards light `l` in `scatter`.
+    /// The delta lobes are excluded as they can't be hit by arbitrary directions.
+    pub fn scatter_pdf(&self, l: DVec3, v: DVec3, n: DVec3, front_face: bool) -> f64 {
+        if self.is_smooth() && self.transparent {
+            return 0.0;
+        }
+        if self.transparent {
+            return self.dielectric_pdf(l, v, n, front_face);
+        }
+        if n.dot(l).is_sign_positive() != n.dot(v).is_sign_positive() {
+            return 0.0;
+        }
+        if self.is_smooth() {
+            let diffuse = n.dot(l).abs() * f64::consts::FRAC_1_PI;
+            return (1.0 - self.specular_probability(n, v)) * diffuse;
+        }
+
+        // Multiple Importance Sampling
+        let f = self.specular_probability(n, v);
+        let h = (l + v).normalize();
+        let specular = self.beckmann_pdf(n, h) / (4.0 * h.dot(v).abs());
+        let diffuse = n.dot(l).abs() * f64::consts::FRAC_1_PI;
+        f * specular + (1.0 - f) * diffuse
+    }
+}
+
+/// The sheen of Estevez and Kulla, whose microfacets are the fibers standing on the surface, so
+/// that the light is mostly reflected at grazing angles. The lobe is added on top of the other
+/// lobes of opaque material.
+/// References:
+/// Estevez and Kulla 2017, Production Friendly Microfacet Sheen BRDF
+/// https://github.com/KhronosGroup/glTF/tree/main/extensions/2.0/Khronos/KHR_materials_sheen
+impl Material {
+    /// Whether the material has the sheen lobe.
+    fn has_sheen(&self) -> bool {
+        !self.transparent && self.sheen != color::BLACK
+    }
+
+    /// The BRDF of the sheen lobe, zero if the material has no sheen.
+    fn sheen_bsdf(&self, l: DVec3, v: DVec3, n: DVec3) -> DVec3 {
+        if !self.has_sheen() {
+            return DVec3::ZERO;
+        }
+        // The roughness is clamped to keep the distribution finite.
+        let alpha = self.sheen_roughness.max(0.07).powi(2);
+        let (n_dot_l, n_dot_v) = (n.dot(l).abs(), n.dot(v).abs());
+        if n_dot_l == 0.0 || n_dot_v == 0.0 {
+            return DVec3::ZERO;
+        }
+        // The "Charlie" distribution D = (2 + 1 / α) sin(θ_h)^(1 / α) / 2π.
+        let h = (l + v).normalize_or_zero();
+        let sin_h = (1.0 - n.dot(h).powi(2)).max(0.0).sqrt();
+        let d = (2.0 + 1.0 / alpha) * sin_h.powf(1.0 / alpha) / f64::consts::TAU;
+        // The visibility of the fitted shadowing of fibers.
+        let lambda = |cos: f64| {
+            if cos < 0.5 {
+                sheen_l(cos, alpha).exp()
+            } else {
+                (2.0 * sheen_l(0.5, alpha) - sheen_l(1.0 - cos, alpha)).exp()
+            }
         };
+        let g = 1.0 / (1.0 + lambda(n_dot_v) + lambda(n_dot_l));
+        self.sheen * d * g / (4.0 * n_dot_l * n_dot_v)
+    }
+}
 
-        let l = if rng.random_bool(f as f64) {
-            // specular
-            let h = beckmann(rng);
-            -v.reflect(h)
-        } else if !self.transparent {
-            // diffuse
-            let dir = random_cosine_weight_on_hemisphere(rng);
-            world_onb.transform(dir)
+/// The curve fitted by Estevez and Kulla whose exponential is the Λ of sheen shadowing.
+fn sheen_l(x: f64, alpha: f64) -> f64 {
+    let t = (1.0 - alpha).powi(2);
+    let a = 21.5473.lerp(25.3245, t);
+    let b = 3.82987.lerp(3.32435, t);
+    let c = 0.19823.lerp(0.16801, t);
+    let d = (-1.97760).lerp(-1.27393, t);
+    let e = (-4.32054).lerp(-4.85967, t);
+    a / (1.0 + b * x.powf(c)) + d * x + e
+}
+
+/// Rough dielectric of Walter et al. which reflects or refracts on the sampled microfacet by the
+/// exact Fresnel reflectance.
+/// References:
+/// https://www.graphics.cornell.edu/~bjw/microfacetbsdf.pdf
+impl Material {
+    /// Get the ratio of refractive indices η_l / η_v between the side of light and view for
+    /// refraction.
+    fn eta(&self, front_face: bool) -> f64 {
+        // front_face equals to -v.dot(n).is_sign_negative() which implemented in `shape.rs`.
+        if front_face {
+            self.index
         } else {
-            // transmit
-            let h = beckmann(rng);
-            let cos_v = h.dot(v);
-            let v_perp = v - h * cos_v;
-            let l_perp = -v_perp / eta_t;
-            let sin2_l = l_perp.length_squared();
-
-            if sin2_l > 1.0 {
-                // Total internal reflection.
-                // We should not return reflection for `l` here, as this branch is for transmission only.
-                return None;
+            1.0 / self.index
+        }
+    }
+
+    /// Get the generalized half vector of refraction which lies in the same side of `n`.
+    fn refraction_half_vector(l: DVec3, v: DVec3, n: DVec3, eta: f64) -> DVec3 {
+        // h = -(η_v * v + η_l * l).normalize()
+        let h = -(v + eta * l).normalize();
+        if h.dot(n) < 0.0 { -h } else { h }
+    }
+
+    /// The BSDF of rough dielectric.
+    fn dielectric_bsdf(&self, l: DVec3, v: DVec3, n: DVec3, front_face: bool) -> DVec3 {
+        let n_dot_l = n.dot(l);
+        let n_dot_v = n.dot(v);
+        let eta = self.eta(front_face);
+        let alpha = self.alpha();
+        let g = gf::smith_schlick_ggx(alpha, n, l, v);
+
+        if n_dot_l.is_sign_positive() == n_dot_v.is_sign_positive() {
+            // Reflection: F(v • h) D G / (4 |n • l| |n • v|)
+            let h = (l + v).normalize();
+            let f = fresnel::dielectric(v.dot(h), eta);
+            let d = ndf::beckmann(alpha, n.dot(h));
+            DVec3::splat(f * d * g / (4.0 * n_dot_l * n_dot_v).abs())
+        } else {
+            // Transmission:
+            // |l • h| |v • h| / (|n • l| |n • v|) * (1 - F) D G / ((v • h) + η (l • h))^2
+            let h = Self::refraction_half_vector(l, v, n, eta);
+            let h_dot_v = v.dot(h);
+            let h_dot_l = l.dot(h);
+            if h_dot_v <= 0.0 || h_dot_l >= 0.0 {
+                return DVec3::ZERO;
             }
-            let cos_l = (1.0 - sin2_l).sqrt();
-            -cos_v.signum() * h * cos_l + l_perp
-        };
+            let f = fresnel::dielectric(h_dot_v, eta);
+            let d = ndf::beckmann(alpha, n.dot(h));
+            let btdf = (h_dot_l * h_dot_v / (n_dot_l * n_dot_v)).abs()
+                * ((1.0 - f) * d * g / (h_dot_v + eta * h_dot_l).powi(2));
+            btdf * self.color
+        }
+    }
 
-        // Multiple Importance Sampling
-        let mut pdf = 0.0;
-        pdf += {
+    /// Sample the microfacet, then reflect with probability F or refract otherwise.
+    fn dielectric_scatter(
+        &self,
+        rng: &mut StdRng,
+        world_onb: &ONB,
+        v: DVec3,
+        front_face: bool,
+    ) -> Option<DVec3> {
+        let h = self.sample_beckmann(rng, world_onb);
+        let cos_v = v.dot(h);
+        if cos_v <= 0.0 {
+            // The microfacet is back facing to view.
+            return None;
+        }
+        let eta = self.eta(front_face);
+        if rng.random_bool(fresnel::dielectric(cos_v, eta)) {
+            Some(-v.reflect(h))
+        } else {
+            // l = (c / η - √(1 + (c^2 - 1) / η^2)) h - v / η, where c = v • h
+            let k = 1.0 + (cos_v * cos_v - 1.0) / (eta * eta);
+            Some((cos_v / eta - k.max(0.0).sqrt()) * h - v / eta)
+        }
+    }
+
+    /// Get the PDF of sampling the direction towards light `l` in `dielectric_scatter`.
+    fn dielectric_pdf(&self, l: DVec3, v: DVec3, n: DVec3, front_face: bool) -> f64 {
+        let eta = self.eta(front_face);
+        if n.dot(l).is_sign_positive() == n.dot(v).is_sign_positive() {
+            // p = F * p(h) / (4 |v • h|)
             let h = (l + v).normalize();
-            let p_h = beckmann_pdf(h);
-            f * p_h / (4.0 * h.dot(v).abs())
-        };
-        pdf += if !self.transparent {
-            // diffuse component
-            (1.0 - f) * n.dot(l).abs() * f64::consts::FRAC_1_PI
-        } else if n.dot(v).is_sign_positive() != n.dot(l).is_sign_positive() {
-            // transmit component
-            let h = -(l * eta_t + v).normalize();
-            let h_dot_v = h.dot(v);
-            let h_dot_l = h.dot(l);
-            let jacobian = h_dot_v.abs() / (eta_t * h_dot_l + h_dot_v).powi(2);
-            let p_h = beckmann_pdf(h);
-            (1.0 - f) * p_h * jacobian
+            let h_dot_v = v.dot(h);
+            fresnel::dielectric(h_dot_v, eta) * self.beckmann_pdf(n, h) / (4.0 * h_dot_v.abs())
         } else {
-            0.0
-        };
+            // p = (1 - F) * p(h) * η^2 |l • h| / ((v • h) + η (l • h))^2
+            let h = Self::refraction_half_vector(l, v, n, eta);
+            let h_dot_v = v.dot(h);
+            let h_dot_l = l.dot(h);
+            if h_dot_v <= 0.0 || h_dot_l >= 0.0 {
+                return 0.0;
+            }
+            let jacobian = eta * eta * h_dot_l.abs() / (h_dot_v + eta * h_dot_l).powi(2);
+            (1.0 - fresnel::dielectric(h_dot_v, eta)) * self.beckmann_pdf(n, h) * jacobian
+        }
+    }
+}
+
+impl Bsdf for Material {
+    fn eval(&self, rec: &HitRecord, l: DVec3, v: DVec3) -> Color {
+        let (material, n) = self.shade(rec);
+        material.bsdf(l, v, n, rec.front_face)
+    }
+
+    fn sample(&self, rec: &HitRecord, v: DVec3, rng: &mut StdRng) -> Option<BsdfSample> {
+        let (material, n) = self.shade(rec);
+        material.scatter(rng, n, v, rec.front_face)
+    }
+
+    fn pdf(&self, rec: &HitRecord, l: DVec3, v: DVec3) -> f64 {
+        let (material, n) = self.shade(rec);
+        material.scatter_pdf(l, v, n, rec.front_face)
+    }
 
-        Some((l, pdf))
+    fn is_delta(&self) -> bool {
+        self.is_smooth() && (self.transparent || self.metallic >= 1.0)
+    }
+
+    fn roughness(&self) -> Option<f64> {
+        Some(self.roughness)
+    }
+
+    fn regularized(&self, min_roughness: f64) -> Option<Arc<dyn Bsdf>> {
+        if self.roughness >= min_roughness {
+            return None;
+        }
+        Some(Arc::new(Self {
+            roughness: min_roughness.min(1.0),
+            ..self.clone()
+        }))
+    }
+
+    fn diffuse_albedo(&self, rec: &HitRecord, v: DVec3) -> Option<Color> {
+        if self.transparent
+            || self.metallic > 0.0
+            || self.roughness < DIFFUSE_ROUGHNESS
+            || self.has_sheen()
+        {
+            return None;
+        }
+        // The albedo of the diffuse lobe, the broad specular lobe is neglected.
+        let (material, n) = self.shade(rec);
+        let f = fresnel::schlick(material.index, material.color, 0.0, n.dot(v).abs());
+        Some((1.0 - f) * material.color)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Option<Color> {
+        Some(self.resolve(rec).color)
+    }
+
+    fn emitted(&self, rec: &HitRecord, v: DVec3) -> Color {
+        if self.emittance == 0.0 || (self.one_sided && !rec.front_face) {
+            return color::BLACK;
+        }
+        let cos = rec.normal.dot(v.normalize_or_zero()).abs();
+        let radiance = self.emittance * self.emission_profile.radiance(cos);
+        match &self.emission {
+            Some(emission) => radiance * emission.value(rec),
+            None => radiance * self.resolve(rec).color,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    /// The number of samples of each estimate.
+    const SAMPLES: usize = 100_000;
+
+    /// The strategies which are compared with the default floor, which is the first one.
+    const STRATEGIES: [LobeSelection; 4] = [
+        LobeSelection::FresnelFloor(0.2),
+        LobeSelection::Albedo,
+        LobeSelection::Fresnel,
+        LobeSelection::Fixed(0.5),
+    ];
+
+    /// The diffuse, glossy metal and glossy dielectric materials.
+    fn materials() -> [Material; 3] {
+        let color = Color::new(0.8, 0.6, 0.4);
+        [
+            Material::diffuse(color),
+            Material::metallic(color, 0.3),
+            Material {
+                index: 1.5,
+                ..Material::specular(color, 0.3)
+            },
+        ]
+    }
+
+    /// The viewing directions from normal incidence to grazing around the normal +z.
+    fn views() -> [DVec3; 3] {
+        [1.0f64, 0.5, 0.1].map(|cos| DVec3::new((1.0 - cos * cos).sqrt(), 0.0, cos))
+    }
+
+    /// Get the mean and the variance of the mean of the samples.
+    fn moments(samples: impl Iterator<Item = f64>) -> (f64, f64) {
+        let samples: Vec<f64> = samples.collect();
+        let n = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / n;
+        let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0);
+        (mean, variance / n)
+    }
+
+    /// Estimate the directional albedo by the luminance of the weights of `scatter`.
+    fn estimate(material: &Material, v: DVec3) -> (f64, f64) {
+        let mut rng = StdRng::seed_from_u64(7);
+        moments((0..SAMPLES).map(|_| {
+            material
+                .scatter(&mut rng, DVec3::Z, v, true)
+                .map_or(0.0, |sample| color::luminance(sample.weight))
+        }))
+    }
+
+    /// Estimate the directional albedo by sampling the sphere uniformly.
+    fn reference(material: &Material, v: DVec3) -> (f64, f64) {
+        let mut rng = StdRng::seed_from_u64(11);
+        moments((0..4 * SAMPLES).map(|_| {
+            let l = sampling::uniform_sphere(rng.random());
+            let f = material.bsdf(l, v, DVec3::Z, true);
+            color::luminance(f) * l.z.max(0.0) * 4.0 * f64::consts::PI
+        }))
+    }
+
+    #[test]
+    fn lobe_selection_is_unbiased() {
+        for material in materials() {
+            for v in views() {
+                let (expected, error) = reference(&material, v);
+                for strategy in STRATEGIES {
+                    let material = material.clone().lobe_selection(strategy);
+                    let (mean, variance) = estimate(&material, v);
+                    let tolerance = 4.0 * (error + variance).sqrt();
+                    assert!(
+                        (mean - expected).abs() < tolerance,
+                        "{mean} vs {expected} ± {tolerance}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lobe_selection_does_not_add_variance_to_floor() {
+        for material in materials() {
+            for v in views() {
+                let floor = estimate(&material.clone().lobe_selection(STRATEGIES[0]), v).1;
+                for strategy in [LobeSelection::Albedo, LobeSelection::Fresnel] {
+                    let variance = estimate(&material.clone().lobe_selection(strategy), v).1;
+                    // Allow for the noise of the estimated variances.
+                    assert!(variance <= 1.1 * floor, "{variance} vs {floor}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn diffuse_is_continuous_across_smooth_boundary() {
+        let (v, l) = (DVec3::Z, DVec3::new(0.6, 0.0, 0.8));
+        for metallic in [0.0, 0.5, 1.0] {
+            let material = |alpha| {
+                let material = Material {
+                    metallic,
+                    ..Material::diffuse(Color::new(0.8, 0.6, 0.4))
+                };
+                let roughness = material.roughness_mapping.roughness(alpha);
+                Material {
+                    roughness,
+                    ..material
+                }
+            };
+            // Away from the mirror direction, the narrow specular lobe is negligible.
+            let smooth = material(0.5 * DELTA_ROUGHNESS).bsdf(l, v, DVec3::Z, true);
+            let rough = material(2.0 * DELTA_ROUGHNESS).bsdf(l, v, DVec3::Z, true);
+            assert!(
+                smooth.abs_diff_eq(rough, 1e-3 * smooth.max_element() + 1e-9),
+                "{smooth} vs {rough}"
+            );
+        }
     }
 }
@@ -0,0 +1,317 @@
+use std::f64;
+
+use glam::DVec3;
+use rand::{Rng, rngs::StdRng};
+
+use crate::{
+    color::{self, Color},
+    material::{Bsdf, BsdfSample, DELTA_ROUGHNESS, fresnel, gf, ndf},
+    onb::ONB,
+    sampler, sampling,
+    shape::HitRecord,
+};
+
+/// The wavelengths in nm of the red, green and blue channels of thin film interference.
+const WAVELENGTHS: [f64; 3] = [650.0, 532.0, 450.0];
+
+/// The index of refraction of the mica flakes under the thin film of pearlescent pigments.
+const FLAKE_INDEX: f64 = 1.6;
+
+/// The layered paint of cars, whose base of pigment and metallic flakes is under a clear coat.
+/// The flakes are tilted around the surface normal by a Beckmann distribution, and seen as
+/// separate sparkles where the pixel footprint is smaller than a flake, or as the glossy lobe
+/// of their statistical distribution of normals otherwise. A thin film on the flakes makes
+/// their color shift with the viewing angle.
+/// The light passing through the coat is attenuated by its Fresnel transmittance in and out,
+/// while the refraction of directions is neglected.
+/// References:
+/// Ershov et al. 2001, Rendering Pearlescent Appearance Based on Paint-Composition Modelling
+/// Jakob et al. 2014, Discrete Stochastic Microfacet Models
+#[derive(Clone)]
+pub struct CarPaint {
+    /// The diffuse color of the pigment between flakes.
+    pub color: Color,
+
+    /// The reflectance of flakes at normal incidence.
+    pub flake_color: Color,
+
+    /// The fraction of the surface covered by flakes in [0, 1].
+    pub flake_density: f64,
+
+    /// The size of flakes in world units.
+    pub flake_size: f64,
+
+    /// The α of the Beckmann distribution of flake normals around the surface normal.
+    pub flake_spread: f64,
+
+    /// The α of the Beckmann distribution of microfacets on each flake.
+    pub flake_roughness: f64,
+
+    /// The thickness of the thin film on flakes in nm, zero for no iridescence. The flakes with
+    /// the film are mica instead of metal.
+    pub film_thickness: f64,
+
+    /// The index of refraction of the thin film.
+    pub film_index: f64,
+
+    /// The weight of the clear coat in [0, 1], zero for no coat.
+    pub clearcoat: f64,
+
+    /// The index of refraction of the clear coat.
+    pub clearcoat_index: f64,
+
+    /// The α of the Beckmann distribution of the clear coat, which is a perfect mirror below
+    /// `DELTA_ROUGHNESS`.
+    pub clearcoat_roughness: f64,
+}
+
+/// The flake lobe at an intersection.
+struct Flake {
+    /// The normal of the flake, or the surface normal for the statistical distribution.
+    normal: DVec3,
+
+    /// The α of the lobe around `normal`.
+    alpha: f64,
+
+    /// The fraction of the surface covered by the lobe.
+    coverage: f64,
+}
+
+impl CarPaint {
+    /// Create the paint of the pigment `color` with silver flakes under a smooth clear coat.
+    pub const fn new(color: Color) -> Self {
+        Self {
+            color,
+            flake_color: Color::new(0.9, 0.9, 0.9),
+            flake_density: 0.3,
+            flake_size: 0.002,
+            flake_spread: 0.15,
+            flake_roughness: 0.05,
+            film_thickness: 0.0,
+            film_index: 1.5,
+            clearcoat: 1.0,
+            clearcoat_index: 1.5,
+            clearcoat_roughness: 0.0,
+        }
+    }
+
+    /// Create the pearlescent paint whose mica flakes are coated by a thin film of titanium
+    /// dioxide of `thickness` nm, e.g. 300 to 600 nm for hues shifting from blue to green and
+    /// purple.
+    pub const fn iridescent(color: Color, thickness: f64) -> Self {
+        Self::new(color)
+            .flakes(color::WHITE, 0.5, 0.002)
+            .film(thickness, 2.6)
+    }
+
+    /// Set the color, the coverage in [0, 1] and the size in world units of flakes.
+    pub const fn flakes(mut self, color: Color, density: f64, size: f64) -> Self {
+        self.flake_color = color;
+        self.flake_density = density.clamp(0.0, 1.0);
+        self.flake_size = size;
+        self
+    }
+
+    /// Set the α of the distribution of flake normals and the α of the microfacets on flakes.
+    pub const fn flake_roughness(mut self, spread: f64, roughness: f64) -> Self {
+        self.flake_spread = spread;
+        self.flake_roughness = roughness;
+        self
+    }
+
+    /// Set the thickness in nm and the index of refraction of the thin film on flakes.
+    pub const fn film(mut self, thickness: f64, index: f64) -> Self {
+        self.film_thickness = thickness;
+        self.film_index = index;
+        self
+    }
+
+    /// Set the weight, the index of refraction and the α of the clear coat.
+    pub const fn clearcoat(mut self, weight: f64, index: f64, roughness: f64) -> Self {
+        self.clearcoat = weight.clamp(0.0, 1.0);
+        self.clearcoat_index = index;
+        self.clearcoat_roughness = roughness;
+        self
+    }
+
+    /// Whether the clear coat is a perfect mirror.
+    fn is_coat_smooth(&self) -> bool {
+        self.clearcoat_roughness < DELTA_ROUGHNESS
+    }
+
+    /// Get the reflectance of the clear coat at the angle of `cos`.
+    fn coat_fresnel(&self, cos: f64) -> f64 {
+        self.clearcoat * fresnel::dielectric(cos, self.clearcoat_index)
+    }
+
+    /// Get the flake lobe at the intersection. Each cell of the grid of flake size holds a
+    /// flake with the probability of flake density, whose normal is drawn by the hash of the
+    /// cell, unless the pixel footprint covers many flakes.
+    fn flake(&self, rec: &HitRecord, n: DVec3) -> Flake {
+        if self.flake_size <= 0.0 || rec.footprint >= self.flake_size {
+            return Flake {
+                normal: n,
+                alpha: (self.flake_spread.powi(2) + self.flake_roughness.powi(2)).sqrt(),
+                coverage: self.flake_density,
+            };
+        }
+        let cell = (rec.p / self.flake_size).floor();
+        let seed = [cell.x, cell.y, cell.z]
+            .iter()
+            .fold(rec.instance_seed, |seed, &c| {
+                sampler::hash_combine(seed, c as i64 as u32)
+            });
+        let random = |dim| sampler::hash_unit(seed, dim);
+        if random(0) >= self.flake_density {
+            return Flake {
+                normal: n,
+                alpha: self.flake_roughness,
+                coverage: 0.0,
+            };
+        }
+        let tilt = sampling::beckmann((random(1), random(2)), self.flake_spread);
+        Flake {
+            normal: ONB::new(n).transform(tilt),
+            alpha: self.flake_roughness,
+            coverage: 1.0,
+        }
+    }
+
+    /// Get the reflectance of a flake at the angle of `cos` to its microfacet.
+    fn flake_fresnel(&self, cos: f64) -> Color {
+        if self.film_thickness <= 0.0 {
+            return fresnel::schlick(1.5, self.flake_color, 1.0, cos.abs());
+        }
+        let film = thin_film(
+            cos.abs(),
+            self.film_thickness,
+            (self.clearcoat_index, self.film_index, FLAKE_INDEX),
+        );
+        film * self.flake_color
+    }
+
+    /// Get the probabilities of sampling the clear coat, the flake and the diffuse lobes.
+    fn lobe_probabilities(&self, flake: &Flake, n: DVec3, v: DVec3) -> [f64; 3] {
+        let coat = self.coat_fresnel(n.dot(v));
+        let flakes = (1.0 - coat) * flake.coverage * color::luminance(self.flake_color);
+        // The diffuse lobe is always left a chance, which covers the others.
+        let diffuse =
+            ((1.0 - coat) * (1.0 - self.flake_density) * color::luminance(self.color)).max(0.05);
+        let sum = coat + flakes + diffuse;
+        [coat / sum, flakes / sum, diffuse / sum]
+    }
+
+    /// Evaluate the non-delta lobes and get their PDF of sampling.
+    fn eval_pdf(&self, rec: &HitRecord, l: DVec3, v: DVec3) -> (Color, f64) {
+        let n = rec.normal;
+        let (n_dot_l, n_dot_v) = (n.dot(l), n.dot(v));
+        if n_dot_l <= 0.0 || n_dot_v <= 0.0 {
+            return (color::BLACK, 0.0);
+        }
+        let flake = self.flake(rec, n);
+        let [p_coat, p_flake, p_diffuse] = self.lobe_probabilities(&flake, n, v);
+        let h = (l + v).normalize();
+        let h_dot_v = h.dot(v);
+        let (mut f, mut pdf) = (color::BLACK, p_diffuse * n_dot_l * f64::consts::FRAC_1_PI);
+
+        if !self.is_coat_smooth() && self.clearcoat > 0.0 {
+            let alpha = self.clearcoat_roughness;
+            let d = ndf::beckmann(alpha, n.dot(h));
+            let g = gf::smith_schlick_ggx(alpha, n, l, v);
+            f += Color::splat(self.coat_fresnel(h_dot_v) * d * g / (4.0 * n_dot_l * n_dot_v));
+            pdf += p_coat * sampling::beckmann_pdf(alpha, n.dot(h)) / (4.0 * h_dot_v);
+        }
+        // The base under the coat.
+        let transmittance = (1.0 - self.coat_fresnel(n_dot_v)) * (1.0 - self.coat_fresnel(n_dot_l));
+        let diffuse = self.color * (1.0 - self.flake_density) * f64::consts::FRAC_1_PI;
+        let mut base = diffuse;
+        let m_dot_h = flake.normal.dot(h);
+        if flake.coverage > 0.0 && m_dot_h > 0.0 {
+            let d = ndf::beckmann(flake.alpha, m_dot_h);
+            let g = gf::smith_schlick_ggx(flake.alpha, n, l, v);
+            base +=
+                self.flake_fresnel(h_dot_v) * flake.coverage * d * g / (4.0 * n_dot_l * n_dot_v);
+            pdf += p_flake * sampling::beckmann_pdf(flake.alpha, m_dot_h) / (4.0 * h_dot_v);
+        }
+        f += base * transmittance;
+        (f, pdf)
+    }
+}
+
+impl Bsdf for CarPaint {
+    fn eval(&self, rec: &HitRecord, l: DVec3, v: DVec3) -> Color {
+        self.eval_pdf(rec, l, v).0
+    }
+
+    fn sample(&self, rec: &HitRecord, v: DVec3, rng: &mut StdRng) -> Option<BsdfSample> {
+        let n = rec.normal;
+        if n.dot(v) <= 0.0 {
+            return None;
+        }
+        let flake = self.flake(rec, n);
+        let [p_coat, p_flake, _] = self.lobe_probabilities(&flake, n, v);
+        let u: f64 = rng.random();
+        let l = if u < p_coat {
+            if self.is_coat_smooth() {
+                let f = self.coat_fresnel(n.dot(v));
+                return Some(BsdfSample::delta(
+                    -v.reflect(n),
+                    p_coat,
+                    Color::splat(f / p_coat),
+                ));
+            }
+            let h =
+                ONB::new(n).transform(sampling::beckmann(rng.random(), self.clearcoat_roughness));
+            -v.reflect(h)
+        } else if u < p_coat + p_flake {
+            let h = ONB::new(flake.normal).transform(sampling::beckmann(rng.random(), flake.alpha));
+            -v.reflect(h)
+        } else {
+            ONB::new(n).transform(sampling::cosine_hemisphere(rng.random()))
+        };
+        let (f, pdf) = self.eval_pdf(rec, l, v);
+        (pdf > 0.0).then(|| BsdfSample::new(l, pdf, f, n))
+    }
+
+    fn pdf(&self, rec: &HitRecord, l: DVec3, v: DVec3) -> f64 {
+        self.eval_pdf(rec, l, v).1
+    }
+
+    fn roughness(&self) -> Option<f64> {
+        Some(self.clearcoat_roughness)
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Option<Color> {
+        Some(self.color.lerp(self.flake_color, self.flake_density))
+    }
+}
+
+/// Get the reflectance of the thin film of `thickness` nm at the angle of `cos` in the medium
+/// above, where the indices of refraction of the medium, the film and the substrate are
+/// `indices`. The interference of the reflections on both sides of the film is computed for
+/// the wavelength of each channel, and averaged over polarizations.
+fn thin_film(cos: f64, thickness: f64, (n1, n2, n3): (f64, f64, f64)) -> Color {
+    let sin1 = (1.0 - cos * cos).max(0.0).sqrt();
+    let cos_in = |n: f64| (1.0 - (n1 * sin1 / n).powi(2)).max(0.0).sqrt();
+    let (cos2, cos3) = (cos_in(n2), cos_in(n3));
+    // The amplitude coefficients of reflection of s and p polarizations.
+    let rs = |na: f64, ca: f64, nb: f64, cb: f64| (na * ca - nb * cb) / (na * ca + nb * cb);
+    let rp = |na: f64, ca: f64, nb: f64, cb: f64| (nb * ca - na * cb) / (nb * ca + na * cb);
+    let coefficients = [
+        (rs(n1, cos, n2, cos2), rs(n2, cos2, n3, cos3)),
+        (rp(n1, cos, n2, cos2), rp(n2, cos2, n3, cos3)),
+    ];
+    let reflectance = WAVELENGTHS.map(|wavelength| {
+        // The phase difference of the path through the film.
+        let delta = f64::consts::TAU / wavelength * 2.0 * n2 * thickness * cos2;
+        coefficients
+            .iter()
+            .map(|&(r12, r23)| {
+                let cross = 2.0 * r12 * r23 * delta.cos();
+                (r12 * r12 + r23 * r23 + cross) / (1.0 + (r12 * r23).powi(2) + cross)
+            })
+            .sum::<f64>()
+            / 2.0
+    });
+    Color::from_array(reflectance)
+}
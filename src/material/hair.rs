@@ -0,0 +1,455 @@
+use std::{f64, sync::Arc};
+
+use glam::DVec3;
+use rand::{Rng, rngs::StdRng};
+
+use crate::{
+    color::{self, Color},
+    material::{Bsdf, BsdfSample, fresnel},
+    shape::HitRecord,
+};
+
+/// The number of scattering lobes of `Hair` before the residual lobe, i.e. R, TT and TRT.
+const LOBES: usize = 3;
+
+/// The absorption coefficients of eumelanin and pheomelanin per unit concentration.
+const EUMELANIN: Color = Color::new(0.419, 0.697, 1.37);
+const PHEOMELANIN: Color = Color::new(0.187, 0.4, 1.05);
+
+/// The scattering of light by hair and fur fibers, which are dielectric cylinders tinted by the
+/// melanin inside. The light reflected on the surface (R), transmitted through the fiber (TT)
+/// and reflected inside once (TRT) are lobes of longitudinal and azimuthal distributions, and
+/// the higher order scattering is a residual lobe.
+/// The fiber follows the tangent of the intersection, and the offset from its axis is `2v - 1`,
+/// which are provided by `Curve`.
+/// References:
+/// Chiang et al. 2016, A Practical and Controllable Hair and Fur Model for Production Path
+/// Tracing
+/// Pharr et al., Physically Based Rendering 3rd edition, 9.9 Hair Scattering
+#[derive(Clone)]
+pub struct Hair {
+    /// The absorption coefficient inside the fiber per unit of the fiber radius.
+    pub sigma_a: Color,
+
+    /// The longitudinal roughness in [0, 1], which widens the lobes along the fiber.
+    pub longitudinal_roughness: f64,
+
+    /// The azimuthal roughness in [0, 1], which widens the lobes around the fiber.
+    pub azimuthal_roughness: f64,
+
+    /// The angle of the scales on the fiber surface in degrees, which shifts the highlights.
+    pub scale_angle: f64,
+
+    /// The index of refraction of the fiber.
+    pub index: f64,
+}
+
+impl Hair {
+    /// Create the hair of the absorption coefficient `sigma_a`.
+    pub const fn new(sigma_a: Color) -> Self {
+        Self {
+            sigma_a,
+            longitudinal_roughness: 0.3,
+            azimuthal_roughness: 0.3,
+            scale_angle: 2.0,
+            index: 1.55,
+        }
+    }
+
+    /// Create the hair by the concentrations of eumelanin and pheomelanin, e.g. about 8 for
+    /// black hair, 1.3 for brown hair and 0.3 for blonde hair with no pheomelanin, and red hair
+    /// with more pheomelanin.
+    pub fn melanin(eumelanin: f64, pheomelanin: f64) -> Self {
+        Self::new(EUMELANIN * eumelanin.max(0.0) + PHEOMELANIN * pheomelanin.max(0.0))
+    }
+
+    /// Create the hair whose multiple scattering approximately has the color, e.g. for dyed
+    /// hair and fur whose color is picked by artists. It depends on the azimuthal roughness,
+    /// which should be set by `roughness` rather than the field.
+    pub fn color(color: Color) -> Self {
+        let hair = Self::new(Color::ZERO);
+        Self {
+            sigma_a: hair.sigma_a_of(color),
+            ..hair
+        }
+    }
+
+    /// Set the longitudinal and azimuthal roughness. The absorption of hair created by `color`
+    /// is updated to keep its color.
+    pub fn roughness(self, longitudinal: f64, azimuthal: f64) -> Self {
+        let color = self.albedo_color();
+        let hair = Self {
+            longitudinal_roughness: longitudinal.clamp(0.0, 1.0),
+            azimuthal_roughness: azimuthal.clamp(0.0, 1.0),
+            ..self
+        };
+        Self {
+            sigma_a: hair.sigma_a_of(color),
+            ..hair
+        }
+    }
+
+    /// Set the angle of the scales on the fiber surface in degrees.
+    pub const fn scale_angle(mut self, degrees: f64) -> Self {
+        self.scale_angle = degrees;
+        self
+    }
+
+    /// Set the index of refraction of the fiber.
+    pub const fn index(mut self, index: f64) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// Get the factor of the azimuthal roughness which maps the absorption to the color.
+    fn color_factor(&self) -> f64 {
+        let b = self.azimuthal_roughness;
+        5.969 - 0.215 * b + 2.532 * b.powi(2) - 10.73 * b.powi(3)
+            + 5.574 * b.powi(4)
+            + 0.245 * b.powi(5)
+    }
+
+    /// Get the absorption coefficient whose multiple scattering has the color.
+    fn sigma_a_of(&self, color: Color) -> Color {
+        let factor = self.color_factor();
+        color
+            .max(Color::splat(1e-4))
+            .to_array()
+            .map(|c| (c.ln() / factor).powi(2))
+            .into()
+    }
+
+    /// Get the color of the multiple scattering, the inverse of `sigma_a_of`.
+    fn albedo_color(&self) -> Color {
+        let factor = self.color_factor();
+        self.sigma_a
+            .to_array()
+            .map(|s| (-s.sqrt() * factor).exp())
+            .into()
+    }
+
+    /// Get the fiber frame at the intersection whose x axis is along the fiber and whose z axis
+    /// faces `v`, and the offset from the axis. `None` if the tangent is unknown or parallel to
+    /// `v`.
+    fn frame(&self, rec: &HitRecord, v: DVec3) -> Option<Frame> {
+        let x = rec.tangent.normalize_or_zero();
+        let z = (v - x * x.dot(v)).normalize_or_zero();
+        if x == DVec3::ZERO || z == DVec3::ZERO {
+            return None;
+        }
+        Some(Frame {
+            x,
+            y: z.cross(x),
+            z,
+            h: (2.0 * rec.v - 1.0).clamp(-1.0, 1.0),
+        })
+    }
+
+    /// Get the parameters of the lobes for the view direction in the local frame.
+    fn lobes(&self, v: DVec3, h: f64) -> Lobes {
+        let beta_m = self.longitudinal_roughness;
+        let beta_n = self.azimuthal_roughness;
+        // The variance of the longitudinal lobes, which is widened for higher orders.
+        let variance = (0.726 * beta_m + 0.812 * beta_m.powi(2) + 3.7 * beta_m.powi(20)).powi(2);
+        let variances = [variance, variance / 4.0, variance * 4.0, variance * 4.0];
+        // The scale of the logistic distributions of azimuthal lobes.
+        let scale = (f64::consts::PI / 8.0).sqrt()
+            * (0.265 * beta_n + 1.194 * beta_n.powi(2) + 5.372 * beta_n.powi(22));
+
+        let sin_theta_o = v.x;
+        let cos_theta_o = safe_sqrt(1.0 - sin_theta_o * sin_theta_o);
+        let sin_theta_t = sin_theta_o / self.index;
+        let cos_theta_t = safe_sqrt(1.0 - sin_theta_t * sin_theta_t);
+        // The modified index of refraction of the azimuthal projection.
+        let eta = safe_sqrt(self.index * self.index - sin_theta_o * sin_theta_o) / cos_theta_o;
+        let sin_gamma_t = (h / eta).clamp(-1.0, 1.0);
+        let cos_gamma_t = safe_sqrt(1.0 - sin_gamma_t * sin_gamma_t);
+        // The transmittance of a path through the fiber.
+        let transmittance = (-self.sigma_a * (2.0 * cos_gamma_t / cos_theta_t)).exp();
+
+        let cos_gamma_o = safe_sqrt(1.0 - h * h);
+        let f = fresnel::dielectric(cos_theta_o * cos_gamma_o, self.index);
+        let tt = (1.0 - f).powi(2) * transmittance;
+        let trt = tt * transmittance * f;
+        let residual =
+            trt * transmittance * f / (Color::ONE - transmittance * f).max(Color::splat(1e-6));
+
+        // The view angles tilted by the scales, by -2α for R, α for TT and 4α for TRT.
+        let (sin_alpha, cos_alpha) = self.scale_angle.to_radians().sin_cos();
+        let (sin_2alpha, cos_2alpha) = (2.0 * sin_alpha * cos_alpha, 1.0 - 2.0 * sin_alpha.powi(2));
+        let (sin_4alpha, cos_4alpha) = (
+            2.0 * sin_2alpha * cos_2alpha,
+            1.0 - 2.0 * sin_2alpha.powi(2),
+        );
+        let tilt = |sin: f64, cos: f64| {
+            (
+                sin_theta_o * cos + cos_theta_o * sin,
+                (cos_theta_o * cos - sin_theta_o * sin).abs(),
+            )
+        };
+        Lobes {
+            attenuations: [Color::splat(f), tt, trt, residual],
+            variances,
+            scale,
+            gamma_o: h.clamp(-1.0, 1.0).asin(),
+            gamma_t: sin_gamma_t.asin(),
+            thetas: [
+                tilt(-sin_2alpha, cos_2alpha),
+                tilt(sin_alpha, cos_alpha),
+                tilt(sin_4alpha, cos_4alpha),
+                (sin_theta_o, cos_theta_o),
+            ],
+        }
+    }
+
+    /// Evaluate the BSDF and the PDF between the local directions, where the BSDF is divided by
+    /// the cosine of `l` to the shading normal, which the renderer multiplies back.
+    fn eval_local(&self, l: DVec3, v: DVec3, h: f64, cos_n: f64) -> (Color, f64) {
+        let lobes = self.lobes(v, h);
+        let sin_theta_i = l.x;
+        let cos_theta_i = safe_sqrt(1.0 - sin_theta_i * sin_theta_i);
+        let phi = l.z.atan2(l.y) - v.z.atan2(v.y);
+        let pdfs = lobes.pdfs();
+        let (mut f, mut pdf) = (Color::ZERO, 0.0);
+        for (p, lobe_pdf) in pdfs.into_iter().enumerate() {
+            let (sin_theta_o, cos_theta_o) = lobes.thetas[p];
+            let m = longitudinal(
+                cos_theta_i,
+                cos_theta_o,
+                sin_theta_i,
+                sin_theta_o,
+                lobes.variances[p],
+            );
+            let n = if p < LOBES {
+                azimuthal(phi, p, lobes.scale, lobes.gamma_o, lobes.gamma_t)
+            } else {
+                f64::consts::FRAC_1_PI / 2.0
+            };
+            f += lobes.attenuations[p] * m * n;
+            pdf += lobe_pdf * m * n;
+        }
+        if cos_n.abs() > 0.0 {
+            f /= cos_n.abs();
+        }
+        (f, pdf)
+    }
+}
+
+/// The local frame of a fiber, see `Hair::frame`.
+struct Frame {
+    x: DVec3,
+    y: DVec3,
+    z: DVec3,
+
+    /// The offset from the axis of the fiber in [-1, 1].
+    h: f64,
+}
+
+impl Frame {
+    /// Transform the direction into the local frame.
+    fn to_local(&self, w: DVec3) -> DVec3 {
+        DVec3::new(w.dot(self.x), w.dot(self.y), w.dot(self.z))
+    }
+
+    /// Transform the local direction into the world.
+    fn to_world(&self, w: DVec3) -> DVec3 {
+        self.x * w.x + self.y * w.y + self.z * w.z
+    }
+}
+
+/// The parameters of the lobes of `Hair` for a view direction.
+struct Lobes {
+    /// The attenuations of R, TT, TRT and the residual lobe.
+    attenuations: [Color; LOBES + 1],
+
+    /// The variances of the longitudinal distributions.
+    variances: [f64; LOBES + 1],
+
+    /// The scale of the azimuthal distributions.
+    scale: f64,
+
+    /// The azimuthal angles of the incident and the refracted rays to the fiber axis.
+    gamma_o: f64,
+    gamma_t: f64,
+
+    /// The sine and cosine of the view angle to the normal plane of the fiber, tilted by the
+    /// scales for each lobe.
+    thetas: [(f64, f64); LOBES + 1],
+}
+
+impl Lobes {
+    /// Get the probabilities to sample the lobes, which are proportional to the luminance of
+    /// their attenuations.
+    fn pdfs(&self) -> [f64; LOBES + 1] {
+        let luminances = self.attenuations.map(color::luminance);
+        let sum: f64 = luminances.iter().sum();
+        if sum > 0.0 {
+            luminances.map(|y| y / sum)
+        } else {
+            [1.0, 0.0, 0.0, 0.0]
+        }
+    }
+}
+
+impl Bsdf for Hair {
+    fn eval(&self, rec: &HitRecord, l: DVec3, v: DVec3) -> Color {
+        let Some(frame) = self.frame(rec, v) else {
+            return color::BLACK;
+        };
+        let local = (frame.to_local(l), frame.to_local(v));
+        self.eval_local(local.0, local.1, frame.h, rec.normal.dot(l))
+            .0
+    }
+
+    fn sample(&self, rec: &HitRecord, v: DVec3, rng: &mut StdRng) -> Option<BsdfSample> {
+        let frame = self.frame(rec, v)?;
+        let v_local = frame.to_local(v);
+        let lobes = self.lobes(v_local, frame.h);
+        // Choose a lobe by the luminance of attenuations.
+        let pdfs = lobes.pdfs();
+        let mut u: f64 = rng.random();
+        let mut p = 0;
+        while p < LOBES && u >= pdfs[p] {
+            u -= pdfs[p];
+            p += 1;
+        }
+        // Sample the longitudinal lobe around the mirror direction of the tilted view.
+        let (sin_theta_o, cos_theta_o) = lobes.thetas[p];
+        let variance = lobes.variances[p];
+        let u1 = rng.random::<f64>().max(1e-5);
+        let cos_theta =
+            (1.0 + variance * (u1 + (1.0 - u1) * (-2.0 / variance).exp()).ln()).clamp(-1.0, 1.0);
+        let sin_theta = safe_sqrt(1.0 - cos_theta * cos_theta);
+        let cos_phi = (f64::consts::TAU * rng.random::<f64>()).cos();
+        let sin_theta_i = -cos_theta * sin_theta_o + sin_theta * cos_phi * cos_theta_o;
+        let cos_theta_i = safe_sqrt(1.0 - sin_theta_i * sin_theta_i);
+        // Sample the azimuthal lobe around the deflection of the path.
+        let u2: f64 = rng.random();
+        let dphi = if p < LOBES {
+            deflection(p, lobes.gamma_o, lobes.gamma_t)
+                + sample_trimmed_logistic(u2, lobes.scale, -f64::consts::PI, f64::consts::PI)
+        } else {
+            f64::consts::TAU * u2
+        };
+        let phi_i = v_local.z.atan2(v_local.y) + dphi;
+        let l_local = DVec3::new(
+            sin_theta_i,
+            cos_theta_i * phi_i.cos(),
+            cos_theta_i * phi_i.sin(),
+        );
+        let l = frame.to_world(l_local);
+        let (f, pdf) = self.eval_local(l_local, v_local, frame.h, rec.normal.dot(l));
+        (pdf > 0.0).then(|| BsdfSample::new(l, pdf, f, rec.normal))
+    }
+
+    fn pdf(&self, rec: &HitRecord, l: DVec3, v: DVec3) -> f64 {
+        let Some(frame) = self.frame(rec, v) else {
+            return 0.0;
+        };
+        let local = (frame.to_local(l), frame.to_local(v));
+        self.eval_local(local.0, local.1, frame.h, rec.normal.dot(l))
+            .1
+    }
+
+    fn roughness(&self) -> Option<f64> {
+        Some(self.longitudinal_roughness)
+    }
+
+    fn regularized(&self, min_roughness: f64) -> Option<Arc<dyn Bsdf>> {
+        if self.longitudinal_roughness >= min_roughness && self.azimuthal_roughness >= min_roughness
+        {
+            return None;
+        }
+        let min_roughness = min_roughness.min(1.0);
+        Some(Arc::new(Self {
+            longitudinal_roughness: self.longitudinal_roughness.max(min_roughness),
+            azimuthal_roughness: self.azimuthal_roughness.max(min_roughness),
+            ..self.clone()
+        }))
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Option<Color> {
+        Some(self.albedo_color())
+    }
+}
+
+/// Get the square root clamped to zero for the negative rounding errors.
+fn safe_sqrt(x: f64) -> f64 {
+    x.max(0.0).sqrt()
+}
+
+/// The modified Bessel function of the first kind of order zero.
+fn bessel_i0(x: f64) -> f64 {
+    let (mut sum, mut term) = (0.0, 1.0);
+    let x2 = x * x / 4.0;
+    for i in 1..=10 {
+        sum += term;
+        term *= x2 / (i * i) as f64;
+    }
+    sum
+}
+
+/// The logarithm of `bessel_i0`, which is approximated asymptotically for large `x`.
+fn log_bessel_i0(x: f64) -> f64 {
+    if x > 12.0 {
+        x + 0.5 * (-(f64::consts::TAU).ln() + (1.0 / x).ln() + 1.0 / (8.0 * x))
+    } else {
+        bessel_i0(x).ln()
+    }
+}
+
+/// The longitudinal scattering function of the variance between the view and light angles to
+/// the normal plane of the fiber.
+fn longitudinal(
+    cos_theta_i: f64,
+    cos_theta_o: f64,
+    sin_theta_i: f64,
+    sin_theta_o: f64,
+    v: f64,
+) -> f64 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        // The logarithmic form which doesn't overflow for small variances.
+        (log_bessel_i0(a) - b - 1.0 / v + f64::consts::LN_2 + (1.0 / (2.0 * v)).ln()).exp()
+    } else {
+        (-b).exp() * bessel_i0(a) / ((1.0 / v).sinh() * 2.0 * v)
+    }
+}
+
+/// The azimuthal deflection of the path of lobe `p` through the fiber.
+fn deflection(p: usize, gamma_o: f64, gamma_t: f64) -> f64 {
+    2.0 * p as f64 * gamma_t - 2.0 * gamma_o + p as f64 * f64::consts::PI
+}
+
+/// The azimuthal scattering function of lobe `p` at the azimuthal angle `phi` between the view
+/// and light directions.
+fn azimuthal(phi: f64, p: usize, scale: f64, gamma_o: f64, gamma_t: f64) -> f64 {
+    let dphi = (phi - deflection(p, gamma_o, gamma_t) + f64::consts::PI)
+        .rem_euclid(f64::consts::TAU)
+        - f64::consts::PI;
+    trimmed_logistic(dphi, scale, -f64::consts::PI, f64::consts::PI)
+}
+
+/// The logistic distribution of `scale`.
+fn logistic(x: f64, scale: f64) -> f64 {
+    let e = (-x.abs() / scale).exp();
+    e / (scale * (1.0 + e).powi(2))
+}
+
+/// The cumulative distribution function of `logistic`.
+fn logistic_cdf(x: f64, scale: f64) -> f64 {
+    1.0 / (1.0 + (-x / scale).exp())
+}
+
+/// The logistic distribution normalized over [a, b].
+fn trimmed_logistic(x: f64, scale: f64, a: f64, b: f64) -> f64 {
+    logistic(x, scale) / (logistic_cdf(b, scale) - logistic_cdf(a, scale))
+}
+
+/// Sample `trimmed_logistic` by inverting its cumulative distribution function.
+fn sample_trimmed_logistic(u: f64, scale: f64, a: f64, b: f64) -> f64 {
+    let k = logistic_cdf(b, scale) - logistic_cdf(a, scale);
+    let x = -scale * (1.0 / (u * k + logistic_cdf(a, scale)) - 1.0).ln();
+    x.clamp(a, b)
+}
@@ -0,0 +1,94 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use crate::{material::Bsdf, object::Object};
+
+/// The materials keyed by name which are shared by the objects referring to them, see
+/// `Object::named_material`. Replacing an entry in the library of a scene updates every object
+/// which uses it, and scene files store each material once.
+#[derive(Clone, Default)]
+pub struct MaterialLibrary {
+    materials: BTreeMap<String, Arc<dyn Bsdf>>,
+}
+
+impl MaterialLibrary {
+    /// Create an empty library.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builder-style add or replace the material of `name`.
+    pub fn with<M>(mut self, name: &str, material: M) -> Self
+    where
+        M: Bsdf + 'static,
+    {
+        self.insert(name, Arc::new(material));
+        self
+    }
+
+    /// Add or replace the material of `name`, the replaced one is returned.
+    pub fn insert(&mut self, name: &str, material: Arc<dyn Bsdf>) -> Option<Arc<dyn Bsdf>> {
+        self.materials.insert(name.to_string(), material)
+    }
+
+    /// Get the material of `name`.
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn Bsdf>> {
+        self.materials.get(name)
+    }
+
+    /// Set the material of `obj` if it refers to a material in the library by name. False is
+    /// returned if the name isn't in the library, whose object keeps its material until the
+    /// name is added.
+    pub fn bind(&self, obj: &mut Object) -> bool {
+        let Some(name) = obj.material_name.as_deref() else {
+            return true;
+        };
+        match self.get(name) {
+            Some(material) => {
+                obj.material = material.clone();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get the number of materials.
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    /// Whether the library has no material.
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
+
+    /// Iterate over the names and materials in the order of names.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Arc<dyn Bsdf>)> {
+        self.materials
+            .iter()
+            .map(|(name, material)| (name.as_str(), material))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::DVec3;
+
+    use super::*;
+    use crate::{color, material::Material, scene::file::ObjectDesc, shape::sphere::Sphere};
+
+    #[test]
+    fn unknown_names_are_reported() {
+        let mut obj = Object::new(Sphere::new(DVec3::ZERO, None, 1.0)).named_material("steel");
+        let library = MaterialLibrary::new();
+        assert!(!library.bind(&mut obj));
+        let err = ObjectDesc::from_object(&obj, &library)
+            .err()
+            .map(|e| e.to_string());
+        assert_eq!(err.as_deref(), Some("Unknown material: steel"));
+
+        let library = library.with("steel", Material::metallic(color::WHITE, 0.2));
+        assert!(library.bind(&mut obj));
+        assert!(Arc::ptr_eq(&obj.material, library.get("steel").unwrap()));
+        assert!(ObjectDesc::from_object(&obj, &library).is_ok());
+    }
+}
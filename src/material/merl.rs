@@ -0,0 +1,222 @@
+use std::{
+    f64, fs, io,
+    path::{Path, PathBuf},
+};
+
+use glam::DVec3;
+use rand::{Rng, rngs::StdRng};
+
+use crate::{
+    color::Color,
+    material::{Bsdf, BsdfSample},
+    onb::ONB,
+    sampling,
+    shape::HitRecord,
+};
+
+/// The resolution of half vector polar angle in MERL table.
+const RES_THETA_H: usize = 90;
+/// The resolution of difference vector polar angle in MERL table.
+const RES_THETA_D: usize = 90;
+/// The resolution of difference vector azimuth angle in MERL table, only a half of [0, 2π)
+/// is stored thanks to reciprocity.
+const RES_PHI_D: usize = 180;
+/// The scale factors of red, green and blue channels in MERL table.
+const SCALE: [f64; 3] = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+/// The probability to sample by the tabulated half vector distribution rather than cosine
+/// weighted hemisphere.
+const TABLE_PROB: f64 = 0.5;
+
+/// Measured isotropic BRDF from the MERL 100 database, stored as half/difference angles.
+/// References:
+/// https://www.merl.com/brdf/
+pub struct MerlBrdf {
+    /// Red, green and blue BRDF values of all half/difference angle samples.
+    data: Vec<[f64; 3]>,
+
+    /// The cumulative distribution of half vector polar angle bins over solid angle.
+    theta_h_cdf: Vec<f64>,
+
+    /// The file which the BRDF is loaded from.
+    path: Option<PathBuf>,
+}
+
+impl MerlBrdf {
+    /// Load MERL binary file whose content is three `i32` dimensions followed by the doubles of
+    /// red, green and blue channels.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let brdf = Self::from_bytes(&fs::read(&path)?)?;
+        Ok(Self {
+            path: Some(path.as_ref().to_path_buf()),
+            ..brdf
+        })
+    }
+
+    /// Get the file which the BRDF is loaded from.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Parse MERL binary content.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        if bytes.len() < 12 {
+            return Err(invalid("MERL file is too short"));
+        }
+        let dims: Vec<usize> = bytes[..12]
+            .chunks_exact(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .collect();
+        let n = RES_THETA_H * RES_THETA_D * RES_PHI_D;
+        if dims.iter().product::<usize>() != n {
+            return Err(invalid("MERL file has unexpected dimensions"));
+        }
+        if bytes.len() != 12 + 3 * n * 8 {
+            return Err(invalid("MERL file has unexpected size"));
+        }
+        let values: Vec<f64> = bytes[12..]
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        let data: Vec<[f64; 3]> = (0..n)
+            .map(|i| {
+                // Negative values mark missing measurements.
+                [0, 1, 2].map(|c| (values[c * n + i] * SCALE[c]).max(0.0))
+            })
+            .collect();
+        let theta_h_cdf = Self::build_theta_h_cdf(&data);
+        Ok(Self {
+            data,
+            theta_h_cdf,
+            path: None,
+        })
+    }
+
+    /// The polar angle range of the half vector bin `i`. The bins are non-linear in MERL table.
+    fn theta_h_range(i: usize) -> (f64, f64) {
+        let angle = |i: usize| (i as f64 / RES_THETA_H as f64).powi(2) * f64::consts::FRAC_PI_2;
+        (angle(i), angle(i + 1))
+    }
+
+    /// Build the CDF of half vector bins, where the weight of one bin is the average luminance
+    /// of the bin multiplied by its solid angle.
+    fn build_theta_h_cdf(data: &[[f64; 3]]) -> Vec<f64> {
+        let per_bin = RES_THETA_D * RES_PHI_D;
+        let mut cdf = Vec::with_capacity(RES_THETA_H);
+        let mut sum = 0.0;
+        for i in 0..RES_THETA_H {
+            let avg = data[i * per_bin..(i + 1) * per_bin]
+                .iter()
+                .map(|c| 0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2])
+                .sum::<f64>()
+                / per_bin as f64;
+            let (lo, hi) = Self::theta_h_range(i);
+            sum += avg * (lo.cos() - hi.cos());
+            cdf.push(sum);
+        }
+        if sum > 0.0 {
+            cdf.iter_mut().for_each(|c| *c /= sum);
+        } else {
+            // Fallback to the uniform distribution for the black material.
+            cdf.iter_mut()
+                .enumerate()
+                .for_each(|(i, c)| *c = (i + 1) as f64 / RES_THETA_H as f64);
+        }
+        cdf
+    }
+
+    /// Get the angles (θ_h, θ_d, φ_d) of half/difference coordinates.
+    fn half_diff(l: DVec3, v: DVec3, n: DVec3) -> (f64, f64, f64) {
+        let h = (l + v).normalize();
+        let theta_h = n.dot(h).clamp(-1.0, 1.0).acos();
+        let theta_d = h.dot(l).clamp(-1.0, 1.0).acos();
+        // The x axis of difference frame is perpendicular to h and lies in the plane of n and h.
+        let tangent = n - h * n.dot(h);
+        let e_x = if tangent.length_squared() > 1e-12 {
+            -tangent.normalize()
+        } else {
+            h.any_orthonormal_vector()
+        };
+        let e_y = h.cross(e_x);
+        let phi_d = l.dot(e_y).atan2(l.dot(e_x));
+        (theta_h, theta_d, phi_d)
+    }
+
+    /// Get the index of MERL table from half/difference angles.
+    fn index(theta_h: f64, theta_d: f64, phi_d: f64) -> usize {
+        let theta_h_idx = if theta_h <= 0.0 {
+            0
+        } else {
+            ((theta_h / f64::consts::FRAC_PI_2).sqrt() * RES_THETA_H as f64) as usize
+        };
+        let theta_d_idx = (theta_d / f64::consts::FRAC_PI_2 * RES_THETA_D as f64) as usize;
+        // Reciprocity makes φ_d and φ_d + π the same.
+        let phi_d = if phi_d < 0.0 {
+            phi_d + f64::consts::PI
+        } else {
+            phi_d
+        };
+        let phi_d_idx = (phi_d / f64::consts::PI * RES_PHI_D as f64) as usize;
+        phi_d_idx.min(RES_PHI_D - 1)
+            + theta_d_idx.min(RES_THETA_D - 1) * RES_PHI_D
+            + theta_h_idx.min(RES_THETA_H - 1) * RES_PHI_D * RES_THETA_D
+    }
+}
+
+impl Bsdf for MerlBrdf {
+    fn eval(&self, rec: &HitRecord, l: DVec3, v: DVec3) -> Color {
+        let n = rec.normal;
+        if n.dot(l) <= 0.0 || n.dot(v) <= 0.0 {
+            return Color::ZERO;
+        }
+        let (theta_h, theta_d, phi_d) = Self::half_diff(l, v, n);
+        let [r, g, b] = self.data[Self::index(theta_h, theta_d, phi_d)];
+        Color::new(r, g, b)
+    }
+
+    fn pdf(&self, rec: &HitRecord, l: DVec3, v: DVec3) -> f64 {
+        let n = rec.normal;
+        let n_dot_l = n.dot(l);
+        if n_dot_l <= 0.0 || n.dot(v) <= 0.0 {
+            return 0.0;
+        }
+        let h = (l + v).normalize();
+        let theta_h = n.dot(h).clamp(-1.0, 1.0).acos();
+        let i = ((theta_h / f64::consts::FRAC_PI_2).sqrt() * RES_THETA_H as f64) as usize;
+        let i = i.min(RES_THETA_H - 1);
+        let prob = self.theta_h_cdf[i] - if i == 0 { 0.0 } else { self.theta_h_cdf[i - 1] };
+        let (lo, hi) = Self::theta_h_range(i);
+        // Uniform over the solid angle of the bin, then the Jacobian from h to l.
+        let p_h = prob / (f64::consts::TAU * (lo.cos() - hi.cos()));
+        let p_table = p_h / (4.0 * h.dot(v).abs());
+        let p_cosine = n_dot_l * f64::consts::FRAC_1_PI;
+        TABLE_PROB * p_table + (1.0 - TABLE_PROB) * p_cosine
+    }
+
+    fn sample(&self, rec: &HitRecord, v: DVec3, rng: &mut StdRng) -> Option<BsdfSample> {
+        let n = rec.normal;
+        let world_onb = ONB::new(n);
+        let l = if rng.random_bool(TABLE_PROB) {
+            // Pick a half vector bin, then a uniform direction over its solid angle.
+            let u = rng.random::<f64>();
+            let i = self
+                .theta_h_cdf
+                .partition_point(|&c| c < u)
+                .min(RES_THETA_H - 1);
+            let (lo, hi) = Self::theta_h_range(i);
+            let cos_t = lo.cos() - rng.random::<f64>() * (lo.cos() - hi.cos());
+            let sin_t = (1.0 - cos_t * cos_t).max(0.0).sqrt();
+            let phi = rng.random::<f64>() * f64::consts::TAU;
+            let h = world_onb.transform(DVec3::new(phi.cos() * sin_t, phi.sin() * sin_t, cos_t));
+            -v.reflect(h)
+        } else {
+            world_onb.transform(sampling::cosine_hemisphere(rng.random()))
+        };
+        let pdf = self.pdf(rec, l, v);
+        if pdf > 0.0 {
+            Some(BsdfSample::new(l, pdf, self.eval(rec, l, v), n))
+        } else {
+            None
+        }
+    }
+}
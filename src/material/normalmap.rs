@@ -0,0 +1,116 @@
+use std::io;
+
+use glam::DVec3;
+use image::ImageReader;
+
+use crate::{onb::ONB, shape::HitRecord};
+
+/// A tangent space normal map, whose mipmaps keep the average of unnormalized normals. The
+/// shorter the average normal of the texels in a pixel footprint, the more the normals vary
+/// under the pixel, which is turned into roughness by Toksvig's method so that the highlights
+/// of bumpy surfaces don't sparkle at a distance.
+/// References:
+/// Toksvig, Mipmapping Normal Maps, Journal of Graphics Tools 2005
+pub struct NormalMap {
+    /// The mipmap levels from full resolution to 1 x 1, each with its width, height and the
+    /// normals in row-major order.
+    levels: Vec<(u32, u32, Vec<DVec3>)>,
+
+    /// The file which the normal map is loaded from.
+    path: Option<String>,
+}
+
+impl NormalMap {
+    /// Create a normal map from the tangent space normals of `width` x `height` texels in
+    /// row-major order from the top, whose z axis is the surface normal.
+    pub fn new(width: u32, height: u32, normals: Vec<DVec3>) -> Self {
+        assert_eq!((width * height) as usize, normals.len());
+        let normals: Vec<DVec3> = normals.into_iter().map(DVec3::normalize_or_zero).collect();
+        let mut levels = vec![(width, height, normals)];
+        while let Some((w, h, texels)) = levels.last().filter(|(w, h, _)| *w > 1 || *h > 1) {
+            let (nw, nh) = (w.div_ceil(2), h.div_ceil(2));
+            let mut next = Vec::with_capacity((nw * nh) as usize);
+            for y in 0..nh {
+                for x in 0..nw {
+                    let mut sum = DVec3::ZERO;
+                    let mut count = 0;
+                    for yy in 2 * y..(2 * y + 2).min(*h) {
+                        for xx in 2 * x..(2 * x + 2).min(*w) {
+                            sum += texels[(yy * w + xx) as usize];
+                            count += 1;
+                        }
+                    }
+                    next.push(sum / count as f64);
+                }
+            }
+            levels.push((nw, nh, next));
+        }
+        Self { levels, path: None }
+    }
+
+    /// Load a normal map from an image whose RGB channels in [0, 1] encode the xyz of normals
+    /// in [-1, 1].
+    pub fn load(path: &str) -> io::Result<Self> {
+        let img = ImageReader::open(path)?
+            .decode()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+            .to_rgb32f();
+        let (width, height) = img.dimensions();
+        let normals = img
+            .pixels()
+            .map(|p| DVec3::new(p[0] as f64, p[1] as f64, p[2] as f64) * 2.0 - 1.0)
+            .collect();
+        Ok(Self {
+            path: Some(path.to_string()),
+            ..Self::new(width, height, normals)
+        })
+    }
+
+    /// Get the file which the normal map is loaded from.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// Get the average tangent space normal over the square footprint of width `size` in
+    /// texture coordinates around (u, v), which is shorter than one where the normals vary.
+    pub fn lookup(&self, u: f64, v: f64, size: f64) -> DVec3 {
+        let (width, height, _) = &self.levels[0];
+        let texels = size * *width.max(height) as f64;
+        let level = if texels > 1.0 {
+            (texels.log2().round() as usize).min(self.levels.len() - 1)
+        } else {
+            0
+        };
+        let (w, h, normals) = &self.levels[level];
+        let x = ((u.rem_euclid(1.0) * *w as f64) as u32).min(w - 1);
+        let y = (((1.0 - v.rem_euclid(1.0)) * *h as f64) as u32).min(h - 1);
+        normals[(y * w + x) as usize]
+    }
+
+    /// Get the shading normal at the intersection and the variance of normals under the pixel
+    /// footprint, which is added to the squared α of microfacet distribution.
+    pub fn shade(&self, rec: &HitRecord) -> (DVec3, f64) {
+        let scale = rec.tangent.length();
+        let size = if scale > 0.0 {
+            rec.footprint / scale
+        } else {
+            0.0
+        };
+        let average = self.lookup(rec.u, rec.v, size);
+        let length = average.length();
+        if length == 0.0 {
+            return (rec.normal, 0.0);
+        }
+        // The tangent frame around the normal, in which the x axis follows the u direction.
+        let n = rec.normal;
+        let tangent = (rec.tangent - n * n.dot(rec.tangent)).normalize_or_zero();
+        let local = average / length;
+        let normal = if tangent == DVec3::ZERO {
+            ONB::new(n).transform(local)
+        } else {
+            let bitangent = n.cross(tangent);
+            tangent * local.x + bitangent * local.y + n * local.z
+        };
+        (normal.normalize(), (1.0 - length) / length)
+    }
+}
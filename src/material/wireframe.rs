@@ -0,0 +1,137 @@
+use std::sync::Arc;
+
+use glam::DVec3;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    color::{self, Color},
+    material::{Bsdf, BsdfSample, Material},
+    shape::HitRecord,
+};
+
+/// The lines drawn by `Wireframe` on top of the base material.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Overlay {
+    /// The edges of the primitives, whose width is a fraction of the primitive. The triangles
+    /// are outlined by their barycentric coordinates, the other shapes by the border of their
+    /// (u, v) patch, e.g. the sides of quads.
+    Edges { width: f64 },
+
+    /// A grid of `cells` by `cells` squares over the texture coordinates in [0, 1), whose line
+    /// width is a fraction of a cell. Stretched or flipped cells show the problems of UV layout.
+    UvGrid { cells: u32, width: f64 },
+}
+
+/// A debug material which draws lines in a flat diffuse color on top of the base material, so
+/// that the mesh density and UV layout are visible in renders.
+#[derive(Clone)]
+pub struct Wireframe {
+    /// The material between the lines.
+    pub base: Arc<dyn Bsdf>,
+
+    /// The lines to draw.
+    pub overlay: Overlay,
+
+    /// The diffuse material of the lines.
+    line: Material,
+}
+
+impl Wireframe {
+    /// Draw the edges of primitives on top of `base`.
+    pub fn edges<M>(base: M, width: f64) -> Self
+    where
+        M: Bsdf + 'static,
+    {
+        Self::new(Arc::new(base), Overlay::Edges { width })
+    }
+
+    /// Draw a UV grid of `cells` by `cells` squares on top of `base`.
+    pub fn uv_grid<M>(base: M, cells: u32, width: f64) -> Self
+    where
+        M: Bsdf + 'static,
+    {
+        Self::new(Arc::new(base), Overlay::UvGrid { cells, width })
+    }
+
+    /// Draw the lines of `overlay` in black on top of `base`.
+    pub fn new(base: Arc<dyn Bsdf>, overlay: Overlay) -> Self {
+        Self {
+            base,
+            overlay,
+            line: Material::diffuse(color::BLACK),
+        }
+    }
+
+    /// Set the color of lines.
+    pub fn line_color(mut self, color: Color) -> Self {
+        self.line.color = color;
+        self
+    }
+
+    /// Get the color of lines.
+    pub fn color(&self) -> Color {
+        self.line.color
+    }
+
+    /// Whether the intersection is on a line of the overlay.
+    pub fn on_line(&self, rec: &HitRecord) -> bool {
+        match self.overlay {
+            Overlay::Edges { width } => {
+                let distance = match rec.barycentric {
+                    Some((b1, b2)) => b1.min(b2).min(1.0 - b1 - b2),
+                    None => rec.u.min(rec.v).min(1.0 - rec.u).min(1.0 - rec.v),
+                };
+                distance < width
+            }
+            Overlay::UvGrid { cells, width } => {
+                let near_line = |x: f64| {
+                    let f = (x * cells as f64).rem_euclid(1.0);
+                    f.min(1.0 - f) < width / 2.0
+                };
+                near_line(rec.u) || near_line(rec.v)
+            }
+        }
+    }
+
+    /// Get the material at the intersection.
+    fn at(&self, rec: &HitRecord) -> &dyn Bsdf {
+        if self.on_line(rec) {
+            &self.line
+        } else {
+            self.base.as_ref()
+        }
+    }
+}
+
+impl Bsdf for Wireframe {
+    fn eval(&self, rec: &HitRecord, l: DVec3, v: DVec3) -> Color {
+        self.at(rec).eval(rec, l, v)
+    }
+
+    fn sample(&self, rec: &HitRecord, v: DVec3, rng: &mut StdRng) -> Option<BsdfSample> {
+        self.at(rec).sample(rec, v, rng)
+    }
+
+    fn pdf(&self, rec: &HitRecord, l: DVec3, v: DVec3) -> f64 {
+        self.at(rec).pdf(rec, l, v)
+    }
+
+    fn is_delta(&self) -> bool {
+        // The lines are only sampled by the BSDF on delta materials.
+        self.base.is_delta()
+    }
+
+    fn diffuse_albedo(&self, rec: &HitRecord, v: DVec3) -> Option<Color> {
+        self.at(rec).diffuse_albedo(rec, v)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Option<Color> {
+        self.at(rec).albedo(rec)
+    }
+
+    fn emitted(&self, rec: &HitRecord, v: DVec3) -> Color {
+        self.at(rec).emitted(rec, v)
+    }
+}
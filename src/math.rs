@@ -1,6 +1,8 @@
-use glam::{DMat4, DVec3, Vec4Swizzles};
+use glam::{DMat3, DMat4, DQuat, DVec3, Vec4Swizzles};
 use rand::Rng;
-use std::f64;
+use std::{f64, ops::Mul};
+
+use crate::{aabb::Aabb, interval::Interval};
 
 pub type DPoint3 = DVec3;
 
@@ -15,8 +17,7 @@ pub fn random_in_range(min: u32, max: u32) -> u32 {
 /// Vector utilities module for Vec3 operations
 pub mod vec {
     use super::*;
-    use rand::{random_range, rngs::StdRng};
-    use rand_distr::{Distribution, UnitDisc};
+    use rand::random_range;
 
     /// Generate a random vector with each component in [0, 1)
     #[inline]
@@ -45,14 +46,6 @@ pub mod vec {
         let z = 1.0 - r2;
         DVec3::new(x, y, z)
     }
-
-    /// Randomly generate a vector on the surface of a unit hemisphere using Malley's method.
-    #[inline]
-    pub fn random_cosine_weight_on_hemisphere(rng: &mut StdRng) -> DVec3 {
-        let [x, y]: [f64; 2] = UnitDisc.sample(rng);
-        let z = (1.0 - x * x - y * y).sqrt();
-        DVec3::new(x, y, z)
-    }
 }
 
 #[derive(Default)]
@@ -105,3 +98,174 @@ pub enum Axis {
     Y = 1,
     Z = 2,
 }
+
+/// An affine transformation with its inverse and normal matrix, which transforms points, vectors,
+/// normals and rays from local space into world space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    /// The matrix from local space to world space.
+    pub matrix: DMat4,
+
+    /// The matrix from world space to local space.
+    pub inverse: DMat4,
+
+    /// The inverse transpose of the linear part of `matrix`, which transforms normals.
+    pub normal: DMat3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Transform {
+    /// The transformation which does nothing.
+    pub const IDENTITY: Self = Self {
+        matrix: DMat4::IDENTITY,
+        inverse: DMat4::IDENTITY,
+        normal: DMat3::IDENTITY,
+    };
+
+    /// Create the transformation from an invertible affine matrix.
+    pub fn new(matrix: DMat4) -> Self {
+        Self {
+            matrix,
+            inverse: matrix.inverse(),
+            normal: DMat3::from_mat4(matrix).inverse().transpose(),
+        }
+    }
+
+    /// Translate by vector `v`.
+    pub fn translation(v: DVec3) -> Self {
+        Self::new(DMat4::from_translation(v))
+    }
+
+    /// Rotate by the quaternion.
+    pub fn rotation(rotation: DQuat) -> Self {
+        Self::new(DMat4::from_quat(rotation))
+    }
+
+    /// Rotate around the coordinate axis by `angle` in radians.
+    pub fn axis_rotation(axis: Axis, angle: f64) -> Self {
+        let mut axis_vec = DVec3::ZERO;
+        axis_vec[axis as usize] = 1.0;
+        Self::rotation(DQuat::from_axis_angle(axis_vec, angle))
+    }
+
+    /// Scale by the factor of each axis.
+    pub fn scale(scale: DVec3) -> Self {
+        Self::new(DMat4::from_scale(scale))
+    }
+
+    /// Scale, then rotate, then translate, e.g. the node of a scene hierarchy.
+    pub fn from_scale_rotation_translation(
+        scale: DVec3,
+        rotation: DQuat,
+        translation: DVec3,
+    ) -> Self {
+        Self::new(DMat4::from_scale_rotation_translation(
+            scale,
+            rotation,
+            translation,
+        ))
+    }
+
+    /// Place the local space at `eye` and look at `target`, where the local -z axis is the view
+    /// direction and the local y axis is close to `up`, e.g. a camera rig.
+    pub fn look_at(eye: DPoint3, target: DPoint3, up: DVec3) -> Self {
+        let inverse = DMat4::look_at_rh(eye, target, up);
+        let matrix = inverse.inverse();
+        Self {
+            matrix,
+            inverse,
+            normal: DMat3::from_mat4(matrix).inverse().transpose(),
+        }
+    }
+
+    /// Get the transformation which applies `other` first and then `self`.
+    pub fn compose(&self, other: &Self) -> Self {
+        Self {
+            matrix: self.matrix * other.matrix,
+            inverse: other.inverse * self.inverse,
+            normal: self.normal * other.normal,
+        }
+    }
+
+    /// Get the inverse transformation.
+    pub fn inverse(&self) -> Self {
+        Self {
+            matrix: self.inverse,
+            inverse: self.matrix,
+            normal: DMat3::from_mat4(self.matrix).transpose(),
+        }
+    }
+
+    /// Transform a point.
+    pub fn point(&self, p: DPoint3) -> DPoint3 {
+        self.matrix.transform_point3(p)
+    }
+
+    /// Transform a vector which isn't affected by translation.
+    pub fn vector(&self, v: DVec3) -> DVec3 {
+        self.matrix.transform_vector3(v)
+    }
+
+    /// Transform a normal vector and normalize it.
+    pub fn normal(&self, n: DVec3) -> DVec3 {
+        (self.normal * n).normalize()
+    }
+
+    /// Transform a ray, whose direction is not normalized so that the distance `t` along the ray
+    /// is kept.
+    pub fn ray(&self, r: &Ray) -> Ray {
+        r.apply_transform(&self.matrix)
+    }
+
+    /// Get the bounding box of the transformed box.
+    pub fn bbox(&self, bbox: &Aabb) -> Aabb {
+        let Aabb { x, y, z } = *bbox;
+        let mut min = DVec3::INFINITY;
+        let mut max = DVec3::NEG_INFINITY;
+        for corner in [
+            DVec3::new(x.min, y.min, z.min),
+            DVec3::new(x.min, y.min, z.max),
+            DVec3::new(x.min, y.max, z.min),
+            DVec3::new(x.min, y.max, z.max),
+            DVec3::new(x.max, y.min, z.min),
+            DVec3::new(x.max, y.min, z.max),
+            DVec3::new(x.max, y.max, z.min),
+            DVec3::new(x.max, y.max, z.max),
+        ] {
+            let p = self.point(corner);
+            min = min.min(p);
+            max = max.max(p);
+        }
+        Aabb::new(
+            Interval::new(min.x, max.x),
+            Interval::new(min.y, max.y),
+            Interval::new(min.z, max.z),
+        )
+    }
+}
+
+impl Mul for Transform {
+    type Output = Self;
+
+    /// Compose the transformations, see `Transform::compose`.
+    fn mul(self, rhs: Self) -> Self {
+        self.compose(&rhs)
+    }
+}
+
+impl From<DMat4> for Transform {
+    fn from(matrix: DMat4) -> Self {
+        Self::new(matrix)
+    }
+}
+
+impl From<Transform> for DMat4 {
+    fn from(transform: Transform) -> Self {
+        transform.matrix
+    }
+}
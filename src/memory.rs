@@ -0,0 +1,102 @@
+use std::{fmt, io, mem::size_of};
+
+use crate::{bvh::BvhNode, color::Color, object::Object};
+
+/// The bytes of one mebibyte, which the reports are printed in.
+const MIB: f64 = (1 << 20) as f64;
+
+/// The memory consumed by a scene, see `Scene::memory_report`. Only the buffers which grow
+/// with the scene are counted, i.e. the heap of the shapes, images and BVH, not the renderer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// The bytes of objects and their shapes, where the shapes shared by instances are counted
+    /// once.
+    pub objects: usize,
+
+    /// The bytes of images, e.g. the panorama of background.
+    pub images: usize,
+
+    /// The bytes of BVH and the wide BVH converted from it.
+    pub bvh: usize,
+}
+
+impl MemoryReport {
+    /// Get the bytes of all categories.
+    pub const fn total(&self) -> usize {
+        self.objects + self.images + self.bvh
+    }
+
+    /// Estimate the bytes of BVH built by `Scene::build_bvh` for `num_objects` objects, which
+    /// is the upper bound of a binary tree whose leaves hold at least one object.
+    pub const fn estimate_bvh(num_objects: usize) -> usize {
+        num_objects * size_of::<Object>() + 2 * num_objects * size_of::<BvhNode>()
+    }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.1} MiB (objects {:.1} MiB, images {:.1} MiB, BVH {:.1} MiB)",
+            self.total() as f64 / MIB,
+            self.objects as f64 / MIB,
+            self.images as f64 / MIB,
+            self.bvh as f64 / MIB,
+        )
+    }
+}
+
+/// The limit of memory for loading a scene, see `Scene::load_with_budget`. A scene over the
+/// budget fails to load with a clear error instead of being killed by the OS in the middle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryBudget {
+    /// The maximal bytes of `MemoryReport::total`.
+    pub limit: usize,
+
+    /// Whether the images are downscaled to fit into the budget left by the other categories,
+    /// otherwise a scene over the budget is an error.
+    pub downscale_images: bool,
+}
+
+impl MemoryBudget {
+    /// Create a budget of `limit` bytes, which downscales images to fit.
+    pub const fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            downscale_images: true,
+        }
+    }
+
+    /// Create a budget of `limit` mebibytes.
+    pub const fn mib(limit: usize) -> Self {
+        Self::new(limit << 20)
+    }
+
+    /// Set whether the images are downscaled to fit into the budget.
+    pub const fn downscale_images(mut self, downscale: bool) -> Self {
+        self.downscale_images = downscale;
+        self
+    }
+
+    /// Get the maximal number of pixels of the images which fit into the budget left by the
+    /// objects and BVH of `report`, `None` if the images aren't downscaled.
+    pub fn max_image_pixels(&self, report: &MemoryReport) -> Option<usize> {
+        self.downscale_images
+            .then(|| self.limit.saturating_sub(report.objects + report.bvh) / size_of::<Color>())
+    }
+
+    /// Check the `report` against the budget, returning `ErrorKind::OutOfMemory` with the usage
+    /// of each category if it's over the budget.
+    pub fn check(&self, report: &MemoryReport) -> io::Result<()> {
+        if report.total() <= self.limit {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::OutOfMemory,
+            format!(
+                "the scene needs {report}, which is over the memory budget of {:.1} MiB",
+                self.limit as f64 / MIB
+            ),
+        ))
+    }
+}
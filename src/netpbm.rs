@@ -0,0 +1,57 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use image::RgbImage;
+
+use crate::{buffer::Buffer, color::Color};
+
+/// Write the 8-bit image as binary PPM (P6), which is read by almost every image tool.
+pub fn write_ppm<W: Write>(image: &RgbImage, mut writer: W) -> io::Result<()> {
+    write!(writer, "P6\n{} {}\n255\n", image.width(), image.height())?;
+    writer.write_all(image.as_raw())
+}
+
+/// Write the linear colors of an image in (y * width + x) order as PFM, i.e. the 32-bit float
+/// HDR format of Netpbm. The scale -1.0 marks little-endian values, and the rows are stored
+/// from bottom to top.
+pub fn write_pfm<W: Write>(
+    width: u32,
+    height: u32,
+    colors: &[Color],
+    mut writer: W,
+) -> io::Result<()> {
+    if colors.len() != width as usize * height as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Expect {} colors, got {}", width * height, colors.len()),
+        ));
+    }
+    write!(writer, "PF\n{width} {height}\n-1.0\n")?;
+    for row in colors.chunks_exact(width.max(1) as usize).rev() {
+        for color in row {
+            for c in color.to_array() {
+                writer.write_all(&(c as f32).to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Save the 8-bit image as binary PPM file.
+pub fn save_ppm<P: AsRef<Path>>(image: &RgbImage, path: P) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_ppm(image, &mut writer)?;
+    writer.flush()
+}
+
+/// Save the linear radiance of the buffer as PFM file, which keeps the HDR values without
+/// post-processing.
+pub fn save_pfm<P: AsRef<Path>>(buffer: &Buffer, path: P) -> io::Result<()> {
+    let (width, height) = buffer.dimensions();
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_pfm(width, height, &buffer.colors(), &mut writer)?;
+    writer.flush()
+}
@@ -0,0 +1,131 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+use glam::DVec3;
+
+use crate::{color::Color, math::DPoint3, shape::mesh::TriangleMesh};
+
+/// Load the Wavefront OBJ file as a triangle mesh, see `read`.
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<TriangleMesh> {
+    let mut mesh = read(BufReader::new(File::open(&path)?))?;
+    mesh.path = Some(path.as_ref().to_path_buf());
+    Ok(mesh)
+}
+
+/// Read the Wavefront OBJ content as a triangle mesh. The positions `v` with the optional
+/// colors after them, the texture coordinates `vt`, the normals `vn` and the faces `f` are
+/// read, whose polygons are split into fans of triangles, and the other statements such as
+/// groups and materials are ignored. The corners of faces which share the same position,
+/// texture coordinates and normal share a vertex of the mesh. The normals, texture
+/// coordinates and colors are only kept if all the vertices have them.
+pub fn read<R: BufRead>(reader: R) -> io::Result<TriangleMesh> {
+    let invalid = |line: usize, msg: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("OBJ line {}: {msg}", line + 1),
+        )
+    };
+    let mut positions: Vec<DPoint3> = Vec::new();
+    let mut colors: Vec<Option<Color>> = Vec::new();
+    let mut uvs: Vec<(f64, f64)> = Vec::new();
+    let mut normals: Vec<DVec3> = Vec::new();
+    // The corners of the faces as the indices of the position, texture coordinates and
+    // normal, and the vertex of the mesh of each distinct corner.
+    let mut corners: Vec<(usize, Option<usize>, Option<usize>)> = Vec::new();
+    let mut vertices: HashMap<(usize, Option<usize>, Option<usize>), u32> = HashMap::new();
+    let mut triangles: Vec<[u32; 3]> = Vec::new();
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let mut floats = || -> io::Result<Vec<f64>> {
+            tokens
+                .by_ref()
+                .map(|s| s.parse().map_err(|_| invalid(number, "invalid number")))
+                .collect()
+        };
+        match keyword {
+            "v" => {
+                let values = floats()?;
+                if values.len() < 3 {
+                    return Err(invalid(number, "the position has less than 3 values"));
+                }
+                positions.push(DVec3::new(values[0], values[1], values[2]));
+                colors
+                    .push((values.len() >= 6).then(|| DVec3::new(values[3], values[4], values[5])));
+            }
+            "vt" => {
+                let values = floats()?;
+                if values.is_empty() {
+                    return Err(invalid(number, "the texture coordinates are missing"));
+                }
+                uvs.push((values[0], values.get(1).copied().unwrap_or(0.0)));
+            }
+            "vn" => {
+                let values = floats()?;
+                if values.len() < 3 {
+                    return Err(invalid(number, "the normal has less than 3 values"));
+                }
+                normals.push(DVec3::new(values[0], values[1], values[2]));
+            }
+            "f" => {
+                // The indices start from 1, and the negative ones count back from the end.
+                let index = |s: &str, count: usize| -> io::Result<Option<usize>> {
+                    if s.is_empty() {
+                        return Ok(None);
+                    }
+                    let i: i64 = s.parse().map_err(|_| invalid(number, "invalid index"))?;
+                    let i = if i < 0 { count as i64 + i } else { i - 1 };
+                    if i < 0 || i >= count as i64 {
+                        return Err(invalid(number, "the index is out of range"));
+                    }
+                    Ok(Some(i as usize))
+                };
+                let mut face = Vec::new();
+                for corner in tokens {
+                    let mut parts = corner.split('/');
+                    let p = index(parts.next().unwrap_or(""), positions.len())?
+                        .ok_or_else(|| invalid(number, "the position index is missing"))?;
+                    let t = index(parts.next().unwrap_or(""), uvs.len())?;
+                    let n = index(parts.next().unwrap_or(""), normals.len())?;
+                    let key = (p, t, n);
+                    let vertex = *vertices.entry(key).or_insert_with(|| {
+                        corners.push(key);
+                        corners.len() as u32 - 1
+                    });
+                    face.push(vertex);
+                }
+                if face.len() < 3 {
+                    return Err(invalid(number, "the face has less than 3 vertices"));
+                }
+                triangles.extend((1..face.len() - 1).map(|i| [face[0], face[i], face[i + 1]]));
+            }
+            _ => {}
+        }
+    }
+    if triangles.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "OBJ file has no faces",
+        ));
+    }
+
+    let mut mesh = TriangleMesh::new(corners.iter().map(|c| positions[c.0]).collect(), triangles);
+    if let Some(normals) = corners.iter().map(|c| c.2.map(|n| normals[n])).collect() {
+        mesh = mesh.normals(normals);
+    }
+    if let Some(uvs) = corners.iter().map(|c| c.1.map(|t| uvs[t])).collect() {
+        mesh = mesh.uvs(uvs);
+    }
+    if let Some(colors) = corners.iter().map(|c| colors[c.0]).collect() {
+        mesh = mesh.colors(colors);
+    }
+    Ok(mesh)
+}
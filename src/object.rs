@@ -1,21 +1,65 @@
-use std::sync::Arc;
+use std::{any::Any, sync::Arc};
+
+use serde::{Deserialize, Serialize};
 
 use crate::{
     aabb::Aabb,
-    color,
+    camera::Camera,
+    color::{self, Color},
     interval::Interval,
-    material::Material,
-    math::Ray,
-    shape::{Bounded, HitRecord, Hittable},
+    material::{Bsdf, Material},
+    math::{Ray, Transform},
+    sampler,
+    shape::{Bounded, HitRecord, Hittable, Instance, lod::Lod},
+    texture::Texture,
 };
 
+/// Which faces of an object are seen by camera rays.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sides {
+    /// Both faces are seen and shaded alike, e.g. open meshes and single-sided quads.
+    #[default]
+    TwoSided,
+
+    /// The back faces are skipped by camera rays, which see the geometry behind them, e.g. the
+    /// inside of closed meshes and the walls of a room seen from outside. Other rays see both
+    /// faces, so the culled faces still cast shadows.
+    CullBackfaces,
+}
+
 #[derive(Clone)]
 pub struct Object {
     /// The shape of object
     pub shape: Arc<dyn Bounded>,
 
     /// The material of object
-    pub material: Arc<Material>,
+    pub material: Arc<dyn Bsdf>,
+
+    /// The light group of emission from object, `None` for the default group.
+    pub light_group: Option<Arc<str>>,
+
+    /// The name of material in the library of scene, `None` if the material is owned.
+    pub material_name: Option<Arc<str>>,
+
+    /// The random seed which textures and materials vary by, e.g. the hue and roughness jitter
+    /// of `Material`, so that the instances of one object don't look identical.
+    pub seed: u32,
+
+    /// The index of object in `Scene::objects`, which is assigned when the object is added to
+    /// the scene and reported by `Scene::raycast`.
+    pub id: usize,
+
+    /// The name which the object is found by, see `Scene::object_by_name`.
+    pub name: Option<Arc<str>>,
+
+    /// Which faces of the object are seen by camera rays.
+    pub sides: Sides,
+
+    /// Whether the object is the emitter of an area light, see `Light::emitter`. The light is
+    /// sampled directly at the surfaces, so the emission is only seen by camera rays and after
+    /// delta bounces.
+    pub emitter: bool,
 }
 
 impl Object {
@@ -27,21 +71,133 @@ impl Object {
         Self {
             shape: Arc::new(shape),
             material: Arc::new(Material::diffuse(color::GREY)),
+            light_group: None,
+            material_name: None,
+            seed: 0,
+            id: 0,
+            name: None,
+            sides: Sides::TwoSided,
+            emitter: false,
         }
     }
 
+    /// Set which faces of the object are seen by camera rays.
+    pub fn sides(mut self, sides: Sides) -> Self {
+        self.sides = sides;
+        self
+    }
+
+    /// Set the name which the object is found by, e.g. to focus the camera on it.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(Arc::from(name));
+        self
+    }
+
     /// Set material for object
-    pub fn material(mut self, material: Material) -> Self {
+    pub fn material<M>(mut self, material: M) -> Self
+    where
+        M: Bsdf + 'static,
+    {
         self.material = Arc::new(material);
+        self.material_name = None;
+        self
+    }
+
+    /// Use the material of `name` in the library of scene, which is bound when the object is
+    /// added to the scene or the material is added to the library, see `Scene::with_material`.
+    pub fn named_material(mut self, name: &str) -> Self {
+        self.material_name = Some(Arc::from(name));
+        self
+    }
+
+    /// Override the base color of the material, e.g. to vary the instances of one object. Only
+    /// `Material` has the color, other BSDFs are left unchanged.
+    pub fn material_color(self, color: Color) -> Self {
+        self.map_material(|material| material.color = color)
+    }
+
+    /// Override the texture of the material like `material_color`.
+    pub fn material_texture<T>(self, texture: T) -> Self
+    where
+        T: Texture + 'static,
+    {
+        self.map_material(|material| material.texture = Some(Arc::new(texture)))
+    }
+
+    /// Replace the material by its copy changed by `f` if it is a `Material`. The copy is owned
+    /// by the object instead of the library of scene.
+    fn map_material<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut Material),
+    {
+        let any: &dyn Any = self.material.as_ref();
+        if let Some(material) = any.downcast_ref::<Material>() {
+            let mut material = material.clone();
+            f(&mut material);
+            self.material = Arc::new(material);
+            self.material_name = None;
+        }
+        self
+    }
+
+    /// Create an instance of the object placed by `transform`, which shares the geometry with
+    /// the object instead of duplicating it. The material and light group are copied, and can
+    /// be overridden per instance, e.g. by `material` or `material_color`. The seed is hashed
+    /// from the transformation, so the instances at different places vary differently.
+    pub fn instance(&self, transform: impl Into<Transform>) -> Self {
+        self.instance_of(self.shape.clone(), transform.into())
+    }
+
+    /// Create an instance like `instance`, whose shape is the level of detail selected for the
+    /// instance seen from `camera`.
+    pub fn instance_lod(
+        &self,
+        lod: &Lod,
+        transform: impl Into<Transform>,
+        camera: &Camera,
+    ) -> Self {
+        let transform = transform.into();
+        self.instance_of(lod.select(&transform, camera), transform)
+    }
+
+    /// Create an instance of `shape` placed by `transform` with the properties of the object.
+    fn instance_of(&self, shape: Arc<dyn Bounded>, transform: Transform) -> Self {
+        let seed = transform
+            .matrix
+            .to_cols_array()
+            .iter()
+            .fold(self.seed, |seed, value| {
+                let bits = value.to_bits();
+                sampler::hash_combine(seed, (bits ^ (bits >> 32)) as u32)
+            });
+        Self {
+            shape: Arc::new(Instance::new(shape, transform)),
+            seed,
+            ..self.clone()
+        }
+    }
+
+    /// Set the random seed which textures and materials vary by.
+    pub fn seed(mut self, seed: u32) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Set the light group of emission from object.
+    pub fn light_group(mut self, group: &str) -> Self {
+        self.light_group = Some(Arc::from(group));
         self
     }
 }
 
 impl Hittable for Object {
-    /// Set the material for `rec` and call `intersect` of the member `shape`.
+    /// Set the material and light group for `rec` and call `intersect` of the member `shape`.
     fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
         self.shape.intersect(r, ray_t).map(|mut rec| {
             rec.material = Some(self.material.clone());
+            rec.light_group = self.light_group.clone();
+            rec.instance_seed = self.seed;
+            rec.object_id = self.id;
             rec
         })
     }
@@ -8,16 +8,17 @@ pub struct ONB {
 }
 
 impl ONB {
-    /// Create a new ortho-normal basis from given axis vector.
+    /// Create a new right-handed ortho-normal basis from given axis vector, which is continuous
+    /// except at -z and free of branches.
+    /// References:
+    /// Duff et al. 2017, Building an Orthonormal Basis, Revisited
     pub fn new(n: DVec3) -> Self {
         let w = n.normalize();
-        let r = if w.x.abs() > 0.9 {
-            DVec3::new(0.0, 1.0, 0.0)
-        } else {
-            DVec3::new(1.0, 0.0, 0.0)
-        };
-        let v = w.cross(r).normalize();
-        let u = w.cross(v);
+        let sign = 1.0_f64.copysign(w.z);
+        let a = -1.0 / (sign + w.z);
+        let b = w.x * w.y * a;
+        let u = DVec3::new(1.0 + sign * w.x * w.x * a, sign * b, -sign * w.x);
+        let v = DVec3::new(b, sign + w.y * w.y * a, -w.y);
         Self { u, v, w }
     }
 
@@ -25,4 +26,10 @@ impl ONB {
     pub fn transform(&self, vec: DVec3) -> DVec3 {
         vec.x * self.u + vec.y * self.v + vec.z * self.w
     }
+
+    /// Transform vec into the local coordinates of ortho-normal basis, which is the inverse of
+    /// `transform`.
+    pub fn to_local(&self, vec: DVec3) -> DVec3 {
+        DVec3::new(vec.dot(self.u), vec.dot(self.v), vec.dot(self.w))
+    }
 }
@@ -0,0 +1,177 @@
+use std::{io, path::Path, time::Duration};
+
+use exr::prelude::*;
+use glam::DVec3;
+
+use crate::{aov::DEPTH_AOV, buffer::Buffer, color::Color, renderer::Renderer};
+
+/// The metadata of a render which is written as the attributes of EXR file, so that the
+/// pipeline can tell how an image was made.
+#[derive(Clone, Debug)]
+pub struct RenderMetadata {
+    /// The average number of samples per pixel.
+    pub samples: f64,
+
+    /// The wall time of rendering, `None` if unknown.
+    pub render_time: Option<Duration>,
+
+    /// Whether the random numbers are seeded by tile and pass, i.e. the image is reproducible,
+    /// see `Renderer::deterministic`. The seeds of other renders come from the OS.
+    pub deterministic: bool,
+
+    /// The maximum number of bounces of paths.
+    pub max_bounces: u32,
+
+    /// Whether all paths are traced to `max_bounces`, see `Renderer::fixed_depth`.
+    pub fixed_depth: bool,
+
+    /// The depth from which paths are terminated by Russian roulette, `None` if disabled.
+    pub roulette_depth: Option<u32>,
+
+    /// The origin of camera.
+    pub camera_origin: DVec3,
+
+    /// The right, up and backward axes of camera.
+    pub camera_axes: [DVec3; 3],
+
+    /// The vertical field of view of camera in degrees.
+    pub camera_fov: f64,
+
+    /// The distance from the camera to the plane in focus.
+    pub focus_distance: f64,
+
+    /// The radius of the lens for depth of field.
+    pub lens_radius: f64,
+}
+
+impl RenderMetadata {
+    /// Collect the metadata of the `buffer` rendered by `renderer`.
+    pub fn new(renderer: &Renderer, buffer: &Buffer) -> Self {
+        let cam = &renderer.cam;
+        let c_z = cam.c_x.cross(cam.c_y);
+        let center = cam.film_point(0.5, 0.5);
+        let focus_distance = (cam.origin - center).dot(c_z);
+        let (width, height) = buffer.dimensions();
+        let total: u64 = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| buffer.sample_count(x, y) as u64)
+            .sum();
+        Self {
+            samples: total as f64 / (width as f64 * height as f64).max(1.0),
+            render_time: None,
+            deterministic: renderer.deterministic,
+            max_bounces: renderer.max_bounces,
+            fixed_depth: renderer.fixed_depth,
+            roulette_depth: renderer.effective_roulette_depth(),
+            camera_origin: cam.origin,
+            camera_axes: [cam.c_x, cam.c_y, c_z],
+            camera_fov: 2.0
+                * (0.5 * cam.viewport_height / focus_distance)
+                    .atan()
+                    .to_degrees(),
+            focus_distance,
+            lens_radius: cam.lens_radius,
+        }
+    }
+
+    /// Set the wall time of rendering.
+    pub const fn render_time(mut self, time: Duration) -> Self {
+        self.render_time = Some(time);
+        self
+    }
+
+    /// Get the matrix from world space to camera space for the standard `worldToCamera`
+    /// attribute, which transforms the row vectors like Imath, i.e. the translation is the last
+    /// row.
+    fn world_to_camera(&self) -> [f32; 16] {
+        // The columns are the camera axes, and the translation moves the origin to zero.
+        let mut matrix = [0.0; 16];
+        for (col, axis) in self.camera_axes.iter().enumerate() {
+            for (row, value) in axis.to_array().into_iter().enumerate() {
+                matrix[4 * row + col] = value as f32;
+            }
+            matrix[12 + col] = -axis.dot(self.camera_origin) as f32;
+        }
+        matrix[15] = 1.0;
+        matrix
+    }
+
+    /// Add the metadata to the attributes of the EXR layer.
+    pub(crate) fn write_to(&self, attributes: &mut LayerAttributes) {
+        attributes.software_name = Some(Text::from(env!("CARGO_PKG_NAME")));
+        attributes.world_to_camera = Some(self.world_to_camera());
+        attributes.focus = Some(self.focus_distance as f32);
+        let vec3 = |v: DVec3| AttributeValue::FloatVec3((v.x as f32, v.y as f32, v.z as f32));
+        let mut values = vec![
+            ("samplesPerPixel", AttributeValue::F32(self.samples as f32)),
+            (
+                "deterministic",
+                AttributeValue::I32(self.deterministic as i32),
+            ),
+            ("maxBounces", AttributeValue::I32(self.max_bounces as i32)),
+            ("fixedDepth", AttributeValue::I32(self.fixed_depth as i32)),
+            ("cameraOrigin", vec3(self.camera_origin)),
+            ("cameraFov", AttributeValue::F32(self.camera_fov as f32)),
+            ("lensRadius", AttributeValue::F32(self.lens_radius as f32)),
+        ];
+        if let Some(depth) = self.roulette_depth {
+            values.push(("rouletteDepth", AttributeValue::I32(depth as i32)));
+        }
+        if let Some(time) = self.render_time {
+            values.push(("renderTime", AttributeValue::F32(time.as_secs_f32())));
+        }
+        for (name, value) in values {
+            attributes.other.insert(Text::from(name), value);
+        }
+    }
+}
+
+/// Save the linear radiance and all AOVs of the buffer as a single EXR file with 32-bit float
+/// channels. The radiance is the default `R`, `G`, `B` layer, and each AOV is the layer of
+/// its name, e.g. `lightgroup.key.R`, except that the depth AOV is the single `Z` channel. The
+/// metadata if any is written as header attributes.
+pub fn save_exr<P: AsRef<Path>>(
+    buffer: &Buffer,
+    metadata: Option<&RenderMetadata>,
+    path: P,
+) -> io::Result<()> {
+    let (width, height) = buffer.dimensions();
+    let mut channels = SmallVec::new();
+    // Add the first channels of colors named by `names` after `prefix`.
+    let mut add_layer = |prefix: &str, names: &[&str], colors: Vec<Color>| {
+        for (i, channel) in names.iter().enumerate() {
+            let samples = colors.iter().map(|c| c[i] as f32).collect();
+            channels.push(AnyChannel::new(
+                format!("{prefix}{channel}").as_str(),
+                FlatSamples::F32(samples),
+            ));
+        }
+    };
+    add_layer("", &["R", "G", "B"], buffer.colors());
+    for name in buffer.aov_names() {
+        let Some(colors) = buffer.aov_colors(name) else {
+            continue;
+        };
+        if name == DEPTH_AOV {
+            // The depth is the single `Z` channel which compositors recognize.
+            add_layer("", &["Z"], colors);
+        } else {
+            add_layer(&format!("{name}."), &["R", "G", "B"], colors);
+        }
+    }
+
+    let mut attributes = LayerAttributes::default();
+    if let Some(metadata) = metadata {
+        metadata.write_to(&mut attributes);
+    }
+    let layer = Layer::new(
+        (width as usize, height as usize),
+        attributes,
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(channels),
+    );
+    Image::from_layer(layer)
+        .write()
+        .to_file(path)
+        .map_err(|err| io::Error::other(err.to_string()))
+}
@@ -0,0 +1,592 @@
+use std::{
+    f64, fs,
+    io::{self, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use image::{Rgb, RgbImage};
+
+use lens::LensFlare;
+
+use crate::{
+    buffer::Buffer,
+    color::{self, Color},
+    sampler,
+};
+
+pub mod lens;
+
+/// A 3D color lookup table which maps display encoded colors to display encoded colors, e.g.
+/// the look of a show or the response of a film stock.
+pub struct Lut3d {
+    /// The number of entries along each axis.
+    size: usize,
+    /// The input color which maps to the first entry.
+    domain_min: Color,
+    /// The input color which maps to the last entry.
+    domain_max: Color,
+    /// The output colors with red changing fastest, then green and blue.
+    table: Vec<Color>,
+}
+
+impl Lut3d {
+    /// Load a LUT from an Adobe/Resolve `.cube` file.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse a LUT from the content of a `.cube` file. Only 3D LUTs are supported.
+    pub fn parse(content: &str) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let triple = |fields: &[&str]| -> io::Result<Color> {
+            let values = fields
+                .iter()
+                .map(|field| field.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| invalid(format!("Invalid number in cube file: {err}")))?;
+            match values[..] {
+                [r, g, b] => Ok(Color::new(r, g, b)),
+                _ => Err(invalid(format!("Expect 3 values, got {}", values.len()))),
+            }
+        };
+
+        let mut size = None;
+        let mut domain_min = Color::ZERO;
+        let mut domain_max = Color::ONE;
+        let mut table = Vec::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[0] {
+                "TITLE" => {}
+                "LUT_3D_SIZE" => {
+                    let n = fields
+                        .get(1)
+                        .and_then(|n| n.parse::<usize>().ok())
+                        .filter(|&n| n >= 2)
+                        .ok_or_else(|| invalid(format!("Invalid LUT size: {line}")))?;
+                    size = Some(n);
+                }
+                "LUT_1D_SIZE" => return Err(invalid("1D LUT is not supported".to_string())),
+                "DOMAIN_MIN" => domain_min = triple(&fields[1..])?,
+                "DOMAIN_MAX" => domain_max = triple(&fields[1..])?,
+                _ => table.push(triple(&fields)?),
+            }
+        }
+        let size = size.ok_or_else(|| invalid("Missing LUT_3D_SIZE".to_string()))?;
+        if table.len() != size.pow(3) {
+            return Err(invalid(format!(
+                "Expect {} entries, got {}",
+                size.pow(3),
+                table.len()
+            )));
+        }
+        Ok(Self {
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    /// Look up the color by trilinear interpolation, the colors out of domain are clamped.
+    pub fn apply(&self, color: Color) -> Color {
+        let max = (self.size - 1) as f64;
+        let p = ((color - self.domain_min) / (self.domain_max - self.domain_min))
+            .clamp(Color::ZERO, Color::ONE)
+            * max;
+        let p0 = p.floor().min(Color::splat(max - 1.0));
+        let t = p - p0;
+        let [x, y, z] = p0.to_array().map(|v| v as usize);
+        let at = |dx: usize, dy: usize, dz: usize| -> Color {
+            self.table[((z + dz) * self.size + y + dy) * self.size + x + dx]
+        };
+        let lerp_x = |dy, dz| at(0, dy, dz).lerp(at(1, dy, dz), t.x);
+        let lerp_y = |dz| lerp_x(0, dz).lerp(lerp_x(1, dz), t.y);
+        lerp_y(0).lerp(lerp_y(1), t.z)
+    }
+}
+
+/// The range of log2 luminance covered by the histogram of auto exposure.
+const HISTOGRAM_EV: (f64, f64) = (-20.0, 20.0);
+
+/// The number of bins of the histogram of auto exposure.
+const HISTOGRAM_BINS: usize = 256;
+
+/// The automatic exposure which maps the log-average luminance of the image to a middle grey
+/// `key`. The darkest and brightest pixels are clipped by percentiles, so that a small light
+/// source or a black background doesn't dominate the exposure.
+#[derive(Clone, Copy)]
+pub struct AutoExposure {
+    /// The luminance to which the log-average luminance is mapped.
+    pub key: f64,
+
+    /// The fraction of darkest pixels which are ignored.
+    pub low_percentile: f64,
+
+    /// The fraction of pixels below which the luminance is counted, the brighter pixels are
+    /// ignored.
+    pub high_percentile: f64,
+}
+
+impl AutoExposure {
+    /// Create auto exposure with middle grey key and 5% clipping on both ends.
+    pub const fn new() -> Self {
+        Self {
+            key: 0.18,
+            low_percentile: 0.05,
+            high_percentile: 0.95,
+        }
+    }
+
+    /// Set the luminance to which the log-average luminance is mapped.
+    pub const fn key(mut self, key: f64) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Set the fractions of darkest pixels and of pixels below the brightest ignored ones.
+    pub const fn percentiles(mut self, low: f64, high: f64) -> Self {
+        self.low_percentile = low;
+        self.high_percentile = high;
+        self
+    }
+
+    /// Get the exposure in stops for the linear colors of an image from the histogram of
+    /// their log2 luminance. Black pixels are ignored, and zero is returned if all pixels are
+    /// black.
+    pub fn exposure(&self, colors: &[Color]) -> f64 {
+        let (min_ev, max_ev) = HISTOGRAM_EV;
+        let bin_width = (max_ev - min_ev) / HISTOGRAM_BINS as f64;
+        let mut histogram = [0u32; HISTOGRAM_BINS];
+        for &color in colors {
+            let lum = color::luminance(color);
+            if lum > 0.0 && lum.is_finite() {
+                let bin = ((lum.log2() - min_ev) / bin_width) as isize;
+                histogram[bin.clamp(0, HISTOGRAM_BINS as isize - 1) as usize] += 1;
+            }
+        }
+        let total: u32 = histogram.iter().sum();
+        if total == 0 {
+            return 0.0;
+        }
+        // Average the bin centers between the percentiles, the bins on the bounds are counted
+        // partially.
+        let low = self.low_percentile.clamp(0.0, 1.0) * total as f64;
+        let high = self.high_percentile.clamp(0.0, 1.0).max(low / total as f64) * total as f64;
+        let (mut below, mut sum, mut count) = (0.0, 0.0, 0.0);
+        for (i, &n) in histogram.iter().enumerate() {
+            let n = n as f64;
+            let kept = (below + n).min(high) - below.max(low);
+            if kept > 0.0 {
+                sum += kept * (min_ev + (i as f64 + 0.5) * bin_width);
+                count += kept;
+            }
+            below += n;
+        }
+        let log_average = if count > 0.0 {
+            sum / count
+        } else {
+            // The percentiles are equal, take the bin at the percentile.
+            let mut below = 0.0;
+            let bin = histogram
+                .iter()
+                .position(|&n| {
+                    below += n as f64;
+                    below >= low
+                })
+                .unwrap_or(HISTOGRAM_BINS - 1);
+            min_ev + (bin as f64 + 0.5) * bin_width
+        };
+        self.key.log2() - log_average
+    }
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The luminance of middle grey which the exposure checks are relative to.
+const MIDDLE_GREY: f64 = 0.18;
+
+/// The bands of false color by the exposed luminance in stops above middle grey. Each band is
+/// below its bound, and the pixels with any clipped channel are red regardless of luminance.
+const FALSE_COLOR_BANDS: [(f64, [u8; 3]); 7] = [
+    // Crushed blacks.
+    (-6.0, [96, 0, 128]),
+    (-4.0, [0, 64, 255]),
+    (-2.0, [0, 160, 160]),
+    (-0.5, [96, 96, 96]),
+    // Middle grey.
+    (0.5, [0, 192, 0]),
+    (2.0, [192, 192, 192]),
+    (f64::INFINITY, [255, 224, 0]),
+];
+
+/// The color of false color for the clipped pixels.
+const CLIPPED_COLOR: [u8; 3] = [255, 0, 0];
+
+/// The range of stops above middle grey covered by the printed histogram, one bin per stop.
+const PRINTED_HISTOGRAM_EV: (i32, i32) = (-10, 4);
+
+/// The width of the longest bar of the printed histogram in characters.
+const PRINTED_HISTOGRAM_WIDTH: usize = 50;
+
+/// The dithering of the quantization to 8 bits, which trades the banding of smooth gradients,
+/// e.g. skies and soft shadows, for fine noise.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dither {
+    /// Quantize each pixel independently.
+    #[default]
+    None,
+
+    /// Add the noise of triangular distribution over ±1 level, whose error is independent of
+    /// the color. The noise is fixed for each pixel, so that progressive images don't flicker.
+    Triangular,
+
+    /// Diffuse the error of each pixel to the following neighbors (Floyd-Steinberg in
+    /// serpentine order), which pushes the noise to high frequencies like blue noise.
+    ErrorDiffusion,
+}
+
+impl Dither {
+    /// Quantize the display colors of an image in (y * width + x) order to rgb bytes.
+    pub fn quantize(self, colors: &[Color], width: u32) -> Vec<u8> {
+        match self {
+            Self::None => colors.iter().flat_map(|&c| color::quantize(c)).collect(),
+            Self::Triangular => {
+                let to_unit = |bits: u64| (bits & 0xffff_ffff) as f64 / (1u64 << 32) as f64;
+                colors
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(i, &c)| {
+                        let (x, y) = (i as u32 % width, i as u32 / width);
+                        let noise = Color::from_array([0, 1, 2].map(|channel| {
+                            let bits = sampler::seed(x, y, channel);
+                            to_unit(bits) + to_unit(bits >> 32) - 1.0
+                        }));
+                        color::quantize(c + noise / 256.0)
+                    })
+                    .collect()
+            }
+            Self::ErrorDiffusion => error_diffusion(colors, width as usize),
+        }
+    }
+}
+
+/// Quantize the colors by Floyd-Steinberg error diffusion. The levels are in the scale of
+/// `color::quantize`, i.e. the color c is the level 256c - 0.5 rounded to nearest.
+fn error_diffusion(colors: &[Color], width: usize) -> Vec<u8> {
+    let mut levels: Vec<Color> = colors.iter().map(|&c| 256.0 * c - 0.5).collect();
+    let mut bytes = vec![0; 3 * colors.len()];
+    let height = colors.len() / width.max(1);
+    for y in 0..height {
+        // Alternate the direction of rows, so that the error doesn't drift to one side.
+        let dir = if y % 2 == 0 { 1 } else { -1 };
+        for k in 0..width {
+            let x = if dir > 0 { k } else { width - 1 - k };
+            let i = y * width + x;
+            let quantized = levels[i].round().clamp(Color::ZERO, Color::splat(255.0));
+            let error = levels[i] - quantized;
+            bytes[3 * i..3 * i + 3].copy_from_slice(&quantized.to_array().map(|c| c as u8));
+            let mut diffuse = |dx: isize, dy: usize, weight: f64| {
+                let nx = x as isize + dx * dir;
+                if (0..width as isize).contains(&nx) && y + dy < height {
+                    levels[(y + dy) * width + nx as usize] += error * weight;
+                }
+            };
+            diffuse(1, 0, 7.0 / 16.0);
+            diffuse(-1, 1, 3.0 / 16.0);
+            diffuse(0, 1, 5.0 / 16.0);
+            diffuse(1, 1, 1.0 / 16.0);
+        }
+    }
+    bytes
+}
+
+/// The ISO at which the exposed value 1.0 fills the full well of the sensor.
+const BASE_ISO: f64 = 100.0;
+
+/// The noise of a digital camera sensor, which matches the grain of renders to photographed
+/// plates. The exposed linear colors are counted as photo-electrons, where the value 1.0 fills
+/// the full well at base ISO 100, and a higher ISO amplifies fewer electrons to the same
+/// value. The photon shot noise is Poisson distributed over the electrons, and the read noise
+/// of the electronics is added regardless of the signal, both approximated by Gaussians, so
+/// that the shadows of high ISO are the noisiest.
+/// The noise is fixed for each pixel and `seed`, so that progressive images don't flicker,
+/// and the seed is changed for the frames of animation.
+/// References:
+/// Foi et al. 2008, Practical Poissonian-Gaussian Noise Modeling and Fitting for Single-Image
+/// Raw-Data
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SensorNoise {
+    /// The sensitivity of the sensor.
+    pub iso: f64,
+
+    /// The number of electrons which saturate a photosite.
+    pub full_well: f64,
+
+    /// The standard deviation of the read noise in electrons.
+    pub read_noise: f64,
+
+    /// The seed of the noise pattern.
+    pub seed: u32,
+}
+
+impl SensorNoise {
+    /// Create the noise of a full frame sensor at `iso`, whose photosites hold 30000 electrons
+    /// with the read noise of 3 electrons.
+    pub const fn new(iso: f64) -> Self {
+        Self {
+            iso,
+            full_well: 30000.0,
+            read_noise: 3.0,
+            seed: 0,
+        }
+    }
+
+    /// Set the number of electrons which saturate a photosite, the smaller the noisier, e.g.
+    /// about 5000 of phone cameras.
+    pub const fn full_well(mut self, electrons: f64) -> Self {
+        self.full_well = electrons;
+        self
+    }
+
+    /// Set the standard deviation of the read noise in electrons.
+    pub const fn read_noise(mut self, electrons: f64) -> Self {
+        self.read_noise = electrons;
+        self
+    }
+
+    /// Set the seed of the noise pattern.
+    pub const fn seed(mut self, seed: u32) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Add the noise to the exposed linear color of the pixel (x, y).
+    pub fn apply(&self, color: Color, x: u32, y: u32) -> Color {
+        // The electrons of the exposed value 1.0.
+        let scale = self.full_well * BASE_ISO / self.iso.max(f64::MIN_POSITIVE);
+        if scale <= 0.0 || !scale.is_finite() {
+            return color;
+        }
+        let to_unit = |bits: u64| (bits >> 11) as f64 / (1u64 << 53) as f64;
+        Color::from_array([0, 1, 2].map(|channel| {
+            let electrons = color[channel].max(0.0) * scale;
+            let deviation = (electrons + self.read_noise * self.read_noise).sqrt();
+            // A standard normal number by Box-Muller transform.
+            let bits = sampler::seed(x, y, sampler::hash_combine(self.seed, channel as u32));
+            let (u1, u2) = (
+                1.0 - to_unit(bits),
+                to_unit(sampler::seed(x, y, bits as u32)),
+            );
+            let normal = (-2.0 * u1.ln()).sqrt() * (f64::consts::TAU * u2).cos();
+            (electrons + deviation * normal) / scale
+        }))
+    }
+}
+
+/// The post-processing stage which converts the linear radiance of the buffer to the display
+/// colors of output image.
+#[derive(Clone)]
+pub struct PostProcess {
+    /// The exposure adjustment in stops, which is added to the automatic exposure if any.
+    pub exposure: f64,
+
+    /// The automatic exposure computed from the image, `None` for manual exposure only.
+    pub auto_exposure: Option<AutoExposure>,
+
+    /// The LUT applied to the gamma corrected colors.
+    pub lut: Option<Arc<Lut3d>>,
+
+    /// The dithering of the quantization of output image.
+    pub dither: Dither,
+
+    /// The noise of camera sensor added to the exposed colors, `None` for clean images.
+    pub noise: Option<SensorNoise>,
+
+    /// The strength of the lateral chromatic aberration of the exposed colors, 0.0 for none,
+    /// see `lens::chromatic_aberration`.
+    pub aberration: f64,
+
+    /// The lens flare added to the exposed colors, `None` for no flare.
+    pub flare: Option<LensFlare>,
+}
+
+impl Default for PostProcess {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PostProcess {
+    /// Create a post-process without any adjustment, which outputs the same image as
+    /// `Buffer::image`.
+    pub const fn new() -> Self {
+        Self {
+            exposure: 0.0,
+            auto_exposure: None,
+            lut: None,
+            dither: Dither::None,
+            noise: None,
+            aberration: 0.0,
+            flare: None,
+        }
+    }
+
+    /// Set the exposure adjustment in stops, e.g. 1.0 doubles the brightness.
+    pub const fn exposure(mut self, stops: f64) -> Self {
+        self.exposure = stops;
+        self
+    }
+
+    /// Set the automatic exposure computed from the image.
+    pub const fn auto_exposure(mut self, auto: AutoExposure) -> Self {
+        self.auto_exposure = Some(auto);
+        self
+    }
+
+    /// Set the LUT applied to the gamma corrected colors.
+    pub fn lut(mut self, lut: Lut3d) -> Self {
+        self.lut = Some(Arc::new(lut));
+        self
+    }
+
+    /// Set the dithering of the quantization of output image.
+    pub const fn dither(mut self, dither: Dither) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    /// Set the noise of camera sensor added to the exposed colors.
+    pub const fn sensor_noise(mut self, noise: SensorNoise) -> Self {
+        self.noise = Some(noise);
+        self
+    }
+
+    /// Set the strength of the lateral chromatic aberration, i.e. the difference of the
+    /// magnification of red and blue from green, e.g. 0.002 for a cheap wide lens.
+    pub const fn chromatic_aberration(mut self, strength: f64) -> Self {
+        self.aberration = strength;
+        self
+    }
+
+    /// Set the lens flare added to the exposed colors.
+    pub const fn lens_flare(mut self, flare: LensFlare) -> Self {
+        self.flare = Some(flare);
+        self
+    }
+
+    /// Get the total exposure in stops for the linear colors of an image.
+    pub fn total_exposure(&self, colors: &[Color]) -> f64 {
+        let auto = self.auto_exposure.map_or(0.0, |auto| auto.exposure(colors));
+        self.exposure + auto
+    }
+
+    /// Convert a linear color to the pixel of output image with the manual exposure only. The
+    /// single pixel is neither noisy nor dithered.
+    pub fn pixel(&self, color: Color) -> [u8; 3] {
+        color::quantize(self.display(color, self.exposure))
+    }
+
+    /// Convert a linear color to the display color with the total `exposure` in stops.
+    fn display(&self, color: Color, exposure: f64) -> Color {
+        let mut display = color::gamma_encode(color * exposure.exp2());
+        if let Some(lut) = &self.lut {
+            display = lut.apply(display);
+        }
+        display
+    }
+
+    /// Visualize the exposed luminance of the buffer in false color bands to check the
+    /// lighting levels before a long final render: purple for crushed blacks, blue to teal for
+    /// shadows, green around middle grey, light grey and yellow for highlights, and red for
+    /// clipped pixels.
+    pub fn false_color(&self, buffer: &Buffer) -> RgbImage {
+        let colors = buffer.colors();
+        let scale = self.total_exposure(&colors).exp2();
+        let (width, height) = buffer.dimensions();
+        RgbImage::from_fn(width, height, |x, y| {
+            let color = colors[(y * width + x) as usize] * scale;
+            if color.max_element() >= 1.0 {
+                return Rgb(CLIPPED_COLOR);
+            }
+            let stops = (color::luminance(color) / MIDDLE_GREY).log2();
+            let (_, rgb) = FALSE_COLOR_BANDS
+                .iter()
+                .find(|(bound, _)| stops < *bound)
+                .unwrap_or(&FALSE_COLOR_BANDS[FALSE_COLOR_BANDS.len() - 1]);
+            Rgb(*rgb)
+        })
+    }
+
+    /// Print the histogram of the exposed luminance of the buffer in stops above middle grey
+    /// as text, followed by the fractions of black and clipped pixels. The pixels out of the
+    /// range are counted in the first and the last bins.
+    pub fn write_histogram<W: Write>(&self, buffer: &Buffer, mut writer: W) -> io::Result<()> {
+        let colors = buffer.colors();
+        let scale = self.total_exposure(&colors).exp2();
+        let (min_ev, max_ev) = PRINTED_HISTOGRAM_EV;
+        let num_bins = (max_ev - min_ev) as i64;
+        let mut histogram = vec![0usize; num_bins as usize];
+        let (mut black, mut clipped) = (0, 0);
+        for &color in &colors {
+            let color = color * scale;
+            let lum = color::luminance(color);
+            if color.max_element() >= 1.0 {
+                clipped += 1;
+            }
+            if lum > 0.0 && lum.is_finite() {
+                let bin = (lum / MIDDLE_GREY).log2().floor() as i64 - min_ev as i64;
+                histogram[bin.clamp(0, num_bins - 1) as usize] += 1;
+            } else {
+                black += 1;
+            }
+        }
+        let total = colors.len().max(1) as f64;
+        let most = histogram.iter().copied().max().unwrap_or(0).max(1);
+        for (i, &n) in histogram.iter().enumerate().rev() {
+            let bar = "#".repeat(n * PRINTED_HISTOGRAM_WIDTH / most);
+            let ev = min_ev + i as i32;
+            let line = format!("{ev:+4} EV {:6.2}% {bar}", 100.0 * n as f64 / total);
+            writeln!(writer, "{}", line.trim_end())?;
+        }
+        writeln!(writer, "black   {:6.2}%", 100.0 * black as f64 / total)?;
+        writeln!(writer, "clipped {:6.2}%", 100.0 * clipped as f64 / total)
+    }
+
+    /// Convert the buffer to the output image. The lens flare and the chromatic aberration are
+    /// applied to the exposed HDR colors before the sensor noise and the display encoding.
+    pub fn image(&self, buffer: &Buffer) -> RgbImage {
+        let colors = buffer.colors();
+        let scale = self.total_exposure(&colors).exp2();
+        let (width, height) = buffer.dimensions();
+        let mut exposed: Vec<Color> = colors.iter().map(|&c| c * scale).collect();
+        if let Some(flare) = &self.flare {
+            exposed = flare.apply(&exposed, width, height);
+        }
+        if self.aberration != 0.0 {
+            exposed = lens::chromatic_aberration(&exposed, width, height, self.aberration);
+        }
+        let display: Vec<Color> = exposed
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| {
+                let c = match &self.noise {
+                    Some(noise) => noise.apply(c, i as u32 % width, i as u32 / width),
+                    None => c,
+                };
+                self.display(c, 0.0)
+            })
+            .collect();
+        RgbImage::from_raw(width, height, self.dither.quantize(&display, width))
+            .expect("Incorrect image size.")
+    }
+}
@@ -0,0 +1,223 @@
+use std::f64;
+
+use crate::color::{self, Color};
+
+/// The factor by which the bright pass is downsampled before the flare is spread, which blurs
+/// the ghosts and streaks and keeps them cheap.
+const DOWNSAMPLE: u32 = 4;
+
+/// The tints of the successive ghosts, like the reflections between lens coatings.
+const GHOST_TINTS: [[f64; 3]; 4] = [
+    [1.0, 0.8, 0.4],
+    [0.4, 1.0, 0.6],
+    [0.5, 0.6, 1.0],
+    [1.0, 0.5, 0.9],
+];
+
+/// The lens flare spread from the bright pixels of the exposed image: the ghosts reflected by
+/// the lens elements on the line through the light and the center of the frame, and the
+/// streaks of a star filter or the diffraction of aperture blades. Only the light above
+/// `threshold` flares, so that the flare follows the light sources and their highlights.
+/// References:
+/// Chapman 2013, Pseudo Lens Flare
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LensFlare {
+    /// The exposed luminance above which the light flares, 1.0 is the clipping of display.
+    pub threshold: f64,
+
+    /// The number of ghosts.
+    pub ghosts: u32,
+
+    /// The step of the magnification of the successive ghosts through the center, whose
+    /// ghosts alternate between the mirrored side and the side of the light.
+    pub ghost_spacing: f64,
+
+    /// The fraction of the bright light which is reflected to each ghost.
+    pub ghost_intensity: f64,
+
+    /// The number of arms of the streaks, 0 for no streaks.
+    pub streaks: u32,
+
+    /// The length of the streaks as the fraction of the image width.
+    pub streak_length: f64,
+
+    /// The fraction of the bright light which is spread to all arms of the streaks.
+    pub streak_intensity: f64,
+
+    /// The angle of the first arm of the streaks in radians counterclockwise from the x axis.
+    pub streak_angle: f64,
+}
+
+impl LensFlare {
+    /// Create the flare of four ghosts and a star of six arms from the light above
+    /// `threshold`.
+    pub const fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            ghosts: 4,
+            ghost_spacing: 0.4,
+            ghost_intensity: 0.02,
+            streaks: 6,
+            streak_length: 0.15,
+            streak_intensity: 0.1,
+            streak_angle: f64::consts::FRAC_PI_6 / 2.0,
+        }
+    }
+
+    /// Set the number of ghosts, the step of their magnification through the center and the
+    /// fraction of the bright light reflected to each of them.
+    pub const fn ghosts(mut self, count: u32, spacing: f64, intensity: f64) -> Self {
+        self.ghosts = count;
+        self.ghost_spacing = spacing;
+        self.ghost_intensity = intensity;
+        self
+    }
+
+    /// Set the number of arms of the streaks, their length as the fraction of the image width
+    /// and the fraction of the bright light spread to them.
+    pub const fn streaks(mut self, arms: u32, length: f64, intensity: f64) -> Self {
+        self.streaks = arms;
+        self.streak_length = length;
+        self.streak_intensity = intensity;
+        self
+    }
+
+    /// Set the angle of the first arm of the streaks in radians.
+    pub const fn streak_angle(mut self, angle: f64) -> Self {
+        self.streak_angle = angle;
+        self
+    }
+
+    /// Add the flare to the exposed linear colors of an image in (y * width + x) order.
+    pub fn apply(&self, colors: &[Color], width: u32, height: u32) -> Vec<Color> {
+        let (w, h) = (width.div_ceil(DOWNSAMPLE), height.div_ceil(DOWNSAMPLE));
+        let bright = self.bright_pass(colors, width, height);
+        let mut flare = vec![Color::ZERO; bright.len()];
+
+        // The ghost k is the image of the light scaled through the center c by the
+        // magnification m, which alternates between the mirrored side and the side of the
+        // light, i.e. the pixel p samples the light at s = c + (p - c) / m. The magnified
+        // ghosts are dimmed by the area they spread over, and the ghosts of the light near the
+        // border fade out.
+        if self.ghosts > 0 && self.ghost_intensity > 0.0 {
+            let (cx, cy) = (w as f64 / 2.0, h as f64 / 2.0);
+            for (i, pixel) in flare.iter_mut().enumerate() {
+                let (x, y) = ((i as u32 % w) as f64 + 0.5, (i as u32 / w) as f64 + 0.5);
+                for k in 1..=self.ghosts {
+                    let sign = if k % 2 == 1 { -1.0 } else { 1.0 };
+                    let m = sign * k as f64 * self.ghost_spacing;
+                    if m == 0.0 {
+                        continue;
+                    }
+                    let (sx, sy) = (cx + (x - cx) / m, cy + (y - cy) / m);
+                    if !(0.0..w as f64).contains(&sx) || !(0.0..h as f64).contains(&sy) {
+                        continue;
+                    }
+                    let distance = ((sx / cx - 1.0).powi(2) + (sy / cy - 1.0).powi(2)).sqrt();
+                    let falloff = (1.0 - distance / f64::consts::SQRT_2).max(0.0).powi(2);
+                    let tint = GHOST_TINTS[(k as usize - 1) % GHOST_TINTS.len()];
+                    let weight = falloff * self.ghost_intensity / (m * m).max(1.0);
+                    *pixel += bilinear(&bright, w, h, (sx, sy)) * Color::from_array(tint) * weight;
+                }
+            }
+        }
+
+        // Each arm smears the light along its direction with the exponential falloff, whose
+        // weights sum to the share of the arm.
+        let length = (self.streak_length * w as f64).round() as usize;
+        if self.streaks > 0 && self.streak_intensity > 0.0 && length > 0 {
+            let decay = |k: usize| (-4.0 * k as f64 / length as f64).exp();
+            let total: f64 = (1..=length).map(decay).sum();
+            let share = self.streak_intensity / (self.streaks as f64 * total);
+            for arm in 0..self.streaks {
+                let angle = self.streak_angle + f64::consts::TAU * arm as f64 / self.streaks as f64;
+                // The image y runs downwards.
+                let (dx, dy) = (angle.cos(), -angle.sin());
+                for (i, pixel) in flare.iter_mut().enumerate() {
+                    let (x, y) = ((i as u32 % w) as f64 + 0.5, (i as u32 / w) as f64 + 0.5);
+                    for k in 1..=length {
+                        let (sx, sy) = (x - dx * k as f64, y - dy * k as f64);
+                        if !(0.0..w as f64).contains(&sx) || !(0.0..h as f64).contains(&sy) {
+                            break;
+                        }
+                        *pixel += bilinear(&bright, w, h, (sx, sy)) * (decay(k) * share);
+                    }
+                }
+            }
+        }
+
+        colors
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| {
+                let (x, y) = ((i as u32 % width) as f64, (i as u32 / width) as f64);
+                let low = ((x + 0.5) / DOWNSAMPLE as f64, (y + 0.5) / DOWNSAMPLE as f64);
+                c + bilinear(&flare, w, h, low)
+            })
+            .collect()
+    }
+
+    /// Get the light above the threshold, downsampled by averaging blocks of pixels.
+    fn bright_pass(&self, colors: &[Color], width: u32, height: u32) -> Vec<Color> {
+        let (w, h) = (width.div_ceil(DOWNSAMPLE), height.div_ceil(DOWNSAMPLE));
+        let mut bright = vec![Color::ZERO; (w * h) as usize];
+        let mut counts = vec![0u32; bright.len()];
+        for (i, &c) in colors.iter().enumerate() {
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            let lum = color::luminance(c);
+            let j = ((y / DOWNSAMPLE) * w + x / DOWNSAMPLE) as usize;
+            if lum > self.threshold && lum.is_finite() {
+                bright[j] += c * ((lum - self.threshold) / lum);
+            }
+            counts[j] += 1;
+        }
+        for (c, n) in bright.iter_mut().zip(counts) {
+            *c /= n.max(1) as f64;
+        }
+        bright
+    }
+}
+
+/// Shift the red and blue channels of the linear colors of an image in (y * width + x) order
+/// radially from the center, like the lateral chromatic aberration of a lens whose
+/// magnification depends on the wavelength. The red image is magnified by `1 + strength` and
+/// the blue one by `1 - strength`, so that the edges towards the corners fringe red and blue.
+pub fn chromatic_aberration(
+    colors: &[Color],
+    width: u32,
+    height: u32,
+    strength: f64,
+) -> Vec<Color> {
+    let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+    colors
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| {
+            let (x, y) = (
+                (i as u32 % width) as f64 + 0.5,
+                (i as u32 / width) as f64 + 0.5,
+            );
+            let channel = |scale: f64, channel: usize| {
+                let p = (cx + (x - cx) / scale, cy + (y - cy) / scale);
+                bilinear(colors, width, height, p)[channel]
+            };
+            Color::new(channel(1.0 + strength, 0), c.y, channel(1.0 - strength, 2))
+        })
+        .collect()
+}
+
+/// Interpolate the colors of an image at the continuous position (x, y) whose pixel centers
+/// are at half integers, which is clamped to the edges.
+fn bilinear(colors: &[Color], width: u32, height: u32, (x, y): (f64, f64)) -> Color {
+    let (x, y) = (x - 0.5, y - 0.5);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (x - x0, y - y0);
+    let pixel = |i: f64, j: f64| {
+        let i = i.clamp(0.0, width as f64 - 1.0) as u32;
+        let j = j.clamp(0.0, height as f64 - 1.0) as u32;
+        colors[(j * width + i) as usize]
+    };
+    let top = pixel(x0, y0) * (1.0 - tx) + pixel(x0 + 1.0, y0) * tx;
+    let bottom = pixel(x0, y0 + 1.0) * (1.0 - tx) + pixel(x0 + 1.0, y0 + 1.0) * tx;
+    top * (1.0 - ty) + bottom * ty
+}
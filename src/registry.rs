@@ -0,0 +1,112 @@
+use std::{collections::HashMap, io, sync::Arc};
+
+use crate::{integrator::Integrator, material::Bsdf, shape::Bounded, texture::Texture};
+
+/// The parameters of a plugin in the scene file, i.e. the fields of its table except `type`.
+pub type Params = toml::Table;
+
+/// The function to create a plugin from its parameters.
+pub type Factory<T> = Arc<dyn Fn(&Params) -> io::Result<Arc<T>> + Send + Sync>;
+
+/// The custom implementations of `Bsdf`, `Texture`, shapes (`Bounded`) and `Integrator` which
+/// are created by name from scene files, so that downstream crates extend the renderer without
+/// forking it. A table in the scene file whose `type` isn't built-in is created by the factory
+/// registered with that name, e.g. `shape = { type = "torus", major = 1.0, minor = 0.25 }`,
+/// see `SceneLoader::registry`.
+///
+/// The plugins can't be saved to scene files, since the registry can't describe them back.
+#[derive(Default, Clone)]
+pub struct Registry {
+    /// The factories of BSDFs by name.
+    bsdfs: HashMap<String, Factory<dyn Bsdf>>,
+
+    /// The factories of textures by name.
+    textures: HashMap<String, Factory<dyn Texture>>,
+
+    /// The factories of shapes by name.
+    shapes: HashMap<String, Factory<dyn Bounded>>,
+
+    /// The factories of integrators by name.
+    integrators: HashMap<String, Factory<dyn Integrator>>,
+}
+
+impl Registry {
+    /// Create an empty registry, which only allows the built-in types.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the factory of BSDFs named `name`, replacing the previous one.
+    pub fn register_bsdf<F>(mut self, name: &str, factory: F) -> Self
+    where
+        F: Fn(&Params) -> io::Result<Arc<dyn Bsdf>> + Send + Sync + 'static,
+    {
+        self.bsdfs.insert(name.to_string(), Arc::new(factory));
+        self
+    }
+
+    /// Register the factory of textures named `name`, replacing the previous one.
+    pub fn register_texture<F>(mut self, name: &str, factory: F) -> Self
+    where
+        F: Fn(&Params) -> io::Result<Arc<dyn Texture>> + Send + Sync + 'static,
+    {
+        self.textures.insert(name.to_string(), Arc::new(factory));
+        self
+    }
+
+    /// Register the factory of shapes named `name`, replacing the previous one. The plugin
+    /// shapes can't be area lights.
+    pub fn register_shape<F>(mut self, name: &str, factory: F) -> Self
+    where
+        F: Fn(&Params) -> io::Result<Arc<dyn Bounded>> + Send + Sync + 'static,
+    {
+        self.shapes.insert(name.to_string(), Arc::new(factory));
+        self
+    }
+
+    /// Register the factory of integrators named `name`, replacing the previous one.
+    pub fn register_integrator<F>(mut self, name: &str, factory: F) -> Self
+    where
+        F: Fn(&Params) -> io::Result<Arc<dyn Integrator>> + Send + Sync + 'static,
+    {
+        self.integrators.insert(name.to_string(), Arc::new(factory));
+        self
+    }
+
+    /// Create the BSDF registered as `name` from `params`.
+    pub fn bsdf(&self, name: &str, params: &Params) -> io::Result<Arc<dyn Bsdf>> {
+        create(&self.bsdfs, "material", name, params)
+    }
+
+    /// Create the texture registered as `name` from `params`.
+    pub fn texture(&self, name: &str, params: &Params) -> io::Result<Arc<dyn Texture>> {
+        create(&self.textures, "texture", name, params)
+    }
+
+    /// Create the shape registered as `name` from `params`.
+    pub fn shape(&self, name: &str, params: &Params) -> io::Result<Arc<dyn Bounded>> {
+        create(&self.shapes, "shape", name, params)
+    }
+
+    /// Create the integrator registered as `name` from `params`.
+    pub fn integrator(&self, name: &str, params: &Params) -> io::Result<Arc<dyn Integrator>> {
+        create(&self.integrators, "integrator", name, params)
+    }
+}
+
+/// Create the plugin of `kind` registered as `name` in `factories`.
+fn create<T: ?Sized>(
+    factories: &HashMap<String, Factory<T>>,
+    kind: &str,
+    name: &str,
+    params: &Params,
+) -> io::Result<Arc<T>> {
+    let factory = factories.get(name).ok_or_else(|| {
+        // The built-in types with invalid fields are parsed as plugins as well.
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unknown {kind} type `{name}`, or invalid fields of the built-in type"),
+        )
+    })?;
+    factory(params)
+}
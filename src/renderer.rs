@@ -1,21 +1,71 @@
-use std::f64;
+use std::{
+    cell::RefCell,
+    f64,
+    io::{self, Write},
+    ops::Range,
+    path::Path,
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
 
 use glam::DVec3;
 use image::RgbImage;
 use indicatif::{ProgressBar, ProgressStyle};
-use rand::SeedableRng;
 use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 
-use crate::buffer::Buffer;
+use crate::aov::{
+    ALBEDO_AOV, DEPTH_AOV, DepthEncoding, FirstHit, MOTION_AOV, NORMAL_AOV, POSITION_AOV,
+};
+use crate::buffer::{Buffer, SplatBuffer};
+use crate::bvh::packet::Frustum;
+use crate::bvh::{Bvh, TraversalMetric, TraversalStats};
 use crate::camera::Camera;
+use crate::clamp::IndirectClamp;
 use crate::color::{self, Color};
+use crate::convergence::Convergence;
+use crate::filter::Filter;
+use crate::guiding::PathGuide;
+use crate::handle::RenderHandle;
+use crate::imgdiff;
 use crate::interval::Interval;
-use crate::light::Light;
-use crate::math::Ray;
-use crate::math::random;
-use crate::scene::Scene;
-use crate::shape::{HitRecord, Hittable};
+use crate::irradiance::IrradianceCache;
+use crate::light::{Light, LightSelection};
+use crate::lightcull::LightCulling;
+use crate::lightpath::{self, BounceRecord, LightPath, LightRecord, PathEvent, PathVertex};
+use crate::material::{Bsdf, BsdfSample, Material};
+use crate::math::{DPoint3, Ray};
+use crate::openexr::RenderMetadata;
+use crate::post::PostProcess;
+use crate::sampler;
+use crate::scene::{BACKGROUND_LIGHT_GROUP, Scene};
+use crate::shadow::{ShadowBatch, ShadowRay};
+use crate::shape::{HitRecord, Hittable, Sampleable};
+use crate::temporal::FrameHistory;
+use crate::tile::{self, Tile, TileOrder, TileResult};
+use crate::watchdog::{StallReport, StalledRay, Watchdog};
+use crate::writer::ImageWriter;
+
+/// The roughness below which a bounce is considered glossy or specular for regularization.
+const GLOSSY_ROUGHNESS: f64 = 0.3;
+
+/// The width and height in pixels of the pixel blocks traced as packets.
+const PACKET_SIZE: u32 = 8;
+
+/// The length of the last segment of recorded paths which escape to the background, relative
+/// to the scene scale.
+const ESCAPE_LENGTH: f64 = 10.0;
 
 pub struct Renderer {
     /// The camera to use
@@ -30,11 +80,323 @@ pub struct Renderer {
     /// The height of output image
     pub height: u32,
 
+    /// The factor by which the film is traced at reduced resolution and upscaled to the output
+    /// size, for fast drafts of lighting. One means full resolution.
+    pub draft_scale: u32,
+
     /// The number of samplings for one pixel in an image.
     pub num_samples: u32,
 
     /// The maximum number of the light bounces in the image.
     pub max_bounces: u32,
+
+    /// Whether all paths run to `max_bounces` without Russian roulette or irradiance cache
+    /// lookups, see `fixed_depth`.
+    pub fixed_depth: bool,
+
+    /// Whether to output the radiance of each light group as AOVs.
+    pub light_group_aovs: bool,
+
+    /// The minimal roughness of the vertices after the first glossy or specular bounce, which
+    /// makes caustic paths visible with a bias. Zero means no regularization.
+    pub regularization: f64,
+
+    /// The minimal roughness of the vertices from each depth as the steps of depth and roughness,
+    /// which is a cheaper alternative to regularization, see `roughness_clamp`.
+    pub roughness_clamps: Vec<(u32, f64)>,
+
+    /// The limits of the indirect light brought by each bounce.
+    pub indirect_clamp: IndirectClamp,
+
+    /// How the lights are picked for the direct lighting of each shading point.
+    pub light_selection: LightSelection,
+
+    /// Whether the direct lighting skips the lights which can't reach the BVH region of the
+    /// shading point, see `light_culling`.
+    pub light_culling: bool,
+
+    /// The light lists of BVH regions, which are built at the first lookup.
+    culled_lights: OnceLock<Option<LightCulling>>,
+
+    /// The reconstruction filter which splats samples to the neighboring pixels.
+    pub filter: Filter,
+
+    /// The wall-clock budget of rendering, after which no more pass is started.
+    pub max_time: Option<Duration>,
+
+    /// The estimated relative error of pixels below which the rendering stops early.
+    pub target_noise: Option<f64>,
+
+    /// The number of render threads, zero means all logical cores.
+    pub threads: usize,
+
+    /// The thread pool of `threads` threads, which is built at the first pass and reused by the
+    /// following ones.
+    thread_pool: Mutex<Option<Arc<ThreadPool>>>,
+
+    /// The size of square tiles in pixels which are dispatched to render threads.
+    pub tile_size: u32,
+
+    /// The order in which tiles are dispatched to render threads.
+    pub tile_order: TileOrder,
+
+    /// The handle to cancel, pause and query the render from another thread.
+    pub handle: Option<RenderHandle>,
+
+    /// The function called by render threads with the pixels of each finished tile.
+    pub tile_callback: Option<TileCallback>,
+
+    /// The time limit of each tile in a pass, after which the paths of the tile are stopped and
+    /// the stall is reported, `None` means unlimited.
+    pub tile_time_limit: Option<Duration>,
+
+    /// The function called with the diagnostics of stalled tiles, which are printed to stderr
+    /// if it is `None`.
+    pub stall_callback: Option<StallCallback>,
+
+    /// The depth from which paths are terminated by Russian roulette, `None` means disabled.
+    pub roulette_depth: Option<u32>,
+
+    /// Whether to output the path length and the roulette termination depth as AOVs.
+    pub path_aovs: bool,
+
+    /// The encoding of the depth AOV, `None` means disabled.
+    pub depth_aov: Option<DepthEncoding>,
+
+    /// Whether to output the world position of the first hit as AOV.
+    pub position_aov: bool,
+
+    /// Whether to output the world normal and albedo of the first hit as AOVs.
+    pub normal_albedo_aovs: bool,
+
+    /// Whether to output the motion vectors of the first hit as AOV.
+    pub motion_aov: bool,
+
+    /// The camera of the previous frame of animation, which the camera motion of motion vectors
+    /// is relative to. `None` means that the camera doesn't move.
+    pub previous_camera: Option<Camera>,
+
+    /// Whether to trace the primary rays of 8x8 pixel blocks together as packets.
+    pub packet_tracing: bool,
+
+    /// Whether the shadow rays of the direct lighting of first hits are deferred to the end of
+    /// each tile and traced in sorted order, see `shadow_batching`.
+    pub shadow_batching: bool,
+
+    /// The minimal distance of intersections along the rays leaving a surface, i.e. bounces,
+    /// transmission and shadow rays, which avoids self-intersection acne.
+    pub ray_epsilon: f64,
+
+    /// Whether the rays leaving smooth shaded triangles start from the curved surface of their
+    /// vertex normals, which removes the polygonal shadow terminator, see
+    /// `shape::terminator_origin`.
+    pub terminator_fix: bool,
+
+    /// The minimal distance of intersections along camera rays.
+    pub near_clip: f64,
+
+    /// The maximal distance of intersections along camera rays, beyond which the background is
+    /// seen.
+    pub far_clip: f64,
+
+    /// The typical size of the scene in world units, which `ray_epsilon` and the clip distances
+    /// are relative to.
+    pub scene_scale: f64,
+
+    /// Whether to render tiles one by one on the calling thread with the random numbers seeded
+    /// by tile and pass, so that the same settings reproduce the same image bit by bit.
+    pub deterministic: bool,
+
+    /// The post-processing stage which converts the rendered radiance to the output image.
+    pub post: PostProcess,
+
+    /// The format of the files saved by `render_to_file`, `None` to choose it by the extension
+    /// of path.
+    pub writer: Option<ImageWriter>,
+
+    /// The irradiance cache which interpolates the indirect light of diffuse surfaces seen
+    /// directly or through delta BSDFs, `None` means disabled.
+    pub irradiance_cache: Option<Arc<IrradianceCache>>,
+
+    /// The path guiding which is trained before rendering, `None` means disabled.
+    pub path_guide: Option<Arc<PathGuide>>,
+
+    /// The history of the previous frame of animation which seeds the accumulation of samples,
+    /// `None` means disabled.
+    pub temporal_history: Option<Arc<FrameHistory>>,
+}
+
+/// The function to receive finished tiles, see `Renderer::on_tile`.
+pub type TileCallback = Arc<dyn Fn(&TileResult) + Send + Sync>;
+
+/// The function to receive the diagnostics of stalled tiles, see `Renderer::on_stall`.
+pub type StallCallback = Arc<dyn Fn(&StallReport) + Send + Sync>;
+
+/// The state of one camera path which is shared by all of its bounces.
+pub struct PathContext<'a> {
+    /// The product of BSDF weights from the camera to current vertex.
+    pub throughput: Color,
+
+    /// The names of light groups whose radiance is recorded, empty if disabled.
+    light_groups: &'a [Arc<str>],
+
+    /// The values of AOVs in the order of `Renderer::aov_names`.
+    pub aovs: Vec<Color>,
+
+    /// The minimal roughness of the following vertices which is raised by regularization.
+    pub min_roughness: f64,
+
+    /// The number of surface vertices of the path.
+    pub length: u32,
+
+    /// The depth at which the path is terminated by Russian roulette, if any.
+    pub roulette_depth: Option<u32>,
+
+    /// The geometry of the first hit, `None` if the path escapes.
+    pub first_hit: Option<FirstHit>,
+
+    /// The vertices of the path if it is recorded for debugging, see `Renderer::record_paths`.
+    pub vertices: Option<Vec<PathVertex>>,
+
+    /// Whether the background is already sampled through the portals of the scene at the
+    /// previous vertex, so that the escaped ray doesn't count it again.
+    pub through_portals: bool,
+
+    /// Whether the area lights are already sampled at the previous vertex, so that the ray
+    /// hitting their emitters doesn't count them again.
+    pub lights_sampled: bool,
+
+    /// Whether the path has been scattered by a non-delta lobe, after which the irradiance
+    /// cache isn't looked up.
+    pub scattered: bool,
+
+    /// The time after which the path is stopped, see `Renderer::tile_time_limit`.
+    pub deadline: Option<Instant>,
+
+    /// The ray at which the path is stopped by the deadline, if any.
+    pub stalled: Option<StalledRay>,
+
+    /// The index of the sample in the pixel sequence and the rotation of the pixel, which
+    /// stratify the light selection of the vertices. `None` uses random numbers instead.
+    pub sequence: Option<(u64, f64)>,
+
+    /// The shadow rays of the first hit of the path whose tests are deferred, see
+    /// `Renderer::shadow_batching`. `None` tests them at once, like those of the following
+    /// vertices and of the recorded paths, whose light records need the results.
+    pub shadow_rays: Option<Vec<ShadowRay>>,
+}
+
+impl<'a> PathContext<'a> {
+    /// Create the context of a camera path which records the radiance of `light_groups`.
+    pub fn new(light_groups: &'a [Arc<str>]) -> Self {
+        Self {
+            throughput: color::WHITE,
+            light_groups,
+            aovs: vec![Color::ZERO; light_groups.len()],
+            min_roughness: 0.0,
+            length: 0,
+            roulette_depth: None,
+            first_hit: None,
+            vertices: None,
+            through_portals: false,
+            lights_sampled: false,
+            scattered: false,
+            deadline: None,
+            stalled: None,
+            sequence: None,
+            shadow_rays: None,
+        }
+    }
+
+    /// Append a vertex with current throughput to the recorded path.
+    fn record(&mut self, p: DVec3, normal: Option<DVec3>, event: PathEvent, emitted: Color) {
+        let throughput = self.throughput;
+        if let Some(vertices) = &mut self.vertices {
+            vertices.push(PathVertex {
+                p,
+                normal,
+                event,
+                throughput,
+                emitted,
+                object: None,
+                material: None,
+                lights: Vec::new(),
+                bounce: None,
+            });
+        }
+    }
+
+    /// Get the last vertex of the recorded path.
+    fn last_vertex(&mut self) -> Option<&mut PathVertex> {
+        self.vertices.as_mut().and_then(|v| v.last_mut())
+    }
+
+    /// Record the direct light sample of the light `light` at the last vertex.
+    fn record_light(&mut self, light: usize, radiance: Color, occluded: bool) {
+        if let Some(vertex) = self.last_vertex() {
+            vertex.lights.push(LightRecord {
+                light,
+                radiance,
+                occluded,
+            });
+        }
+    }
+
+    /// Append the vertex of a ray escaping to the background to the recorded path.
+    fn record_escape(&mut self, ray: &Ray, background: Color, scene_scale: f64) {
+        if self.vertices.is_some() {
+            let far = ray.ori + ray.dir.normalize() * ESCAPE_LENGTH * scene_scale;
+            self.record(far, None, PathEvent::Escape, background);
+        }
+    }
+
+    /// Mark the last vertex of the recorded path as the end of path by `event`.
+    fn end_path(&mut self, event: PathEvent) {
+        if let Some(vertex) = self.last_vertex() {
+            vertex.event = event;
+        }
+    }
+
+    /// Record the radiance arriving at current vertex from the light in `group`.
+    pub fn add_light(&mut self, group: Option<&str>, radiance: Color) {
+        if let Some(index) = self.group_index(group) {
+            self.aovs[index] += self.throughput * radiance;
+        }
+    }
+
+    /// Get the index of the AOV of light `group`, `None` if light groups aren't recorded.
+    fn group_index(&self, group: Option<&str>) -> Option<usize> {
+        if self.light_groups.is_empty() {
+            return None;
+        }
+        let index = group
+            .and_then(|group| self.light_groups.iter().position(|g| &**g == group))
+            .unwrap_or(0);
+        Some(index)
+    }
+
+    /// Defer the shadow ray of the radiance arriving at current vertex from the light in
+    /// `group`, which is added to the sample if nothing in `ray_t` occludes it.
+    fn defer_light(&mut self, ray: Ray, ray_t: Interval, group: Option<&str>, radiance: Color) {
+        let aov = self.group_index(group);
+        let radiance = self.throughput * radiance;
+        if let Some(rays) = &mut self.shadow_rays {
+            rays.push(ShadowRay {
+                ray,
+                ray_t,
+                radiance,
+                aov,
+            });
+        }
+    }
+
+    /// Scale the light group radiance recorded since `before` by `ratio`, which keeps light
+    /// groups summing up to the clamped radiance.
+    fn rescale_light_groups(&mut self, before: &[Color], ratio: Color) {
+        for (value, before) in self.aovs.iter_mut().zip(before) {
+            *value = *before + (*value - *before) * ratio;
+        }
+    }
 }
 
 impl Renderer {
@@ -45,8 +407,48 @@ impl Renderer {
             scene,
             width: 800,
             height: 600,
+            draft_scale: 1,
             max_bounces: 50,
+            fixed_depth: false,
             num_samples: 100,
+            light_group_aovs: false,
+            regularization: 0.0,
+            roughness_clamps: Vec::new(),
+            indirect_clamp: IndirectClamp::new(100.0),
+            light_selection: LightSelection::All,
+            light_culling: false,
+            culled_lights: OnceLock::new(),
+            filter: Filter::Box(0.5),
+            max_time: None,
+            target_noise: None,
+            threads: 0,
+            thread_pool: Mutex::new(None),
+            tile_size: 32,
+            tile_order: TileOrder::Scanline,
+            handle: None,
+            tile_callback: None,
+            tile_time_limit: None,
+            stall_callback: None,
+            roulette_depth: None,
+            path_aovs: false,
+            depth_aov: None,
+            position_aov: false,
+            normal_albedo_aovs: false,
+            motion_aov: false,
+            previous_camera: None,
+            temporal_history: None,
+            packet_tracing: false,
+            shadow_batching: false,
+            ray_epsilon: 1e-3,
+            terminator_fix: true,
+            near_clip: 1e-3,
+            far_clip: f64::INFINITY,
+            scene_scale: 1.0,
+            deterministic: cfg!(feature = "deterministic"),
+            post: PostProcess::new(),
+            writer: None,
+            irradiance_cache: None,
+            path_guide: None,
         }
     }
 
@@ -62,6 +464,16 @@ impl Renderer {
         self
     }
 
+    /// Trace the film at `1 / scale` of the output resolution in each axis and upscale it with
+    /// a sharpening bicubic filter, see `Buffer::upscale`. The pixel coordinates of
+    /// `get_color`, `inspect_pixel`, `traversal_stats` and tile callbacks are on the reduced
+    /// film.
+    pub const fn draft(mut self, scale: u32) -> Self {
+        assert!(scale > 0, "Draft scale must be positive!");
+        self.draft_scale = scale;
+        self
+    }
+
     /// Set number of samplings for one pixel.
     pub const fn num_samples(mut self, n: u32) -> Self {
         self.num_samples = n;
@@ -74,104 +486,1119 @@ impl Renderer {
         self
     }
 
+    /// Trace all paths to exactly `depth` bounces without Russian roulette or irradiance cache
+    /// lookups, so that integrator variants are compared at the same depth for debugging. The
+    /// depth is written into the metadata of output, see `RenderMetadata`.
+    pub const fn fixed_depth(mut self, depth: u32) -> Self {
+        self.max_bounces = depth;
+        self.fixed_depth = true;
+        self
+    }
+
+    /// Set whether to output the radiance of each light group as AOVs.
+    pub const fn light_group_aovs(mut self, enable: bool) -> Self {
+        self.light_group_aovs = enable;
+        self
+    }
+
+    /// Set the strength of path regularization which is the minimal roughness of the vertices
+    /// after the first glossy or specular bounce.
+    pub const fn regularization(mut self, strength: f64) -> Self {
+        self.regularization = strength;
+        self
+    }
+
+    /// Raise the roughness of the vertices from `depth` on to at least `min_roughness`, e.g.
+    /// `roughness_clamp(2, 0.3)` after the second bounce, where the camera hit is at depth 0.
+    /// Unlike regularization, it doesn't depend on the bounces before, so the glossy and
+    /// specular surfaces seen directly and through the first bounces stay sharp. The steps of
+    /// deeper depths take the maximum with the shallower ones.
+    pub fn roughness_clamp(mut self, depth: u32, min_roughness: f64) -> Self {
+        self.roughness_clamps.push((depth, min_roughness));
+        self
+    }
+
+    /// Get the minimal roughness of the vertices at `depth` by `roughness_clamps`.
+    fn clamped_roughness(&self, depth: u32) -> f64 {
+        self.roughness_clamps
+            .iter()
+            .filter(|(d, _)| *d <= depth)
+            .map(|(_, roughness)| *roughness)
+            .fold(0.0, f64::max)
+    }
+
+    /// Set the limits of the indirect light brought by each bounce from diffuse and specular
+    /// surfaces, see `IndirectClamp`. The default clamps each channel to 100.
+    pub const fn indirect_clamp(mut self, clamp: IndirectClamp) -> Self {
+        self.indirect_clamp = clamp;
+        self
+    }
+
+    /// Set how the lights are picked for direct lighting, e.g. `LightSelection::Power` for
+    /// scenes of many lights mixing dim and bright ones. The pick is stratified over the samples
+    /// of each pixel by Halton sequence.
+    pub const fn light_selection(mut self, selection: LightSelection) -> Self {
+        self.light_selection = selection;
+        self
+    }
+
+    /// Skip the lights which can't reach the region of the shading point, i.e. whose falloff
+    /// range ends before it or which are behind a single quad, e.g. the lights of other rooms in
+    /// a building. The regions follow the BVH, and the scenes without BVH or with section planes
+    /// aren't culled.
+    pub const fn light_culling(mut self, enable: bool) -> Self {
+        self.light_culling = enable;
+        self
+    }
+
+    /// Set the reconstruction filter which splats samples to the neighboring pixels.
+    pub const fn filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Set the wall-clock budget of rendering. The frame is rendered in passes and the rendering
+    /// stops once the budget is used up, even if `num_samples` is not reached.
+    pub const fn max_time(mut self, time: Duration) -> Self {
+        self.max_time = Some(time);
+        self
+    }
+
+    /// Set the target of estimated average relative error of pixels. The frame is rendered in
+    /// passes and the rendering stops once the noise falls below the target.
+    pub const fn target_noise(mut self, noise: f64) -> Self {
+        self.target_noise = Some(noise);
+        self
+    }
+
+    /// Set the number of render threads, zero means all logical cores.
+    pub const fn threads(mut self, n: usize) -> Self {
+        self.threads = n;
+        self
+    }
+
+    /// Set the size of square tiles in pixels.
+    pub const fn tile_size(mut self, size: u32) -> Self {
+        self.tile_size = size;
+        self
+    }
+
+    /// Set the order in which tiles are dispatched to render threads.
+    pub const fn tile_order(mut self, order: TileOrder) -> Self {
+        self.tile_order = order;
+        self
+    }
+
+    /// Set the handle to cancel, pause and query the render from another thread, the host keeps
+    /// a clone of the handle.
+    pub fn handle(mut self, handle: RenderHandle) -> Self {
+        self.handle = Some(handle);
+        self
+    }
+
+    /// Set the function called with the pixels of each finished tile as soon as it finishes,
+    /// e.g. to send tiles to a live viewer. It is called from render threads concurrently.
+    pub fn on_tile<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&TileResult) + Send + Sync + 'static,
+    {
+        self.tile_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Set the time limit of each tile in a pass, which turns pathological scenes into
+    /// diagnostics instead of hung renders, e.g. a ray bouncing between mirrors forever. The
+    /// paths running over the limit are stopped at their next bounce and the rest of the tile
+    /// is skipped in the pass. A watchdog thread also reports the tiles which don't stop, e.g.
+    /// an intersection never returns.
+    pub const fn tile_time_limit(mut self, limit: Duration) -> Self {
+        self.tile_time_limit = Some(limit);
+        self
+    }
+
+    /// Set the function called with the diagnostics of stalled tiles, i.e. the offending pixel,
+    /// sample and ray, see `tile_time_limit`. It is called from render threads concurrently.
+    pub fn on_stall<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&StallReport) + Send + Sync + 'static,
+    {
+        self.stall_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Set the depth from which paths are terminated by Russian roulette with the probability
+    /// of their throughput, which saves time on dim paths without bias.
+    pub const fn roulette_depth(mut self, depth: u32) -> Self {
+        self.roulette_depth = Some(depth);
+        self
+    }
+
+    /// Set whether to output the average path length and roulette termination depth as AOVs
+    /// `path.length` and `path.roulette`, see `Buffer::aov_heatmap` to visualize them.
+    pub const fn path_aovs(mut self, enable: bool) -> Self {
+        self.path_aovs = enable;
+        self
+    }
+
+    /// Set the encoding of the depth of the first hit which is output as AOV `depth`, e.g. for
+    /// compositing fog and depth of field in post. The planes must be valid, see
+    /// `DepthEncoding::is_valid`.
+    pub const fn depth_aov(mut self, encoding: DepthEncoding) -> Self {
+        assert!(
+            encoding.is_valid(),
+            "Depth planes must satisfy 0 <= near < far!"
+        );
+        self.depth_aov = Some(encoding);
+        self
+    }
+
+    /// Set whether to output the world position of the first hit as AOV `position`, e.g. for
+    /// screen-space relighting and projection mapping in external tools. The escaped rays are
+    /// at the origin.
+    pub const fn position_aov(mut self, enable: bool) -> Self {
+        self.position_aov = enable;
+        self
+    }
+
+    /// Set whether to output the world normal facing the camera and the albedo of the first hit
+    /// as AOVs `normal` and `albedo`. The escaped rays are zero in both.
+    pub const fn normal_albedo_aovs(mut self, enable: bool) -> Self {
+        self.normal_albedo_aovs = enable;
+        self
+    }
+
+    /// Set whether to output the 2D motion vectors in pixels as AOV `motion`, e.g. for temporal
+    /// denoisers and motion blur in post. The vector of a sample points from its position in
+    /// current frame to the position of the same surface point in the previous frame, whose
+    /// object motion is the displacement over the shutter interval, i.e. the shutter is open
+    /// for the whole frame, and whose camera is `previous_camera`.
+    pub const fn motion_aov(mut self, enable: bool) -> Self {
+        self.motion_aov = enable;
+        self
+    }
+
+    /// Set the camera of the previous frame of animation for the camera motion of motion
+    /// vectors and temporal accumulation.
+    pub fn previous_camera(mut self, camera: Camera) -> Self {
+        self.previous_camera = Some(camera);
+        self
+    }
+
+    /// Set the history of the previous frame of animation, whose pixels are reprojected into
+    /// this frame as the first round of samples where they are still valid, so that mostly
+    /// static shots converge with less samples per frame. See `FrameHistory`, the camera of
+    /// previous frame is `previous_camera`.
+    pub fn temporal_history(mut self, history: FrameHistory) -> Self {
+        self.temporal_history = Some(Arc::new(history));
+        self
+    }
+
+    /// Set whether to trace the primary rays of 8x8 pixel blocks together through the BVH. The
+    /// nodes outside of the frustum of a block are culled at once for pinhole cameras. It only
+    /// takes effect when the BVH of scene is built.
+    pub const fn packet_tracing(mut self, enable: bool) -> Self {
+        self.packet_tracing = enable;
+        self
+    }
+
+    /// Set whether to collect the shadow rays of the direct lighting of first hits in each tile
+    /// and trace them at the end of the tile, sorted by direction and origin so that successive
+    /// rays traverse the same parts of the BVH. It speeds up scenes of many lights, where the
+    /// shadow rays are the majority of rays. The shadow rays of the following bounces are
+    /// traced at once, since the indirect clamp and path guiding need their direct light, so
+    /// the image is the same as without batching. Custom integrators test the shadow rays at
+    /// once.
+    pub const fn shadow_batching(mut self, enable: bool) -> Self {
+        self.shadow_batching = enable;
+        self
+    }
+
+    /// Set the minimal distance of intersections along the rays leaving a surface.
+    pub const fn ray_epsilon(mut self, epsilon: f64) -> Self {
+        self.ray_epsilon = epsilon;
+        self
+    }
+
+    /// Set whether the rays leaving smooth shaded triangles start from the curved surface of
+    /// their vertex normals. It's enabled by default, disable it to compare with the flat faces.
+    pub const fn terminator_fix(mut self, enable: bool) -> Self {
+        self.terminator_fix = enable;
+        self
+    }
+
+    /// Get the origin of the ray leaving the surface of `rec` in direction `l`.
+    fn ray_origin(&self, rec: &HitRecord, l: DVec3) -> DPoint3 {
+        if self.terminator_fix {
+            rec.origin_towards(l)
+        } else {
+            rec.p
+        }
+    }
+
+    /// Set the minimal distance of intersections along camera rays.
+    pub const fn near_clip(mut self, distance: f64) -> Self {
+        self.near_clip = distance;
+        self
+    }
+
+    /// Set the maximal distance of intersections along camera rays, e.g. to hide the distant
+    /// geometry around an interior. The bounces aren't clipped.
+    pub const fn far_clip(mut self, distance: f64) -> Self {
+        self.far_clip = distance;
+        self
+    }
+
+    /// Set the typical size of the scene in world units, e.g. 1e6 for a planet with the default
+    /// ray epsilon and near clip.
+    pub const fn scene_scale(mut self, scale: f64) -> Self {
+        self.scene_scale = scale;
+        self
+    }
+
+    /// Set whether to render in the deterministic reference mode, which is enabled by default
+    /// with the `deterministic` feature. All parallelism is disabled, and the random numbers of
+    /// each tile are seeded by its location and the number of accumulated samples, so the image
+    /// only depends on the settings and is used as the ground truth of regression tests.
+    /// `max_time` still depends on the speed of machine and shouldn't be used with it. The
+    /// images are identical across platforms as long as their math libraries agree on the
+    /// transcendental functions.
+    pub const fn deterministic(mut self, enable: bool) -> Self {
+        self.deterministic = enable;
+        self
+    }
+
+    /// Set the post-processing stage which converts the rendered radiance to the output image of
+    /// `render`.
+    pub fn post_process(mut self, post: PostProcess) -> Self {
+        self.post = post;
+        self
+    }
+
+    /// Set the format of the files saved by `render_to_file` regardless of the extension of
+    /// path.
+    pub const fn image_writer(mut self, writer: ImageWriter) -> Self {
+        self.writer = Some(writer);
+        self
+    }
+
+    /// Set the irradiance cache for the indirect light of diffuse surfaces, which is much faster
+    /// in interiors with a controllable bias, see `IrradianceCache`. The light group AOVs record
+    /// the cached indirect light in the default group.
+    pub fn irradiance_cache(mut self, cache: IrradianceCache) -> Self {
+        self.irradiance_cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// Set the path guiding which samples the bounces towards the incident radiance learned in
+    /// the training passes before rendering, see `PathGuide`. The samples of training passes
+    /// are discarded.
+    pub fn path_guiding(mut self, guide: PathGuide) -> Self {
+        self.path_guide = Some(Arc::new(guide));
+        self
+    }
+
+    /// Get the interval of intersections along a ray leaving a surface up to `t_max`.
+    fn surface_interval(&self, t_max: f64) -> Interval {
+        let epsilon = self.ray_epsilon * self.scene_scale;
+        Interval::new(epsilon, t_max - epsilon)
+    }
+
+    /// Get the indices of the lights which can reach `p`, `None` means all lights.
+    fn culled_lights(&self, p: DPoint3) -> Option<&[u32]> {
+        if !self.light_culling {
+            return None;
+        }
+        self.culled_lights
+            .get_or_init(|| {
+                let bvh = self
+                    .scene
+                    .bvh
+                    .as_ref()
+                    .filter(|_| self.scene.sections.is_empty())?;
+                Some(LightCulling::build(
+                    bvh,
+                    &self.scene.lights,
+                    &self.scene.objects,
+                    self.ray_epsilon * self.scene_scale,
+                ))
+            })
+            .as_ref()?
+            .lights_at(p)
+    }
+
+    /// Get the interval of intersections along camera rays.
+    fn camera_interval(&self) -> Interval {
+        Interval::new(
+            self.near_clip * self.scene_scale,
+            self.far_clip * self.scene_scale,
+        )
+    }
+
+    /// Get the names of light groups recorded as AOVs.
+    fn recorded_light_groups(&self) -> Vec<Arc<str>> {
+        if self.light_group_aovs {
+            self.scene.light_group_names()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Get the names of AOVs which are stored into `Buffer` when sampling.
+    pub fn aov_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .recorded_light_groups()
+            .iter()
+            .map(|group| format!("lightgroup.{group}"))
+            .collect();
+        if self.path_aovs {
+            // The roulette depth is zero for the paths which are not terminated by roulette.
+            names.extend(["path.length".to_string(), "path.roulette".to_string()]);
+        }
+        if self.depth_aov.is_some() {
+            names.push(DEPTH_AOV.to_string());
+        }
+        if self.position_aov {
+            names.push(POSITION_AOV.to_string());
+        }
+        if self.normal_albedo_aovs {
+            names.extend([NORMAL_AOV.to_string(), ALBEDO_AOV.to_string()]);
+        }
+        if self.motion_aov {
+            names.push(MOTION_AOV.to_string());
+        }
+        names
+    }
+
     /// Trace the ray and return the color.
     pub fn trace_ray(&self, ray: &Ray, num_bounces: u32, rng: &mut StdRng) -> Color {
+        self.trace_path(ray, num_bounces, rng, &mut PathContext::new(&[]))
+    }
+
+    /// Trace the ray and return the color, the AOVs are recorded into `ctx`.
+    pub fn trace_path(
+        &self,
+        ray: &Ray,
+        num_bounces: u32,
+        rng: &mut StdRng,
+        ctx: &mut PathContext,
+    ) -> Color {
         if num_bounces == 0 {
             return color::BLACK;
         }
+        if let Some(deadline) = ctx.deadline
+            && Instant::now() >= deadline
+        {
+            // Stop the path which runs over the time limit of its tile.
+            ctx.stalled.get_or_insert(StalledRay {
+                origin: ray.ori,
+                dir: ray.dir,
+                depth: self.max_bounces.saturating_sub(num_bounces),
+            });
+            return color::BLACK;
+        }
+
+        // Start ray interval above zero to avoid shadow acne.
+        let hit = if num_bounces == self.max_bounces {
+            self.scene.intersect_camera(ray, self.camera_interval())
+        } else {
+            self.intersect(ray, self.surface_interval(f64::INFINITY))
+        };
+        self.shade(ray, hit, num_bounces, rng, ctx)
+    }
+
+    /// Estimate the irradiance at the surface point `p` with `normal` from one path, which is
+    /// the radiance of a white diffuse surface there scaled by π, e.g. baking lightmaps.
+    pub(crate) fn irradiance(
+        &self,
+        p: DPoint3,
+        normal: DVec3,
+        time: f64,
+        rng: &mut StdRng,
+    ) -> Color {
+        let rec = HitRecord {
+            p,
+            normal,
+            front_face: true,
+            material: Some(Arc::new(Material::diffuse(color::WHITE))),
+            ..Default::default()
+        };
+        let ray = Ray::new(p + normal, -normal, time);
+        let radiance = self.shade(
+            &ray,
+            Some(rec),
+            self.max_bounces,
+            rng,
+            &mut PathContext::new(&[]),
+        );
+        f64::consts::PI * radiance
+    }
 
-        // Start ray interval above zero (1e-3) to avoid shadow acne.
-        match self.intersect(ray, Interval::new(1e-3, f64::INFINITY)) {
-            None => self.scene.background.sample(ray.dir),
-            Some(rec) => {
-                let mut color = rec.material().emittance * rec.material().color;
+    /// Get the color of the ray from its closest intersection `hit` and continue the path.
+    fn shade(
+        &self,
+        ray: &Ray,
+        hit: Option<HitRecord>,
+        num_bounces: u32,
+        rng: &mut StdRng,
+        ctx: &mut PathContext,
+    ) -> Color {
+        match hit {
+            None if ctx.through_portals => {
+                ctx.record_escape(ray, color::BLACK, self.scene_scale);
+                color::BLACK
+            }
+            None => {
+                let background = self.scene.background.sample(ray.dir);
+                ctx.add_light(Some(BACKGROUND_LIGHT_GROUP), background);
+                ctx.record_escape(ray, background, self.scene_scale);
+                background
+            }
+            Some(mut rec) => {
+                let depth = self.max_bounces.saturating_sub(num_bounces);
+                let first = ctx.length == 0;
+                if first {
+                    rec.footprint = rec.t * self.pixel_spread();
+                }
+                ctx.length = depth + 1;
+                let material = self.regularize(rec.material.clone().unwrap(), depth, ctx);
+                if first {
+                    let albedo = if self.normal_albedo_aovs {
+                        material.albedo(&rec).unwrap_or_default()
+                    } else {
+                        Color::ZERO
+                    };
+                    ctx.first_hit = Some(FirstHit {
+                        p: rec.p,
+                        normal: rec.normal,
+                        albedo,
+                        velocity: rec.velocity,
+                    });
+                }
+                let sampled = ctx.lights_sampled
+                    && self
+                        .scene
+                        .objects
+                        .get(rec.object_id)
+                        .is_some_and(|obj| obj.emitter);
+                let mut color = if sampled {
+                    color::BLACK
+                } else {
+                    material.emitted(&rec, -ray.dir)
+                };
+                ctx.add_light(rec.light_group.as_deref(), color);
+                let event = if material.is_delta() {
+                    PathEvent::Specular
+                } else {
+                    PathEvent::Scatter
+                };
+                ctx.record(rec.p, Some(rec.normal), event, color);
+                if ctx.vertices.is_some() {
+                    let object =
+                        (rec.object_id < self.scene.objects.len()).then_some(rec.object_id);
+                    if let Some(vertex) = ctx.last_vertex() {
+                        vertex.object = object;
+                        vertex.material = Some(lightpath::describe_material(&*material));
+                    }
+                }
                 let v = -ray.dir;
                 // Sample two kinds of light: directive light, indirective light.
                 // 1. directive light. The light only bounces one time.
-                color += self.sample_lights(&rec, ray.t, v, rng);
+                if !material.is_delta() {
+                    color += self.sample_lights(&rec, &*material, ray.t, v, rng, ctx);
+                }
                 // 2. indirective light which means bounced light.
-                if let Some((l, pdf)) = rec.material().scatter(rng, rec.normal, v, rec.front_face) {
-                    let f = rec.material().bsdf(l, v, rec.normal, rec.front_face);
-                    let scatter = Ray::new(rec.p, l, ray.t);
-                    let indirect = 1.0 / pdf
-                        * f
-                        * rec.normal.dot(l).abs()
-                        * self.trace_ray(&scatter, num_bounces - 1, rng);
+                if let Some(cache) = &self.irradiance_cache
+                    && !self.fixed_depth
+                    && !ctx.scattered
+                    && num_bounces > 1
+                    && let Some(albedo) = material.diffuse_albedo(&rec, v)
+                {
+                    let irradiance = self.cached_irradiance(cache, &rec, num_bounces, ray.t, rng);
+                    let indirect = albedo * f64::consts::FRAC_1_PI * irradiance;
+                    ctx.add_light(None, indirect);
+                    ctx.end_path(PathEvent::Cached);
+                    return color + indirect;
+                }
+                if let Some(sample) = self.sample_bsdf(&rec, &*material, v, rng) {
+                    if ctx.vertices.is_some() {
+                        let f = (!sample.delta).then(|| material.eval(&rec, sample.l, v));
+                        if let Some(vertex) = ctx.last_vertex() {
+                            vertex.bounce = Some(BounceRecord {
+                                l: sample.l,
+                                pdf: sample.pdf,
+                                f,
+                                weight: sample.weight,
+                            });
+                        }
+                    }
+                    let scatter = Ray::new(self.ray_origin(&rec, sample.l), sample.l, ray.t);
+                    let mut weight = sample.weight;
+                    if let Some(d) = self.effective_roulette_depth()
+                        && depth + 1 >= d
+                    {
+                        // Survive by the throughput of the extended path.
+                        let survival = (ctx.throughput * weight).max_element().clamp(0.05, 1.0);
+                        if rng.random::<f64>() >= survival {
+                            ctx.roulette_depth = Some(depth + 1);
+                            ctx.end_path(PathEvent::Roulette);
+                            return color;
+                        }
+                        weight /= survival;
+                    }
+                    if num_bounces == 1 {
+                        ctx.end_path(PathEvent::MaxDepth);
+                    }
+                    let (throughput, min_roughness) = (ctx.throughput, ctx.min_roughness);
+                    let (through_portals, scattered) = (ctx.through_portals, ctx.scattered);
+                    let lights_sampled = ctx.lights_sampled;
+                    let before = ctx.aovs.clone();
+                    // The shadow rays of the following vertices are traced at once, so that the
+                    // clamp and the path guide see their direct light.
+                    let shadow_rays = ctx.shadow_rays.take();
+                    ctx.throughput *= weight;
+                    ctx.through_portals =
+                        !material.is_delta() && !sample.delta && !self.scene.portals.is_empty();
+                    ctx.lights_sampled = !material.is_delta() && !sample.delta;
+                    ctx.scattered = scattered || !sample.delta;
+                    if material.roughness().is_some_and(|r| r < GLOSSY_ROUGHNESS) {
+                        ctx.min_roughness = ctx.min_roughness.max(self.regularization);
+                    }
+                    let incident = self.trace_path(&scatter, num_bounces - 1, rng, ctx);
+                    if let Some(guide) = &self.path_guide
+                        && !sample.delta
+                    {
+                        guide.record(rec.p, sample.l, color::luminance(incident) / sample.pdf);
+                    }
+                    let indirect = weight * incident;
+                    (ctx.throughput, ctx.min_roughness) = (throughput, min_roughness);
+                    (ctx.through_portals, ctx.scattered) = (through_portals, scattered);
+                    (ctx.lights_sampled, ctx.shadow_rays) = (lights_sampled, shadow_rays);
                     if indirect.is_finite() {
-                        color += indirect.min(DVec3::splat(100.0));
+                        let specular = sample.delta
+                            || material.roughness().is_some_and(|r| r < GLOSSY_ROUGHNESS);
+                        let clamped = self.indirect_clamp.clamp(indirect, specular);
+                        if clamped != indirect {
+                            let ratio = Color::select(
+                                indirect.cmpgt(Color::ZERO),
+                                clamped / indirect,
+                                color::WHITE,
+                            );
+                            ctx.rescale_light_groups(&before, ratio);
+                        }
+                        color += clamped;
+                    } else {
+                        ctx.aovs = before;
                     }
+                } else {
+                    ctx.end_path(PathEvent::Absorb);
                 }
                 color
             }
         }
     }
 
+    /// Sample the direction of bounce from the BSDF, or from the mixture of BSDF and the
+    /// learned distribution of path guiding.
+    fn sample_bsdf(
+        &self,
+        rec: &HitRecord,
+        material: &dyn Bsdf,
+        v: DVec3,
+        rng: &mut StdRng,
+    ) -> Option<BsdfSample> {
+        let Some(guide) = self.path_guide.as_ref().filter(|_| !material.is_delta()) else {
+            return material.sample(rec, v, rng);
+        };
+        guide.with_distribution(rec.p, |distribution| {
+            let Some(distribution) = distribution else {
+                return material.sample(rec, v, rng);
+            };
+            let alpha = guide.bsdf_fraction;
+            if rng.random::<f64>() < alpha {
+                let mut sample = material.sample(rec, v, rng)?;
+                if sample.delta {
+                    // The delta lobes are only sampled from BSDF.
+                    sample.weight /= alpha;
+                    sample.pdf *= alpha;
+                } else {
+                    let pdf = alpha * sample.pdf + (1.0 - alpha) * distribution.pdf(sample.l);
+                    sample.weight *= sample.pdf / pdf;
+                    sample.pdf = pdf;
+                }
+                Some(sample)
+            } else {
+                let l = distribution.sample(rng);
+                let pdf = alpha * material.pdf(rec, l, v) + (1.0 - alpha) * distribution.pdf(l);
+                (pdf > 0.0).then(|| BsdfSample::new(l, pdf, material.eval(rec, l, v), rec.normal))
+            }
+        })
+    }
+
+    /// Get the irradiance of indirect light at `rec` from the irradiance cache. The records are
+    /// estimated by tracing the hemisphere like the diffuse bounces of paths.
+    fn cached_irradiance(
+        &self,
+        cache: &IrradianceCache,
+        rec: &HitRecord,
+        num_bounces: u32,
+        shutter_time: f64,
+        rng: &mut StdRng,
+    ) -> Color {
+        cache.irradiance(rec.p, rec.normal, self.scene_scale, rng, |dir, rng| {
+            let ray = Ray::new(self.ray_origin(rec, dir), dir, shutter_time);
+            let hit = self.intersect(&ray, self.surface_interval(f64::INFINITY));
+            let distance = hit.as_ref().map_or(f64::INFINITY, |hit| hit.t);
+            let mut ctx = PathContext::new(&[]);
+            ctx.scattered = true;
+            ctx.through_portals = !self.scene.portals.is_empty();
+            ctx.lights_sampled = true;
+            let radiance = self.shade(&ray, hit, num_bounces - 1, rng, &mut ctx);
+            // Clamp the radiance and drop the non-finite values like the diffuse bounces of
+            // paths.
+            let radiance = if radiance.is_finite() {
+                self.indirect_clamp.clamp(radiance, false)
+            } else {
+                Color::ZERO
+            };
+            (radiance, distance)
+        })
+    }
+
+    /// Get the width of the footprint of a pixel at unit distance from the camera, which the
+    /// footprint of camera rays at their hits grows by.
+    fn pixel_spread(&self) -> f64 {
+        self.cam.viewport_height / self.cam.focus_distance() / self.film_size().1 as f64
+    }
+
+    /// Get the depth from which paths are terminated by Russian roulette, `None` if disabled or
+    /// the depth is fixed.
+    pub fn effective_roulette_depth(&self) -> Option<u32> {
+        self.roulette_depth.filter(|_| !self.fixed_depth)
+    }
+
+    /// Get the width and height of the traced film, which is reduced by `self.draft_scale`.
+    pub fn film_size(&self) -> (u32, u32) {
+        (
+            self.width.div_ceil(self.draft_scale),
+            self.height.div_ceil(self.draft_scale),
+        )
+    }
+
+    /// Get the material whose roughness is raised by the regularization of the path and the
+    /// roughness clamp of `depth`.
+    fn regularize(&self, material: Arc<dyn Bsdf>, depth: u32, ctx: &PathContext) -> Arc<dyn Bsdf> {
+        let min_roughness = ctx.min_roughness.max(self.clamped_roughness(depth));
+        if min_roughness > 0.0 {
+            material.regularized(min_roughness).unwrap_or(material)
+        } else {
+            material
+        }
+    }
+
     /// Sample the ray towards lights in the scene for the given `world_pos` and return the color.
     fn sample_lights(
         &self,
         rec: &HitRecord,
+        material: &dyn Bsdf,
         shutter_time: f64,
         ray_view: DVec3,
         rng: &mut StdRng,
+        ctx: &mut PathContext,
     ) -> Color {
         let mut color_from_lights = Color::ZERO;
-        let material = rec.material();
         let pos = rec.p;
         let n = rec.normal;
-        let front_face = rec.front_face;
 
-        for light in &self.scene.lights {
+        // The light picked by power and its probability, `None` to sample all lights.
+        let selected = match self.light_selection {
+            LightSelection::All => None,
+            LightSelection::Power => {
+                let u = match ctx.sequence {
+                    Some((index, offset)) => sampler::halton_1d(ctx.length as usize, index, offset),
+                    None => rng.random(),
+                };
+                self.scene.light_distribution.sample(u)
+            }
+        };
+        // The lights which can reach `pos`, or all of them.
+        let culled = self.culled_lights(pos);
+        let all = if culled.is_none() {
+            0..self.scene.lights.len()
+        } else {
+            0..0
+        };
+        let indices = culled.into_iter().flatten().map(|&i| i as usize).chain(all);
+        for i in indices {
+            let light = &self.scene.lights[i];
+            let group = self.scene.light_group(i).map(|group| &**group);
             match light {
                 Light::Ambient(color_ambient) => {
-                    color_from_lights += color_ambient * material.color;
+                    // Estimate the reflectance of the unoccluded ambient light by one sample.
+                    if let Some(sample) = material.sample(rec, ray_view, rng) {
+                        let reflectance = sample.weight;
+                        if reflectance.is_finite() {
+                            color_from_lights += color_ambient * reflectance;
+                            ctx.add_light(group, color_ambient * reflectance);
+                            ctx.record_light(i, color_ambient * reflectance, false);
+                        }
+                    }
                 }
                 _ => {
+                    let weight = match selected {
+                        None => 1.0,
+                        Some((j, pmf)) if j == i => 1.0 / pmf,
+                        Some(_) => continue,
+                    };
                     let (intensity, ray_light, t_micro) = light.illuminate(pos, rng, shutter_time);
+                    let shadow = Ray::new(self.ray_origin(rec, ray_light), ray_light, shutter_time);
+                    if ctx.shadow_rays.is_some() {
+                        let f = material.eval(rec, ray_light, ray_view);
+                        let radiance = f * intensity * n.dot(ray_light).abs() * weight;
+                        ctx.defer_light(shadow, self.surface_interval(t_micro), group, radiance);
+                        continue;
+                    }
                     let close_hit = self
-                        .intersect(
-                            &Ray::new(pos, ray_light, shutter_time),
-                            Interval::new(1e-3, t_micro - 1e-3),
-                        )
+                        .intersect(&shadow, self.surface_interval(t_micro))
                         .map(|rec| rec.t);
 
                     // The light can reach the world position `pos`.
                     if close_hit.is_none() {
-                        let f = material.bsdf(ray_light, ray_view, n, front_face);
+                        let f = material.eval(rec, ray_light, ray_view);
                         // The integrand of monte carlo integral.
                         // intensity equals to (attenuation * pdf)
-                        color_from_lights += f * intensity * n.dot(ray_light).abs();
+                        let radiance = f * intensity * n.dot(ray_light).abs() * weight;
+                        color_from_lights += radiance;
+                        ctx.add_light(group, radiance);
+                        ctx.record_light(i, radiance, false);
+                    } else {
+                        ctx.record_light(i, Color::ZERO, true);
                     }
                 }
             }
         }
+
+        // The background through portals, which are recorded after the lights.
+        for (k, portal) in self.scene.portals.iter().enumerate() {
+            let index = self.scene.lights.len() + k;
+            let (p, portal_n, pdf) = portal.sample(pos, rng, shutter_time);
+            let disp = p - pos;
+            let len = disp.length();
+            let ray_light = disp / len;
+            let shadow = Ray::new(self.ray_origin(rec, ray_light), ray_light, shutter_time);
+            let ray_t = self.surface_interval(f64::INFINITY);
+            let deferred = ctx.shadow_rays.is_some();
+            if !deferred && self.intersect(&shadow, ray_t).is_some() {
+                ctx.record_light(index, Color::ZERO, true);
+                continue;
+            }
+            // Convert the PDF over portal area to solid angle like area lights.
+            let surface_area = ray_light.dot(portal_n).abs() / (len * len);
+            let background = self.scene.background.sample(ray_light);
+            let f = material.eval(rec, ray_light, ray_view);
+            let radiance = f * background * surface_area / pdf * n.dot(ray_light).abs();
+            if deferred {
+                ctx.defer_light(shadow, ray_t, Some(BACKGROUND_LIGHT_GROUP), radiance);
+                continue;
+            }
+            color_from_lights += radiance;
+            ctx.add_light(Some(BACKGROUND_LIGHT_GROUP), radiance);
+            ctx.record_light(index, radiance, false);
+        }
         color_from_lights
     }
 
-    /// Get the pixel color of a specified location in film plane.
+    /// Get the pixel color of a specified location in film plane, which is the average of the
+    /// samples inside the pixel.
     pub fn get_color(&self, col: u32, row: u32, iterations: u32, rng: &mut StdRng) -> Color {
         let mut pixel_color = Color::default();
-        // Sampling stratifications + Monte Carlo approximation.
-        let iter_sqrt = (iterations as f64).sqrt() as u32;
-        for y in 0..iter_sqrt {
-            for x in 0..iter_sqrt {
-                let s = (col as f64 + (x as f64 + random()) / iter_sqrt as f64) / self.width as f64;
-                let t =
-                    (row as f64 + (y as f64 + random()) / iter_sqrt as f64) / self.height as f64;
-                let r = self.cam.get_ray(s, t, rng);
-                let sample_color = self.trace_ray(&r, self.max_bounces, rng);
-                // Avoid NaN and infinity in color which may cause pixel acne.
-                if sample_color.is_finite() {
-                    pixel_color += sample_color;
+        let mut count = 0;
+        let mut batch = ShadowBatch::new();
+        self.trace_pixel(
+            col,
+            row,
+            0..iterations,
+            rng,
+            &[],
+            None,
+            |x, y, color, aovs, rays| batch.push((col, row), (x, y), color, aovs, rays),
+        );
+        batch.resolve(self, |_, _, _, _, color, _| {
+            pixel_color += color;
+            count += 1;
+        });
+        pixel_color / count.max(1) as f64
+    }
+
+    /// Trace the samples in range `indices` of the pixel sequence of a specified location in film
+    /// plane, and call `emit` with the film position in pixels, the color, the AOVs and the
+    /// deferred shadow rays of each sample. The samples after `deadline` are skipped, and the index and the ray of the sample
+    /// stopped by it are returned.
+    /// The sub-pixel positions are taken from Halton sequence rotated per pixel, so that the
+    /// samples of successive passes continue the same well-distributed sequence.
+    #[allow(clippy::too_many_arguments)]
+    fn trace_pixel<F>(
+        &self,
+        col: u32,
+        row: u32,
+        indices: Range<u32>,
+        rng: &mut StdRng,
+        light_groups: &[Arc<str>],
+        deadline: Option<Instant>,
+        mut emit: F,
+    ) -> Option<(u32, StalledRay)>
+    where
+        F: FnMut(f64, f64, Color, Vec<Color>, Vec<ShadowRay>),
+    {
+        for index in indices {
+            let (film_x, film_y, r) = self.primary_ray(col, row, index, rng);
+            let mut ctx = PathContext::new(light_groups);
+            ctx.deadline = deadline;
+            ctx.sequence = Some((index as u64, sampler::pixel_offset(col, row).0));
+            ctx.shadow_rays = self.shadow_batching.then(Vec::new);
+            let sample_color = match &self.scene.integrator {
+                Some(integrator) => integrator.radiance(self, &r, rng),
+                None => self.trace_path(&r, self.max_bounces, rng, &mut ctx),
+            };
+            if let Some(stalled) = ctx.stalled {
+                return Some((index, stalled));
+            }
+            self.emit_sample(film_x, film_y, &r, sample_color, ctx, &mut emit);
+        }
+        None
+    }
+
+    /// Trace the first `count` samples of a pixel like `trace_pixel` with a fixed `seed`, and
+    /// record the vertices of their paths for debugging why the pixel is bright or black. The
+    /// paths can be exported by `lightpath::save` and inspected in a 3D viewer.
+    pub fn record_paths(&self, col: u32, row: u32, count: u32, seed: u64) -> Vec<LightPath> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count)
+            .map(|index| {
+                let (_, _, r) = self.primary_ray(col, row, index, &mut rng);
+                let mut ctx = PathContext::new(&[]);
+                ctx.vertices = Some(Vec::new());
+                ctx.record(r.ori, None, PathEvent::Camera, Color::ZERO);
+                let color = self.trace_path(&r, self.max_bounces, &mut rng, &mut ctx);
+                LightPath {
+                    pixel: (col, row),
+                    vertices: ctx.vertices.unwrap_or_default(),
+                    color,
+                }
+            })
+            .collect()
+    }
+
+    /// Trace the first `count` samples of a pixel like `record_paths` and write the report of
+    /// each bounce, see `lightpath::write_report`.
+    pub fn inspect_pixel<W: Write>(
+        &self,
+        col: u32,
+        row: u32,
+        count: u32,
+        seed: u64,
+        writer: W,
+    ) -> io::Result<()> {
+        lightpath::write_report(&self.record_paths(col, row, count, seed), writer)
+    }
+
+    /// Trace the samples of all pixels in `block` like `trace_pixel`, whose primary rays are
+    /// intersected with the BVH together as packets. The samples of a pixel start from the index
+    /// `first(col, row)`, and `emit` is called with the pixel location in addition. The pixel,
+    /// the index and the ray of the sample stopped by `deadline` are returned.
+    #[allow(clippy::too_many_arguments)]
+    fn trace_packet<S, F>(
+        &self,
+        bvh: &Bvh,
+        block: Tile,
+        first: S,
+        iterations: u32,
+        rng: &mut StdRng,
+        light_groups: &[Arc<str>],
+        deadline: Option<Instant>,
+        mut emit: F,
+    ) -> Option<((u32, u32), u32, StalledRay)>
+    where
+        S: Fn(u32, u32) -> u32,
+        F: FnMut(u32, u32, f64, f64, Color, Vec<Color>, Vec<ShadowRay>),
+    {
+        // All rays of the block start from the camera origin without depth of field, so the
+        // frustum through the block corners bounds them.
+        let frustum = (self.cam.lens_radius == 0.0).then(|| {
+            let (width, height) = self.film_size();
+            let corner = |col: u32, row: u32| {
+                let (s, t) = (col as f64 / width as f64, row as f64 / height as f64);
+                self.cam.film_point(s, t) - self.cam.origin
+            };
+            let (x0, y0) = (block.x, block.y);
+            let (x1, y1) = (block.x + block.width, block.y + block.height);
+            Frustum::new(
+                self.cam.origin,
+                [
+                    corner(x0, y0),
+                    corner(x1, y0),
+                    corner(x1, y1),
+                    corner(x0, y1),
+                ],
+            )
+        });
+        for i in 0..iterations {
+            let mut pixels = Vec::new();
+            let mut rays = Vec::new();
+            for row in block.y..block.y + block.height {
+                for col in block.x..block.x + block.width {
+                    let (film_x, film_y, r) = self.primary_ray(col, row, first(col, row) + i, rng);
+                    pixels.push((col, row, film_x, film_y));
+                    rays.push(r);
+                }
+            }
+            let ray_t = self.camera_interval();
+            let hits = bvh.intersect_packet(&rays, frustum.as_ref(), ray_t);
+            for ((col, row, film_x, film_y), (r, hit)) in
+                pixels.into_iter().zip(rays.iter().zip(hits))
+            {
+                let hit = self.scene.cull_backfaces(r, ray_t, hit);
+                let mut ctx = PathContext::new(light_groups);
+                ctx.deadline = deadline;
+                let index = first(col, row) + i;
+                ctx.sequence = Some((index as u64, sampler::pixel_offset(col, row).0));
+                ctx.shadow_rays = self.shadow_batching.then(Vec::new);
+                let sample_color = self.shade(r, hit, self.max_bounces, rng, &mut ctx);
+                if let Some(stalled) = ctx.stalled {
+                    return Some(((col, row), index, stalled));
                 }
+                self.emit_sample(
+                    film_x,
+                    film_y,
+                    r,
+                    sample_color,
+                    ctx,
+                    &mut |x, y, color, aovs, rays| emit(col, row, x, y, color, aovs, rays),
+                );
+            }
+        }
+        None
+    }
+
+    /// Get the ray through the center of a pixel from the camera origin at time 0.0 and its
+    /// closest intersection, e.g. the geometry of pixels for temporal reprojection.
+    pub(crate) fn center_hit(&self, col: u32, row: u32) -> (Ray, Option<HitRecord>) {
+        let (width, height) = self.film_size();
+        let s = (col as f64 + 0.5) / width as f64;
+        let t = (row as f64 + 0.5) / height as f64;
+        let dir = (self.cam.film_point(s, t) - self.cam.origin).normalize();
+        let ray = Ray::new(self.cam.origin, dir, 0.0);
+        let hit = self.scene.intersect_camera(&ray, self.camera_interval());
+        (ray, hit)
+    }
+
+    /// Get the film position in pixels and the camera ray of the sample `index` of a pixel.
+    /// The sub-pixel positions are taken from Halton sequence rotated per pixel.
+    fn primary_ray(&self, col: u32, row: u32, index: u32, rng: &mut StdRng) -> (f64, f64, Ray) {
+        // Low discrepancy sequence + Monte Carlo approximation.
+        let (x, y) = sampler::halton_2d(index as u64, sampler::pixel_offset(col, row));
+        let film_x = col as f64 + x;
+        let film_y = row as f64 + y;
+        let (width, height) = self.film_size();
+        let s = film_x / width as f64;
+        let t = film_y / height as f64;
+        (film_x, film_y, self.cam.get_ray(s, t, rng))
+    }
+
+    /// Call `emit` with the sample, the AOVs and the deferred shadow rays of its path unless the
+    /// color is invalid.
+    fn emit_sample<F>(
+        &self,
+        x: f64,
+        y: f64,
+        ray: &Ray,
+        color: Color,
+        ctx: PathContext,
+        emit: &mut F,
+    ) where
+        F: FnMut(f64, f64, Color, Vec<Color>, Vec<ShadowRay>),
+    {
+        // Avoid NaN and infinity in color which may cause pixel acne.
+        if color.is_finite() {
+            let mut aovs = ctx.aovs;
+            if self.path_aovs {
+                aovs.push(Color::splat(ctx.length as f64));
+                aovs.push(Color::splat(ctx.roulette_depth.unwrap_or(0) as f64));
+            }
+            let hit = ctx.first_hit;
+            if let Some(encoding) = &self.depth_aov {
+                let depth = hit.map(|hit| self.cam.depth(hit.p));
+                aovs.push(Color::splat(encoding.encode(depth)));
             }
+            if self.position_aov {
+                aovs.push(hit.map_or(Color::ZERO, |hit| hit.p));
+            }
+            if self.normal_albedo_aovs {
+                aovs.push(hit.map_or(Color::ZERO, |hit| hit.normal));
+                aovs.push(hit.map_or(Color::ZERO, |hit| hit.albedo));
+            }
+            if self.motion_aov {
+                aovs.push(self.motion_vector(ray, hit.as_ref()));
+            }
+            emit(x, y, color, aovs, ctx.shadow_rays.unwrap_or_default());
         }
-        pixel_color / iterations as f64
     }
 
-    /// Get all pixel colors in film plane and store into `buffer`.
+    /// Get the motion vector in pixels of the camera ray whose first hit is `hit`, see
+    /// `motion_aov`. The rays escaping to the background only move with the rotation of camera.
+    fn motion_vector(&self, ray: &Ray, hit: Option<&FirstHit>) -> Color {
+        let previous = self.previous_camera.as_ref().unwrap_or(&self.cam);
+        let (current, before) = match hit {
+            Some(hit) => (
+                self.cam.project(hit.p),
+                previous.project(hit.p - hit.velocity),
+            ),
+            None => (self.cam.project_dir(ray.dir), previous.project_dir(ray.dir)),
+        };
+        match (current, before) {
+            (Some((i0, j0)), Some((i1, j1))) => Color::new(
+                (i1 - i0) * self.film_size().0 as f64,
+                (j1 - j0) * self.film_size().1 as f64,
+                0.0,
+            ),
+            _ => Color::ZERO,
+        }
+    }
+
+    /// Get all pixel colors in film plane with `iterations` more samples per pixel and store into
+    /// `buffer`.
     pub fn sample(&self, iterations: u32, buffer: &mut Buffer) {
+        let (width, height) = self.film_size();
+        let tiles = tile::tiles(width, height, self.tile_size, self.tile_order);
+        // The number of samples per pixel of each tile.
+        let tiles = match self.tile_order {
+            TileOrder::Adaptive => tile::schedule_by_error(
+                tiles,
+                buffer,
+                iterations,
+                self.num_samples,
+                self.target_noise,
+            ),
+            _ => tiles.into_iter().map(|tile| (tile, iterations)).collect(),
+        };
+        // The progress counts the samples of the pass per pixel, so that the boosted and the
+        // skipped tiles of adaptive passes keep it in step with the passes.
+        let pass = iterations as u64;
+        if let Some(handle) = &self.handle {
+            let scheduled: u64 = tiles
+                .iter()
+                .map(|(tile, _)| (tile.width * tile.height) as u64)
+                .sum();
+            handle.advance((width as u64 * height as u64 - scheduled) * pass);
+        }
+        let mut counts = vec![0; (width * height) as usize];
+        for (tile, iterations) in &tiles {
+            for y in tile.y..tile.y + tile.height {
+                let row = (y * width + tile.x) as usize;
+                counts[row..row + tile.width as usize].fill(*iterations);
+            }
+        }
         // Progress bar
-        let pb = ProgressBar::new(self.height as u64);
+        let pb = ProgressBar::new(tiles.len() as u64);
         pb.set_style(
             ProgressStyle::default_bar()
                 .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} ({eta})")
@@ -179,30 +1606,312 @@ impl Renderer {
                 .progress_chars("=>-"),
         );
 
-        // Pixel colors
-        let colors: Vec<_> = (0..self.height)
-            .into_par_iter()
-            .map(|row| {
-                let mut rng = StdRng::from_os_rng();
-                let row_pixels: Vec<Color> = (0..self.width)
-                    .map(|col| self.get_color(col, row, iterations, &mut rng))
-                    .collect();
-
-                // Update progress bar after finish each row
-                pb.inc(1);
-                row_pixels
-            })
-            .flatten()
-            .collect();
-        buffer.add_samples(colors);
+        // Splat the samples of each tile to the pixels around them. The tiles are pulled by
+        // threads in order so that the order of tiles is respected.
+        let light_groups = self.recorded_light_groups();
+        let num_aovs = self.aov_names().len();
+        let new_splat = || SplatBuffer::new(width, height, num_aovs);
+        let watchdog = self.tile_time_limit.map(Watchdog::new);
+        let trace_tile = |mut splat: SplatBuffer, (tile, iterations): (Tile, u32)| {
+            if let Some(handle) = &self.handle
+                && !handle.proceed()
+            {
+                return splat;
+            }
+            let mut rng = if self.deterministic {
+                let pass = buffer.sample_count(tile.x, tile.y);
+                StdRng::seed_from_u64(sampler::seed(tile.x, tile.y, pass))
+            } else {
+                StdRng::from_os_rng()
+            };
+            // The sum and the number of samples of each pixel in tile.
+            let mut sums = vec![(Color::ZERO, 0); (tile.width * tile.height) as usize];
+            let mut record = |col: u32, row: u32, x, y, color, aovs: Vec<Color>| {
+                splat.splat(x, y, color, &aovs, &self.filter);
+                let sum = &mut sums[((row - tile.y) * tile.width + col - tile.x) as usize];
+                sum.0 += color;
+                sum.1 += 1;
+            };
+            // The samples whose shadow rays are deferred to the end of tile.
+            let mut batch = ShadowBatch::new();
+            let mut emit = |col, row, x, y, color, aovs, rays: Vec<ShadowRay>| {
+                if rays.is_empty() {
+                    record(col, row, x, y, color, aovs);
+                } else {
+                    batch.push((col, row), (x, y), color, aovs, rays);
+                }
+            };
+            let flight = watchdog.as_ref().map(|watchdog| watchdog.start(tile));
+            let deadline = flight.map(|(_, deadline)| deadline);
+            let progress = |pixel, sample| {
+                if let (Some(watchdog), Some((slot, _))) = (&watchdog, flight) {
+                    watchdog.progress(slot, pixel, sample);
+                }
+            };
+            // Continue the pixel sequence from the samples already in the buffer.
+            let mut stalled = None;
+            match self.packet_bvh() {
+                Some(bvh) => {
+                    for block in
+                        tile::tiles(tile.width, tile.height, PACKET_SIZE, TileOrder::Scanline)
+                    {
+                        let block = Tile {
+                            x: tile.x + block.x,
+                            y: tile.y + block.y,
+                            ..block
+                        };
+                        progress((block.x, block.y), buffer.sample_count(block.x, block.y));
+                        stalled = self.trace_packet(
+                            bvh,
+                            block,
+                            |col, row| buffer.sample_count(col, row),
+                            iterations,
+                            &mut rng,
+                            &light_groups,
+                            deadline,
+                            &mut emit,
+                        );
+                        if let Some((pixel, sample, _)) = stalled {
+                            progress(pixel, sample);
+                            break;
+                        }
+                    }
+                }
+                None => {
+                    'pixels: for row in tile.y..tile.y + tile.height {
+                        for col in tile.x..tile.x + tile.width {
+                            let first = buffer.sample_count(col, row);
+                            progress((col, row), first);
+                            if let Some((sample, ray)) = self.trace_pixel(
+                                col,
+                                row,
+                                first..first + iterations,
+                                &mut rng,
+                                &light_groups,
+                                deadline,
+                                |x, y, color, aovs, rays| emit(col, row, x, y, color, aovs, rays),
+                            ) {
+                                progress((col, row), sample);
+                                stalled = Some(((col, row), sample, ray));
+                                break 'pixels;
+                            }
+                        }
+                    }
+                }
+            }
+            batch.resolve(self, &mut record);
+            if let (Some(watchdog), Some((slot, _))) = (&watchdog, flight)
+                && let Some(report) = watchdog.finish(slot, stalled.map(|(_, _, ray)| ray))
+            {
+                self.report_stall(&report);
+            }
+            if let Some(callback) = &self.tile_callback {
+                callback(&TileResult {
+                    tile,
+                    iterations,
+                    pixels: sums
+                        .into_iter()
+                        .map(|(sum, count)| sum / count.max(1) as f64)
+                        .collect(),
+                });
+            }
+
+            // Update progress bar after finish each tile
+            pb.inc(1);
+            if let Some(handle) = &self.handle {
+                handle.advance((tile.width * tile.height) as u64 * pass);
+            }
+            splat
+        };
+        let splat = self.watch_tiles(watchdog.as_ref(), || {
+            if self.deterministic {
+                tiles.into_iter().fold(new_splat(), trace_tile)
+            } else {
+                self.install(|| {
+                    tiles
+                        .into_iter()
+                        .par_bridge()
+                        .fold(new_splat, trace_tile)
+                        .reduce(new_splat, SplatBuffer::merge)
+                })
+            }
+        });
+
+        // Discard the unfinished pass of cancelled render.
+        if self.is_cancelled() {
+            pb.abandon();
+            return;
+        }
+        // The pixels of skipped tiles get no round.
+        let (colors, aovs) = splat.resolve();
+        let pixels = (0..height).flat_map(|y| (0..width).map(move |x| (x, y)));
+        for (((x, y), color), &count) in pixels.clone().zip(colors).zip(&counts) {
+            if count > 0 {
+                buffer.add_round(x, y, color, count);
+            }
+        }
+        for (name, aov) in self.aov_names().iter().zip(aovs) {
+            for (((x, y), color), &count) in pixels.clone().zip(aov).zip(&counts) {
+                if count > 0 {
+                    buffer.add_aov_round(name, x, y, color, count);
+                }
+            }
+        }
         pb.finish_with_message("Done!");
     }
 
+    /// Run `op` which traces the tiles of a pass, while the tiles running over the time limit
+    /// are reported by a watchdog thread if `watchdog` is given.
+    fn watch_tiles<R, OP>(&self, watchdog: Option<&Watchdog>, op: OP) -> R
+    where
+        OP: FnOnce() -> R,
+    {
+        let Some(watchdog) = watchdog else {
+            return op();
+        };
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            s.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    thread::sleep(watchdog.poll_interval());
+                    for report in watchdog.check() {
+                        self.report_stall(&report);
+                    }
+                }
+            });
+            let result = op();
+            done.store(true, Ordering::Relaxed);
+            result
+        })
+    }
+
+    /// Pass the diagnostics of a stalled tile to `self.stall_callback`, or print it.
+    fn report_stall(&self, report: &StallReport) {
+        match &self.stall_callback {
+            Some(callback) => callback(report),
+            None => eprintln!("{report}"),
+        }
+    }
+
+    /// Get the BVH to trace primary rays as packets, `None` if packet tracing isn't used.
+    fn packet_bvh(&self) -> Option<&Bvh> {
+        // The packet doesn't apply to paths without any bounce, custom integrators or section
+        // planes.
+        if self.packet_tracing
+            && self.max_bounces > 0
+            && self.scene.integrator.is_none()
+            && self.scene.sections.is_empty()
+        {
+            self.scene.bvh.as_ref()
+        } else {
+            None
+        }
+    }
+
+    /// Whether the render has been cancelled through `self.handle`.
+    fn is_cancelled(&self) -> bool {
+        self.handle.as_ref().is_some_and(RenderHandle::is_cancelled)
+    }
+
+    /// Run `op` in a thread pool of `self.threads` threads, or the global pool if it is zero.
+    pub(crate) fn install<R, OP>(&self, op: OP) -> R
+    where
+        R: Send,
+        OP: FnOnce() -> R + Send,
+    {
+        if self.threads == 0 {
+            return op();
+        }
+        // The pool is rebuilt only if `self.threads` has changed since, and the lock is released
+        // before `op` runs so that it can install again.
+        let pool = {
+            let mut cached = self.thread_pool.lock().unwrap();
+            match cached.as_ref() {
+                Some(pool) if pool.current_num_threads() == self.threads => Arc::clone(pool),
+                _ => {
+                    let pool = ThreadPoolBuilder::new()
+                        .num_threads(self.threads)
+                        .build()
+                        .expect("Failed to build thread pool.");
+                    Arc::clone(cached.insert(Arc::new(pool)))
+                }
+            }
+        };
+        pool.install(op)
+    }
+
+    /// Count the BVH traversal work of the primary ray through the center of each pixel of the
+    /// film, see `film_size`. Without BVH, every object is tested.
+    pub fn traversal_stats(&self) -> Vec<TraversalStats> {
+        let (width, height) = self.film_size();
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut stats = Vec::with_capacity((width * height) as usize);
+        for row in 0..height {
+            for col in 0..width {
+                let s = (col as f64 + 0.5) / width as f64;
+                let t = (row as f64 + 0.5) / height as f64;
+                let r = self.cam.get_ray(s, t, &mut rng);
+                let mut pixel = TraversalStats::default();
+                match &self.scene.bvh {
+                    Some(bvh) => {
+                        bvh.intersect_with_stats(&r, self.camera_interval(), &mut pixel);
+                    }
+                    None => pixel.primitive_tests = self.scene.objects.len() as u32,
+                }
+                stats.push(pixel);
+            }
+        }
+        stats
+    }
+
+    /// Visualize the BVH traversal work of primary rays as a heatmap of the film size, which is
+    /// normalized by the maximum of the image, see `traversal_stats`.
+    pub fn traversal_heatmap(&self, metric: TraversalMetric) -> RgbImage {
+        let values: Vec<f64> = self
+            .traversal_stats()
+            .iter()
+            .map(|stats| stats.get(metric) as f64)
+            .collect();
+        let max = values.iter().copied().fold(1.0, f64::max);
+        let (width, height) = self.film_size();
+        imgdiff::heatmap(&values, width, height, max)
+    }
+
+    /// Render the image for given scene and return `Buffer` which contains AOVs.
+    pub fn render_buffer(&self) -> Buffer {
+        // Stopping criteria can only be checked between passes, and cancellation keeps the
+        // finished passes only.
+        let progressive =
+            self.max_time.is_some() || self.target_noise.is_some() || self.handle.is_some();
+        self.render_passes(
+            |iterations_acc| {
+                if progressive {
+                    iterations_acc.max(1)
+                } else {
+                    self.num_samples
+                }
+            },
+            |_, _| {},
+        )
+    }
+
     /// Render the image for given scene and return `RgbImage`.
     pub fn render(&self) -> RgbImage {
-        let mut buffer = Buffer::new(self.width, self.height);
-        self.sample(self.num_samples, &mut buffer);
-        buffer.image()
+        self.post.image(&self.render_buffer())
+    }
+
+    /// Render the image for given scene and save it to `path` in the format of `self.writer`
+    /// or by the extension of path, see `ImageWriter`. The EXR file carries the render
+    /// metadata.
+    pub fn render_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let writer = match self.writer {
+            Some(writer) => writer,
+            None => ImageWriter::from_path(&path)?,
+        };
+        let start = Instant::now();
+        let buffer = self.render_buffer();
+        let metadata = RenderMetadata::new(self, &buffer).render_time(start.elapsed());
+        writer.write(&buffer, &self.post, Some(&metadata), path)
     }
 
     /// Render the image for given scene and call customized function for each epoch.
@@ -210,17 +1919,110 @@ impl Renderer {
     where
         F: Fn(u32, &Buffer),
     {
-        let mut buffer = Buffer::new(self.width, self.height);
+        self.render_passes(|_| interval, callback);
+    }
+
+    /// Render the image progressively, the whole frame is refined at 1, 2, 4, ... samples per
+    /// pixel until `self.num_samples`, and call customized function after each pass. Unlike
+    /// `iterative_render`, early passes are cheap so that the previews come quickly.
+    pub fn progressive_render<F>(&self, callback: F)
+    where
+        F: Fn(u32, &Buffer),
+    {
+        // Each pass doubles the total number of samples per pixel.
+        self.render_passes(|iterations_acc| iterations_acc.max(1), callback);
+    }
+
+    /// Render the image progressively like `progressive_render` and record the error after
+    /// each pass into `convergence`.
+    pub fn render_convergence(&self, convergence: &mut Convergence) -> Buffer {
+        let convergence = RefCell::new(convergence);
+        self.render_passes(
+            |iterations_acc| iterations_acc.max(1),
+            |iterations, buffer| convergence.borrow_mut().record(iterations, buffer),
+        )
+    }
+
+    /// Train the path guiding if any in the passes of 1, 2, 4, ... samples per pixel, whose
+    /// images are discarded.
+    fn train_guiding(&self) {
+        let Some(guide) = &self.path_guide else {
+            return;
+        };
+        let Some(bounds) = self.scene.bbox() else {
+            return;
+        };
+        guide.reset(bounds);
+        guide.set_recording(true);
+        for pass in 0..guide.training_passes {
+            if self.is_cancelled() {
+                break;
+            }
+            let (width, height) = self.film_size();
+            self.sample(1 << pass, &mut Buffer::new(width, height));
+            guide.refine(pass);
+        }
+        guide.set_recording(false);
+    }
+
+    /// Render the image in passes until `self.num_samples` or a stopping criterion is reached.
+    /// `step` gives the number of samples of next pass from the accumulated number, and
+    /// `callback` is called after each pass.
+    fn render_passes<S, F>(&self, step: S, callback: F) -> Buffer
+    where
+        S: Fn(u32) -> u32,
+        F: Fn(u32, &Buffer),
+    {
+        self.train_guiding();
+        let start = Instant::now();
+        let (width, height) = self.film_size();
+        let mut buffer = Buffer::new(width, height);
+        if let Some(history) = &self.temporal_history {
+            history.reproject(self, &mut buffer);
+        }
+        if let Some(handle) = &self.handle {
+            let pixels = width as u64 * height as u64;
+            handle.start(pixels * self.num_samples as u64);
+        }
         // The accumulate value is used in callback to get progress information.
         let mut iterations_acc = 0;
         // The max number to sample is `self.samples`, so we need limit it.
         // For each epoch, the sample result will be stored in corresponding pxiel position in `Buffer` which is
         // flatten pixel color array.
-        while iterations_acc < self.num_samples {
-            let step = interval.min(self.num_samples - iterations_acc);
+        while iterations_acc < self.num_samples && !self.is_cancelled() {
+            let mut step = step(iterations_acc).min(self.num_samples - iterations_acc);
+            if let Some(max_time) = self.max_time {
+                let elapsed = start.elapsed();
+                if elapsed >= max_time {
+                    break;
+                }
+                // Shrink the pass to fit the remaining time by the speed of previous passes.
+                if iterations_acc > 0 {
+                    let per_sample = elapsed.as_secs_f64() / iterations_acc as f64;
+                    let remaining = (max_time - elapsed).as_secs_f64();
+                    step = step.min((remaining / per_sample) as u32).max(1);
+                }
+            }
             self.sample(step, &mut buffer);
+            if self.is_cancelled() {
+                break;
+            }
             iterations_acc += step;
-            callback(iterations_acc, &buffer);
+            if self.draft_scale > 1 {
+                callback(iterations_acc, &buffer.upscale(self.width, self.height));
+            } else {
+                callback(iterations_acc, &buffer);
+            }
+            if let Some(target) = self.target_noise
+                && buffer.noise_estimate().is_some_and(|noise| noise <= target)
+            {
+                break;
+            }
+        }
+        if self.draft_scale > 1 {
+            buffer.upscale(self.width, self.height)
+        } else {
+            buffer
         }
     }
 }
@@ -228,18 +2030,252 @@ impl Renderer {
 impl Hittable for Renderer {
     /// Get closest intersection of ray with intersectable objects.
     fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        if let Some(bvh) = &self.scene.bvh {
-            return bvh.intersect(r, ray_t);
+        self.scene.intersect(r, ray_t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Object;
+    use crate::scene::Background;
+    use crate::shape::{quad::Quad, sphere::Sphere};
+
+    /// Estimate the irradiance at the point below the center of a unit quad light at height 1.
+    fn irradiance_below(visible: bool) -> f64 {
+        let light = Light::area(
+            Quad::new(
+                DVec3::new(-0.5, 1.0, -0.5),
+                DVec3::new(1.0, 0.0, 0.0),
+                DVec3::new(0.0, 0.0, 1.0),
+            ),
+            color::WHITE,
+            1.0,
+        );
+        let scene = if visible {
+            Scene::new().with_visible_light(light)
+        } else {
+            Scene::new().with_light(light)
+        };
+        let cam = Camera::new(
+            DVec3::new(0.0, 0.0, 3.0),
+            DVec3::ZERO,
+            DVec3::Y,
+            40.0,
+            1.0,
+            0.0,
+            1.0,
+        );
+        let renderer = Renderer::new(cam, scene.build_bvh()).max_bounces(4);
+        let mut rng = StdRng::seed_from_u64(1);
+        let n = 20_000;
+        let sum: Color = (0..n)
+            .map(|_| renderer.irradiance(DVec3::ZERO, DVec3::Y, 0.0, &mut rng))
+            .sum();
+        color::luminance(sum / n as f64)
+    }
+
+    #[test]
+    fn visible_area_light_is_not_counted_twice() {
+        let hidden = irradiance_below(false);
+        let visible = irradiance_below(true);
+        // The irradiance of a unit square of unit radiance at unit distance above its center.
+        let expected = 4.0 * (0.5 / 1.25f64.sqrt()) * (0.5 / 1.25f64.sqrt()).atan();
+        assert!(
+            (hidden - expected).abs() < 0.02 * expected,
+            "{hidden} vs {expected}"
+        );
+        assert!(
+            (visible - hidden).abs() < 0.02 * hidden,
+            "{visible} vs {hidden}"
+        );
+    }
+
+    #[test]
+    fn deterministic_renders_of_motion_blur_are_identical() {
+        let render = || {
+            let scene = Scene::new()
+                .with_obj(Object::new(Sphere::new(
+                    DVec3::new(-0.5, 0.0, 0.0),
+                    Some(DVec3::new(0.5, 0.0, 0.0)),
+                    0.5,
+                )))
+                .background(Background::Color(color::WHITE))
+                .build_bvh();
+            let cam = Camera::new(
+                DVec3::new(0.0, 0.0, 3.0),
+                DVec3::ZERO,
+                DVec3::Y,
+                40.0,
+                1.0,
+                0.0,
+                1.0,
+            );
+            Renderer::new(cam, scene)
+                .width(16)
+                .height(16)
+                .num_samples(4)
+                .deterministic(true)
+                .render()
+        };
+        assert_eq!(render().into_raw(), render().into_raw());
+    }
+
+    #[test]
+    fn adaptive_tiles_skip_converged_tiles() {
+        // The sphere lit by a small light is noisy, while the background is converged after
+        // the first passes.
+        let light = Light::area(
+            Quad::new(
+                DVec3::new(-0.1, 2.0, -0.1),
+                DVec3::new(0.2, 0.0, 0.0),
+                DVec3::new(0.0, 0.0, 0.2),
+            ),
+            color::WHITE,
+            50.0,
+        );
+        let scene = Scene::new()
+            .with_obj(Object::new(Sphere::new(
+                DVec3::new(-0.6, 0.0, 0.0),
+                None,
+                0.4,
+            )))
+            .with_light(light)
+            .background(Background::Color(color::WHITE))
+            .build_bvh();
+        let cam = Camera::new(
+            DVec3::new(0.0, 0.0, 3.0),
+            DVec3::ZERO,
+            DVec3::Y,
+            40.0,
+            1.0,
+            0.0,
+            1.0,
+        );
+        let handle = RenderHandle::new();
+        let buffer = Renderer::new(cam, scene)
+            .width(32)
+            .height(32)
+            .tile_size(8)
+            .num_samples(64)
+            .tile_order(TileOrder::Adaptive)
+            .target_noise(1e-3)
+            .handle(handle.clone())
+            .render_buffer();
+        // The center of the sphere and a corner of the background.
+        let (sphere, background) = (buffer.sample_count(8, 16), buffer.sample_count(28, 4));
+        assert!(sphere > 2 * background, "{sphere} vs {background} samples");
+        // The boosted tiles stay within the samples, and the progress counts the passes.
+        let counts = (0..32).flat_map(|y| (0..32).map(move |x| (x, y)));
+        assert_eq!(
+            counts.map(|(x, y)| buffer.sample_count(x, y)).max(),
+            Some(64)
+        );
+        assert_eq!(handle.progress(), 1.0);
+    }
+
+    #[test]
+    fn thread_pool_is_reused_by_passes() {
+        let cam = Camera::new(DVec3::Z, DVec3::ZERO, DVec3::Y, 40.0, 1.0, 0.0, 1.0);
+        let mut renderer = Renderer::new(cam, Scene::new()).threads(2);
+        let pool = || {
+            renderer
+                .thread_pool
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(Arc::as_ptr)
+        };
+        assert_eq!(renderer.install(rayon::current_num_threads), 2);
+        let first = pool();
+        assert_eq!(renderer.install(rayon::current_num_threads), 2);
+        assert!(first.is_some() && first == pool());
+        renderer.threads = 3;
+        assert_eq!(renderer.install(rayon::current_num_threads), 3);
+    }
+
+    #[test]
+    fn traversal_stats_cover_the_draft_film() {
+        let cam = Camera::new(DVec3::Z * 3.0, DVec3::ZERO, DVec3::Y, 40.0, 2.0, 0.0, 1.0);
+        let scene = Scene::new()
+            .with_obj(Object::new(Sphere::new(DVec3::ZERO, None, 0.5)))
+            .build_bvh();
+        let renderer = Renderer::new(cam, scene).width(30).height(15).draft(4);
+        assert_eq!(renderer.traversal_stats().len(), 8 * 4);
+        let heatmap = renderer.traversal_heatmap(TraversalMetric::NodeVisits);
+        assert_eq!(heatmap.dimensions(), (8, 4));
+    }
+
+    #[test]
+    fn shadow_batching_keeps_clamped_renders() {
+        // The floor and the sphere under a small light bounce the direct light to each other,
+        // which the low clamp cuts.
+        let render = |batching: bool| {
+            let light = Light::area(
+                Quad::new(
+                    DVec3::new(-0.1, 1.5, -0.1),
+                    DVec3::new(0.2, 0.0, 0.0),
+                    DVec3::new(0.0, 0.0, 0.2),
+                ),
+                color::WHITE,
+                100.0,
+            );
+            let floor = Quad::new(
+                DVec3::new(-2.0, -0.5, -2.0),
+                DVec3::new(0.0, 0.0, 4.0),
+                DVec3::new(4.0, 0.0, 0.0),
+            );
+            let scene = Scene::new()
+                .with_obj(Object::new(Sphere::new(DVec3::ZERO, None, 0.5)))
+                .with_obj(Object::new(floor))
+                .with_light(light)
+                .build_bvh();
+            let cam = Camera::new(
+                DVec3::new(0.0, 0.5, 3.0),
+                DVec3::ZERO,
+                DVec3::Y,
+                40.0,
+                1.0,
+                0.0,
+                1.0,
+            );
+            Renderer::new(cam, scene)
+                .width(16)
+                .height(16)
+                .num_samples(8)
+                .max_bounces(4)
+                .indirect_clamp(IndirectClamp::new(0.05))
+                .shadow_batching(batching)
+                .deterministic(true)
+                .render_buffer()
+                .colors()
+        };
+        let (batched, unbatched) = (render(true), render(false));
+        for (a, b) in batched.iter().zip(&unbatched) {
+            assert!(
+                a.abs_diff_eq(*b, 1e-9 * b.max_element().max(1.0)),
+                "{a} vs {b}"
+            );
         }
-        let mut rec = None;
-        let mut closest_so_far = ray_t.max;
-        for obj in &self.scene.objects {
-            let search_interval = Interval::new(ray_t.min, closest_so_far);
-            if let Some(obj_rec) = obj.intersect(r, search_interval) {
-                closest_so_far = obj_rec.t;
-                rec = Some(obj_rec);
-            }
+    }
+
+    #[test]
+    fn recorded_paths_name_the_hit_objects() {
+        // The camera looks at the second of the spheres, the first one is behind it.
+        let scene = Scene::new()
+            .with_obj(Object::new(Sphere::new(
+                DVec3::new(0.0, 0.0, -3.0),
+                None,
+                0.5,
+            )))
+            .with_obj(Object::new(Sphere::new(DVec3::ZERO, None, 0.5)))
+            .background(Background::Color(color::WHITE))
+            .build_bvh();
+        let cam = Camera::new(DVec3::Z * 3.0, DVec3::ZERO, DVec3::Y, 40.0, 1.0, 0.0, 1.0);
+        let renderer = Renderer::new(cam, scene).width(9).height(9).max_bounces(1);
+        for path in renderer.record_paths(4, 4, 4, 1) {
+            assert_eq!(path.vertices[0].object, None);
+            assert_eq!(path.vertices[1].object, Some(1));
         }
-        rec
     }
 }
@@ -0,0 +1,202 @@
+/// The largest f64 which is less than 1.0.
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+/// The prime bases of the dimensions of Halton sequence after the two of `halton_2d`.
+const PRIMES: [u32; 16] = [5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61];
+
+/// Get the radical inverse of `index` in `base`, which is the `index`-th point of van der
+/// Corput sequence.
+pub fn radical_inverse(base: u32, mut index: u64) -> f64 {
+    let base = base as u64;
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_n = inv_base;
+    let mut result = 0.0;
+    while index > 0 {
+        result += (index % base) as f64 * inv_base_n;
+        index /= base;
+        inv_base_n *= inv_base;
+    }
+    result.min(ONE_MINUS_EPSILON)
+}
+
+/// Get the `index`-th point of 2D Halton sequence with bases 2 and 3, which is rotated by `offset`
+/// (Cranley-Patterson rotation) to decorrelate the pixels.
+pub fn halton_2d(index: u64, offset: (f64, f64)) -> (f64, f64) {
+    let x = radical_inverse(2, index) + offset.0;
+    let y = radical_inverse(3, index) + offset.1;
+    (x.fract(), y.fract())
+}
+
+/// Get the `index`-th point of the dimension `dim` of Halton sequence after the two of
+/// `halton_2d`, which is rotated by `offset`. The dimensions beyond the table of bases wrap
+/// around.
+pub fn halton_1d(dim: usize, index: u64, offset: f64) -> f64 {
+    (radical_inverse(PRIMES[dim % PRIMES.len()], index) + offset).fract()
+}
+
+/// Get the fixed random offset of a pixel by hashing its location, so that the sequence of a
+/// pixel continues across rendering passes.
+pub fn pixel_offset(x: u32, y: u32) -> (f64, f64) {
+    let hash = splitmix64(((y as u64) << 32) | x as u64);
+    let to_unit = |bits: u64| (bits >> 11) as f64 / (1u64 << 53) as f64;
+    (to_unit(hash), to_unit(splitmix64(hash)))
+}
+
+/// Get the seed of random number generator from a location and a pass `index`, e.g. to
+/// reproduce the random numbers of a tile.
+pub fn seed(x: u32, y: u32, index: u32) -> u64 {
+    splitmix64(splitmix64(((y as u64) << 32) | x as u64) ^ index as u64)
+}
+
+/// Get the fixed random seed of a pixel for `sobol_2d`, in the same way as `pixel_offset`.
+pub fn pixel_seed(x: u32, y: u32) -> u32 {
+    (splitmix64(((y as u64) << 32) | x as u64) >> 32) as u32
+}
+
+/// Get the `index`-th point of 2D Sobol sequence with Owen scrambling by `seed`. Different
+/// seeds give the independent randomizations of the sequence, which keep its stratification
+/// unlike Cranley-Patterson rotation. The index is also shuffled, so that a prefix of any
+/// length is a well distributed set. `index` is wrapped at 2^32.
+/// References:
+/// Burley 2020, Practical Hash-based Owen Scrambling
+pub fn sobol_2d(index: u64, seed: u32) -> (f64, f64) {
+    let index = nested_uniform_scramble(index as u32, seed);
+    let x = nested_uniform_scramble(index.reverse_bits(), hash_combine(seed, 0));
+    let y = nested_uniform_scramble(sobol_dim1(index), hash_combine(seed, 1));
+    (to_unit_f64(x), to_unit_f64(y))
+}
+
+/// Get the second dimension of Sobol sequence in fixed point, whose generator matrix is
+/// built from the direction numbers v_i = v_(i-1) ^ (v_(i-1) >> 1). The first dimension is
+/// simply the reversed bits of index.
+fn sobol_dim1(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut v = 1 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+/// Owen scramble the fixed point number `x` in [0, 1), where each bit is flipped depending on
+/// the random hash of its higher bits.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// A hash whose each bit only depends on the lower bits of `x`, which performs Owen
+/// scrambling on the reversed bits.
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6C50_B47C);
+    x ^= x.wrapping_mul(0xB82F_1E52);
+    x ^= x.wrapping_mul(0xC7AF_E638);
+    x ^= x.wrapping_mul(0x8D22_F6E6);
+    x
+}
+
+/// Get the fixed random number in [0, 1) of a dimension of `seed`, e.g. the variations of an
+/// instance from its seed.
+pub fn hash_unit(seed: u32, dim: u32) -> f64 {
+    to_unit_f64(hash_combine(seed, dim))
+}
+
+/// Derive the seed of a dimension from the seed of sequence.
+pub fn hash_combine(seed: u32, dim: u32) -> u32 {
+    (splitmix64(((seed as u64) << 32) | dim as u64) >> 32) as u32
+}
+
+/// Map a 32-bit fixed point number to [0, 1).
+fn to_unit_f64(x: u32) -> f64 {
+    (x as f64 / (1u64 << 32) as f64).min(ONE_MINUS_EPSILON)
+}
+
+/// SplitMix64 hash function.
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether each of the elementary intervals of area 2^-k has exactly one of the points,
+    /// whose number is 2^k, i.e. the points are a (0, k, 2)-net in base 2.
+    fn is_net(points: &[(f64, f64)]) -> bool {
+        let k = points.len().trailing_zeros();
+        (0..=k).all(|a| {
+            let (nx, ny) = (1usize << a, 1usize << (k - a));
+            let mut counts = vec![0u32; points.len()];
+            for &(x, y) in points {
+                counts[(y * ny as f64) as usize * nx + (x * nx as f64) as usize] += 1;
+            }
+            counts.iter().all(|&count| count == 1)
+        })
+    }
+
+    #[test]
+    fn sobol_prefixes_are_stratified() {
+        for seed in [0, 1, 0xDEAD_BEEF, pixel_seed(17, 42)] {
+            for k in 0..=10 {
+                let points: Vec<_> = (0..1 << k).map(|i| sobol_2d(i, seed)).collect();
+                assert!(is_net(&points), "seed {seed} with 2^{k} points");
+            }
+        }
+    }
+
+    #[test]
+    fn sobol_seeds_decorrelate() {
+        let sequence = |seed| (0..256).map(|i| sobol_2d(i, seed)).collect::<Vec<_>>();
+        let (a, b) = (sequence(pixel_seed(0, 0)), sequence(pixel_seed(1, 0)));
+        assert!(is_net(&a) && is_net(&b));
+        let same = a.iter().zip(&b).filter(|(p, q)| p == q).count();
+        assert!(same < 4, "{same} of the points are the same");
+    }
+
+    #[test]
+    fn halton_is_uniform() {
+        // The χ² of the counts in the bins, which is far below its expected value of
+        // `BINS - 1` for random points, as the low discrepancy points fill the bins evenly.
+        // Half of it is more than 5 standard deviations below.
+        const BINS: usize = 256;
+        const POINTS: usize = 4096;
+        let chi2 = |bins: &[u32]| {
+            let expected = (POINTS / BINS) as f64;
+            bins.iter()
+                .map(|&count| (count as f64 - expected).powi(2) / expected)
+                .sum::<f64>()
+        };
+        for (x, y) in [(0, 0), (3, 5), (640, 480)] {
+            let offset = pixel_offset(x, y);
+            let mut bins = vec![0u32; BINS];
+            for i in 0..POINTS as u64 {
+                let (u, v) = halton_2d(i, offset);
+                bins[(v * 16.0) as usize * 16 + (u * 16.0) as usize] += 1;
+            }
+            assert!(chi2(&bins) < BINS as f64 / 2.0, "χ² = {}", chi2(&bins));
+            for dim in [0, 5, 15] {
+                let mut bins = vec![0u32; BINS];
+                for i in 0..POINTS as u64 {
+                    bins[(halton_1d(dim, i, offset.0) * BINS as f64) as usize] += 1;
+                }
+                assert!(chi2(&bins) < BINS as f64 / 2.0, "χ² = {}", chi2(&bins));
+            }
+        }
+    }
+
+    #[test]
+    fn pixel_offsets_differ() {
+        let offsets: Vec<_> = (0..64).map(|i| pixel_offset(i % 8, i / 8)).collect();
+        for (i, a) in offsets.iter().enumerate() {
+            assert!((0.0..1.0).contains(&a.0) && (0.0..1.0).contains(&a.1));
+            assert!(offsets[i + 1..].iter().all(|b| a != b));
+        }
+    }
+}
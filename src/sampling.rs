@@ -0,0 +1,430 @@
+use std::f64::consts::{FRAC_1_PI, FRAC_PI_2, FRAC_PI_4, PI, TAU};
+
+use glam::DVec3;
+
+// The functions map uniform random numbers `u` in [0, 1)^2 to the samples, so that they work
+// with both random and low discrepancy sequences. Directions are in the local space whose z
+// axis is the normal, see `ONB`.
+
+/// Map `u` to a point on the unit disk by the concentric mapping, which preserves the
+/// stratification of `u`.
+/// References:
+/// Shirley and Chiu 1997, A Low Distortion Map Between Disk and Square
+pub fn concentric_disk(u: (f64, f64)) -> (f64, f64) {
+    let (x, y) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+    if x == 0.0 && y == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, FRAC_PI_4 * (y / x))
+    } else {
+        (y, FRAC_PI_2 - FRAC_PI_4 * (x / y))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+/// Get the PDF of `concentric_disk` over the area of unit disk.
+pub const fn concentric_disk_pdf() -> f64 {
+    FRAC_1_PI
+}
+
+/// Sample a direction on the hemisphere with the density proportional to cosθ by projecting
+/// the disk up (Malley's method).
+pub fn cosine_hemisphere(u: (f64, f64)) -> DVec3 {
+    let (x, y) = concentric_disk(u);
+    let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+    DVec3::new(x, y, z)
+}
+
+/// Get the PDF of `cosine_hemisphere` for the direction with `cos_theta`.
+pub fn cosine_hemisphere_pdf(cos_theta: f64) -> f64 {
+    cos_theta.max(0.0) * FRAC_1_PI
+}
+
+/// Sample a direction uniformly on the unit sphere.
+pub fn uniform_sphere(u: (f64, f64)) -> DVec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let (sin_phi, cos_phi) = (TAU * u.1).sin_cos();
+    DVec3::new(r * cos_phi, r * sin_phi, z)
+}
+
+/// Get the PDF of `uniform_sphere` over solid angle.
+pub const fn uniform_sphere_pdf() -> f64 {
+    1.0 / (4.0 * PI)
+}
+
+/// Sample a direction uniformly in the cone around z axis whose half angle has cosine
+/// `cos_max`, e.g. towards a spherical light.
+pub fn uniform_cone(u: (f64, f64), cos_max: f64) -> DVec3 {
+    let cos_t = 1.0 - u.0 * (1.0 - cos_max);
+    let sin_t = (1.0 - cos_t * cos_t).max(0.0).sqrt();
+    let (sin_phi, cos_phi) = (TAU * u.1).sin_cos();
+    DVec3::new(sin_t * cos_phi, sin_t * sin_phi, cos_t)
+}
+
+/// Get the PDF of `uniform_cone` over solid angle.
+pub fn uniform_cone_pdf(cos_max: f64) -> f64 {
+    1.0 / (TAU * (1.0 - cos_max))
+}
+
+/// Sample the microfacet normal from Beckmann distribution with roughness `alpha` by
+/// Probability Integral Transform.
+pub fn beckmann(u: (f64, f64), alpha: f64) -> DVec3 {
+    // θ = arctan √(-α^2 ln(1 - U))
+    let tan2_t = -alpha * alpha * (1.0 - u.0).ln();
+    let cos_t = (1.0 + tan2_t).sqrt().recip();
+    let sin_t = (1.0 - cos_t * cos_t).max(0.0).sqrt();
+    let (sin_phi, cos_phi) = (TAU * u.1).sin_cos();
+    DVec3::new(sin_t * cos_phi, sin_t * sin_phi, cos_t)
+}
+
+/// Get the PDF of `beckmann` for the microfacet normal with `cos_theta`, which equals to
+/// D(h) * cosθ.
+pub fn beckmann_pdf(alpha: f64, cos_theta: f64) -> f64 {
+    // p = 1 / (π α^2 cos^3 θ) * e^(-tan^2(θ) / α^2)
+    let a2 = alpha * alpha;
+    let cos2_t = cos_theta * cos_theta;
+    let tan2_t = (1.0 - cos2_t) / cos2_t;
+    (-tan2_t / a2).exp() / (PI * a2 * cos2_t * cos_theta.abs())
+}
+
+/// Sample the microfacet normal from GGX distribution with roughness `alpha`, which is the
+/// square of perceptual roughness in `ndf::ggx`.
+pub fn ggx(u: (f64, f64), alpha: f64) -> DVec3 {
+    // tan^2 θ = α^2 U / (1 - U)
+    let tan2_t = alpha * alpha * u.0 / (1.0 - u.0);
+    let cos_t = (1.0 + tan2_t).sqrt().recip();
+    let sin_t = (1.0 - cos_t * cos_t).max(0.0).sqrt();
+    let (sin_phi, cos_phi) = (TAU * u.1).sin_cos();
+    DVec3::new(sin_t * cos_phi, sin_t * sin_phi, cos_t)
+}
+
+/// Get the GGX distribution D(h) with roughness `alpha` for the normal with `cos_theta`.
+pub fn ggx_d(alpha: f64, cos_theta: f64) -> f64 {
+    let a2 = alpha * alpha;
+    let cos2_t = cos_theta * cos_theta;
+    a2 * FRAC_1_PI / (cos2_t * (a2 - 1.0) + 1.0).powi(2)
+}
+
+/// Get the PDF of `ggx` for the microfacet normal with `cos_theta`, which equals to
+/// D(h) * cosθ.
+pub fn ggx_pdf(alpha: f64, cos_theta: f64) -> f64 {
+    ggx_d(alpha, cos_theta) * cos_theta.abs()
+}
+
+/// Get Smith's masking function G1 of GGX distribution for the direction `w`.
+pub fn ggx_g1(alpha: f64, w: DVec3) -> f64 {
+    let cos2_t = w.z * w.z;
+    let tan2_t = (1.0 - cos2_t).max(0.0) / cos2_t;
+    let lambda = ((1.0 + alpha * alpha * tan2_t).sqrt() - 1.0) / 2.0;
+    1.0 / (1.0 + lambda)
+}
+
+/// Sample the microfacet normal visible from the view direction `v` (z >= 0) in GGX
+/// distribution, which avoids the samples of back facing microfacets.
+/// References:
+/// Heitz 2018, Sampling the GGX Distribution of Visible Normals
+pub fn ggx_vndf(u: (f64, f64), alpha: f64, v: DVec3) -> DVec3 {
+    // Stretch the view direction to the hemisphere configuration.
+    let vh = DVec3::new(alpha * v.x, alpha * v.y, v.z).normalize();
+    let len2 = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if len2 > 0.0 {
+        DVec3::new(-vh.y, vh.x, 0.0) / len2.sqrt()
+    } else {
+        DVec3::X
+    };
+    let t2 = vh.cross(t1);
+    // Sample the projected area of the hemisphere.
+    let r = u.0.sqrt();
+    let (sin_phi, cos_phi) = (TAU * u.1).sin_cos();
+    let p1 = r * cos_phi;
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * sin_phi;
+    let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+    // Unstretch back to the ellipsoid configuration.
+    DVec3::new(alpha * nh.x, alpha * nh.y, nh.z.max(1e-6)).normalize()
+}
+
+/// Get the PDF of `ggx_vndf` for the microfacet normal `h`, which equals to
+/// G1(v) * max(0, v • h) * D(h) / cosθ_v.
+pub fn ggx_vndf_pdf(alpha: f64, v: DVec3, h: DVec3) -> f64 {
+    if v.z <= 0.0 || h.z <= 0.0 {
+        return 0.0;
+    }
+    ggx_g1(alpha, v) * v.dot(h).max(0.0) * ggx_d(alpha, h.z) / v.z
+}
+
+/// Sample a distance in [t_min, t_max] along the ray from `origin` in unit direction `dir`
+/// with the density proportional to the inverse squared distance to a point light at
+/// `light`, which is the equiangular sampling for single scattering in participating media.
+/// Return the distance and its PDF.
+/// References:
+/// Kulla and Fajardo 2012, Importance Sampling Techniques for Path Tracing in Participating Media
+pub fn equiangular(
+    u: f64,
+    origin: DVec3,
+    dir: DVec3,
+    light: DVec3,
+    t_min: f64,
+    t_max: f64,
+) -> (f64, f64) {
+    // The distance along the ray to the closest point to the light, and the distance from
+    // the light to the ray.
+    let delta = (light - origin).dot(dir);
+    let d = (origin + delta * dir - light).length().max(1e-9);
+    let theta_a = ((t_min - delta) / d).atan();
+    let theta_b = ((t_max - delta) / d).atan();
+    let t = delta + d * (theta_a + u * (theta_b - theta_a)).tan();
+    (t, equiangular_pdf(t, origin, dir, light, t_min, t_max))
+}
+
+/// Get the PDF of `equiangular` for the distance `t`.
+pub fn equiangular_pdf(
+    t: f64,
+    origin: DVec3,
+    dir: DVec3,
+    light: DVec3,
+    t_min: f64,
+    t_max: f64,
+) -> f64 {
+    if t < t_min || t > t_max {
+        return 0.0;
+    }
+    let delta = (light - origin).dot(dir);
+    let d = (origin + delta * dir - light).length().max(1e-9);
+    let theta_a = ((t_min - delta) / d).atan();
+    let theta_b = ((t_max - delta) / d).atan();
+    d / ((theta_b - theta_a) * (d * d + (t - delta).powi(2)))
+}
+
+/// Sample the barycentric coordinates (b1, b2) of a point uniformly on a triangle, and the
+/// coordinate of the first vertex is 1 - b1 - b2.
+pub fn triangle_barycentric(u: (f64, f64)) -> (f64, f64) {
+    let s = u.0.sqrt();
+    (1.0 - s, u.1 * s)
+}
+
+/// Get the PDF of `triangle_barycentric` over the area of triangle.
+pub fn triangle_pdf(area: f64) -> f64 {
+    area.recip()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    /// The number of samples drawn from each sampler.
+    const SAMPLES: usize = 100_000;
+
+    /// The resolution of the bins of the histograms in each dimension, and the number of points
+    /// per dimension which integrate the PDF over a bin.
+    const BINS: usize = 24;
+    const STEPS: usize = 32;
+
+    /// Check the histogram of `SAMPLES` samples drawn into the bins against the probabilities
+    /// of the bins by the χ² test. The bins whose expected counts are too low are pooled.
+    fn assert_histogram(expected: &[f64], mut bin: impl FnMut(&mut StdRng) -> Option<usize>) {
+        let total: f64 = expected.iter().sum();
+        assert!((total - 1.0).abs() < 1e-2, "PDF integrates to {total}");
+
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut counts = vec![0u32; expected.len()];
+        for _ in 0..SAMPLES {
+            let i = bin(&mut rng).expect("The sample is out of the domain");
+            counts[i] += 1;
+        }
+        let (mut chi2, mut dof) = (0.0, 0);
+        let (mut pooled_count, mut pooled_expected) = (0.0, 0.0);
+        for (&count, &p) in counts.iter().zip(expected) {
+            let count = count as f64;
+            let p = p * SAMPLES as f64;
+            if p == 0.0 {
+                assert_eq!(count, 0.0, "The sample is out of the support of PDF");
+            } else if p < 5.0 {
+                (pooled_count, pooled_expected) = (pooled_count + count, pooled_expected + p);
+            } else {
+                chi2 += (count - p).powi(2) / p;
+                dof += 1;
+            }
+        }
+        if pooled_expected > 0.0 {
+            chi2 += (pooled_count - pooled_expected).powi(2) / pooled_expected;
+            dof += 1;
+        }
+        // The χ² of `dof - 1` degrees of freedom is far below the bound unless the histogram
+        // doesn't match.
+        let dof = (dof - 1) as f64;
+        assert!(
+            chi2 < dof + 6.0 * (2.0 * dof).sqrt(),
+            "χ² = {chi2} of {dof} dof"
+        );
+    }
+
+    /// Check the sampler of directions against its PDF over solid angle, binned by z and φ
+    /// whose bins have equal solid angles.
+    fn check_directions(sample: impl Fn((f64, f64)) -> DVec3, pdf: impl Fn(DVec3) -> f64) {
+        let direction = |z: f64, phi: f64| {
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            DVec3::new(r * phi.cos(), r * phi.sin(), z)
+        };
+        // The bins and their integration points are spaced evenly in z and φ.
+        let step = 1.0 / (BINS * STEPS) as f64;
+        let expected: Vec<f64> = (0..BINS * BINS)
+            .map(|bin| {
+                let (i, j) = (bin / BINS, bin % BINS);
+                let sum: f64 = (0..STEPS * STEPS)
+                    .map(|k| {
+                        let z = ((i * STEPS + k / STEPS) as f64 + 0.5) * step;
+                        let phi = ((j * STEPS + k % STEPS) as f64 + 0.5) * step;
+                        pdf(direction(2.0 * z - 1.0, TAU * phi))
+                    })
+                    .sum();
+                sum * 4.0 * PI * step * step
+            })
+            .collect();
+        assert_histogram(&expected, |rng| {
+            let d = sample(rng.random());
+            let bin = |x: f64| ((x * BINS as f64) as usize).min(BINS - 1);
+            let phi = d.y.atan2(d.x).rem_euclid(TAU);
+            Some(bin((d.z + 1.0) / 2.0) * BINS + bin(phi / TAU))
+        });
+    }
+
+    /// Check the sampler of points on the square `[min, max]^2` against its PDF over area.
+    fn check_points(
+        sample: impl Fn((f64, f64)) -> (f64, f64),
+        pdf: impl Fn(f64, f64) -> f64,
+        (min, max): (f64, f64),
+    ) {
+        let size = (max - min) / BINS as f64;
+        let step = size / STEPS as f64;
+        let expected: Vec<f64> = (0..BINS * BINS)
+            .map(|bin| {
+                let (i, j) = (bin / BINS, bin % BINS);
+                let sum: f64 = (0..STEPS * STEPS)
+                    .map(|k| {
+                        let x = min + i as f64 * size + ((k / STEPS) as f64 + 0.5) * step;
+                        let y = min + j as f64 * size + ((k % STEPS) as f64 + 0.5) * step;
+                        pdf(x, y)
+                    })
+                    .sum();
+                sum * step * step
+            })
+            .collect();
+        assert_histogram(&expected, |rng| {
+            let (x, y) = sample(rng.random());
+            let (i, j) = (((x - min) / size).floor(), ((y - min) / size).floor());
+            let range = 0.0..BINS as f64;
+            (range.contains(&i) && range.contains(&j)).then_some(i as usize * BINS + j as usize)
+        });
+    }
+
+    #[test]
+    fn cosine_hemisphere_matches_pdf() {
+        check_directions(cosine_hemisphere, |d| cosine_hemisphere_pdf(d.z));
+    }
+
+    #[test]
+    fn uniform_sphere_matches_pdf() {
+        check_directions(uniform_sphere, |_| uniform_sphere_pdf());
+    }
+
+    #[test]
+    fn uniform_cone_matches_pdf() {
+        for cos_max in [0.5, -0.25] {
+            check_directions(
+                |u| uniform_cone(u, cos_max),
+                |d| {
+                    if d.z >= cos_max {
+                        uniform_cone_pdf(cos_max)
+                    } else {
+                        0.0
+                    }
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn microfacet_normals_match_pdf() {
+        for alpha in [0.3, 0.8] {
+            let upper = |pdf: f64, d: DVec3| if d.z > 0.0 { pdf } else { 0.0 };
+            check_directions(|u| ggx(u, alpha), |h| upper(ggx_pdf(alpha, h.z), h));
+            check_directions(
+                |u| beckmann(u, alpha),
+                |h| upper(beckmann_pdf(alpha, h.z), h),
+            );
+            for v in [
+                DVec3::Z,
+                DVec3::new(0.6, 0.0, 0.8),
+                DVec3::new(-0.3, 0.9, 0.1),
+            ] {
+                let v = v.normalize();
+                check_directions(|u| ggx_vndf(u, alpha, v), |h| ggx_vndf_pdf(alpha, v, h));
+            }
+        }
+    }
+
+    #[test]
+    fn concentric_disk_matches_pdf() {
+        check_points(
+            concentric_disk,
+            |x, y| {
+                if x * x + y * y <= 1.0 {
+                    concentric_disk_pdf()
+                } else {
+                    0.0
+                }
+            },
+            (-1.0, 1.0),
+        );
+    }
+
+    #[test]
+    fn triangle_matches_pdf() {
+        // The barycentric coordinates span the triangle of area 1/2.
+        check_points(
+            triangle_barycentric,
+            |b1, b2| {
+                if b1 + b2 <= 1.0 {
+                    triangle_pdf(0.5)
+                } else {
+                    0.0
+                }
+            },
+            (0.0, 1.0),
+        );
+    }
+
+    #[test]
+    fn equiangular_matches_pdf() {
+        let (origin, dir, light) = (DVec3::ZERO, DVec3::X, DVec3::new(2.0, 0.5, 0.0));
+        for (t_min, t_max) in [(0.0, 4.0), (2.5, 10.0)] {
+            let width = (t_max - t_min) / (BINS * BINS) as f64;
+            let step = width / STEPS as f64;
+            let expected: Vec<f64> = (0..BINS * BINS)
+                .map(|bin| {
+                    let sum: f64 = (0..STEPS)
+                        .map(|k| {
+                            let t = t_min + bin as f64 * width + (k as f64 + 0.5) * step;
+                            equiangular_pdf(t, origin, dir, light, t_min, t_max)
+                        })
+                        .sum();
+                    sum * step
+                })
+                .collect();
+            assert_histogram(&expected, |rng| {
+                let (t, pdf) = equiangular(rng.random(), origin, dir, light, t_min, t_max);
+                let expected = equiangular_pdf(t, origin, dir, light, t_min, t_max);
+                assert!((pdf - expected).abs() <= 1e-9 * expected);
+                let i = ((t - t_min) / width).floor();
+                (0.0..(BINS * BINS) as f64)
+                    .contains(&i)
+                    .then_some(i as usize)
+            });
+        }
+    }
+}
@@ -1,10 +1,42 @@
+use std::{any::Any, collections::HashSet, f64::consts::PI, fs, io, path::Path, sync::Arc};
+
 use glam::DVec3;
 use image::ImageReader;
 
+use crate::camera::Camera;
 use crate::color::{self, Color};
 use crate::image::HdrImage;
-use crate::light::Light;
-use crate::{bvh::BvhNode, object::Object};
+use crate::light::{Light, LightDistribution};
+use crate::{
+    aabb::Aabb,
+    bvh::{Bvh, DEFAULT_LEAF_SIZE, cache, wide::WideBvh},
+    clip::{self, SectionPlane},
+    integrator::Integrator,
+    interval::Interval,
+    material::{Bsdf, library::MaterialLibrary},
+    math::{DPoint3, Ray},
+    memory::{MemoryBudget, MemoryReport},
+    object::{Object, Sides},
+    sampler, sampling,
+    shape::{Bounded, HitRecord, Hittable, Instance, quad::Quad},
+};
+
+pub mod file;
+pub mod load;
+#[cfg(feature = "scripting")]
+pub mod script;
+
+use load::SceneLoader;
+
+/// The name of light group for the lights without a group.
+pub const DEFAULT_LIGHT_GROUP: &str = "default";
+
+/// The name of light group for the background.
+pub const BACKGROUND_LIGHT_GROUP: &str = "background";
+
+/// The minimal fraction of the probe rays of `Scene::detect_portals` which escape through a face
+/// of the scene bounds for the face to get a portal, which ignores tiny gaps between walls.
+const MIN_PORTAL_FRACTION: f64 = 0.001;
 
 #[derive(Default)]
 pub struct Scene {
@@ -14,11 +46,38 @@ pub struct Scene {
     /// The list of lights in the scene.
     pub lights: Vec<Light>,
 
+    /// The light group of each light in `lights`. Missing entries belong to the default group.
+    pub light_groups: Vec<Option<Arc<str>>>,
+
     /// The BVH for the scene.
-    pub bvh: Option<BvhNode>,
+    pub bvh: Option<Bvh>,
+
+    /// The wide BVH converted from `bvh`, which is preferred in ray intersection.
+    pub wide_bvh: Option<Box<dyn Bounded>>,
 
     /// The background color of the scene
     pub background: Background,
+
+    /// The openings through which all the light of background enters the scene, e.g. windows
+    /// of interiors. If any, the background is sampled through them as area lights.
+    pub portals: Vec<Quad>,
+
+    /// The named materials which objects refer to, see `Object::named_material`.
+    pub materials: MaterialLibrary,
+
+    /// The integrator which replaces the built-in path tracer, `None` for the built-in one.
+    pub integrator: Option<Arc<dyn Integrator>>,
+
+    /// The named cameras besides the default camera of scene file, e.g. the shots to choose
+    /// from, see `compare::render_contact_sheet`.
+    pub cameras: Vec<(String, Camera)>,
+
+    /// The planes which cut away the geometry in front of them for all rays, see
+    /// `Scene::with_section`.
+    pub sections: Vec<SectionPlane>,
+
+    /// The distribution which selects `lights` by their power, see `Scene::build_lights`.
+    pub light_distribution: LightDistribution,
 }
 
 impl Scene {
@@ -27,6 +86,44 @@ impl Scene {
         Default::default()
     }
 
+    /// Set the integrator which estimates the radiance of camera rays instead of the built-in
+    /// path tracer, e.g. one registered by a plugin, see `registry::Registry`.
+    pub fn integrator(mut self, integrator: Arc<dyn Integrator>) -> Self {
+        self.integrator = Some(integrator);
+        self
+    }
+
+    /// Get the first object of `name`, see `Object::name`.
+    pub fn object_by_name(&self, name: &str) -> Option<&Object> {
+        self.objects
+            .iter()
+            .find(|obj| obj.name.as_deref() == Some(name))
+    }
+
+    /// Add a named camera, which replaces the camera of the same name.
+    pub fn with_camera(mut self, name: &str, camera: Camera) -> Self {
+        match self.cameras.iter_mut().find(|(n, _)| n == name) {
+            Some((_, cam)) => *cam = camera,
+            None => self.cameras.push((name.to_string(), camera)),
+        }
+        self
+    }
+
+    /// Get the named camera.
+    pub fn camera(&self, name: &str) -> Option<&Camera> {
+        self.cameras
+            .iter()
+            .find_map(|(n, cam)| (n == name).then_some(cam))
+    }
+
+    /// Add a section plane which cuts away the geometry in front of it, e.g. a cutaway of a
+    /// building. The cut is seen by all rays, so light enters through it. The solids cut open
+    /// are closed by the cap material of the plane if any, which requires closed surfaces.
+    pub fn with_section(mut self, plane: SectionPlane) -> Self {
+        self.sections.push(plane);
+        self
+    }
+
     /// Set the background of the scene.
     pub fn background(mut self, background: Background) -> Self {
         self.background = background;
@@ -35,7 +132,30 @@ impl Scene {
 
     /// Builder-style add that consumes and returns the Scene.
     pub fn with_obj(mut self, obj: Object) -> Self {
-        self.objects.push(obj);
+        self.push_obj(obj);
+        self
+    }
+
+    /// Add or replace the material of `name` in the library, and bind it to every object which
+    /// refers to the name. Like adding objects, call `build_bvh` again to render the change if
+    /// the BVH is built.
+    pub fn with_material<M>(mut self, name: &str, material: M) -> Self
+    where
+        M: Bsdf + 'static,
+    {
+        self.materials.insert(name, Arc::new(material));
+        for obj in &mut self.objects {
+            self.materials.bind(obj);
+        }
+        self
+    }
+
+    /// Set the library of named materials, and bind them to the objects which refer to them.
+    pub fn material_library(mut self, library: MaterialLibrary) -> Self {
+        self.materials = library;
+        for obj in &mut self.objects {
+            self.materials.bind(obj);
+        }
         self
     }
 
@@ -44,10 +164,19 @@ impl Scene {
     where
         I: IntoIterator<Item = Object>,
     {
-        self.objects.extend(obj_list);
+        for obj in obj_list {
+            self.push_obj(obj);
+        }
         self
     }
 
+    /// Push the object with its index as the ID, and bind its named material.
+    fn push_obj(&mut self, mut obj: Object) {
+        obj.id = self.objects.len();
+        self.materials.bind(&mut obj);
+        self.objects.push(obj);
+    }
+
     /// Add a Light to Scene.
     pub fn with_light(mut self, light: Light) -> Self {
         self.lights.push(light);
@@ -63,16 +192,371 @@ impl Scene {
         self
     }
 
+    /// Add a Light in the named light group to Scene.
+    pub fn with_light_in_group(mut self, light: Light, group: &str) -> Self {
+        self.light_groups.resize(self.lights.len(), None);
+        self.light_groups.push(Some(Arc::from(group)));
+        self.lights.push(light);
+        self
+    }
+
+    /// Add a Light to Scene, and also its emitter for area light so that the light is visible
+    /// to camera rays and after delta bounces, see `Light::emitter`.
+    pub fn with_visible_light(mut self, light: Light) -> Self {
+        if let Some(emitter) = light.emitter() {
+            self.push_obj(emitter);
+        }
+        self.with_light(light)
+    }
+
+    /// Add a Light in the named light group to Scene like `with_visible_light`, the emitter is
+    /// in the same light group.
+    pub fn with_visible_light_in_group(mut self, light: Light, group: &str) -> Self {
+        if let Some(emitter) = light.emitter() {
+            self.push_obj(emitter.light_group(group));
+        }
+        self.with_light_in_group(light, group)
+    }
+
+    /// Cast a ray from `origin` in the direction `dir` and get the closest hit, e.g. picking and
+    /// line-of-sight tests of the applications embedding the renderer. The intersection is
+    /// computed by the same BVH as rendering if built, and it doesn't depend on the renderer.
+    pub fn raycast(&self, origin: DPoint3, dir: DVec3) -> Option<RaycastHit> {
+        let dir = dir.try_normalize()?;
+        let r = Ray::new(origin, dir, 0.0);
+        let rec = self.intersect(&r, Interval::new(0.0, f64::INFINITY))?;
+        Some(RaycastHit {
+            object_id: rec.object_id,
+            position: rec.p,
+            normal: rec.normal,
+            u: rec.u,
+            v: rec.v,
+            distance: rec.t,
+        })
+    }
+
+    /// Get the closest intersection of a camera ray like `intersect`, but the back faces of the
+    /// objects which cull them are skipped, see `Sides::CullBackfaces`.
+    pub fn intersect_camera(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        self.cull_backfaces(r, ray_t, self.intersect(r, ray_t))
+    }
+
+    /// Continue the camera ray `r` behind `hit` while it is a culled back face, see
+    /// `intersect_camera`.
+    pub fn cull_backfaces(
+        &self,
+        r: &Ray,
+        ray_t: Interval,
+        mut hit: Option<HitRecord>,
+    ) -> Option<HitRecord> {
+        while let Some(rec) = &hit {
+            let culled = !rec.front_face
+                && self
+                    .objects
+                    .get(rec.object_id)
+                    .is_some_and(|obj| obj.sides == Sides::CullBackfaces);
+            if !culled {
+                break;
+            }
+            hit = self.intersect(r, Interval::new(rec.t.next_up(), ray_t.max));
+        }
+        hit
+    }
+
+    /// Get the bounding box of all objects, `None` for an empty scene.
+    pub fn bbox(&self) -> Option<Aabb> {
+        self.objects
+            .iter()
+            .map(|obj| obj.bbox())
+            .reduce(|a, b| Aabb::surrounding_box(&a, &b))
+    }
+
+    /// Add a portal through which the background lights the scene. The portals should cover
+    /// all openings, as the background seen through other directions is ignored after diffuse
+    /// and glossy bounces.
+    pub fn with_portal(mut self, portal: Quad) -> Self {
+        self.portals.push(portal);
+        self
+    }
+
+    /// Add a list of portals, e.g. the result of `detect_portals`.
+    pub fn with_portals<I>(mut self, portals: I) -> Self
+    where
+        I: IntoIterator<Item = Quad>,
+    {
+        self.portals.extend(portals);
+        self
+    }
+
+    /// Detect the large openings of an enclosed scene as portals. `num_rays` rays are shot from
+    /// the `probe` point inside the scene, e.g. the camera of an interior, and the escaped rays
+    /// are collected by the faces of the scene bounds they leave through. The portal of each
+    /// face is the rectangle around the exit points on the face, which covers the openings
+    /// behind it.
+    pub fn detect_portals(&self, probe: DPoint3, num_rays: usize) -> Vec<Quad> {
+        let Some(bbox) = self.bbox() else {
+            return Vec::new();
+        };
+        let min = DVec3::new(bbox.x.min, bbox.y.min, bbox.z.min);
+        let max = DVec3::new(bbox.x.max, bbox.y.max, bbox.z.max);
+        // The bounds and the number of exit points of each face, in the order of -x, +x, -y,
+        // +y, -z and +z.
+        let mut faces: [Option<(DVec3, DVec3)>; 6] = [None; 6];
+        let mut counts = [0; 6];
+        let mut distances = [0.0f64; 6];
+        for i in 0..num_rays {
+            let dir = sampling::uniform_sphere(sampler::sobol_2d(i as u64, 0));
+            let ray = Ray::new(probe, dir, 0.0);
+            if self
+                .intersect(&ray, Interval::new(0.0, f64::INFINITY))
+                .is_some()
+            {
+                continue;
+            }
+            let exits = DVec3::select(dir.cmpgt(DVec3::ZERO), max, min);
+            let t = (exits - probe) / dir;
+            let axis = t.min_position();
+            if t[axis].is_nan() || t[axis] < 0.0 {
+                continue;
+            }
+            let p = probe + t[axis] * dir;
+            let face = axis * 2 + (dir[axis] > 0.0) as usize;
+            faces[face] = Some(faces[face].map_or((p, p), |(lo, hi)| (lo.min(p), hi.max(p))));
+            counts[face] += 1;
+            distances[face] = distances[face].max(t[axis]);
+        }
+
+        let min_count = (MIN_PORTAL_FRACTION * num_rays as f64).max(1.0);
+        let mut portals = Vec::new();
+        for (face, bounds) in faces.into_iter().enumerate() {
+            let Some((lo, hi)) = bounds else { continue };
+            if (counts[face] as f64) < min_count {
+                continue;
+            }
+            // The exit points fall short of the edges of openings by the spacing between
+            // neighboring rays at most, so the rectangle is extended by the spacing.
+            let margin = distances[face] * (4.0 * PI / num_rays as f64).sqrt();
+            let lo = (lo - margin).max(min);
+            let hi = (hi + margin).min(max);
+            let (axis, b, c) = (face / 2, (face / 2 + 1) % 3, (face / 2 + 2) % 3);
+            let mut origin = lo;
+            origin[axis] = if face % 2 == 1 { max[axis] } else { min[axis] };
+            let extent = hi - lo;
+            if extent[b] > 0.0 && extent[c] > 0.0 {
+                let mut u = DVec3::ZERO;
+                let mut v = DVec3::ZERO;
+                u[b] = extent[b];
+                v[c] = extent[c];
+                portals.push(Quad::new(origin, u, v));
+            }
+        }
+        portals
+    }
+
+    /// Get the light group of the light with index `i`, `None` for the default group.
+    pub fn light_group(&self, i: usize) -> Option<&Arc<str>> {
+        self.light_groups.get(i).and_then(|group| group.as_ref())
+    }
+
+    /// Get the names of all light groups in the scene. The first two are always the default
+    /// group and the background.
+    pub fn light_group_names(&self) -> Vec<Arc<str>> {
+        let mut names: Vec<Arc<str>> = vec![
+            Arc::from(DEFAULT_LIGHT_GROUP),
+            Arc::from(BACKGROUND_LIGHT_GROUP),
+        ];
+        let tags = (0..self.lights.len())
+            .filter_map(|i| self.light_group(i))
+            .chain(
+                self.objects
+                    .iter()
+                    .filter_map(|obj| obj.light_group.as_ref()),
+            );
+        for tag in tags {
+            if !names.contains(tag) {
+                names.push(tag.clone());
+            }
+        }
+        names
+    }
+
     /// Build BVH from current objects which should call after scene setup.
     /// After built the BVH, you can't add more objects or lights to scene. Or else you should call this function again.
-    pub fn build_bvh(mut self) -> Self {
+    pub fn build_bvh(self) -> Self {
+        self.build_bvh_with_leaf_size(DEFAULT_LEAF_SIZE)
+    }
+
+    /// Build BVH like `build_bvh` whose leaves contain at most `leaf_size` objects. The light
+    /// distribution is also built, see `build_lights`.
+    pub fn build_bvh_with_leaf_size(mut self, leaf_size: usize) -> Self {
+        self.wide_bvh = None;
         if self.objects.is_empty() {
             self.bvh = None;
         } else {
-            self.bvh = Some(BvhNode::build(self.objects.clone()));
+            self.bvh = Some(Bvh::build(self.objects.clone(), leaf_size));
+        }
+        self.build_lights()
+    }
+
+    /// Build the distribution which selects lights proportional to their power, i.e. area ×
+    /// emittance × luminance, see `LightSelection::Power`. It is called by `build_bvh` and
+    /// `build_bvh_cached`, call it again after changing lights.
+    pub fn build_lights(mut self) -> Self {
+        let radius = self.bbox().map_or(1.0, |bbox| {
+            let (x, y, z) = (bbox.x.size(), bbox.y.size(), bbox.z.size());
+            0.5 * DVec3::new(x, y, z).length()
+        });
+        let powers: Vec<f64> = self.lights.iter().map(|l| l.power(radius)).collect();
+        self.light_distribution = LightDistribution::new(&powers);
+        self
+    }
+
+    /// Build BVH like `build_bvh` if it isn't built, and convert it into the BVH with `N`
+    /// children per node, e.g. `build_wide_bvh::<4>()` for BVH4.
+    pub fn build_wide_bvh<const N: usize>(mut self) -> Self {
+        if self.bvh.is_none() {
+            self = self.build_bvh();
         }
+        self.wide_bvh = self
+            .bvh
+            .as_ref()
+            .map(|bvh| Box::new(WideBvh::<N>::from_bvh(bvh)) as Box<dyn Bounded>);
         self
     }
+
+    /// Save the scene viewed by `camera` to the TOML scene file at `path`, which can be edited
+    /// by hand and loaded by `Scene::load`. Only the built-in shapes, materials and textures are
+    /// supported, and images are referred to by their paths.
+    pub fn save(&self, path: &Path, camera: &Camera) -> io::Result<()> {
+        let file = file::SceneFile::from_scene(self, camera)?;
+        let content = toml::to_string(&file).map_err(io::Error::other)?;
+        fs::write(path, content)
+    }
+
+    /// Load the scene and camera from the TOML scene file at `path`. Call `build_bvh` after
+    /// loading.
+    pub fn load(path: &Path) -> io::Result<(Self, Camera)> {
+        SceneLoader::new().load(path)
+    }
+
+    /// Load the scene and camera like `load` within the memory `budget`, see
+    /// `SceneFile::scene_with_budget`. Call `build_bvh` after loading.
+    pub fn load_with_budget(path: &Path, budget: &MemoryBudget) -> io::Result<(Self, Camera)> {
+        SceneLoader::new().budget(*budget).load(path)
+    }
+
+    /// Get the memory consumed by the objects, images and BVH of the scene. The shapes shared
+    /// by instances are counted once.
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut shapes = HashSet::new();
+        let mut objects = self.objects.capacity() * size_of::<Object>();
+        for obj in &self.objects {
+            let shape: &dyn Bounded = obj.shape.as_ref();
+            if !shapes.insert(Arc::as_ptr(&obj.shape) as *const ()) {
+                continue;
+            }
+            let any: &dyn Any = shape;
+            match any.downcast_ref::<Instance>() {
+                Some(instance) => {
+                    objects += size_of::<Instance>();
+                    if shapes.insert(Arc::as_ptr(instance.shape()) as *const ()) {
+                        objects += instance.shape().as_ref().memory();
+                    }
+                }
+                None => objects += shape.memory(),
+            }
+        }
+        let images = match &self.background {
+            Background::Image(image) => image.memory(),
+            Background::Color(_) => 0,
+        };
+        let bvh = self.bvh.as_ref().map_or(0, Bounded::memory)
+            + self.wide_bvh.as_ref().map_or(0, |bvh| bvh.memory());
+        MemoryReport {
+            objects,
+            images,
+            bvh,
+        }
+    }
+
+    /// Like `build_bvh`, but load the BVH from the cache file at `path` if it was saved for the
+    /// same objects, otherwise build the BVH and save it to `path` for subsequent runs. The
+    /// error of saving the cache is returned besides the scene, which is usable anyway.
+    pub fn build_bvh_cached(mut self, path: &Path) -> (Self, Option<io::Error>) {
+        self.wide_bvh = None;
+        let mut error = None;
+        if self.objects.is_empty() {
+            self.bvh = None;
+        } else {
+            match cache::load(path, &self.objects) {
+                Ok(bvh) => self.bvh = Some(bvh),
+                Err(_) => {
+                    let bvh = Bvh::build(self.objects.clone(), DEFAULT_LEAF_SIZE);
+                    error = cache::save(&bvh, &self.objects, path).err();
+                    self.bvh = Some(bvh);
+                }
+            }
+        }
+        (self.build_lights(), error)
+    }
+}
+
+/// The closest hit of a ray cast into the scene, see `Scene::raycast`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastHit {
+    /// The index of hit object in `Scene::objects`.
+    pub object_id: usize,
+
+    /// The position of hit point.
+    pub position: DPoint3,
+
+    /// The normal vector of surface at the hit point, which faces to the ray.
+    pub normal: DVec3,
+
+    /// The texture coordinates of hit point.
+    pub u: f64,
+    pub v: f64,
+
+    /// The distance from the origin of ray to the hit point.
+    pub distance: f64,
+}
+
+impl Scene {
+    /// Get closest intersection of ray with the objects ignoring section planes.
+    fn intersect_objects(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        if let Some(bvh) = &self.wide_bvh {
+            return bvh.intersect(r, ray_t);
+        }
+        if let Some(bvh) = &self.bvh {
+            return bvh.intersect(r, ray_t);
+        }
+        let mut rec = None;
+        let mut closest_so_far = ray_t.max;
+        for obj in &self.objects {
+            let search_interval = Interval::new(ray_t.min, closest_so_far);
+            if let Some(obj_rec) = obj.intersect(r, search_interval) {
+                closest_so_far = obj_rec.t;
+                rec = Some(obj_rec);
+            }
+        }
+        rec
+    }
+}
+
+impl Hittable for Scene {
+    /// Get closest intersection of ray with the objects, by the BVH if built. The geometry cut
+    /// away by the section planes is skipped, and the ray entering a solid through the cut hits
+    /// the cap.
+    fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        if self.sections.is_empty() {
+            return self.intersect_objects(r, ray_t);
+        }
+        let (kept, entry) = clip::kept_interval(&self.sections, r, ray_t)?;
+        let rec = self.intersect_objects(r, kept)?;
+        entry
+            .and_then(|i| clip::cap_hit(&self.sections[i], r, kept.min, &rec))
+            .or(Some(rec))
+    }
 }
 
 pub enum Background {
@@ -97,14 +581,20 @@ impl Background {
     /// Create `Background` from a panorama image path.
     /// High dynamic range image usually stored in disk using RGBE compression algorithm.
     /// RGBE uses 4 bytes(u8) to analog float.
+    /// Panics if the image can't be loaded, see `load_hdr` for the fallible version.
+    pub fn from_hdr(path: &str) -> Self {
+        Self::load_hdr(path, 1).unwrap_or_else(|err| panic!("Failed to load {path}: {err}"))
+    }
+
+    /// Load `Background` from a panorama image path, which is downscaled by `factor` in both
+    /// axes, e.g. to fit into `MemoryBudget`.
     /// Firstly, We need read and decode the image.
     /// Then, get the pixel into array and create `Background` struct.
-    pub fn from_hdr(path: &str) -> Self {
+    pub fn load_hdr(path: &str, factor: u32) -> io::Result<Self> {
         // Read and decode.
-        let img = ImageReader::open(path)
-            .expect("Failed to open file")
+        let img = ImageReader::open(path)?
             .decode()
-            .expect("Failed to decode image");
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
         // Get pixels into array, BTW width and height.
         let (width, height, pixels) = match img {
             image::DynamicImage::ImageRgb32F(inner) => {
@@ -118,13 +608,8 @@ impl Background {
             }
         };
         // Create `HdrImage` struct.
-        Self::Image(HdrImage::new(
-            width,
-            height,
-            pixels
-                .chunks_exact(3)
-                .map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64))
-                .collect(),
+        Ok(Self::Image(
+            HdrImage::from_rgb(width, height, &pixels, factor).with_path(path),
         ))
     }
 
@@ -136,3 +621,42 @@ impl Background {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{light::Falloff, shape::sphere::Sphere};
+
+    /// Get the scene of 4 spheres lit by a dim and a bright point light.
+    fn spheres() -> Scene {
+        (0..4)
+            .fold(Scene::new(), |scene, i| {
+                let center = DVec3::new(i as f64, 0.0, 0.0);
+                scene.with_obj(Object::new(Sphere::new(center, None, 0.4)))
+            })
+            .with_light(Light::Point(color::WHITE, DVec3::Y, Falloff::default()))
+            .with_light(Light::Point(
+                color::WHITE * 3.0,
+                DVec3::Y,
+                Falloff::default(),
+            ))
+    }
+
+    #[test]
+    fn bvh_cache_reports_save_errors() {
+        let dir = std::env::temp_dir().join(format!("simple-rpt-bvh-{}", std::process::id()));
+        let (scene, error) = spheres().build_bvh_cached(&dir.join("missing").join("scene.bvh"));
+        assert!(scene.bvh.is_some());
+        assert_eq!(error.map(|err| err.kind()), Some(io::ErrorKind::NotFound));
+
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("scene.bvh");
+        let (_, error) = spheres().build_bvh_cached(&path);
+        assert!(error.is_none() && path.exists());
+        let (cached, error) = spheres().build_bvh_cached(&path);
+        assert!(cached.bvh.is_some() && error.is_none());
+        // The lights are selected by power as after `build_bvh`.
+        assert_eq!(cached.light_distribution.pmf(1), 0.75);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
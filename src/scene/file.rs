@@ -0,0 +1,1227 @@
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    io,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use glam::DVec3;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::Camera,
+    clip::SectionPlane,
+    color::{self, Color},
+    image::HdrImage,
+    interval::Interval,
+    light::{Falloff, Light},
+    material::{
+        Bsdf, EmissionProfile, LobeSelection, Material, RoughnessMapping,
+        carpaint::CarPaint,
+        hair::Hair,
+        library::MaterialLibrary,
+        merl::MerlBrdf,
+        normalmap::NormalMap,
+        wireframe::{Overlay, Wireframe},
+    },
+    math::Ray,
+    memory::{MemoryBudget, MemoryReport},
+    obj,
+    object::{Object, Sides},
+    registry::{Params, Registry},
+    shape::{
+        Bounded, Hittable, Sampleable, cube::Cube, curve::Curve, disk::Disk,
+        displaced::DisplacedQuad, mesh::TriangleMesh, quad::Quad, sphere::Sphere,
+    },
+    texture::{SolidColor, Texture, VertexColorTexture},
+};
+
+use super::{
+    Background, Scene,
+    load::{LoadProgress, LoadStage},
+};
+
+/// The content of a TOML scene file. Shapes, materials and textures are the built-in types or
+/// the plugins of `Registry`, while images and measured data are referred to by their paths.
+#[derive(Serialize, Deserialize)]
+pub struct SceneFile {
+    /// The camera to render the scene.
+    pub camera: CameraDesc,
+
+    /// The named cameras which can be rendered instead, see `Scene::cameras`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub cameras: BTreeMap<String, CameraDesc>,
+
+    /// The background of the scene.
+    #[serde(default)]
+    pub background: BackgroundDesc,
+
+    /// The named materials which objects refer to.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub materials: BTreeMap<String, MaterialDesc>,
+
+    /// The visible objects of the scene.
+    #[serde(default)]
+    pub objects: Vec<ObjectDesc>,
+
+    /// The lights of the scene besides the objects marked as light.
+    #[serde(default)]
+    pub lights: Vec<LightDesc>,
+
+    /// The portals through which the background lights the scene.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub portals: Vec<PortalDesc>,
+
+    /// The section planes which cut away geometry, see `Scene::with_section`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sections: Vec<SectionDesc>,
+
+    /// The scripts which generate objects procedurally, see `script::run`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scripts: Vec<ScriptDesc>,
+
+    /// The registered integrator which replaces the built-in path tracer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrator: Option<PluginDesc>,
+}
+
+/// The parameters of `Camera::new`.
+#[derive(Serialize, Deserialize)]
+pub struct CameraDesc {
+    pub look_from: [f64; 3],
+    pub look_to: [f64; 3],
+    pub vup: [f64; 3],
+    pub vfov: f64,
+    pub aspect_ratio: f64,
+    #[serde(default)]
+    pub aperture: f64,
+    #[serde(default = "default_focal_length")]
+    pub focal_length: f64,
+    /// The point to focus on after the scene is loaded, which overrides `focal_length`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus: Option<FocusDesc>,
+}
+
+/// The point which the camera focuses on, see `Camera::focus_on`.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FocusDesc {
+    /// The point in world space.
+    Point { point: [f64; 3] },
+
+    /// The surface of the object of `name` towards its center, see `Object::name`.
+    Object { name: String },
+
+    /// The surface seen at the center of image.
+    Center,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BackgroundDesc {
+    Color { color: [f64; 3] },
+    Image { path: String },
+}
+
+impl Default for BackgroundDesc {
+    fn default() -> Self {
+        Self::Color {
+            color: color::BLACK.to_array(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ObjectDesc {
+    pub shape: ShapeDesc,
+
+    #[serde(default)]
+    pub material: ObjectMaterialDesc,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub light_group: Option<String>,
+
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub seed: u32,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub sides: Sides,
+}
+
+/// The material of an object, either the name of a material in `SceneFile::materials`, e.g.
+/// `material = "brushed_steel"`, or the material itself.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ObjectMaterialDesc {
+    Named(String),
+    Owned(Box<MaterialDesc>),
+}
+
+impl Default for ObjectMaterialDesc {
+    fn default() -> Self {
+        Self::Owned(Box::default())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ShapeDesc {
+    Sphere {
+        center: [f64; 3],
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        center_to: Option<[f64; 3]>,
+        radius: f64,
+    },
+    Quad {
+        origin: [f64; 3],
+        u: [f64; 3],
+        v: [f64; 3],
+    },
+    /// The quad diced into a grid displaced by the luminance of `height` times `scale`.
+    DisplacedQuad {
+        origin: [f64; 3],
+        u: [f64; 3],
+        v: [f64; 3],
+        height: TextureDesc,
+        scale: f64,
+        resolution: u32,
+    },
+    Cube {
+        p1: [f64; 3],
+        p2: [f64; 3],
+    },
+    Disk {
+        center: [f64; 3],
+        normal: [f64; 3],
+        radius: f64,
+    },
+    /// The strand along the cubic Bézier curve of `points`, whose width varies from the start
+    /// to the end.
+    Curve {
+        points: [[f64; 3]; 4],
+        width: [f64; 2],
+    },
+    /// The triangle mesh loaded from the Wavefront OBJ file at `path`.
+    Mesh {
+        path: String,
+    },
+    /// The shape registered in `Registry`.
+    #[serde(untagged)]
+    Plugin(PluginDesc),
+}
+
+/// The type and parameters of a plugin registered in `Registry`, e.g. the table
+/// `{ type = "torus", major = 1.0, minor = 0.25 }`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PluginDesc {
+    /// The name which the plugin is registered as.
+    #[serde(rename = "type")]
+    pub name: String,
+
+    /// The other fields of the table.
+    #[serde(flatten)]
+    pub params: Params,
+}
+
+/// A script which generates objects in the scene file, e.g.
+/// `source = "for i in 0..8 { sphere([i, 0.5, 0], 0.4, diffuse([rand(), rand(), 1])); }"`. It
+/// needs the `scripting` feature.
+#[derive(Serialize, Deserialize)]
+pub struct ScriptDesc {
+    /// The source code of the script.
+    pub source: String,
+
+    /// The seed of the random numbers of the script.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub seed: u64,
+}
+
+/// The parameters of the quad of a portal, see `Scene::with_portal`.
+#[derive(Serialize, Deserialize)]
+pub struct PortalDesc {
+    pub origin: [f64; 3],
+    pub u: [f64; 3],
+    pub v: [f64; 3],
+}
+
+/// A section plane through `point` which cuts away the side that `normal` points to, see
+/// `SectionPlane`.
+#[derive(Serialize, Deserialize)]
+pub struct SectionDesc {
+    pub point: [f64; 3],
+    pub normal: [f64; 3],
+    /// The material of the caps which close the cut of solids.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cap: Option<MaterialDesc>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MaterialDesc {
+    /// The uber-material `Material`.
+    Uber(Box<UberDesc>),
+
+    /// The measured BRDF loaded from a MERL binary file.
+    Merl { path: String },
+
+    /// The debug material which draws lines on top of the base material.
+    Wireframe {
+        base: Box<MaterialDesc>,
+        overlay: Overlay,
+        #[serde(default)]
+        color: [f64; 3],
+    },
+
+    /// The hair BSDF of fibers, see `Hair`.
+    Hair {
+        sigma_a: [f64; 3],
+        longitudinal_roughness: f64,
+        azimuthal_roughness: f64,
+        scale_angle: f64,
+        index: f64,
+    },
+
+    /// The layered car paint, see `CarPaint`.
+    CarPaint {
+        color: [f64; 3],
+        flake_color: [f64; 3],
+        flake_density: f64,
+        flake_size: f64,
+        flake_spread: f64,
+        flake_roughness: f64,
+        film_thickness: f64,
+        film_index: f64,
+        clearcoat: f64,
+        clearcoat_index: f64,
+        clearcoat_roughness: f64,
+    },
+
+    /// The BSDF registered in `Registry`.
+    #[serde(untagged)]
+    Plugin(PluginDesc),
+}
+
+impl Default for MaterialDesc {
+    fn default() -> Self {
+        Self::Uber(Box::default())
+    }
+}
+
+/// The fields of `Material`, the missing ones are taken from the default diffuse material.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UberDesc {
+    pub color: [f64; 3],
+    pub roughness: f64,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub roughness_mapping: RoughnessMapping,
+    pub metallic: f64,
+    pub index: f64,
+    pub emittance: f64,
+    pub transparent: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub texture: Option<TextureDesc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emission: Option<TextureDesc>,
+    /// The path of the normal map image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normal_map: Option<String>,
+    pub one_sided: bool,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub emission_profile: EmissionProfile,
+    pub lobe_selection: LobeSelection,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub hue_jitter: f64,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub roughness_jitter: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sheen: Option<SheenDesc>,
+}
+
+/// The sheen lobe of `Material`, see `Material::sheen`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SheenDesc {
+    pub color: [f64; 3],
+    pub roughness: f64,
+}
+
+impl Default for UberDesc {
+    fn default() -> Self {
+        Self {
+            color: color::GREY.to_array(),
+            roughness: 1.0,
+            roughness_mapping: RoughnessMapping::default(),
+            metallic: 0.0,
+            index: 1.0,
+            emittance: 0.0,
+            transparent: false,
+            texture: None,
+            emission: None,
+            normal_map: None,
+            one_sided: false,
+            emission_profile: EmissionProfile::default(),
+            lobe_selection: LobeSelection::default(),
+            hue_jitter: 0.0,
+            roughness_jitter: 0.0,
+            sheen: None,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TextureDesc {
+    Solid {
+        color: [f64; 3],
+    },
+    VertexColor {
+        fallback: [f64; 3],
+    },
+    /// The texture registered in `Registry`.
+    #[serde(untagged)]
+    Plugin(PluginDesc),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LightDesc {
+    Ambient {
+        color: [f64; 3],
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+    },
+    Directional {
+        color: [f64; 3],
+        direction: [f64; 3],
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+    },
+    Point {
+        color: [f64; 3],
+        position: [f64; 3],
+        #[serde(default, skip_serializing_if = "Falloff::is_inverse_square")]
+        falloff: Falloff,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+    },
+    Area {
+        shape: ShapeDesc,
+        color: [f64; 3],
+        intensity: f64,
+        /// The texture of emission multiplied by `intensity` instead of `color`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        emission: Option<TextureDesc>,
+        /// Whether the light only emits from the front face of the shape.
+        #[serde(default, skip_serializing_if = "is_false")]
+        one_sided: bool,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        emission_profile: EmissionProfile,
+        /// Whether the emitter is also added to the objects of scene.
+        #[serde(default, skip_serializing_if = "is_false")]
+        visible: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+    },
+}
+
+#[cfg(feature = "scripting")]
+use super::script::run as run_script;
+
+/// Fail to run the script, since the interpreter isn't compiled.
+#[cfg(not(feature = "scripting"))]
+fn run_script(_script: &ScriptDesc) -> io::Result<Vec<ObjectDesc>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the scene file has scripts, but the renderer is compiled without the `scripting` feature",
+    ))
+}
+
+fn default_focal_length() -> f64 {
+    1.0
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+fn unsupported(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.to_string())
+}
+
+impl CameraDesc {
+    /// Recover the parameters of `Camera::new` from the camera. The up vector is replaced by the
+    /// y axis of camera, which gives the same camera.
+    pub fn from_camera(cam: &Camera) -> Self {
+        let c_z = cam.c_x.cross(cam.c_y);
+        let look_to = cam.upper_left + cam.u / 2.0 + cam.v / 2.0;
+        let focal_length = (cam.origin - look_to).dot(c_z);
+        Self {
+            look_from: cam.origin.to_array(),
+            look_to: look_to.to_array(),
+            vup: cam.c_y.to_array(),
+            vfov: 2.0
+                * (cam.viewport_height / 2.0 / focal_length)
+                    .atan()
+                    .to_degrees(),
+            aspect_ratio: cam.viewport_width / cam.viewport_height,
+            aperture: 2.0 * cam.lens_radius,
+            focal_length,
+            focus: None,
+        }
+    }
+
+    pub fn camera(&self) -> Camera {
+        Camera::new(
+            DVec3::from_array(self.look_from),
+            DVec3::from_array(self.look_to),
+            DVec3::from_array(self.vup),
+            self.vfov,
+            self.aspect_ratio,
+            self.aperture,
+            self.focal_length,
+        )
+    }
+
+    /// Create the camera like `camera`, which focuses on the point of `focus` in `scene`.
+    pub fn focused_camera(&self, scene: &Scene) -> io::Result<Camera> {
+        let cam = self.camera();
+        let point = match &self.focus {
+            None => return Ok(cam),
+            Some(FocusDesc::Point { point }) => DVec3::from_array(*point),
+            Some(FocusDesc::Object { name }) => {
+                let obj = scene.object_by_name(name).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Unknown object to focus on: {name}"),
+                    )
+                })?;
+                // The surface facing the camera is in focus rather than the center inside.
+                let center = obj.bbox().center();
+                let ray = Ray::new(cam.origin, (center - cam.origin).normalize(), 0.0);
+                obj.intersect(&ray, Interval::new(0.0, f64::INFINITY))
+                    .map_or(center, |rec| rec.p)
+            }
+            Some(FocusDesc::Center) => {
+                let dir = cam.film_point(0.5, 0.5) - cam.origin;
+                let hit = scene.raycast(cam.origin, dir).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Nothing at the center of image to focus on",
+                    )
+                })?;
+                hit.position
+            }
+        };
+        Ok(cam.focus_on(point))
+    }
+}
+
+impl ObjectDesc {
+    /// Describe the object, whose named material must be in `library`. An error is returned for
+    /// the unknown material names, and the shapes, materials and textures which are not built
+    /// in.
+    pub fn from_object(object: &Object, library: &MaterialLibrary) -> io::Result<Self> {
+        if let Some(name) = object.material_name.as_deref()
+            && library.get(name).is_none()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown material: {name}"),
+            ));
+        }
+        Ok(Self {
+            shape: ShapeDesc::from_shape(object.shape.as_ref())?,
+            material: match &object.material_name {
+                Some(name) => ObjectMaterialDesc::Named(name.to_string()),
+                None => ObjectMaterialDesc::Owned(Box::new(MaterialDesc::from_bsdf(
+                    object.material.as_ref(),
+                )?)),
+            },
+            light_group: object.light_group.as_deref().map(str::to_string),
+            seed: object.seed,
+            name: object.name.as_deref().map(str::to_string),
+            sides: object.sides,
+        })
+    }
+
+    /// Create the object, whose named material is looked up in `library` and plugins are
+    /// created by `registry`.
+    pub fn object(&self, library: &MaterialLibrary, registry: &Registry) -> io::Result<Object> {
+        let (material, material_name) = match &self.material {
+            ObjectMaterialDesc::Named(name) => {
+                let material = library.get(name).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Unknown material: {name}"),
+                    )
+                })?;
+                (material.clone(), Some(Arc::from(name.as_str())))
+            }
+            ObjectMaterialDesc::Owned(desc) => (desc.bsdf(registry)?, None),
+        };
+        Ok(Object {
+            shape: self.shape.shape(registry)?,
+            material,
+            light_group: self.light_group.as_deref().map(Arc::from),
+            material_name,
+            seed: self.seed,
+            id: 0,
+            name: self.name.as_deref().map(Arc::from),
+            sides: self.sides,
+            emitter: false,
+        })
+    }
+}
+
+impl ShapeDesc {
+    pub fn from_shape(shape: &dyn Bounded) -> io::Result<Self> {
+        let any: &dyn Any = shape;
+        if let Some(sphere) = any.downcast_ref::<Sphere>() {
+            let (center, center_to) = sphere.centers();
+            Ok(Self::Sphere {
+                center: center.to_array(),
+                center_to: center_to.map(|c| c.to_array()),
+                radius: sphere.radius(),
+            })
+        } else if let Some(quad) = any.downcast_ref::<Quad>() {
+            Ok(Self::Quad {
+                origin: quad.origin.to_array(),
+                u: quad.u.to_array(),
+                v: quad.v.to_array(),
+            })
+        } else if let Some(displaced) = any.downcast_ref::<DisplacedQuad>() {
+            let quad = displaced.quad();
+            let (height, scale) = displaced
+                .height()
+                .ok_or_else(|| unsupported("the displacement function can't be saved"))?;
+            Ok(Self::DisplacedQuad {
+                origin: quad.origin.to_array(),
+                u: quad.u.to_array(),
+                v: quad.v.to_array(),
+                height: TextureDesc::from_texture(height.as_ref())?,
+                scale,
+                resolution: displaced.resolution(),
+            })
+        } else if let Some(cube) = any.downcast_ref::<Cube>() {
+            let (p1, p2) = cube.corners();
+            Ok(Self::Cube {
+                p1: p1.to_array(),
+                p2: p2.to_array(),
+            })
+        } else if let Some(disk) = any.downcast_ref::<Disk>() {
+            Ok(Self::Disk {
+                center: disk.center().to_array(),
+                normal: disk.normal().to_array(),
+                radius: disk.radius(),
+            })
+        } else if let Some(curve) = any.downcast_ref::<Curve>() {
+            let (start, end) = curve.width();
+            Ok(Self::Curve {
+                points: curve.points().map(|p| p.to_array()),
+                width: [start, end],
+            })
+        } else if let Some(mesh) = any.downcast_ref::<TriangleMesh>() {
+            let path = mesh
+                .path()
+                .ok_or_else(|| unsupported("the mesh isn't loaded from a file"))?;
+            Ok(Self::Mesh {
+                path: path.to_string_lossy().into_owned(),
+            })
+        } else {
+            Err(unsupported("the shape can't be saved to scene file"))
+        }
+    }
+
+    /// Create the shape, whose plugins are created by `registry`.
+    pub fn shape(&self, registry: &Registry) -> io::Result<Arc<dyn Bounded>> {
+        match self {
+            ShapeDesc::Cube { p1, p2 } => Ok(Arc::new(Cube::new(
+                DVec3::from_array(*p1),
+                DVec3::from_array(*p2),
+            ))),
+            ShapeDesc::DisplacedQuad {
+                origin,
+                u,
+                v,
+                height,
+                scale,
+                resolution,
+            } => Ok(Arc::new(DisplacedQuad::new(
+                Quad::new(
+                    DVec3::from_array(*origin),
+                    DVec3::from_array(*u),
+                    DVec3::from_array(*v),
+                ),
+                height.texture(registry)?,
+                *scale,
+                *resolution,
+            ))),
+            ShapeDesc::Curve { points, width } => Ok(Arc::new(Curve::new(
+                points.map(DVec3::from_array),
+                width[0],
+                width[1],
+            ))),
+            ShapeDesc::Mesh { path } => Ok(Arc::new(obj::load(path)?)),
+            ShapeDesc::Plugin(desc) => registry.shape(&desc.name, &desc.params),
+            _ => Ok(self.sampleable()?),
+        }
+    }
+
+    /// Create the shape of area light, an error is returned for the shapes which can't be
+    /// sampled.
+    pub fn sampleable(&self) -> io::Result<Arc<dyn Sampleable>> {
+        Ok(match *self {
+            ShapeDesc::Sphere {
+                center,
+                center_to,
+                radius,
+            } => Arc::new(Sphere::new(
+                DVec3::from_array(center),
+                center_to.map(DVec3::from_array),
+                radius,
+            )),
+            ShapeDesc::Quad { origin, u, v } => Arc::new(Quad::new(
+                DVec3::from_array(origin),
+                DVec3::from_array(u),
+                DVec3::from_array(v),
+            )),
+            ShapeDesc::Disk {
+                center,
+                normal,
+                radius,
+            } => Arc::new(Disk::new(
+                DVec3::from_array(center),
+                DVec3::from_array(normal),
+                radius,
+            )),
+            ShapeDesc::Cube { .. } => return Err(unsupported("the cube can't be an area light")),
+            ShapeDesc::DisplacedQuad { .. } => {
+                return Err(unsupported("the displaced quad can't be an area light"));
+            }
+            ShapeDesc::Curve { .. } => return Err(unsupported("the curve can't be an area light")),
+            ShapeDesc::Mesh { .. } => return Err(unsupported("the mesh can't be an area light")),
+            ShapeDesc::Plugin(_) => {
+                return Err(unsupported("the plugin shapes can't be area lights"));
+            }
+        })
+    }
+}
+
+impl MaterialDesc {
+    pub fn from_bsdf(bsdf: &dyn Bsdf) -> io::Result<Self> {
+        let any: &dyn Any = bsdf;
+        if let Some(material) = any.downcast_ref::<Material>() {
+            let texture = |texture: &Option<Arc<dyn Texture>>| {
+                texture
+                    .as_deref()
+                    .map(TextureDesc::from_texture)
+                    .transpose()
+            };
+            Ok(Self::Uber(Box::new(UberDesc {
+                color: material.color.to_array(),
+                roughness: material.roughness,
+                roughness_mapping: material.roughness_mapping,
+                // Undo the offset of `Material::base`.
+                index: material.index - 1e-6,
+                metallic: material.metallic,
+                emittance: material.emittance,
+                transparent: material.transparent,
+                texture: texture(&material.texture)?,
+                emission: texture(&material.emission)?,
+                normal_map: material
+                    .normal_map
+                    .as_ref()
+                    .map(|normal_map| {
+                        normal_map
+                            .path()
+                            .map(str::to_string)
+                            .ok_or_else(|| unsupported("the normal map isn't loaded from a file"))
+                    })
+                    .transpose()?,
+                one_sided: material.one_sided,
+                emission_profile: material.emission_profile.clone(),
+                lobe_selection: material.lobe_selection,
+                hue_jitter: material.hue_jitter,
+                roughness_jitter: material.roughness_jitter,
+                sheen: (material.sheen != color::BLACK).then(|| SheenDesc {
+                    color: material.sheen.to_array(),
+                    roughness: material.sheen_roughness,
+                }),
+            })))
+        } else if let Some(merl) = any.downcast_ref::<MerlBrdf>() {
+            let path = merl
+                .path()
+                .ok_or_else(|| unsupported("the MERL BRDF isn't loaded from a file"))?;
+            Ok(Self::Merl {
+                path: path.to_string_lossy().into_owned(),
+            })
+        } else if let Some(wireframe) = any.downcast_ref::<Wireframe>() {
+            Ok(Self::Wireframe {
+                base: Box::new(Self::from_bsdf(wireframe.base.as_ref())?),
+                overlay: wireframe.overlay,
+                color: wireframe.color().to_array(),
+            })
+        } else if let Some(hair) = any.downcast_ref::<Hair>() {
+            Ok(Self::Hair {
+                sigma_a: hair.sigma_a.to_array(),
+                longitudinal_roughness: hair.longitudinal_roughness,
+                azimuthal_roughness: hair.azimuthal_roughness,
+                scale_angle: hair.scale_angle,
+                index: hair.index,
+            })
+        } else if let Some(paint) = any.downcast_ref::<CarPaint>() {
+            Ok(Self::CarPaint {
+                color: paint.color.to_array(),
+                flake_color: paint.flake_color.to_array(),
+                flake_density: paint.flake_density,
+                flake_size: paint.flake_size,
+                flake_spread: paint.flake_spread,
+                flake_roughness: paint.flake_roughness,
+                film_thickness: paint.film_thickness,
+                film_index: paint.film_index,
+                clearcoat: paint.clearcoat,
+                clearcoat_index: paint.clearcoat_index,
+                clearcoat_roughness: paint.clearcoat_roughness,
+            })
+        } else {
+            Err(unsupported("the material can't be saved to scene file"))
+        }
+    }
+
+    /// Create the BSDF, whose plugins are created by `registry`.
+    pub fn bsdf(&self, registry: &Registry) -> io::Result<Arc<dyn Bsdf>> {
+        let texture = |desc: &Option<TextureDesc>| {
+            desc.as_ref().map(|desc| desc.texture(registry)).transpose()
+        };
+        match self {
+            Self::Uber(desc) => Ok(Arc::new(Material {
+                color: Color::from_array(desc.color),
+                metallic: desc.metallic,
+                emittance: desc.emittance,
+                transparent: desc.transparent,
+                texture: texture(&desc.texture)?,
+                emission: texture(&desc.emission)?,
+                normal_map: desc
+                    .normal_map
+                    .as_deref()
+                    .map(|path| NormalMap::load(path).map(Arc::new))
+                    .transpose()?,
+                one_sided: desc.one_sided,
+                emission_profile: desc.emission_profile.clone(),
+                lobe_selection: desc.lobe_selection,
+                hue_jitter: desc.hue_jitter,
+                roughness_jitter: desc.roughness_jitter,
+                roughness_mapping: desc.roughness_mapping,
+                sheen: desc
+                    .sheen
+                    .as_ref()
+                    .map_or(color::BLACK, |sheen| Color::from_array(sheen.color)),
+                sheen_roughness: desc.sheen.as_ref().map_or(0.5, |sheen| sheen.roughness),
+                ..Material::base(desc.index, desc.roughness)
+            })),
+            Self::Merl { path } => Ok(Arc::new(MerlBrdf::load(path)?)),
+            Self::Wireframe {
+                base,
+                overlay,
+                color,
+            } => Ok(Arc::new(
+                Wireframe::new(base.bsdf(registry)?, *overlay)
+                    .line_color(Color::from_array(*color)),
+            )),
+            Self::Hair {
+                sigma_a,
+                longitudinal_roughness,
+                azimuthal_roughness,
+                scale_angle,
+                index,
+            } => Ok(Arc::new(Hair {
+                sigma_a: Color::from_array(*sigma_a),
+                longitudinal_roughness: *longitudinal_roughness,
+                azimuthal_roughness: *azimuthal_roughness,
+                scale_angle: *scale_angle,
+                index: *index,
+            })),
+            Self::CarPaint {
+                color,
+                flake_color,
+                flake_density,
+                flake_size,
+                flake_spread,
+                flake_roughness,
+                film_thickness,
+                film_index,
+                clearcoat,
+                clearcoat_index,
+                clearcoat_roughness,
+            } => Ok(Arc::new(CarPaint {
+                color: Color::from_array(*color),
+                flake_color: Color::from_array(*flake_color),
+                flake_density: *flake_density,
+                flake_size: *flake_size,
+                flake_spread: *flake_spread,
+                flake_roughness: *flake_roughness,
+                film_thickness: *film_thickness,
+                film_index: *film_index,
+                clearcoat: *clearcoat,
+                clearcoat_index: *clearcoat_index,
+                clearcoat_roughness: *clearcoat_roughness,
+            })),
+            Self::Plugin(desc) => registry.bsdf(&desc.name, &desc.params),
+        }
+    }
+}
+
+impl TextureDesc {
+    pub fn from_texture(texture: &dyn Texture) -> io::Result<Self> {
+        let any: &dyn Any = texture;
+        if let Some(SolidColor(color)) = any.downcast_ref::<SolidColor>() {
+            Ok(Self::Solid {
+                color: color.to_array(),
+            })
+        } else if let Some(texture) = any.downcast_ref::<VertexColorTexture>() {
+            Ok(Self::VertexColor {
+                fallback: texture.fallback.to_array(),
+            })
+        } else {
+            Err(unsupported("the texture can't be saved to scene file"))
+        }
+    }
+
+    /// Create the texture, whose plugins are created by `registry`.
+    pub fn texture(&self, registry: &Registry) -> io::Result<Arc<dyn Texture>> {
+        match self {
+            Self::Solid { color } => Ok(Arc::new(SolidColor(Color::from_array(*color)))),
+            Self::VertexColor { fallback } => Ok(Arc::new(VertexColorTexture::new(
+                Color::from_array(*fallback),
+            ))),
+            Self::Plugin(desc) => registry.texture(&desc.name, &desc.params),
+        }
+    }
+}
+
+impl SceneFile {
+    /// Describe the scene viewed by the camera. The emitters of visible area lights are saved by
+    /// marking the lights instead of objects.
+    pub fn from_scene(scene: &Scene, cam: &Camera) -> io::Result<Self> {
+        if scene.integrator.is_some() {
+            return Err(unsupported("the integrator can't be saved to scene file"));
+        }
+        let shape_ptr = |shape: &dyn Bounded| shape as *const dyn Bounded as *const ();
+        let emitter_of = |light: &Light| match light {
+            Light::Area(shape, ..) => scene.objects.iter().position(|obj| {
+                shape_ptr(obj.shape.as_ref()) == shape_ptr(shape.as_ref() as &dyn Bounded)
+            }),
+            _ => None,
+        };
+        let emitters: Vec<usize> = scene.lights.iter().filter_map(emitter_of).collect();
+        let materials = scene
+            .materials
+            .iter()
+            .map(|(name, material)| {
+                Ok((
+                    name.to_string(),
+                    MaterialDesc::from_bsdf(material.as_ref())?,
+                ))
+            })
+            .collect::<io::Result<BTreeMap<_, _>>>()?;
+        let objects = scene
+            .objects
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !emitters.contains(i))
+            .map(|(_, obj)| ObjectDesc::from_object(obj, &scene.materials))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut lights = Vec::new();
+        for (i, light) in scene.lights.iter().enumerate() {
+            let group = || scene.light_group(i).map(|group| group.to_string());
+            let desc = match light {
+                Light::Ambient(color) => LightDesc::Ambient {
+                    color: color.to_array(),
+                    group: group(),
+                },
+                Light::Directional(color, dir) => LightDesc::Directional {
+                    color: color.to_array(),
+                    direction: dir.to_array(),
+                    group: group(),
+                },
+                Light::Point(color, pos, falloff) => LightDesc::Point {
+                    color: color.to_array(),
+                    position: pos.to_array(),
+                    falloff: *falloff,
+                    group: group(),
+                },
+                Light::Area(shape, material) => LightDesc::Area {
+                    shape: ShapeDesc::from_shape(shape.as_ref())?,
+                    color: material.color.to_array(),
+                    intensity: material.emittance,
+                    emission: material
+                        .emission
+                        .as_deref()
+                        .map(TextureDesc::from_texture)
+                        .transpose()?,
+                    one_sided: material.one_sided,
+                    emission_profile: material.emission_profile.clone(),
+                    visible: emitter_of(light).is_some(),
+                    group: group(),
+                },
+            };
+            lights.push(desc);
+        }
+        let background = match &scene.background {
+            Background::Color(color) => BackgroundDesc::Color {
+                color: color.to_array(),
+            },
+            Background::Image(image) => BackgroundDesc::Image {
+                path: image
+                    .path()
+                    .ok_or_else(|| unsupported("the background isn't loaded from a file"))?
+                    .to_string(),
+            },
+        };
+        let portals = scene
+            .portals
+            .iter()
+            .map(|portal| PortalDesc {
+                origin: portal.origin.to_array(),
+                u: portal.u.to_array(),
+                v: portal.v.to_array(),
+            })
+            .collect();
+        let sections = scene
+            .sections
+            .iter()
+            .map(|plane| {
+                Ok(SectionDesc {
+                    point: plane.point.to_array(),
+                    normal: plane.normal.to_array(),
+                    cap: plane
+                        .cap
+                        .as_deref()
+                        .map(MaterialDesc::from_bsdf)
+                        .transpose()?,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self {
+            camera: CameraDesc::from_camera(cam),
+            cameras: scene
+                .cameras
+                .iter()
+                .map(|(name, cam)| (name.clone(), CameraDesc::from_camera(cam)))
+                .collect(),
+            background,
+            materials,
+            objects,
+            lights,
+            portals,
+            sections,
+            scripts: Vec::new(),
+            integrator: None,
+        })
+    }
+
+    /// Create the scene and camera. The BVH isn't built.
+    pub fn scene(&self) -> io::Result<(Scene, Camera)> {
+        self.build(None, &Registry::new(), &|_| {})
+    }
+
+    /// Create the scene and camera like `scene` within the memory `budget`. The background
+    /// image is loaded last, and downscaled to fit into the budget left by the objects and the
+    /// estimated BVH if `MemoryBudget::downscale_images`. The sizes are checked before the
+    /// image is decoded, so a scene over the budget fails with `ErrorKind::OutOfMemory`.
+    pub fn scene_with_budget(&self, budget: &MemoryBudget) -> io::Result<(Scene, Camera)> {
+        self.build(Some(budget), &Registry::new(), &|_| {})
+    }
+
+    /// Create the scene and camera within the memory `budget` if any, whose plugins are created
+    /// by `registry`, reporting the progress of each stage after parsing to `progress`, see
+    /// `SceneLoader`.
+    pub(crate) fn build(
+        &self,
+        budget: Option<&MemoryBudget>,
+        registry: &Registry,
+        progress: &(dyn Fn(&LoadProgress) + Sync),
+    ) -> io::Result<(Scene, Camera)> {
+        // Report the start of `stage` and get the function to count its finished items.
+        let start = |stage, total| {
+            progress(&LoadProgress {
+                stage,
+                done: 0,
+                total,
+            });
+            let done = AtomicUsize::new(0);
+            move || {
+                progress(&LoadProgress {
+                    stage,
+                    done: done.fetch_add(1, Ordering::Relaxed) + 1,
+                    total,
+                })
+            }
+        };
+        let mut scene = Scene::new();
+        for desc in &self.lights {
+            let (light, group) = match desc {
+                LightDesc::Ambient { color, group } => {
+                    (Light::Ambient(Color::from_array(*color)), group)
+                }
+                LightDesc::Directional {
+                    color,
+                    direction,
+                    group,
+                } => (
+                    Light::Directional(Color::from_array(*color), DVec3::from_array(*direction)),
+                    group,
+                ),
+                LightDesc::Point {
+                    color,
+                    position,
+                    falloff,
+                    group,
+                } => (
+                    Light::Point(
+                        Color::from_array(*color),
+                        DVec3::from_array(*position),
+                        *falloff,
+                    ),
+                    group,
+                ),
+                LightDesc::Area {
+                    shape,
+                    color,
+                    intensity,
+                    emission,
+                    one_sided,
+                    emission_profile,
+                    visible,
+                    group,
+                } => {
+                    let mut material = Material::light(Color::from_array(*color), *intensity)
+                        .one_sided(*one_sided)
+                        .emission_profile(emission_profile.clone());
+                    material.emission = emission
+                        .as_ref()
+                        .map(|desc| desc.texture(registry))
+                        .transpose()?;
+                    let light = Light::Area(shape.sampleable()?, Arc::new(material));
+                    scene = match (group, visible) {
+                        (Some(group), true) => scene.with_visible_light_in_group(light, group),
+                        (Some(group), false) => scene.with_light_in_group(light, group),
+                        (None, true) => scene.with_visible_light(light),
+                        (None, false) => scene.with_light(light),
+                    };
+                    continue;
+                }
+            };
+            scene = match group {
+                Some(group) => scene.with_light_in_group(light, group),
+                None => scene.with_light(light),
+            };
+        }
+        // The materials and objects are independent, e.g. decoding the measured data of one
+        // material doesn't block the others.
+        let finish = start(LoadStage::Materials, self.materials.len());
+        let materials = self
+            .materials
+            .par_iter()
+            .map(|(name, desc)| {
+                let bsdf = desc.bsdf(registry)?;
+                finish();
+                Ok((name, bsdf))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut library = MaterialLibrary::new();
+        for (name, bsdf) in materials {
+            library.insert(name, bsdf);
+        }
+        let finish = start(LoadStage::Scripts, self.scripts.len());
+        let scripted = self
+            .scripts
+            .par_iter()
+            .map(|script| {
+                let objects = run_script(script)?;
+                finish();
+                Ok(objects)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let descs: Vec<&ObjectDesc> = self
+            .objects
+            .iter()
+            .chain(scripted.iter().flatten())
+            .collect();
+        let finish = start(LoadStage::Objects, descs.len());
+        let objects = descs
+            .par_iter()
+            .map(|desc| {
+                let object = desc.object(&library, registry)?;
+                finish();
+                Ok(object)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        scene = scene.with_obj_list(objects).material_library(library);
+        if let Some(desc) = &self.integrator {
+            scene = scene.integrator(registry.integrator(&desc.name, &desc.params)?);
+        }
+        let finish = start(LoadStage::Background, 1);
+        let background = match &self.background {
+            BackgroundDesc::Color { color } => Background::from_color(Color::from_array(*color)),
+            BackgroundDesc::Image { path } => {
+                let mut factor = 1;
+                if let Some(budget) = budget {
+                    let mut report = MemoryReport {
+                        bvh: MemoryReport::estimate_bvh(scene.objects.len()),
+                        ..scene.memory_report()
+                    };
+                    let (width, height) = image::image_dimensions(path)
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                    if let Some(max_pixels) = budget.max_image_pixels(&report) {
+                        factor = HdrImage::downscale_factor(width, height, max_pixels);
+                    }
+                    report.images =
+                        HdrImage::memory_of(width.div_ceil(factor), height.div_ceil(factor));
+                    budget.check(&report)?;
+                }
+                Background::load_hdr(path, factor)?
+            }
+        };
+        finish();
+        scene = scene.background(background);
+        let portals = self.portals.iter().map(|desc| {
+            Quad::new(
+                DVec3::from_array(desc.origin),
+                DVec3::from_array(desc.u),
+                DVec3::from_array(desc.v),
+            )
+        });
+        let mut scene = scene.with_portals(portals);
+        for desc in &self.sections {
+            let mut plane = SectionPlane::new(
+                DVec3::from_array(desc.point),
+                DVec3::from_array(desc.normal),
+            );
+            plane.cap = desc
+                .cap
+                .as_ref()
+                .map(|cap| cap.bsdf(registry))
+                .transpose()?;
+            scene = scene.with_section(plane);
+        }
+        // The cameras focus on the objects, so they are created last.
+        for (name, desc) in &self.cameras {
+            let cam = desc.focused_camera(&scene)?;
+            scene = scene.with_camera(name, cam);
+        }
+        let cam = self.camera.focused_camera(&scene)?;
+        Ok((scene, cam))
+    }
+}
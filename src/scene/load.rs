@@ -0,0 +1,145 @@
+use std::{fmt, fs, io, path::Path, sync::Arc};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::{camera::Camera, memory::MemoryBudget, registry::Registry};
+
+use super::{Scene, file::SceneFile};
+
+/// The stages of loading a scene file in order, see `SceneLoader`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStage {
+    /// Reading and parsing the scene file.
+    Parse,
+
+    /// Creating the named materials, e.g. decoding measured BRDF files.
+    Materials,
+
+    /// Running the scripts which generate objects.
+    Scripts,
+
+    /// Creating the objects with their shapes and owned materials.
+    Objects,
+
+    /// Decoding the background image.
+    Background,
+}
+
+impl fmt::Display for LoadStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Parse => "parse",
+            Self::Materials => "materials",
+            Self::Scripts => "scripts",
+            Self::Objects => "objects",
+            Self::Background => "background",
+        })
+    }
+}
+
+/// The progress of loading a scene, which is reported once at the start of each stage and
+/// after each item is finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadProgress {
+    /// The current stage.
+    pub stage: LoadStage,
+
+    /// The number of finished items of the stage.
+    pub done: usize,
+
+    /// The number of items of the stage.
+    pub total: usize,
+}
+
+/// The function to receive the progress of loading, see `SceneLoader::on_progress`.
+pub type LoadCallback = Arc<dyn Fn(&LoadProgress) + Send + Sync>;
+
+/// The loader of TOML scene files. The materials and objects are created in parallel, and the
+/// progress is reported to a callback, e.g. `progress_bar` for command line tools. The types
+/// which aren't built-in are created by the plugins of `Registry`.
+#[derive(Default, Clone)]
+pub struct SceneLoader {
+    /// The memory budget of the scene, `None` means unlimited.
+    budget: Option<MemoryBudget>,
+
+    /// The function called with the progress, `None` means silent.
+    callback: Option<LoadCallback>,
+
+    /// The plugins which the scene file may refer to.
+    registry: Registry,
+}
+
+impl SceneLoader {
+    /// Create a loader without budget and progress reporting, like `Scene::load`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the memory budget, see `Scene::load_with_budget`.
+    pub const fn budget(mut self, budget: MemoryBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Set the registry of plugins which the scene file may refer to by name.
+    pub fn registry(mut self, registry: Registry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Set the function called with the progress of loading. It is called from the loading
+    /// threads concurrently.
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&LoadProgress) + Send + Sync + 'static,
+    {
+        self.callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Load the scene and camera from the TOML scene file at `path`. Call `build_bvh` after
+    /// loading.
+    pub fn load(&self, path: &Path) -> io::Result<(Scene, Camera)> {
+        self.report(LoadStage::Parse, 0, 1);
+        let content = fs::read_to_string(path)?;
+        let file: SceneFile = toml::from_str(&content)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        self.report(LoadStage::Parse, 1, 1);
+        file.build(self.budget.as_ref(), &self.registry, &|progress| {
+            if let Some(callback) = &self.callback {
+                callback(progress);
+            }
+        })
+    }
+
+    fn report(&self, stage: LoadStage, done: usize, total: usize) {
+        if let Some(callback) = &self.callback {
+            callback(&LoadProgress { stage, done, total });
+        }
+    }
+}
+
+/// Create a progress callback which draws the stages of loading as a progress bar on the
+/// terminal, like the bar of `Renderer::sample`. The bar is cleared when the background is
+/// loaded, which is the last stage.
+pub fn progress_bar() -> impl Fn(&LoadProgress) + Send + Sync + 'static {
+    let pb = ProgressBar::new(0);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("[{elapsed_precise}] loading {msg:10} {bar:40.cyan/blue} {pos}/{len}")
+            .unwrap()
+            .progress_chars("=>-"),
+    );
+    move |progress| {
+        if progress.done == 0 {
+            pb.set_message(progress.stage.to_string());
+            pb.set_length(progress.total as u64);
+            pb.set_position(0);
+        } else {
+            pb.inc(1);
+        }
+        if progress.stage == LoadStage::Background && progress.done == progress.total {
+            pb.finish_and_clear();
+        }
+    }
+}
@@ -0,0 +1,961 @@
+use std::{collections::HashMap, f64::consts, io};
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::{color::Color, material::Material};
+
+use super::file::{MaterialDesc, ObjectDesc, ObjectMaterialDesc, ScriptDesc, ShapeDesc};
+
+/// The maximal number of statements and loop iterations executed by one script, which stops
+/// endless loops. The tests hit it sooner.
+const MAX_STEPS: usize = if cfg!(test) { 1_000_000 } else { 100_000_000 };
+
+/// The maximal nesting of blocks and expressions, which stops the parser and the interpreter
+/// from overflowing the stack.
+const MAX_DEPTH: usize = 128;
+
+/// The operators and punctuation, the longer ones first so that they are matched greedily.
+const PUNCTUATION: [&str; 24] = [
+    "..", "<=", ">=", "==", "!=", "&&", "||", "(", ")", "[", "]", "{", "}", ",", ";", "=", "+",
+    "-", "*", "/", "%", "<", ">", "!",
+];
+
+/// Run the script of scene file, and get the objects which it creates.
+///
+/// The language is a small subset of Rust for procedural scenes:
+/// - Values are numbers, booleans, strings, lists, e.g. `[x, 0.0, z]` for points and colors,
+///   and materials. Lists of numbers are added, subtracted and scaled like vectors.
+/// - Statements are `let x = expr;`, `x = expr;`, `for i in 0..n { }`, `if cond { } else { }`
+///   and function calls, with `//` comments.
+/// - Math: `sin`, `cos`, `tan`, `asin`, `acos`, `atan`, `atan2`, `sqrt`, `abs`, `floor`,
+///   `ceil`, `round`, `exp`, `ln`, `pow`, `min`, `max`, `clamp`, `lerp`, `%` and the constants
+///   `PI` and `TAU`.
+/// - Randomness seeded by `ScriptDesc::seed`: `rand()` in [0, 1) and `rand_range(a, b)`.
+/// - Lists: `len(list)` and `push(list, value)`, which returns the longer list. The list is
+///   appended in place by `list = push(list, value);`, so that lists are built in linear time.
+/// - Materials: `diffuse(color)`, `specular(color, roughness)`, `metallic(color, roughness)`,
+///   `clear(index, roughness)`, `transparent(color, index, roughness)` and
+///   `light(color, emittance)`, like the constructors of `Material`.
+/// - Objects: `sphere(center, radius)`, `quad(origin, u, v)`, `cube(p1, p2)` and
+///   `disk(center, normal, radius)`, whose optional last argument is a material or the name of
+///   a material in the scene file.
+pub fn run(script: &ScriptDesc) -> io::Result<Vec<ObjectDesc>> {
+    let tokens = tokenize(&script.source)?;
+    let program = Parser {
+        tokens,
+        pos: 0,
+        depth: 0,
+    }
+    .program()?;
+    let mut interpreter = Interpreter {
+        scopes: vec![HashMap::from([
+            ("PI".to_string(), Value::Num(consts::PI)),
+            ("TAU".to_string(), Value::Num(consts::TAU)),
+        ])],
+        rng: StdRng::seed_from_u64(script.seed),
+        objects: Vec::new(),
+        steps: 0,
+    };
+    interpreter.block(&program)?;
+    Ok(interpreter.objects)
+}
+
+/// Create the error of script at `line`.
+fn error(line: usize, msg: impl AsRef<str>) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("script line {line}: {}", msg.as_ref()),
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f64),
+    Ident(String),
+    Str(String),
+    Punct(&'static str),
+}
+
+/// Split the source into tokens with their line numbers.
+fn tokenize(source: &str) -> io::Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let text = text.split_once("//").map_or(text, |(code, _)| code);
+        let chars: Vec<char> = text.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if c.is_whitespace() {
+                i += 1;
+            } else if c.is_ascii_digit() {
+                let start = i;
+                // A dot belongs to the number only if a digit follows, e.g. not `0..n`.
+                while i < chars.len()
+                    && (chars[i].is_ascii_digit()
+                        || chars[i] == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+                {
+                    i += 1;
+                }
+                let number: String = chars[start..i].iter().collect();
+                let value = number
+                    .parse()
+                    .map_err(|_| error(line, format!("invalid number `{number}`")))?;
+                tokens.push((Token::Num(value), line));
+            } else if c.is_alphabetic() || c == '_' {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                tokens.push((Token::Ident(chars[start..i].iter().collect()), line));
+            } else if c == '"' {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&c| c == '"')
+                    .ok_or_else(|| error(line, "unterminated string"))?;
+                tokens.push((Token::Str(chars[i + 1..i + 1 + end].iter().collect()), line));
+                i += end + 2;
+            } else if let Some(punct) = PUNCTUATION
+                .iter()
+                .find(|p| p.chars().eq(chars[i..].iter().take(p.len()).copied()))
+            {
+                tokens.push((Token::Punct(punct), line));
+                i += punct.len();
+            } else {
+                return Err(error(line, format!("unexpected character `{c}`")));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+enum Expr {
+    Num(f64),
+    Bool(bool),
+    Str(String),
+    Var(String),
+    List(Vec<Expr>),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+}
+
+struct Stmt {
+    kind: StmtKind,
+
+    /// The line where the statement starts, which errors are reported at.
+    line: usize,
+}
+
+enum StmtKind {
+    Let(String, Expr),
+    Assign(String, Expr),
+    For(String, Expr, Expr, Vec<Stmt>),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    Expr(Expr),
+}
+
+/// The recursive descent parser of the tokens.
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+
+    /// The nesting of the current block or expression, see `MAX_DEPTH`.
+    depth: usize,
+}
+
+impl Parser {
+    /// Get the line of the current token, or of the last token at the end.
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(1, |(_, line)| *line)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> io::Result<Token> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| error(self.line(), "unexpected end of script"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    /// Consume the punctuation `punct` if it's the current token.
+    fn eat(&mut self, punct: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Punct(p)) if *p == punct);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, punct: &str) -> io::Result<()> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            Err(error(self.line(), format!("expected `{punct}`")))
+        }
+    }
+
+    /// Enter a nested block or expression.
+    fn nest(&mut self) -> io::Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(error(self.line(), "the script is nested too deeply"));
+        }
+        Ok(())
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(name)) if name == keyword)
+    }
+
+    fn ident(&mut self) -> io::Result<String> {
+        match self.next()? {
+            Token::Ident(name) => Ok(name),
+            _ => Err(error(self.line(), "expected a name")),
+        }
+    }
+
+    fn program(&mut self) -> io::Result<Vec<Stmt>> {
+        let mut stmts = Vec::new();
+        while self.peek().is_some() {
+            stmts.push(self.stmt()?);
+        }
+        Ok(stmts)
+    }
+
+    fn block(&mut self) -> io::Result<Vec<Stmt>> {
+        self.expect("{")?;
+        self.nest()?;
+        let mut stmts = Vec::new();
+        while !self.eat("}") {
+            stmts.push(self.stmt()?);
+        }
+        self.depth -= 1;
+        Ok(stmts)
+    }
+
+    fn stmt(&mut self) -> io::Result<Stmt> {
+        let line = self.line();
+        let kind = if self.is_keyword("let") {
+            self.pos += 1;
+            let name = self.ident()?;
+            self.expect("=")?;
+            let value = self.expr()?;
+            self.expect(";")?;
+            StmtKind::Let(name, value)
+        } else if self.is_keyword("for") {
+            self.pos += 1;
+            let name = self.ident()?;
+            if !self.is_keyword("in") {
+                return Err(error(self.line(), "expected `in`"));
+            }
+            self.pos += 1;
+            let start = self.expr()?;
+            self.expect("..")?;
+            let end = self.expr()?;
+            StmtKind::For(name, start, end, self.block()?)
+        } else if self.is_keyword("if") {
+            return self.if_stmt();
+        } else if matches!(self.peek(), Some(Token::Ident(_)))
+            && matches!(self.tokens.get(self.pos + 1), Some((Token::Punct("="), _)))
+        {
+            let name = self.ident()?;
+            self.pos += 1;
+            let value = self.expr()?;
+            self.expect(";")?;
+            StmtKind::Assign(name, value)
+        } else {
+            let expr = self.expr()?;
+            self.expect(";")?;
+            StmtKind::Expr(expr)
+        };
+        Ok(Stmt { kind, line })
+    }
+
+    fn if_stmt(&mut self) -> io::Result<Stmt> {
+        let line = self.line();
+        self.pos += 1;
+        let cond = self.expr()?;
+        let then = self.block()?;
+        let otherwise = if self.is_keyword("else") {
+            self.pos += 1;
+            if self.is_keyword("if") {
+                vec![self.if_stmt()?]
+            } else {
+                self.block()?
+            }
+        } else {
+            Vec::new()
+        };
+        Ok(Stmt {
+            kind: StmtKind::If(cond, then, otherwise),
+            line,
+        })
+    }
+
+    fn expr(&mut self) -> io::Result<Expr> {
+        self.binary(0)
+    }
+
+    /// Parse the binary operators from the precedence `level` upwards.
+    fn binary(&mut self, level: usize) -> io::Result<Expr> {
+        const LEVELS: [&[&str]; 5] = [
+            &["||"],
+            &["&&"],
+            &["==", "!=", "<", ">", "<=", ">="],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+        let Some(ops) = LEVELS.get(level) else {
+            return self.unary();
+        };
+        let depth = self.depth;
+        let mut lhs = self.binary(level + 1)?;
+        // Each operator of a chain nests the expression to its left one deeper.
+        while let Some(&op) = ops
+            .iter()
+            .find(|op| matches!(self.peek(), Some(Token::Punct(p)) if p == *op))
+        {
+            self.pos += 1;
+            self.nest()?;
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        self.depth = depth;
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> io::Result<Expr> {
+        let depth = self.depth;
+        self.nest()?;
+        let expr = self.postfix();
+        self.depth = depth;
+        expr
+    }
+
+    fn postfix(&mut self) -> io::Result<Expr> {
+        for op in ["-", "!"] {
+            if self.eat(op) {
+                return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        let mut expr = self.primary()?;
+        while self.eat("[") {
+            self.nest()?;
+            let index = self.expr()?;
+            self.expect("]")?;
+            expr = Expr::Index(Box::new(expr), Box::new(index));
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> io::Result<Expr> {
+        Ok(match self.next()? {
+            Token::Num(value) => Expr::Num(value),
+            Token::Str(value) => Expr::Str(value),
+            Token::Ident(name) if name == "true" => Expr::Bool(true),
+            Token::Ident(name) if name == "false" => Expr::Bool(false),
+            Token::Ident(name) if self.eat("(") => Expr::Call(name, self.list(")")?),
+            Token::Ident(name) => Expr::Var(name),
+            Token::Punct("(") => {
+                let expr = self.expr()?;
+                self.expect(")")?;
+                expr
+            }
+            Token::Punct("[") => Expr::List(self.list("]")?),
+            Token::Punct(p) => return Err(error(self.line(), format!("unexpected `{p}`"))),
+        })
+    }
+
+    /// Parse the comma separated expressions until `close`.
+    fn list(&mut self, close: &str) -> io::Result<Vec<Expr>> {
+        let mut items = Vec::new();
+        while !self.eat(close) {
+            items.push(self.expr()?);
+            if !self.eat(",") {
+                self.expect(close)?;
+                break;
+            }
+        }
+        Ok(items)
+    }
+}
+
+#[derive(Clone)]
+enum Value {
+    Num(f64),
+    Bool(bool),
+    Str(String),
+    List(Vec<Value>),
+    Material(Box<MaterialDesc>),
+    /// The result of the functions which create objects.
+    Unit,
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Self::Num(_) => "number",
+            Self::Bool(_) => "boolean",
+            Self::Str(_) => "string",
+            Self::List(_) => "list",
+            Self::Material(_) => "material",
+            Self::Unit => "nothing",
+        }
+    }
+
+    fn num(&self) -> Result<f64, String> {
+        match self {
+            Self::Num(value) => Ok(*value),
+            _ => Err(format!("expected a number, found {}", self.type_name())),
+        }
+    }
+
+    fn bool(&self) -> Result<bool, String> {
+        match self {
+            Self::Bool(value) => Ok(*value),
+            _ => Err(format!("expected a boolean, found {}", self.type_name())),
+        }
+    }
+
+    fn vec3(&self) -> Result<[f64; 3], String> {
+        match self {
+            Self::List(items) if items.len() == 3 => {
+                Ok([items[0].num()?, items[1].num()?, items[2].num()?])
+            }
+            _ => Err(format!(
+                "expected a list of 3 numbers, found {}",
+                self.type_name()
+            )),
+        }
+    }
+
+    fn color(&self) -> Result<Color, String> {
+        self.vec3().map(Color::from_array)
+    }
+}
+
+/// Apply the arithmetic `op` to numbers, and to lists elementwise like vectors.
+fn arithmetic(op: &str, lhs: Value, rhs: Value) -> Result<Value, String> {
+    use Value::{List, Num};
+    Ok(match (op, lhs, rhs) {
+        ("+", Num(a), Num(b)) => Num(a + b),
+        ("-", Num(a), Num(b)) => Num(a - b),
+        ("*", Num(a), Num(b)) => Num(a * b),
+        ("/", Num(a), Num(b)) => Num(a / b),
+        ("%", Num(a), Num(b)) => Num(a.rem_euclid(b)),
+        ("+" | "-", List(a), List(b)) if a.len() == b.len() => List(
+            a.into_iter()
+                .zip(b)
+                .map(|(a, b)| arithmetic(op, a, b))
+                .collect::<Result<_, _>>()?,
+        ),
+        ("*" | "/", List(a), Num(b)) => List(
+            a.into_iter()
+                .map(|a| arithmetic(op, a, Num(b)))
+                .collect::<Result<_, _>>()?,
+        ),
+        ("*", Num(a), List(b)) => arithmetic(op, List(b), Num(a))?,
+        (_, lhs, rhs) => {
+            return Err(format!(
+                "can't apply `{op}` to {} and {}",
+                lhs.type_name(),
+                rhs.type_name()
+            ));
+        }
+    })
+}
+
+/// Whether the values are equal, the lists are compared by items.
+fn equal(lhs: &Value, rhs: &Value) -> bool {
+    match (lhs, rhs) {
+        (Value::Num(a), Value::Num(b)) => a == b,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Str(a), Value::Str(b)) => a == b,
+        (Value::List(a), Value::List(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equal(a, b))
+        }
+        _ => false,
+    }
+}
+
+struct Interpreter {
+    /// The variables of the nested blocks, the innermost is the last.
+    scopes: Vec<HashMap<String, Value>>,
+
+    rng: StdRng,
+
+    /// The objects created by the script.
+    objects: Vec<ObjectDesc>,
+
+    /// The number of executed statements, see `MAX_STEPS`.
+    steps: usize,
+}
+
+impl Interpreter {
+    fn block(&mut self, stmts: &[Stmt]) -> io::Result<()> {
+        self.scopes.push(HashMap::new());
+        let result = stmts.iter().try_for_each(|stmt| self.stmt(stmt));
+        self.scopes.pop();
+        result
+    }
+
+    /// Count a statement or a loop iteration at `line`, see `MAX_STEPS`.
+    fn step(&mut self, line: usize) -> io::Result<()> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err(error(line, "too many steps, is there an endless loop?"));
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> io::Result<()> {
+        self.step(stmt.line)?;
+        let at_line = |msg: String| error(stmt.line, msg);
+        match &stmt.kind {
+            StmtKind::Let(name, expr) => {
+                let value = self.eval(expr).map_err(at_line)?;
+                let scope = self.scopes.last_mut().expect("the global scope exists");
+                scope.insert(name.clone(), value);
+            }
+            StmtKind::Assign(name, Expr::Call(function, args))
+                if function == "push"
+                    && matches!(args.as_slice(), [Expr::Var(list), _] if list == name) =>
+            {
+                // The list is appended in place instead of being copied by the call.
+                let value = self.eval(&args[1]).map_err(at_line)?;
+                match self.variable(name).map_err(at_line)? {
+                    Value::List(items) => items.push(value),
+                    list => {
+                        return Err(at_line(format!("can't push to {}", list.type_name())));
+                    }
+                }
+            }
+            StmtKind::Assign(name, expr) => {
+                let value = self.eval(expr).map_err(at_line)?;
+                *self.variable(name).map_err(at_line)? = value;
+            }
+            StmtKind::For(name, start, end, body) => {
+                let start = self.eval(start).and_then(|v| v.num()).map_err(at_line)?;
+                let end = self.eval(end).and_then(|v| v.num()).map_err(at_line)?;
+                if !start.is_finite() || !end.is_finite() {
+                    return Err(at_line(format!("the range {start}..{end} isn't finite")));
+                }
+                // The counter would get stuck where the numbers are more than 1 apart.
+                if start < end && (start + 1.0 == start || end - 1.0 == end) {
+                    return Err(at_line(format!("the range {start}..{end} is too large")));
+                }
+                let mut i = start;
+                while i < end {
+                    self.step(stmt.line)?;
+                    self.scopes
+                        .push(HashMap::from([(name.clone(), Value::Num(i))]));
+                    let result = self.block(body);
+                    self.scopes.pop();
+                    result?;
+                    i += 1.0;
+                }
+            }
+            StmtKind::If(cond, then, otherwise) => {
+                if self.eval(cond).and_then(|v| v.bool()).map_err(at_line)? {
+                    self.block(then)?;
+                } else {
+                    self.block(otherwise)?;
+                }
+            }
+            StmtKind::Expr(expr) => {
+                self.eval(expr).map_err(at_line)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the variable `name` of the innermost scope which defines it.
+    fn variable(&mut self, name: &str) -> Result<&mut Value, String> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+            .ok_or_else(|| format!("undefined variable `{name}`"))
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Value, String> {
+        Ok(match expr {
+            Expr::Num(value) => Value::Num(*value),
+            Expr::Bool(value) => Value::Bool(*value),
+            Expr::Str(value) => Value::Str(value.clone()),
+            Expr::Var(name) => self.variable(name)?.clone(),
+            Expr::List(items) => Value::List(
+                items
+                    .iter()
+                    .map(|item| self.eval(item))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Expr::Unary(op, operand) => match (*op, self.eval(operand)?) {
+                ("-", value) => arithmetic("*", value, Value::Num(-1.0))?,
+                (_, value) => Value::Bool(!value.bool()?),
+            },
+            Expr::Binary("&&", lhs, rhs) => {
+                Value::Bool(self.eval(lhs)?.bool()? && self.eval(rhs)?.bool()?)
+            }
+            Expr::Binary("||", lhs, rhs) => {
+                Value::Bool(self.eval(lhs)?.bool()? || self.eval(rhs)?.bool()?)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (self.eval(lhs)?, self.eval(rhs)?);
+                match *op {
+                    "==" => Value::Bool(equal(&lhs, &rhs)),
+                    "!=" => Value::Bool(!equal(&lhs, &rhs)),
+                    "<" => Value::Bool(lhs.num()? < rhs.num()?),
+                    ">" => Value::Bool(lhs.num()? > rhs.num()?),
+                    "<=" => Value::Bool(lhs.num()? <= rhs.num()?),
+                    ">=" => Value::Bool(lhs.num()? >= rhs.num()?),
+                    _ => arithmetic(op, lhs, rhs)?,
+                }
+            }
+            // The items of variables are read in place instead of copying the list.
+            Expr::Index(list, index) => match list.as_ref() {
+                Expr::Var(name) => {
+                    let index = self.eval(index)?.num()?;
+                    item(self.variable(name)?, index)?
+                }
+                list => {
+                    let list = self.eval(list)?;
+                    item(&list, self.eval(index)?.num()?)?
+                }
+            },
+            Expr::Call(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.call(name, &args)?
+            }
+        })
+    }
+
+    fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, String> {
+        let num = |f: fn(f64) -> f64, x: &Value| Ok(Value::Num(f(x.num()?)));
+        match (name, args) {
+            ("sin", [x]) => num(f64::sin, x),
+            ("cos", [x]) => num(f64::cos, x),
+            ("tan", [x]) => num(f64::tan, x),
+            ("asin", [x]) => num(f64::asin, x),
+            ("acos", [x]) => num(f64::acos, x),
+            ("atan", [x]) => num(f64::atan, x),
+            ("sqrt", [x]) => num(f64::sqrt, x),
+            ("abs", [x]) => num(f64::abs, x),
+            ("floor", [x]) => num(f64::floor, x),
+            ("ceil", [x]) => num(f64::ceil, x),
+            ("round", [x]) => num(f64::round, x),
+            ("exp", [x]) => num(f64::exp, x),
+            ("ln", [x]) => num(f64::ln, x),
+            ("atan2", [y, x]) => Ok(Value::Num(y.num()?.atan2(x.num()?))),
+            ("pow", [x, y]) => Ok(Value::Num(x.num()?.powf(y.num()?))),
+            ("min", [x, y]) => Ok(Value::Num(x.num()?.min(y.num()?))),
+            ("max", [x, y]) => Ok(Value::Num(x.num()?.max(y.num()?))),
+            ("clamp", [x, lo, hi]) => Ok(Value::Num(x.num()?.max(lo.num()?).min(hi.num()?))),
+            ("lerp", [a, b, t]) => {
+                let t = t.num()?;
+                arithmetic(
+                    "+",
+                    arithmetic("*", a.clone(), Value::Num(1.0 - t))?,
+                    arithmetic("*", b.clone(), Value::Num(t))?,
+                )
+            }
+            ("rand", []) => Ok(Value::Num(self.rng.random())),
+            ("rand_range", [a, b]) => {
+                let (a, b) = (a.num()?, b.num()?);
+                Ok(Value::Num(a + (b - a) * self.rng.random::<f64>()))
+            }
+            ("len", [Value::List(items)]) => Ok(Value::Num(items.len() as f64)),
+            ("push", [Value::List(items), value]) => {
+                let mut items = items.clone();
+                items.push(value.clone());
+                Ok(Value::List(items))
+            }
+            ("diffuse", [color]) => material(Material::diffuse(color.color()?)),
+            ("specular", [color, roughness]) => {
+                material(Material::specular(color.color()?, roughness.num()?))
+            }
+            ("metallic", [color, roughness]) => {
+                material(Material::metallic(color.color()?, roughness.num()?))
+            }
+            ("clear", [index, roughness]) => {
+                material(Material::clear(index.num()?, roughness.num()?))
+            }
+            ("transparent", [color, index, roughness]) => material(Material::transparent(
+                color.color()?,
+                index.num()?,
+                roughness.num()?,
+            )),
+            ("light", [color, emittance]) => {
+                material(Material::light(color.color()?, emittance.num()?))
+            }
+            ("sphere", [center, radius, rest @ ..]) => self.emit(
+                ShapeDesc::Sphere {
+                    center: center.vec3()?,
+                    center_to: None,
+                    radius: radius.num()?,
+                },
+                rest,
+            ),
+            ("quad", [origin, u, v, rest @ ..]) => self.emit(
+                ShapeDesc::Quad {
+                    origin: origin.vec3()?,
+                    u: u.vec3()?,
+                    v: v.vec3()?,
+                },
+                rest,
+            ),
+            ("cube", [p1, p2, rest @ ..]) => self.emit(
+                ShapeDesc::Cube {
+                    p1: p1.vec3()?,
+                    p2: p2.vec3()?,
+                },
+                rest,
+            ),
+            ("disk", [center, normal, radius, rest @ ..]) => self.emit(
+                ShapeDesc::Disk {
+                    center: center.vec3()?,
+                    normal: normal.vec3()?,
+                    radius: radius.num()?,
+                },
+                rest,
+            ),
+            _ => Err(format!(
+                "unknown function `{name}` or wrong number of arguments ({})",
+                args.len()
+            )),
+        }
+    }
+
+    /// Create the object of `shape` whose material is the optional argument in `rest`.
+    fn emit(&mut self, shape: ShapeDesc, rest: &[Value]) -> Result<Value, String> {
+        let material = match rest {
+            [] => ObjectMaterialDesc::default(),
+            [Value::Str(name)] => ObjectMaterialDesc::Named(name.clone()),
+            [Value::Material(desc)] => ObjectMaterialDesc::Owned(desc.clone()),
+            [value] => {
+                return Err(format!(
+                    "expected a material or its name, found {}",
+                    value.type_name()
+                ));
+            }
+            _ => return Err("too many arguments".to_string()),
+        };
+        self.objects.push(ObjectDesc {
+            shape,
+            material,
+            light_group: None,
+            seed: 0,
+            name: None,
+            sides: Default::default(),
+        });
+        Ok(Value::Unit)
+    }
+}
+
+/// Get the item of `list` at `index`.
+fn item(list: &Value, index: f64) -> Result<Value, String> {
+    let Value::List(items) = list else {
+        return Err(format!("can't index {}", list.type_name()));
+    };
+    items
+        .get(index as usize)
+        .filter(|_| index >= 0.0)
+        .cloned()
+        .ok_or_else(|| format!("index {index} is out of the list of {}", items.len()))
+}
+
+/// Get the value of `material` for the scene file.
+fn material(material: Material) -> Result<Value, String> {
+    MaterialDesc::from_bsdf(&material)
+        .map(|desc| Value::Material(Box::new(desc)))
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_source(source: &str) -> io::Result<Vec<ObjectDesc>> {
+        run(&ScriptDesc {
+            source: source.to_string(),
+            seed: 7,
+        })
+    }
+
+    /// Get the message of the error of the script.
+    fn error_of(source: &str) -> String {
+        run_source(source)
+            .err()
+            .expect("the script fails")
+            .to_string()
+    }
+
+    /// Get the centers and radii of the spheres created by the script.
+    fn spheres(source: &str) -> Vec<([f64; 3], f64)> {
+        run_source(source)
+            .unwrap()
+            .into_iter()
+            .map(|object| match object.shape {
+                ShapeDesc::Sphere { center, radius, .. } => (center, radius),
+                _ => panic!("the script creates only spheres"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn operators_follow_precedence() {
+        let source = "sphere([1 + 2 * 3, -(1 - 3), 10 % 4], 2 * 3 - 4 / 2);";
+        assert_eq!(spheres(source), [([7.0, 2.0, 2.0], 4.0)]);
+        let source = "if 1 < 2 && !(2 <= 1) || false { sphere([0, 0, 0], 1); }";
+        assert_eq!(spheres(source).len(), 1);
+    }
+
+    #[test]
+    fn lists_are_vectors() {
+        let source = "let a = [1, 2, 3];\nsphere(a * 2 - [1, 1, 1], a[2]);\n";
+        assert_eq!(spheres(source), [([1.0, 3.0, 5.0], 3.0)]);
+        let source = "sphere(lerp([0, 0, 0], [2, 4, 6], 0.5), len([1, 2]));";
+        assert_eq!(spheres(source), [([1.0, 2.0, 3.0], 2.0)]);
+    }
+
+    #[test]
+    fn loops_branches_and_scopes() {
+        let source = "
+            let count = 0;
+            for i in 0..10 {
+                let odd = i % 2 == 1;
+                if odd {
+                    count = count + 1;
+                } else if i == 4 {
+                    sphere([i, 0, 0], 1);
+                }
+            }
+            sphere([count, 0, 0], 1);
+        ";
+        assert_eq!(
+            spheres(source),
+            [([4.0, 0.0, 0.0], 1.0), ([5.0, 0.0, 0.0], 1.0)]
+        );
+        let err = error_of("for i in 0..2 { let x = i; }\nsphere([x, 0, 0], 1);");
+        assert_eq!(err, "script line 2: undefined variable `x`");
+    }
+
+    #[test]
+    fn push_appends_in_place() {
+        // The lists of 10^5 items are built in linear time, which take minutes if copied.
+        let source = "
+            let items = [];
+            for i in 0..100000 {
+                items = push(items, i);
+            }
+            let copy = push(items, 0);
+            sphere([len(items), len(copy), items[99999]], 1);
+        ";
+        assert_eq!(spheres(source), [([100000.0, 100001.0, 99999.0], 1.0)]);
+        let err = error_of("let x = 1;\nx = push(x, 2);");
+        assert_eq!(err, "script line 2: can't push to number");
+    }
+
+    #[test]
+    fn materials_are_named_or_owned() {
+        let objects = run_source(
+            "sphere([0, 0, 0], 1, \"glass\");\ncube([0, 0, 0], [1, 1, 1], diffuse([0.5, 0.5, 0.5]));",
+        )
+        .unwrap();
+        assert!(matches!(&objects[0].material, ObjectMaterialDesc::Named(name) if name == "glass"));
+        assert!(matches!(objects[1].material, ObjectMaterialDesc::Owned(_)));
+        let err = error_of("\nsphere([0, 0, 0], 1, 2);");
+        assert_eq!(
+            err,
+            "script line 2: expected a material or its name, found number"
+        );
+    }
+
+    #[test]
+    fn random_numbers_are_seeded() {
+        let source = "for i in 0..4 { sphere([rand(), rand_range(2, 3), 0], 1); }";
+        let (a, b) = (spheres(source), spheres(source));
+        assert_eq!(a, b);
+        assert!(
+            a.iter()
+                .all(|&([x, y, _], _)| (0.0..1.0).contains(&x) && (2.0..3.0).contains(&y))
+        );
+        assert_ne!(a[0], a[1]);
+    }
+
+    #[test]
+    fn endless_loops_are_errors() {
+        for (source, message) in [
+            (
+                "for i in 0..pow(10, 15) {}",
+                "too many steps, is there an endless loop?",
+            ),
+            (
+                "for i in 0..10 {\n    for j in 0..pow(10, 15) {}\n}",
+                "too many steps, is there an endless loop?",
+            ),
+            (
+                "for i in pow(10, 20)..pow(10, 21) {}",
+                "the range 100000000000000000000..1000000000000000000000 is too large",
+            ),
+            ("for i in 0..1 / 0 {}", "the range 0..inf isn't finite"),
+            ("for i in 0..sqrt(-1) {}", "the range 0..NaN isn't finite"),
+        ] {
+            let line = if source.contains('\n') { 2 } else { 1 };
+            assert_eq!(error_of(source), format!("script line {line}: {message}"));
+        }
+    }
+
+    #[test]
+    fn syntax_errors_are_reported_at_their_line() {
+        for (source, message) in [
+            ("let x = 1;\nlet y = ;", "script line 2: unexpected `;`"),
+            ("let x = 1\n", "script line 1: expected `;`"),
+            (
+                "sphere([0, 0, 0], 1);\nlet s = \"open;",
+                "script line 2: unterminated string",
+            ),
+            (
+                "\n\nlet x = 1 # 2;",
+                "script line 3: unexpected character `#`",
+            ),
+            ("for i in 0..2 {", "script line 1: unexpected end of script"),
+        ] {
+            assert_eq!(error_of(source), message);
+        }
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        let nested = |open: &str, inner: &str, close: &str, depth: usize| {
+            format!("{}{inner}{}", open.repeat(depth), close.repeat(depth))
+        };
+        // The deepest parentheses in the arguments of a call.
+        let shallow = nested("(", "1", ")", MAX_DEPTH - 2);
+        assert_eq!(spheres(&format!("sphere([0, 0, 0], {shallow});")).len(), 1);
+        for source in [
+            format!("let x = {};", nested("(", "1", ")", 100_000)),
+            format!("let x = {};", nested("[", "1", "]", 100_000)),
+            format!("let x = {}1;", "-".repeat(100_000)),
+            format!("let x = 1{};", " + 1".repeat(100_000)),
+            format!("let x = [1]{};", "[0]".repeat(100_000)),
+            nested("if true { ", "", "} ", 100_000),
+        ] {
+            let err = error_of(&source);
+            assert_eq!(err, "script line 1: the script is nested too deeply");
+        }
+    }
+}
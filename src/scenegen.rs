@@ -0,0 +1,573 @@
+use std::{f64, sync::Arc};
+
+use glam::{DMat4, DQuat, DVec3};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::{
+    bvh::{Bvh, DEFAULT_LEAF_SIZE},
+    color,
+    interval::Interval,
+    material::Material,
+    math::{DPoint3, Transform},
+    object::Object,
+    sampling,
+    scene::Scene,
+    shape::{HitRecord, sphere::Sphere},
+    texture::Texture,
+};
+
+/// The location and size of one generated object.
+#[derive(Clone, Copy)]
+pub struct Placement {
+    /// The point on the surface where the object stands.
+    pub point: DPoint3,
+
+    /// The normalized normal vector of the surface at `point`.
+    pub normal: DVec3,
+
+    /// The scale factor of the object template.
+    pub scale: f64,
+}
+
+impl Placement {
+    /// Get the transformation of the object template standing on the surface, i.e. scaled,
+    /// rotated by `angle` in radians around its y axis, whose y axis is then aligned with the
+    /// normal, see `Object::instance`.
+    pub fn transform(&self, angle: f64) -> Transform {
+        let rotation =
+            DQuat::from_rotation_arc(DVec3::Y, self.normal) * DQuat::from_rotation_y(angle);
+        Transform::from_scale_rotation_translation(DVec3::splat(self.scale), rotation, self.point)
+    }
+}
+
+/// Push the placement into `placements` if it doesn't overlap with others, the objects are
+/// bounded by spheres of `radius` at scale 1.0.
+fn try_place(placements: &mut Vec<Placement>, placement: Placement, radius: f64) {
+    let overlapped = placements.iter().any(|other| {
+        let min_distance = radius * (placement.scale + other.scale);
+        other.point.distance_squared(placement.point) < min_distance * min_distance
+    });
+    if !overlapped {
+        placements.push(placement);
+    }
+}
+
+/// Get a random scale factor in `range`.
+fn random_scale(range: Interval, rng: &mut StdRng) -> f64 {
+    if range.size() > 0.0 {
+        rng.random_range(range.min..range.max)
+    } else {
+        range.min
+    }
+}
+
+/// Place object templates on a planar surface which defined by an origin point and two basis
+/// vectors like `Quad`, with random jitter, scale and overlap rejection.
+pub struct Scatter {
+    /// The origin of the surface.
+    origin: DPoint3,
+
+    /// The basis vector u of the surface.
+    u: DVec3,
+
+    /// The basis vector v of the surface.
+    v: DVec3,
+
+    /// The jitter of position as a fraction of the spacing between two objects.
+    jitter: f64,
+
+    /// The range of random scale factor.
+    scale: Interval,
+
+    /// The bounding radius of object template at scale 1.0 which is used for overlap rejection.
+    /// Zero means that overlap is allowed.
+    radius: f64,
+
+    /// The seed of random generator, the same seed always produces the same placements.
+    seed: u64,
+}
+
+impl Scatter {
+    /// Create a scatter on the surface `origin + a * u + b * v` where a, b in [0, 1].
+    pub fn new(origin: DPoint3, u: DVec3, v: DVec3) -> Self {
+        Self {
+            origin,
+            u,
+            v,
+            jitter: 0.0,
+            scale: Interval::new(1.0, 1.0),
+            radius: 0.0,
+            seed: 0,
+        }
+    }
+
+    /// Set the jitter of position as a fraction of the spacing between two objects.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Set the range of random scale factor.
+    pub fn scale(mut self, min: f64, max: f64) -> Self {
+        self.scale = Interval::new(min, max);
+        self
+    }
+
+    /// Set the bounding radius of object template to reject overlapping placements.
+    pub fn radius(mut self, radius: f64) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    /// Set the seed of random generator.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// The normalized normal vector of the surface.
+    fn normal(&self) -> DVec3 {
+        self.u.cross(self.v).normalize()
+    }
+
+    /// Place objects on a `nu` x `nv` grid, each object in the center of its cell.
+    pub fn grid(&self, nu: u32, nv: u32) -> Vec<Placement> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut placements = Vec::new();
+        for j in 0..nv {
+            for i in 0..nu {
+                let a = (i as f64 + 0.5 + self.random_jitter(&mut rng)) / nu as f64;
+                let b = (j as f64 + 0.5 + self.random_jitter(&mut rng)) / nv as f64;
+                let point = self.origin + a * self.u + b * self.v;
+                self.try_place(&mut placements, point, &mut rng);
+            }
+        }
+        placements
+    }
+
+    /// Place `count` objects evenly on a ring with `radius` around the center of the surface.
+    pub fn ring(&self, count: u32, radius: f64) -> Vec<Placement> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut placements = Vec::new();
+        let center = self.origin + 0.5 * (self.u + self.v);
+        let (x, y) = (self.u.normalize(), self.v.normalize());
+        for i in 0..count {
+            let phi = (i as f64 + self.random_jitter(&mut rng)) / count as f64 * f64::consts::TAU;
+            let point = center + radius * (phi.cos() * x + phi.sin() * y);
+            self.try_place(&mut placements, point, &mut rng);
+        }
+        placements
+    }
+
+    /// Place objects on uniformly random positions until `count` objects are placed or the
+    /// number of attempts reach `max_attempts`.
+    pub fn random(&self, count: usize, max_attempts: usize) -> Vec<Placement> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut placements = Vec::new();
+        for _ in 0..max_attempts {
+            if placements.len() >= count {
+                break;
+            }
+            let point = self.origin + rng.random::<f64>() * self.u + rng.random::<f64>() * self.v;
+            self.try_place(&mut placements, point, &mut rng);
+        }
+        placements
+    }
+
+    /// Get a random offset in [-jitter/2, jitter/2).
+    fn random_jitter(&self, rng: &mut StdRng) -> f64 {
+        if self.jitter > 0.0 {
+            self.jitter * (rng.random::<f64>() - 0.5)
+        } else {
+            0.0
+        }
+    }
+
+    /// Push the placement into `placements` if it doesn't overlap with others.
+    fn try_place(&self, placements: &mut Vec<Placement>, point: DPoint3, rng: &mut StdRng) {
+        let placement = Placement {
+            point,
+            normal: self.normal(),
+            scale: random_scale(self.scale, rng),
+        };
+        try_place(placements, placement, self.radius);
+    }
+}
+
+/// Place object templates on the surface of a triangle mesh, e.g. grass and rocks on terrain.
+/// The triangles are chosen by area, so the placements are uniform over the surface, and the
+/// optional density texture thins them out.
+pub struct MeshScatter {
+    /// The vertices of triangles in counter-clockwise order seen from the front.
+    triangles: Vec<[DPoint3; 3]>,
+
+    /// The texture coordinates of the vertices of each triangle, `None` to use the barycentric
+    /// coordinates.
+    uvs: Option<Vec<[(f64, f64); 3]>>,
+
+    /// The probability to keep a placement, i.e. the luminance of texture clamped to [0, 1].
+    density: Option<Arc<dyn Texture>>,
+
+    /// The range of random scale factor.
+    scale: Interval,
+
+    /// The bounding radius of object template at scale 1.0 which is used for overlap rejection.
+    /// Zero means that overlap is allowed.
+    radius: f64,
+
+    /// The seed of random generator, the same seed always produces the same placements.
+    seed: u64,
+}
+
+impl MeshScatter {
+    /// Create a scatter on the surface of triangles.
+    pub fn new(triangles: Vec<[DPoint3; 3]>) -> Self {
+        Self {
+            triangles,
+            uvs: None,
+            density: None,
+            scale: Interval::new(1.0, 1.0),
+            radius: 0.0,
+            seed: 0,
+        }
+    }
+
+    /// Set the texture coordinates of the vertices of each triangle, which the density texture
+    /// is looked up by.
+    pub fn uvs(mut self, uvs: Vec<[(f64, f64); 3]>) -> Self {
+        assert_eq!(
+            uvs.len(),
+            self.triangles.len(),
+            "one uv per vertex is required"
+        );
+        self.uvs = Some(uvs);
+        self
+    }
+
+    /// Set the texture of density whose luminance is the probability to keep a placement.
+    pub fn density<T>(mut self, density: T) -> Self
+    where
+        T: Texture + 'static,
+    {
+        self.density = Some(Arc::new(density));
+        self
+    }
+
+    /// Set the range of random scale factor.
+    pub fn scale(mut self, min: f64, max: f64) -> Self {
+        self.scale = Interval::new(min, max);
+        self
+    }
+
+    /// Set the bounding radius of object template to reject overlapping placements.
+    pub fn radius(mut self, radius: f64) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    /// Set the seed of random generator.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Place objects on random positions until `count` objects are placed or the number of
+    /// attempts reach `max_attempts`.
+    pub fn random(&self, count: usize, max_attempts: usize) -> Vec<Placement> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut placements = Vec::new();
+        // The cumulative area of triangles.
+        let cdf: Vec<f64> = self
+            .triangles
+            .iter()
+            .scan(0.0, |sum, [a, b, c]| {
+                *sum += 0.5 * (b - a).cross(c - a).length();
+                Some(*sum)
+            })
+            .collect();
+        let Some(&total) = cdf.last().filter(|&&total| total > 0.0) else {
+            return placements;
+        };
+        for _ in 0..max_attempts {
+            if placements.len() >= count {
+                break;
+            }
+            let u = rng.random::<f64>() * total;
+            let i = cdf.partition_point(|&c| c <= u).min(cdf.len() - 1);
+            let [a, b, c] = self.triangles[i];
+            let (b1, b2) = sampling::triangle_barycentric(rng.random());
+            let b0 = 1.0 - b1 - b2;
+            let mut rec = HitRecord {
+                p: b0 * a + b1 * b + b2 * c,
+                normal: (b - a).cross(c - a).normalize(),
+                front_face: true,
+                u: b1,
+                v: b2,
+                ..Default::default()
+            };
+            if let Some(uvs) = &self.uvs {
+                let [uv0, uv1, uv2] = uvs[i];
+                rec.u = b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0;
+                rec.v = b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1;
+            }
+            if let Some(density) = &self.density {
+                let keep = color::luminance(density.value(&rec)).clamp(0.0, 1.0);
+                if rng.random::<f64>() >= keep {
+                    continue;
+                }
+            }
+            let placement = Placement {
+                point: rec.p,
+                normal: rec.normal,
+                scale: random_scale(self.scale, &mut rng),
+            };
+            try_place(&mut placements, placement, self.radius);
+        }
+        placements
+    }
+}
+
+/// Create objects from placements through the template function.
+pub fn instantiate<F>(placements: &[Placement], seed: u64, mut template: F) -> Vec<Object>
+where
+    F: FnMut(&Placement, &mut StdRng) -> Object,
+{
+    let mut rng = StdRng::seed_from_u64(seed);
+    placements
+        .iter()
+        .map(|placement| template(placement, &mut rng))
+        .collect()
+}
+
+/// A parameter of `Material` which is swept by `MaterialGrid`.
+#[derive(Clone, Copy)]
+pub enum MaterialParam {
+    /// The roughness, clamped between 0.0 and 1.0.
+    Roughness,
+
+    /// The metallic property.
+    Metallic,
+
+    /// The index of refraction.
+    Index,
+
+    /// The emittance.
+    Emittance,
+}
+
+impl MaterialParam {
+    /// The name of parameter in labels.
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Roughness => "roughness",
+            Self::Metallic => "metallic",
+            Self::Index => "index",
+            Self::Emittance => "emittance",
+        }
+    }
+
+    /// Set the parameter of material to value.
+    fn apply(&self, material: &mut Material, value: f64) {
+        match self {
+            Self::Roughness => material.roughness = value.clamp(0.0, 1.0),
+            Self::Metallic => material.metallic = value,
+            // The same offset as `Material::base`.
+            Self::Index => material.index = value + 1e-6,
+            Self::Emittance => material.emittance = value,
+        }
+    }
+}
+
+/// The values of one parameter along an axis of `MaterialGrid`.
+#[derive(Clone, Copy)]
+pub struct Sweep {
+    /// The swept parameter.
+    pub param: MaterialParam,
+
+    /// The range of values, both ends included.
+    pub range: Interval,
+
+    /// The number of values.
+    pub count: u32,
+}
+
+impl Sweep {
+    /// Sweep `param` from `min` to `max` with `count` values.
+    pub fn new(param: MaterialParam, min: f64, max: f64, count: u32) -> Self {
+        Self {
+            param,
+            range: Interval::new(min, max),
+            count,
+        }
+    }
+
+    /// Get the `i`-th value of the sweep.
+    fn value(&self, i: u32) -> f64 {
+        if self.count <= 1 {
+            return self.range.min;
+        }
+        self.range.min + self.range.size() * i as f64 / (self.count - 1) as f64
+    }
+}
+
+/// The text describing the parameters of one sphere in `MaterialGrid`.
+pub struct Label {
+    /// The parameters in "name=value" form.
+    pub text: String,
+
+    /// The center of the sphere.
+    pub point: DPoint3,
+}
+
+/// A grid of spheres whose material parameters vary along two axes, for side by side
+/// comparisons of material parameters.
+pub struct MaterialGrid {
+    /// The spheres of the grid.
+    pub objects: Vec<Object>,
+
+    /// The labels of the spheres in the same order as `objects`.
+    pub labels: Vec<Label>,
+}
+
+impl MaterialGrid {
+    /// Create the spheres from `base` material, where the parameter of `u` varies from left to
+    /// right along x axis and the parameter of `v` varies from bottom to top along y axis. The
+    /// grid is centered at `center` and the spheres are `spacing` apart and face to +z.
+    pub fn new(base: &Material, u: Sweep, v: Sweep, center: DPoint3, spacing: f64) -> Self {
+        let (nu, nv) = (u.count.max(1), v.count.max(1));
+        let u_vec = DVec3::X * spacing * nu as f64;
+        let v_vec = DVec3::Y * spacing * nv as f64;
+        let placements = Scatter::new(center - 0.5 * (u_vec + v_vec), u_vec, v_vec).grid(nu, nv);
+        let radius = 0.4 * spacing;
+        let mut objects = Vec::new();
+        let mut labels = Vec::new();
+        for (index, placement) in placements.iter().enumerate() {
+            let (i, j) = (index as u32 % nu, index as u32 / nu);
+            let (value_u, value_v) = (u.value(i), v.value(j));
+            let mut material = base.clone();
+            u.param.apply(&mut material, value_u);
+            v.param.apply(&mut material, value_v);
+            objects
+                .push(Object::new(Sphere::new(placement.point, None, radius)).material(material));
+            labels.push(Label {
+                text: format!(
+                    "{}={value_u:.2}, {}={value_v:.2}",
+                    u.param.name(),
+                    v.param.name()
+                ),
+                point: placement.point,
+            });
+        }
+        Self { objects, labels }
+    }
+
+    /// Add the spheres into `scene`.
+    pub fn add_to(self, scene: Scene) -> Scene {
+        scene.with_obj_list(self.objects)
+    }
+}
+
+/// The self-similar fractals of stress tests, which are made of the copies of a template object
+/// filling the cube [-1, 1]³, e.g. `Cube` or `Sphere` of radius 1 at the origin.
+#[derive(Clone, Copy, Debug)]
+pub enum Fractal {
+    /// The cube of 20 copies of a third, i.e. without the copies at the center and the face
+    /// centers.
+    MengerSponge,
+
+    /// The tetrahedron of 4 copies of half size at its corners.
+    SierpinskiTetrahedron,
+}
+
+impl Fractal {
+    /// Get the transformations of the copies at one level.
+    pub fn copies(self) -> Vec<Transform> {
+        match self {
+            Fractal::MengerSponge => {
+                let mut copies = Vec::with_capacity(20);
+                for x in -1..=1 {
+                    for y in -1..=1 {
+                        for z in -1..=1 {
+                            let zeros = [x, y, z].iter().filter(|&&c| c == 0).count();
+                            if zeros <= 1 {
+                                let offset = DVec3::new(x as f64, y as f64, z as f64) * 2.0 / 3.0;
+                                copies.push(copy(1.0 / 3.0, offset));
+                            }
+                        }
+                    }
+                }
+                copies
+            }
+            Fractal::SierpinskiTetrahedron => [
+                DVec3::new(1.0, 1.0, 1.0),
+                DVec3::new(1.0, -1.0, -1.0),
+                DVec3::new(-1.0, 1.0, -1.0),
+                DVec3::new(-1.0, -1.0, 1.0),
+            ]
+            .map(|corner| copy(0.5, corner * 0.5))
+            .to_vec(),
+        }
+    }
+
+    /// Get the number of copies of the template at `level`, which saturates at `u64::MAX` for
+    /// the levels too deep to be built anyway.
+    pub fn count(self, level: u32) -> u64 {
+        (self.copies().len() as u64).saturating_pow(level)
+    }
+
+    /// Create the copies of `template` at `level` as separate instances, which stress the BVH
+    /// of scene with `count(level)` objects.
+    pub fn instances(self, template: &Object, level: u32) -> Vec<Object> {
+        let copies = self.copies();
+        let mut transforms = vec![Transform::new(DMat4::IDENTITY)];
+        for _ in 0..level {
+            transforms = transforms
+                .iter()
+                .flat_map(|parent| copies.iter().map(move |child| *parent * *child))
+                .collect();
+        }
+        transforms
+            .into_iter()
+            .map(|transform| template.instance(transform))
+            .collect()
+    }
+
+    /// Create the fractal of `template` at `level` as one object, whose shape is the BVH of the
+    /// instances of the fractal at the previous level. The memory grows linearly with the level
+    /// while the copies of the template grow exponentially, which stresses instancing.
+    pub fn nested(self, template: &Object, level: u32) -> Object {
+        let copies = self.copies();
+        (0..level).fold(template.clone(), |fractal, _| {
+            let instances = copies.iter().map(|copy| fractal.instance(*copy)).collect();
+            Object {
+                shape: Arc::new(Bvh::build(instances, DEFAULT_LEAF_SIZE)),
+                ..template.clone()
+            }
+        })
+    }
+}
+
+/// Get the transformation of a copy scaled by `scale` and moved by `offset`.
+fn copy(scale: f64, offset: DVec3) -> Transform {
+    Transform::from_scale_rotation_translation(DVec3::splat(scale), DQuat::IDENTITY, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shape::sphere::Sphere;
+
+    #[test]
+    fn fractal_counts_match_instances() {
+        let template = Object::new(Sphere::new(DVec3::ZERO, None, 1.0));
+        for fractal in [Fractal::MengerSponge, Fractal::SierpinskiTetrahedron] {
+            for level in 0..3 {
+                let count = fractal.instances(&template, level).len() as u64;
+                assert_eq!(fractal.count(level), count, "{fractal:?} at level {level}");
+            }
+        }
+        assert_eq!(Fractal::MengerSponge.count(14), 20u64.pow(14));
+        assert_eq!(Fractal::MengerSponge.count(15), u64::MAX);
+        assert_eq!(Fractal::SierpinskiTetrahedron.count(u32::MAX), u64::MAX);
+    }
+}
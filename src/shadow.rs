@@ -0,0 +1,145 @@
+use glam::DVec3;
+
+use crate::{
+    color::Color,
+    interval::Interval,
+    math::{DPoint3, Ray},
+    shape::Hittable,
+};
+
+/// The bits of each axis of the quantized directions in the sort key.
+const DIRECTION_BITS: u32 = 5;
+
+/// The bits of each axis of the quantized origins in the sort key.
+const ORIGIN_BITS: u32 = 14;
+
+/// A shadow ray of next event estimation whose test is deferred to the end of its tile, see
+/// `Renderer::shadow_batching`.
+pub struct ShadowRay {
+    /// The ray from the shading point towards the light.
+    pub ray: Ray,
+
+    /// The interval of intersections which occlude the light.
+    pub ray_t: Interval,
+
+    /// The radiance which arrives at the pixel if the light is unoccluded, i.e. weighted by the
+    /// throughput of the path.
+    pub radiance: Color,
+
+    /// The index of the light group AOV which records the radiance, `None` if not recorded.
+    pub aov: Option<usize>,
+}
+
+/// A sample of a pixel whose direct light waits for its shadow rays.
+struct PendingSample {
+    /// The pixel of the sample.
+    pixel: (u32, u32),
+
+    /// The film position of the sample in pixels.
+    film: (f64, f64),
+
+    /// The color without the deferred direct light.
+    color: Color,
+
+    /// The AOVs without the deferred direct light.
+    aovs: Vec<Color>,
+}
+
+/// The samples of a tile and their deferred shadow rays, which are sorted by direction and
+/// origin before traversal so that successive rays visit the same BVH nodes and primitives.
+#[derive(Default)]
+pub struct ShadowBatch {
+    /// The samples in the order they are traced.
+    samples: Vec<PendingSample>,
+
+    /// The shadow rays with the index of their sample.
+    rays: Vec<(usize, ShadowRay)>,
+}
+
+impl ShadowBatch {
+    /// Create an empty batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the sample of `pixel` at the film position `film`, whose direct light is carried by
+    /// `rays`.
+    pub fn push(
+        &mut self,
+        pixel: (u32, u32),
+        film: (f64, f64),
+        color: Color,
+        aovs: Vec<Color>,
+        rays: Vec<ShadowRay>,
+    ) {
+        let index = self.samples.len();
+        self.samples.push(PendingSample {
+            pixel,
+            film,
+            color,
+            aovs,
+        });
+        self.rays.extend(rays.into_iter().map(|ray| (index, ray)));
+    }
+
+    /// Trace the shadow rays against `scene` in sorted order, and call `emit` with the pixel,
+    /// the film position, the color and the AOVs of each sample whose color is finite.
+    pub fn resolve<H, F>(mut self, scene: &H, mut emit: F)
+    where
+        H: Hittable + ?Sized,
+        F: FnMut(u32, u32, f64, f64, Color, Vec<Color>),
+    {
+        if let Some((min, max)) = origin_bounds(&self.rays) {
+            self.rays
+                .sort_by_cached_key(|(_, shadow)| sort_key(&shadow.ray, min, max));
+        }
+        for (index, shadow) in self.rays {
+            if scene.intersect(&shadow.ray, shadow.ray_t).is_some() {
+                continue;
+            }
+            let sample = &mut self.samples[index];
+            sample.color += shadow.radiance;
+            if let Some(aov) = shadow.aov {
+                sample.aovs[aov] += shadow.radiance;
+            }
+        }
+        for sample in self.samples {
+            // Avoid NaN and infinity in color which may cause pixel acne.
+            if sample.color.is_finite() {
+                let ((col, row), (x, y)) = (sample.pixel, sample.film);
+                emit(col, row, x, y, sample.color, sample.aovs);
+            }
+        }
+    }
+}
+
+/// Get the bounding box of the origins of shadow rays, `None` if there is no ray.
+fn origin_bounds(rays: &[(usize, ShadowRay)]) -> Option<(DPoint3, DPoint3)> {
+    let first = rays.first()?.1.ray.ori;
+    Some(rays.iter().fold((first, first), |(min, max), (_, shadow)| {
+        (min.min(shadow.ray.ori), max.max(shadow.ray.ori))
+    }))
+}
+
+/// Get the key which sorts the rays by the octant of direction, then the quantized direction
+/// and the quantized origin in `[min, max]`, both along the Morton curve.
+fn sort_key(ray: &Ray, min: DPoint3, max: DPoint3) -> u64 {
+    let dir = ray.dir.normalize_or_zero();
+    let octant = (dir.x < 0.0) as u64 | ((dir.y < 0.0) as u64) << 1 | ((dir.z < 0.0) as u64) << 2;
+    let direction = morton(dir.abs(), DIRECTION_BITS);
+    let extent = (max - min).max(DVec3::splat(f64::MIN_POSITIVE));
+    let origin = morton((ray.ori - min) / extent, ORIGIN_BITS);
+    (octant << (3 * (DIRECTION_BITS + ORIGIN_BITS))) | (direction << (3 * ORIGIN_BITS)) | origin
+}
+
+/// Get the Morton code of the point in the unit cube quantized to `bits` per axis.
+fn morton(p: DVec3, bits: u32) -> u64 {
+    let scale = ((1u32 << bits) - 1) as f64;
+    let quantize = |x: f64| (x.clamp(0.0, 1.0) * scale) as u64;
+    let (x, y, z) = (quantize(p.x), quantize(p.y), quantize(p.z));
+    (0..bits).fold(0, |code, bit| {
+        code | ((x >> bit) & 1) << (3 * bit)
+            | ((y >> bit) & 1) << (3 * bit + 1)
+            | ((z >> bit) & 1) << (3 * bit + 2)
+    })
+}
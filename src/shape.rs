@@ -1,42 +1,50 @@
-use std::{f64, sync::Arc};
+use std::{any::Any, f64, sync::Arc};
 
-use glam::{DMat3, DMat4, DVec3, DVec4, Vec4Swizzles};
+use glam::DVec3;
 use rand::rngs::StdRng;
 
 use crate::{
     aabb::Aabb,
+    color::Color,
     interval::Interval,
-    material::Material,
-    math::{Axis, DPoint3, Ray},
+    material::Bsdf,
+    math::{Axis, DPoint3, Ray, Transform},
 };
 
 pub mod cube;
+pub mod curve;
+pub mod disk;
+pub mod displaced;
+pub mod lod;
+pub mod mesh;
+pub mod ocean;
 pub mod quad;
 pub mod sphere;
 
 pub trait Hittable: Send + Sync {
     /// Used for `HitRecord` of incident ray.
     fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord>;
-
-    /// Return a random point, normal and the pdf.
-    /// The function is a combination of `pdf` and `random` in Ray Tracing Series 3.
-    fn sample(
-        &self,
-        _target: DPoint3,
-        _rng: &mut StdRng,
-        _shutter_time: f64,
-    ) -> (DPoint3, DVec3, f64) {
-        (
-            DPoint3::splat(1.0),
-            DVec3::splat(1.0),
-            1.0 / (2.0 * f64::consts::PI),
-        )
-    }
 }
 
-pub trait Bounded: Hittable {
+pub trait Bounded: Hittable + Any {
     /// The bounding box of the shape.
     fn bbox(&self) -> Aabb;
+
+    /// The bytes of the shape including its heap, see `Scene::memory_report`.
+    fn memory(&self) -> usize {
+        size_of_val(self)
+    }
+}
+
+/// The shapes whose surface can be sampled, which are used as area lights.
+pub trait Sampleable: Bounded {
+    /// Return a random point seen from `target`, the normal and the PDF over surface area.
+    /// The function is a combination of `pdf` and `random` in Ray Tracing Series 3.
+    fn sample(&self, target: DPoint3, rng: &mut StdRng, shutter_time: f64)
+    -> (DPoint3, DVec3, f64);
+
+    /// Get the surface area, e.g. the power of area lights.
+    fn area(&self) -> f64;
 }
 
 #[derive(Default, Clone)]
@@ -58,11 +66,46 @@ pub struct HitRecord {
     pub front_face: bool,
 
     /// The material of intersect object.
-    pub material: Option<Arc<Material>>,
+    pub material: Option<Arc<dyn Bsdf>>,
 
     /// The coordinates of the object surface mapping to the texture map
     pub u: f64,
     pub v: f64,
+
+    /// The derivative of `p` by `u`, i.e. the direction and scale of the texture coordinate `u`
+    /// on the surface, zero if the shape doesn't provide it.
+    pub tangent: DVec3,
+
+    /// The width of the pixel footprint in world units at the intersection, which textures are
+    /// filtered by, zero for no filtering. It's set by the renderer for camera rays.
+    pub footprint: f64,
+
+    /// The barycentric coordinates of the second and third vertices of the intersect triangle,
+    /// only set by triangle shapes.
+    pub barycentric: Option<(f64, f64)>,
+
+    /// The interpolated vertex color of the intersection, only set by shapes that carry
+    /// per-vertex colors.
+    pub color: Option<Color>,
+
+    /// The light group of intersect object which is used to separate its emission.
+    pub light_group: Option<Arc<str>>,
+
+    /// The random seed of intersect object which textures and materials vary by, see
+    /// `Object::seed`.
+    pub instance_seed: u32,
+
+    /// The index of intersect object in `Scene::objects`, see `Object::id`.
+    pub object_id: usize,
+
+    /// The displacement of the intersection point over the shutter interval, i.e. from time
+    /// 0.0 to 1.0 of rays, which is zero for static shapes.
+    pub velocity: DVec3,
+
+    /// The origin of the rays leaving the surface on the side of `normal`, which is lifted off
+    /// the flat faces of smooth shaded triangles, see `terminator_origin`. `None` to leave from
+    /// `p`.
+    pub shadow_origin: Option<DPoint3>,
 }
 
 impl HitRecord {
@@ -76,102 +119,112 @@ impl HitRecord {
         };
     }
 
-    pub fn material(&self) -> &Material {
-        self.material.as_ref().unwrap()
+    pub fn material(&self) -> &dyn Bsdf {
+        self.material.as_deref().unwrap()
+    }
+
+    /// Get the origin of the ray leaving the surface in direction `l`, which is `shadow_origin`
+    /// on the side of `normal` and `p` for transmission.
+    pub fn origin_towards(&self, l: DVec3) -> DPoint3 {
+        match self.shadow_origin {
+            Some(origin) if l.dot(self.normal) > 0.0 => origin,
+            _ => self.p,
+        }
     }
 }
 
+/// Get the point lifted from `p` on a flat triangle to the curved surface which its vertex
+/// normals describe, whose rays towards lights don't fall into the shadow of the neighboring
+/// faces. It removes the polygonal shadow terminator of low-poly smooth shaded meshes, where
+/// the shading normal faces the light but the flat face doesn't.
+///
+/// `vertices` and `normals` are the vertices of the triangle and their normals on the side of
+/// the outgoing rays, and `(b1, b2)` are the barycentric coordinates of `p` of the second and
+/// third vertices.
+/// References:
+/// Hanika, Hacking the Shadow Terminator, Ray Tracing Gems II
+pub fn terminator_origin(
+    p: DPoint3,
+    vertices: [DPoint3; 3],
+    normals: [DVec3; 3],
+    (b1, b2): (f64, f64),
+) -> DPoint3 {
+    let weights = [1.0 - b1 - b2, b1, b2];
+    let mut offset = DVec3::ZERO;
+    for i in 0..3 {
+        // Project `p` onto the tangent plane of the vertex if it lies below the plane.
+        let tmp = p - vertices[i];
+        let below = tmp.dot(normals[i]).min(0.0);
+        offset += weights[i] * (tmp - below * normals[i]);
+    }
+    p + offset
+}
+
+impl Hittable for Arc<dyn Bounded> {
+    fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        (**self).intersect(r, ray_t)
+    }
+}
+
+impl Bounded for Arc<dyn Bounded> {
+    fn bbox(&self) -> Aabb {
+        (**self).bbox()
+    }
+
+    fn memory(&self) -> usize {
+        size_of_val(self) + (**self).memory()
+    }
+}
+
+/// A placement of the geometry shared with other instances, see `Object::instance`.
+pub type Instance = Transformed<Arc<dyn Bounded>>;
+
 /// A Object that has been composed with a transformation.
 pub struct Transformed<T> {
     /// The hittable shape that need to transforme.
     shape: T,
 
-    /// The transformation matrix to transform object.
-    transform: DMat4,
-
-    /// The inverse of `transform` which use to transform the incident ray.
-    inverse_transform: DMat4,
-
-    #[allow(dead_code)]
-    /// The transformation which extract from `transform` and not contains translate part.
-    linear: DMat3,
-
-    /// The inverse and transpose of `transform` which use to rectify normal vector.
-    normal_transform: DMat3,
+    /// The transformation from the space of shape to world space.
+    transform: Transform,
 }
 
 impl<T> Transformed<T> {
-    pub fn new(shape: T, transform: DMat4) -> Self {
-        let linear = DMat3::from_mat4(transform);
-        let inverse_transform = transform.inverse();
-        let normal_transform = linear.inverse().transpose();
+    pub fn new(shape: T, transform: impl Into<Transform>) -> Self {
         Self {
             shape,
-            transform,
-            linear,
-            inverse_transform,
-            normal_transform,
+            transform: transform.into(),
         }
     }
+
+    /// Get the shape in its own space.
+    pub const fn shape(&self) -> &T {
+        &self.shape
+    }
 }
 
 impl<T: Hittable> Hittable for Transformed<T> {
     fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        let ray_trans = r.apply_transform(&self.inverse_transform);
-        match self.shape.intersect(&ray_trans, ray_t) {
-            None => None,
-            Some(mut rec) => {
-                // Transform intersection point back to world space
-                let p_world = self.transform * rec.p.extend(1.0);
-                rec.p = p_world.xyz();
-
-                // Fix normal vector by multiplying by M^-T
-                rec.normal = self.normal_transform.mul_vec3(rec.normal).normalize();
-
-                Some(rec)
-            }
-        }
+        let ray_trans = r.apply_transform(&self.transform.inverse);
+        self.shape.intersect(&ray_trans, ray_t).map(|mut rec| {
+            // Transform intersection point and normal vector back to world space
+            rec.p = self.transform.point(rec.p);
+            rec.normal = self.transform.normal(rec.normal);
+            rec.velocity = self.transform.vector(rec.velocity);
+            rec.tangent = self.transform.vector(rec.tangent);
+            rec.shadow_origin = rec.shadow_origin.map(|p| self.transform.point(p));
+            rec
+        })
     }
 }
 
 impl<T: Bounded> Bounded for Transformed<T> {
     fn bbox(&self) -> Aabb {
-        let Aabb { x, y, z } = self.shape.bbox();
-
         // Transform all 8 corners and find aabb of transformed shape.
-        let corners = [
-            (x.min, y.min, z.min),
-            (x.min, y.min, z.max),
-            (x.min, y.max, z.min),
-            (x.min, y.max, z.max),
-            (x.max, y.min, z.min),
-            (x.max, y.min, z.max),
-            (x.max, y.max, z.min),
-            (x.max, y.max, z.max),
-        ];
-
-        let mut min_x = f64::INFINITY;
-        let mut max_x = f64::NEG_INFINITY;
-        let mut min_y = f64::INFINITY;
-        let mut max_y = f64::NEG_INFINITY;
-        let mut min_z = f64::INFINITY;
-        let mut max_z = f64::NEG_INFINITY;
-
-        for (cx, cy, cz) in corners {
-            let transformed = self.transform.mul_vec4(DVec4::new(cx, cy, cz, 1.0)).xyz();
-            min_x = min_x.min(transformed.x);
-            max_x = max_x.max(transformed.x);
-            min_y = min_y.min(transformed.y);
-            max_y = max_y.max(transformed.y);
-            min_z = min_z.min(transformed.z);
-            max_z = max_z.max(transformed.z);
-        }
+        self.transform.bbox(&self.shape.bbox())
+    }
 
-        Aabb::new(
-            Interval::new(min_x, max_x),
-            Interval::new(min_y, max_y),
-            Interval::new(min_z, max_z),
-        )
+    fn memory(&self) -> usize {
+        size_of_val(self) - size_of::<T>() + self.shape.memory()
     }
 }
 
@@ -185,14 +238,9 @@ pub trait Transformable<T> {
 
 impl<T: Hittable> Transformable<T> for T {
     fn translate(self, v: DVec3) -> Transformed<T> {
-        Transformed::new(self, DMat4::from_translation(v.into()))
+        Transformed::new(self, Transform::translation(v))
     }
     fn rotate(self, axis: Axis, angle: f64) -> Transformed<T> {
-        let axis_vec = match axis {
-            Axis::X => DVec3::new(1.0, 0.0, 0.0),
-            Axis::Y => DVec3::new(0.0, 1.0, 0.0),
-            Axis::Z => DVec3::new(0.0, 0.0, 1.0),
-        };
-        Transformed::new(self, DMat4::from_axis_angle(axis_vec, angle))
+        Transformed::new(self, Transform::axis_rotation(axis, angle))
     }
 }
@@ -28,6 +28,11 @@ impl Cube {
         );
         Self { p_min, p_max, aabb }
     }
+
+    /// Get the two opposite corners of the cube.
+    pub const fn corners(&self) -> (DPoint3, DPoint3) {
+        (self.p_min, self.p_max)
+    }
 }
 
 impl Hittable for Cube {
@@ -79,7 +84,7 @@ impl Hittable for Cube {
             DVec3::new(0.0, 0.0, 1.0)
         };
 
-        rec.normal = normal;
+        rec.set_face_normal(r, normal);
         Some(rec)
     }
 }
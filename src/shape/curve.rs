@@ -0,0 +1,230 @@
+use std::f64;
+
+use glam::DVec3;
+
+use crate::{
+    aabb::Aabb,
+    interval::Interval,
+    math::{DPoint3, Ray},
+    onb::ONB,
+    shape::{Bounded, HitRecord, Hittable},
+};
+
+/// The deepest subdivision of the curve during intersection.
+const MAX_DEPTH: u32 = 10;
+
+/// A thin strand along a cubic Bézier curve whose width varies linearly from the start to the
+/// end, e.g. hair and fur shaded by `Hair`. It's intersected as a flat ribbon which faces the
+/// ray, and shaded like a cylinder.
+/// The texture coordinate u runs along the curve and v across it, so that `2v - 1` is the
+/// offset of the intersection from the axis of the fiber. The tangent is along the curve.
+/// References:
+/// Nakamaru and Ohno 2002, Ray Tracing for Curves Primitive
+pub struct Curve {
+    /// The control points of the curve.
+    points: [DPoint3; 4],
+
+    /// The width at the start and the end.
+    width: (f64, f64),
+
+    /// The number of times the curve is split in half before its pieces are tested as segments,
+    /// so that the segments are within a fraction of the width from the curve.
+    depth: u32,
+
+    /// The axis-aligned bounding box of the curve.
+    aabb: Aabb,
+}
+
+impl Curve {
+    /// Create a strand along the cubic Bézier curve of the control `points`, whose width varies
+    /// from `start_width` to `end_width`.
+    pub fn new(points: [DPoint3; 4], start_width: f64, end_width: f64) -> Self {
+        let width = (start_width.abs(), end_width.abs());
+        let half = width.0.max(width.1) / 2.0;
+        let aabb = points
+            .iter()
+            .fold(Aabb::from_points(points[0], points[0]), |b, p| {
+                Aabb::surrounding_box(&b, &Aabb::from_points(*p - half, *p + half))
+            })
+            .padding_to_minimal();
+        // The segments are within ε of the curve after splitting the curve 2^depth times, where
+        // the bound of the second derivative is the largest second difference of points.
+        let l0 = (0..2)
+            .map(|i| (points[i] - 2.0 * points[i + 1] + points[i + 2]).length())
+            .fold(0.0, f64::max);
+        let epsilon = (width.0.max(width.1) / 20.0).max(f64::MIN_POSITIVE);
+        let depth = ((f64::consts::SQRT_2 * 6.0 * l0 / (8.0 * epsilon)).log2() / 2.0)
+            .clamp(0.0, MAX_DEPTH as f64) as u32;
+        Self {
+            points,
+            width,
+            depth,
+            aabb,
+        }
+    }
+
+    /// Create the strand through `points` as the Bézier segments of a Catmull-Rom spline, whose
+    /// width tapers from `root_width` at the first point to `tip_width` at the last one.
+    pub fn strand(points: &[DPoint3], root_width: f64, tip_width: f64) -> Vec<Self> {
+        let count = points.len().saturating_sub(1);
+        let point = |i: isize| points[i.clamp(0, count as isize) as usize];
+        let width = |i: usize| root_width + (tip_width - root_width) * i as f64 / count as f64;
+        (0..count)
+            .map(|i| {
+                let (p0, p1) = (point(i as isize), point(i as isize + 1));
+                let (before, after) = (point(i as isize - 1), point(i as isize + 2));
+                let control = [p0, p0 + (p1 - before) / 6.0, p1 - (after - p0) / 6.0, p1];
+                Self::new(control, width(i), width(i + 1))
+            })
+            .collect()
+    }
+
+    /// Get the control points of the curve.
+    pub const fn points(&self) -> &[DPoint3; 4] {
+        &self.points
+    }
+
+    /// Get the width at the start and the end.
+    pub const fn width(&self) -> (f64, f64) {
+        self.width
+    }
+
+    /// Get the width at the parameter `u` along the curve.
+    fn width_at(&self, u: f64) -> f64 {
+        self.width.0 + (self.width.1 - self.width.0) * u
+    }
+
+    /// Find the closest intersection of the piece of `points` from `u0` to `u1` of the curve,
+    /// which is in the space of the ray along z, with the z axis whose depth is in `z_range`.
+    /// The depth and the (u, v) of the hit are written into `closest`, whose depth shrinks the
+    /// range of the later pieces.
+    fn intersect_piece(
+        &self,
+        points: &[DVec3; 4],
+        (u0, u1): (f64, f64),
+        depth: u32,
+        z_range: &mut Interval,
+        closest: &mut Option<(f64, f64, f64)>,
+    ) {
+        let half = self.width_at(u0).max(self.width_at(u1)) / 2.0;
+        let (min, max) = points.iter().fold((points[0], points[0]), |(min, max), p| {
+            (min.min(*p), max.max(*p))
+        });
+        if min.x - half > 0.0
+            || max.x + half < 0.0
+            || min.y - half > 0.0
+            || max.y + half < 0.0
+            || min.z - half > z_range.max
+            || max.z + half < z_range.min
+        {
+            return;
+        }
+        if depth > 0 {
+            let [first, second] = split(points);
+            let middle = (u0 + u1) / 2.0;
+            self.intersect_piece(&first, (u0, middle), depth - 1, z_range, closest);
+            self.intersect_piece(&second, (middle, u1), depth - 1, z_range, closest);
+            return;
+        }
+        // The ray must pass between the planes perpendicular to the ends of the segment.
+        let start =
+            (points[1].y - points[0].y) * -points[0].y + points[0].x * (points[0].x - points[1].x);
+        let end =
+            (points[2].y - points[3].y) * -points[3].y + points[3].x * (points[3].x - points[2].x);
+        if start < 0.0 || end < 0.0 {
+            return;
+        }
+        // The closest point of the segment to the ray in the xy plane.
+        let segment = (points[3] - points[0]).truncate();
+        let length2 = segment.length_squared();
+        if length2 == 0.0 {
+            return;
+        }
+        let w = (-points[0].truncate().dot(segment) / length2).clamp(0.0, 1.0);
+        let u = u0 + (u1 - u0) * w;
+        let width = self.width_at(u);
+        let (p, dp) = evaluate(points, w);
+        let distance2 = p.x * p.x + p.y * p.y;
+        if distance2 > width * width / 4.0 || !z_range.contains(p.z) {
+            return;
+        }
+        let offset = distance2.sqrt() / width;
+        let v = if dp.x * -p.y + p.x * dp.y > 0.0 {
+            0.5 + offset
+        } else {
+            0.5 - offset
+        };
+        z_range.max = p.z;
+        *closest = Some((p.z, u, v));
+    }
+}
+
+impl Hittable for Curve {
+    fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let length = r.dir.length();
+        if length == 0.0 {
+            return None;
+        }
+        // The space of the ray whose origin is the ray origin and whose z axis is the ray.
+        let onb = ONB::new(r.dir);
+        let points = self.points.map(|p| onb.to_local(p - r.ori));
+        let mut z_range = Interval::new(ray_t.min * length, ray_t.max * length);
+        let mut closest = None;
+        self.intersect_piece(&points, (0.0, 1.0), self.depth, &mut z_range, &mut closest);
+        let (z, u, v) = closest?;
+
+        let t = z / length;
+        let (_, tangent) = evaluate(&self.points, u);
+        let mut rec = HitRecord {
+            t,
+            p: r.at(t),
+            u,
+            v,
+            tangent,
+            ..Default::default()
+        };
+        // The normal of the ribbon faces the ray, and the shading normal is rotated around the
+        // tangent to the one of a cylinder at the offset across the fiber.
+        let side = r.dir.cross(tangent).normalize_or_zero();
+        let facing = tangent.cross(side).normalize_or_zero();
+        if facing == DVec3::ZERO {
+            return None;
+        }
+        rec.set_face_normal(r, facing);
+        let offset = (2.0 * v - 1.0).clamp(-1.0, 1.0);
+        rec.normal = rec.normal * (1.0 - offset * offset).sqrt() + side * offset;
+        Some(rec)
+    }
+}
+
+impl Bounded for Curve {
+    fn bbox(&self) -> Aabb {
+        self.aabb
+    }
+}
+
+/// Get the point and the derivative of the cubic Bézier curve of `points` at `u`.
+fn evaluate(points: &[DVec3; 4], u: f64) -> (DVec3, DVec3) {
+    let a = [
+        points[0].lerp(points[1], u),
+        points[1].lerp(points[2], u),
+        points[2].lerp(points[3], u),
+    ];
+    let b = [a[0].lerp(a[1], u), a[1].lerp(a[2], u)];
+    let derivative = if b[1] == b[0] {
+        // The derivative vanishes at the ends of degenerate curves.
+        points[3] - points[0]
+    } else {
+        3.0 * (b[1] - b[0])
+    };
+    (b[0].lerp(b[1], u), derivative)
+}
+
+/// Split the cubic Bézier curve of `points` in half.
+fn split(points: &[DVec3; 4]) -> [[DVec3; 4]; 2] {
+    let [p0, p1, p2, p3] = *points;
+    let (p01, p12, p23) = ((p0 + p1) / 2.0, (p1 + p2) / 2.0, (p2 + p3) / 2.0);
+    let (p012, p123) = ((p01 + p12) / 2.0, (p12 + p23) / 2.0);
+    let middle = (p012 + p123) / 2.0;
+    [[p0, p01, p012, middle], [middle, p123, p23, p3]]
+}
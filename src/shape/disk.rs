@@ -0,0 +1,122 @@
+use std::f64::consts::{PI, TAU};
+
+use glam::DVec3;
+use rand::{Rng, rngs::StdRng};
+
+use crate::{
+    aabb::Aabb,
+    interval::Interval,
+    math::{DPoint3, Ray},
+    onb::ONB,
+    sampling,
+    shape::{Bounded, HitRecord, Hittable, Sampleable},
+};
+
+pub struct Disk {
+    /// The center of the disk.
+    center: DPoint3,
+
+    /// The normalized normal vector of the disk plane.
+    normal: DVec3,
+
+    /// The radius of the disk.
+    radius: f64,
+
+    /// The basis whose z axis is the normal, which maps the disk to the local xy plane.
+    onb: ONB,
+
+    /// The axis-aligned bounding box of disk.
+    aabb: Aabb,
+}
+
+impl Disk {
+    /// Create a disk from its center, normal and radius. Like `Quad`, the normal decides the
+    /// front face, e.g. of the emission of `Material::one_sided` which is off the back face.
+    pub fn new(center: DPoint3, normal: DVec3, radius: f64) -> Self {
+        let normal = normal.normalize();
+        let radius = radius.abs();
+        // The extent along each axis is radius * sinθ for the angle θ between axis and normal.
+        let extent = radius * (DVec3::ONE - normal * normal).max(DVec3::ZERO).powf(0.5);
+        let aabb = Aabb::from_points(center - extent, center + extent).padding_to_minimal();
+        Self {
+            center,
+            normal,
+            radius,
+            onb: ONB::new(normal),
+            aabb,
+        }
+    }
+
+    /// Get the center of the disk.
+    pub const fn center(&self) -> DPoint3 {
+        self.center
+    }
+
+    /// Get the normal of the disk.
+    pub const fn normal(&self) -> DVec3 {
+        self.normal
+    }
+
+    /// Get the radius of the disk.
+    pub const fn radius(&self) -> f64 {
+        self.radius
+    }
+}
+
+impl Hittable for Disk {
+    fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let denominator = self.normal.dot(r.dir);
+
+        // Treat near-parallel rays as misses
+        if denominator.abs() < f64::EPSILON {
+            return None;
+        }
+
+        let root = self.normal.dot(self.center - r.ori) / denominator;
+        if !ray_t.contains(root) {
+            return None;
+        }
+        let p = r.at(root);
+        let local = self.onb.to_local(p - self.center);
+        let dist2 = local.x * local.x + local.y * local.y;
+        if dist2 > self.radius * self.radius {
+            return None;
+        }
+
+        let mut rec = HitRecord {
+            t: root,
+            p,
+            // Polar coordinates of the disk, u for the angle and v for the distance to center.
+            u: (local.y.atan2(local.x) + PI) / TAU,
+            v: dist2.sqrt() / self.radius,
+            ..Default::default()
+        };
+        rec.set_face_normal(r, self.normal);
+        Some(rec)
+    }
+}
+
+impl Sampleable for Disk {
+    /// Get a random point from the disk by concentric mapping and the constant PDF.
+    fn sample(
+        &self,
+        _target: DPoint3,
+        rng: &mut StdRng,
+        _shutter_time: f64,
+    ) -> (DPoint3, DVec3, f64) {
+        let (x, y) = sampling::concentric_disk(rng.random());
+        let p = self.center + self.radius * self.onb.transform(DVec3::new(x, y, 0.0));
+        let pdf = sampling::concentric_disk_pdf() / (self.radius * self.radius);
+        (p, self.normal, pdf)
+    }
+
+    fn area(&self) -> f64 {
+        PI * self.radius * self.radius
+    }
+}
+
+impl Bounded for Disk {
+    fn bbox(&self) -> Aabb {
+        self.aabb
+    }
+}
@@ -0,0 +1,339 @@
+use std::sync::Arc;
+
+use glam::DVec3;
+
+use crate::{
+    aabb::Aabb,
+    color,
+    interval::Interval,
+    math::{DPoint3, Ray},
+    shape::{Bounded, HitRecord, Hittable, quad::Quad, terminator_origin},
+    simd::TriangleBatch,
+    texture::Texture,
+};
+
+/// The number of cells along each side of the blocks in the leaves of the grid BVH, whose two
+/// triangles per cell fill a batch of 8.
+const BLOCK: u32 = 2;
+
+/// A quad diced into a grid of micro-triangles whose vertices are displaced along the normal
+/// by a height texture or by any function, e.g. terrain and ocean patches without meshes
+/// authored elsewhere, see `Ocean`. The
+/// triangles are found through a BVH over the blocks of the grid, whose leaves test the
+/// triangles of a block as a batch.
+/// The normals are smooth across the grid, and the texture coordinates follow the quad.
+pub struct DisplacedQuad {
+    /// The flat quad which is diced.
+    quad: Quad,
+
+    /// The texture whose luminance at the (u, v) of the quad is the height of displacement,
+    /// and the height of the texture value one. `None` if displaced by a function.
+    height: Option<(Arc<dyn Texture>, f64)>,
+
+    /// The number of cells along each side of the grid.
+    resolution: u32,
+
+    /// The displaced vertices of the grid in row-major order along u.
+    vertices: Vec<DPoint3>,
+
+    /// The smooth normals of the vertices on the side of the quad normal.
+    normals: Vec<DVec3>,
+
+    /// The nodes of the grid BVH, the root is the first one.
+    nodes: Vec<GridNode>,
+
+    /// The triangles of the leaves of the grid BVH.
+    batches: Vec<TriangleBatch<8>>,
+}
+
+/// A node of the grid BVH.
+enum GridNode {
+    Leaf {
+        /// The first cell and the number of cells along u and v of the block.
+        cell: (u32, u32),
+        size: (u32, u32),
+        /// The index of the triangles of the cells in `DisplacedQuad::batches`, which are in
+        /// row-major order and two per cell.
+        batch: u32,
+        bbox: Aabb,
+    },
+    Node {
+        /// The index of the right child, the left child follows the node.
+        right: u32,
+        bbox: Aabb,
+    },
+}
+
+impl GridNode {
+    const fn bbox(&self) -> &Aabb {
+        match self {
+            GridNode::Leaf { bbox, .. } | GridNode::Node { bbox, .. } => bbox,
+        }
+    }
+}
+
+impl DisplacedQuad {
+    /// Dice `quad` into `resolution` x `resolution` cells of two triangles, whose vertices are
+    /// lifted along the normal by `scale` times the luminance of `height`. The texture is
+    /// looked up with the (u, v), the flat position and the normal of the quad.
+    pub fn new(quad: Quad, height: Arc<dyn Texture>, scale: f64, resolution: u32) -> Self {
+        let (normal, tangent) = (quad.normal, quad.u);
+        let displacement = |u, v, p| {
+            let rec = HitRecord {
+                p,
+                normal,
+                front_face: true,
+                u,
+                v,
+                tangent,
+                ..Default::default()
+            };
+            normal * color::luminance(height.value(&rec)) * scale
+        };
+        let mut shape = Self::from_fn(quad, resolution, displacement);
+        shape.height = Some((height, scale));
+        shape
+    }
+
+    /// Dice `quad` into `resolution` x `resolution` cells of two triangles, whose vertices are
+    /// moved by the world space offset `displacement(u, v, p)` of the (u, v) and the flat
+    /// position `p` on the quad, e.g. the horizontal and vertical motion of waves.
+    pub fn from_fn<F>(quad: Quad, resolution: u32, displacement: F) -> Self
+    where
+        F: Fn(f64, f64, DPoint3) -> DVec3,
+    {
+        let resolution = resolution.max(1);
+        let n = resolution + 1;
+        let mut vertices = Vec::with_capacity((n * n) as usize);
+        for j in 0..n {
+            for i in 0..n {
+                let (u, v) = (i as f64 / resolution as f64, j as f64 / resolution as f64);
+                let p = quad.origin + u * quad.u + v * quad.v;
+                vertices.push(p + displacement(u, v, p));
+            }
+        }
+        let vertex =
+            |i: u32, j: u32| vertices[(j.min(resolution) * n + i.min(resolution)) as usize];
+        let mut normals = Vec::with_capacity(vertices.len());
+        for j in 0..n {
+            for i in 0..n {
+                // The central differences, which are one-sided on the border.
+                let du = vertex(i + 1, j) - vertex(i.saturating_sub(1), j);
+                let dv = vertex(i, j + 1) - vertex(i, j.saturating_sub(1));
+                let normal = du.cross(dv).normalize_or_zero();
+                normals.push(if normal == DVec3::ZERO {
+                    quad.normal
+                } else {
+                    normal
+                });
+            }
+        }
+        let mut shape = Self {
+            quad,
+            height: None,
+            resolution,
+            vertices,
+            normals,
+            nodes: Vec::new(),
+            batches: Vec::new(),
+        };
+        shape.build((0, 0), (resolution, resolution));
+        shape
+    }
+
+    /// Get the flat quad which is diced.
+    pub const fn quad(&self) -> &Quad {
+        &self.quad
+    }
+
+    /// Get the height texture and the height of the texture value one, `None` if displaced by
+    /// a function.
+    pub fn height(&self) -> Option<(&Arc<dyn Texture>, f64)> {
+        self.height
+            .as_ref()
+            .map(|(texture, scale)| (texture, *scale))
+    }
+
+    /// Get the number of cells along each side of the grid.
+    pub const fn resolution(&self) -> u32 {
+        self.resolution
+    }
+
+    /// Get the index of the vertex of the grid.
+    const fn index(&self, i: u32, j: u32) -> usize {
+        (j * (self.resolution + 1) + i) as usize
+    }
+
+    /// Get the vertex indices of the triangle `k` of the cell (i, j), which are ordered so that
+    /// the geometric normal is on the side of the quad normal.
+    const fn triangle(&self, i: u32, j: u32, k: u32) -> [usize; 3] {
+        let (v00, v10) = (self.index(i, j), self.index(i + 1, j));
+        let (v01, v11) = (self.index(i, j + 1), self.index(i + 1, j + 1));
+        if k == 0 {
+            [v00, v10, v11]
+        } else {
+            [v00, v11, v01]
+        }
+    }
+
+    /// Append the subtree of the block of `size` cells from `cell`, and get its bounding box.
+    fn build(&mut self, cell: (u32, u32), size: (u32, u32)) -> Aabb {
+        if size.0 <= BLOCK && size.1 <= BLOCK {
+            let mut triangles = Vec::with_capacity(8);
+            for j in cell.1..cell.1 + size.1 {
+                for i in cell.0..cell.0 + size.0 {
+                    for k in 0..2 {
+                        triangles.push(self.triangle(i, j, k).map(|v| self.vertices[v]));
+                    }
+                }
+            }
+            let bbox = triangles
+                .iter()
+                .flatten()
+                .fold(
+                    Aabb::from_points(triangles[0][0], triangles[0][0]),
+                    |b, p| Aabb::surrounding_box(&b, &Aabb::from_points(*p, *p)),
+                )
+                .padding_to_minimal();
+            self.nodes.push(GridNode::Leaf {
+                cell,
+                size,
+                batch: self.batches.len() as u32,
+                bbox,
+            });
+            self.batches.push(TriangleBatch::new(&triangles));
+            return bbox;
+        }
+        // The node is filled in after its children.
+        let index = self.nodes.len();
+        self.nodes.push(GridNode::Node {
+            right: 0,
+            bbox: self.quad.aabb,
+        });
+        // Split the longer side in half.
+        let (left, right) = if size.0 >= size.1 {
+            let half = size.0 / 2;
+            (
+                (cell, (half, size.1)),
+                ((cell.0 + half, cell.1), (size.0 - half, size.1)),
+            )
+        } else {
+            let half = size.1 / 2;
+            (
+                (cell, (size.0, half)),
+                ((cell.0, cell.1 + half), (size.0, size.1 - half)),
+            )
+        };
+        let left_bbox = self.build(left.0, left.1);
+        let right_index = self.nodes.len() as u32;
+        let right_bbox = self.build(right.0, right.1);
+        let bbox = Aabb::surrounding_box(&left_bbox, &right_bbox);
+        self.nodes[index] = GridNode::Node {
+            right: right_index,
+            bbox,
+        };
+        bbox
+    }
+
+    /// Fill the record of the hit at `t` of the triangle `k` of the cell (i, j) with the
+    /// barycentric coordinates (b1, b2).
+    fn record(
+        &self,
+        r: &Ray,
+        t: f64,
+        (i, j, k): (u32, u32, u32),
+        (b1, b2): (f64, f64),
+    ) -> HitRecord {
+        let ids = self.triangle(i, j, k);
+        let vertices = ids.map(|v| self.vertices[v]);
+        let (e1, e2) = (vertices[1] - vertices[0], vertices[2] - vertices[0]);
+        let mut rec = HitRecord {
+            t,
+            p: r.at(t),
+            barycentric: Some((b1, b2)),
+            ..Default::default()
+        };
+        rec.set_face_normal(r, e1.cross(e2).normalize());
+        // The texture coordinates and the derivative of position by u of the triangle.
+        let res = self.resolution as f64;
+        let (du, dv, tangent) = if k == 0 {
+            (b1 + b2, b2, e1)
+        } else {
+            (b1, b1 + b2, vertices[1] - vertices[2])
+        };
+        rec.u = (i as f64 + du) / res;
+        rec.v = (j as f64 + dv) / res;
+        rec.tangent = tangent * res;
+
+        let side = if rec.front_face { 1.0 } else { -1.0 };
+        let normals = ids.map(|v| self.normals[v] * side);
+        let shading =
+            (normals[0] * (1.0 - b1 - b2) + normals[1] * b1 + normals[2] * b2).normalize_or_zero();
+        if shading != DVec3::ZERO {
+            rec.normal = shading;
+        }
+        rec.shadow_origin = Some(terminator_origin(rec.p, vertices, normals, (b1, b2)));
+        rec
+    }
+}
+
+impl Hittable for DisplacedQuad {
+    fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let mut closest = None;
+        let mut t_max = ray_t.max;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bbox().intersect(r, Interval::new(ray_t.min, t_max)) {
+                continue;
+            }
+            match node {
+                GridNode::Leaf {
+                    cell, size, batch, ..
+                } => {
+                    let triangles = &self.batches[*batch as usize];
+                    let hit = triangles.intersect(r.ori, r.dir, Interval::new(ray_t.min, t_max));
+                    if let Some(hit) = hit {
+                        let c = hit.lane as u32 / 2;
+                        let triangle = (
+                            cell.0 + c % size.0,
+                            cell.1 + c / size.0,
+                            hit.lane as u32 % 2,
+                        );
+                        t_max = hit.t;
+                        closest = Some((hit.t, triangle, (hit.b1, hit.b2)));
+                    }
+                }
+                GridNode::Node { right, .. } => {
+                    stack.push(*right as usize);
+                    stack.push(index + 1);
+                }
+            }
+        }
+        closest.map(|(t, triangle, barycentric)| self.record(r, t, triangle, barycentric))
+    }
+}
+
+impl Bounded for DisplacedQuad {
+    fn bbox(&self) -> Aabb {
+        *self.nodes[0].bbox()
+    }
+
+    fn memory(&self) -> usize {
+        size_of_val(self)
+            + self.vertices.capacity() * size_of::<DPoint3>()
+            + self.normals.capacity() * size_of::<DVec3>()
+            + self.nodes.capacity() * size_of::<GridNode>()
+            + self.batches.capacity() * size_of::<TriangleBatch<8>>()
+    }
+}
+
+impl Quad {
+    /// Dice the quad into a grid displaced by the height texture, see `DisplacedQuad::new`.
+    pub fn displace<T>(self, height: T, scale: f64, resolution: u32) -> DisplacedQuad
+    where
+        T: Texture + 'static,
+    {
+        DisplacedQuad::new(self, Arc::new(height), scale, resolution)
+    }
+}
@@ -0,0 +1,84 @@
+use std::sync::Arc;
+
+use glam::DVec3;
+
+use crate::{
+    camera::Camera,
+    math::Transform,
+    shape::{Bounded, Instance},
+};
+
+/// The measure of an instance which selects the level of detail.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LodMetric {
+    /// The fraction of image height covered by the bounding sphere of instance.
+    #[default]
+    ProjectedSize,
+
+    /// The distance from the camera to the center of instance.
+    Distance,
+}
+
+/// The shapes of one template from the finest to the coarsest detail, of which each instance
+/// selects one by its projected size or distance, see `Object::instance_lod`. The level is
+/// selected once for the camera of a still, so all rays see the same shape without popping.
+pub struct Lod {
+    /// The shapes with the thresholds of the metric to use them, i.e. the minimal projected
+    /// size or the maximal distance. The threshold of the last level is ignored.
+    levels: Vec<(Arc<dyn Bounded>, f64)>,
+
+    /// The measure compared with the thresholds.
+    metric: LodMetric,
+}
+
+impl Lod {
+    /// Create the levels of detail with the finest shape, which is used by the instances above
+    /// the `threshold` of projected size or within it of distance.
+    pub fn new<T>(shape: T, threshold: f64) -> Self
+    where
+        T: Bounded + 'static,
+    {
+        Self {
+            levels: vec![(Arc::new(shape), threshold)],
+            metric: LodMetric::default(),
+        }
+    }
+
+    /// Add a coarser level which is used by the instances beyond the previous levels and the
+    /// threshold of this level is the next bound.
+    pub fn level<T>(mut self, shape: T, threshold: f64) -> Self
+    where
+        T: Bounded + 'static,
+    {
+        self.levels.push((Arc::new(shape), threshold));
+        self
+    }
+
+    /// Set the measure which the thresholds are compared with.
+    pub const fn metric(mut self, metric: LodMetric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    /// Select the shape of the instance placed by `transform` seen from `camera`. The finest
+    /// level is measured, as the levels should have about the same bounds.
+    pub fn select(&self, transform: &Transform, camera: &Camera) -> Arc<dyn Bounded> {
+        let bbox = Instance::new(self.levels[0].0.clone(), *transform).bbox();
+        let min = DVec3::new(bbox.x.min, bbox.y.min, bbox.z.min);
+        let max = DVec3::new(bbox.x.max, bbox.y.max, bbox.z.max);
+        let center = (min + max) / 2.0;
+        let value = match self.metric {
+            LodMetric::ProjectedSize => camera.projected_size(center, (max - min).length() / 2.0),
+            LodMetric::Distance => (center - camera.origin).length(),
+        };
+        let last = self.levels.len() - 1;
+        let index = self.levels[..last]
+            .iter()
+            .position(|&(_, threshold)| match self.metric {
+                LodMetric::ProjectedSize => value >= threshold,
+                LodMetric::Distance => value <= threshold,
+            })
+            .unwrap_or(last);
+        self.levels[index].0.clone()
+    }
+}
@@ -0,0 +1,297 @@
+use std::path::{Path, PathBuf};
+
+use glam::DVec3;
+
+use crate::{
+    aabb::Aabb,
+    color::Color,
+    interval::Interval,
+    math::{DPoint3, Ray},
+    shape::{Bounded, HitRecord, Hittable, terminator_origin},
+    simd::TriangleBatch,
+};
+
+/// The largest number of triangles in the leaves of the mesh BVH, which fill a batch.
+const LEAF_SIZE: usize = 8;
+
+/// A mesh of triangles which index shared vertices, e.g. the models loaded by `obj::load`.
+/// The vertices may carry smooth normals, texture coordinates and colors, which are
+/// interpolated across the triangles. The triangles are found through a BVH of the mesh, whose
+/// leaves test their triangles as a batch, so that a mesh is a single object of the scene.
+/// Without the texture coordinates of the vertices, (u, v) are the barycentric coordinates
+/// (b1, b2) of the hit.
+pub struct TriangleMesh {
+    /// The positions of the vertices.
+    positions: Vec<DPoint3>,
+
+    /// The normals of the vertices, `None` for flat triangles.
+    normals: Option<Vec<DVec3>>,
+
+    /// The texture coordinates of the vertices.
+    uvs: Option<Vec<(f64, f64)>>,
+
+    /// The colors of the vertices, which are read by `VertexColorTexture`.
+    colors: Option<Vec<Color>>,
+
+    /// The vertex indices of the triangles in the order of the leaves of the BVH.
+    triangles: Vec<[u32; 3]>,
+
+    /// The nodes of the mesh BVH, the root is the first one.
+    nodes: Vec<MeshNode>,
+
+    /// The triangles of the leaves of the mesh BVH.
+    batches: Vec<TriangleBatch<LEAF_SIZE>>,
+
+    /// The file which the mesh is loaded from.
+    pub(crate) path: Option<PathBuf>,
+}
+
+/// A node of the mesh BVH.
+enum MeshNode {
+    Leaf {
+        /// The first triangle of the leaf in `TriangleMesh::triangles`, and the index of the
+        /// batch of its triangles in `TriangleMesh::batches`.
+        first: u32,
+        batch: u32,
+        bbox: Aabb,
+    },
+    Node {
+        /// The index of the right child, the left child follows the node.
+        right: u32,
+        bbox: Aabb,
+    },
+}
+
+impl MeshNode {
+    const fn bbox(&self) -> &Aabb {
+        match self {
+            MeshNode::Leaf { bbox, .. } | MeshNode::Node { bbox, .. } => bbox,
+        }
+    }
+}
+
+impl TriangleMesh {
+    /// Create the mesh of the `triangles` which index the `positions` of the vertices, and
+    /// build its BVH. The indices must be in range of the vertices, and the mesh must have
+    /// a triangle.
+    pub fn new(positions: Vec<DPoint3>, triangles: Vec<[u32; 3]>) -> Self {
+        assert!(!triangles.is_empty(), "The mesh has no triangles");
+        assert!(
+            triangles
+                .iter()
+                .flatten()
+                .all(|&i| (i as usize) < positions.len()),
+            "The triangles index vertices out of range"
+        );
+        let mut mesh = Self {
+            positions,
+            normals: None,
+            uvs: None,
+            colors: None,
+            triangles,
+            nodes: Vec::new(),
+            batches: Vec::new(),
+            path: None,
+        };
+        let count = mesh.triangles.len();
+        mesh.build(0, count);
+        mesh
+    }
+
+    /// Set the smooth normals of the vertices, which are normalized.
+    pub fn normals(mut self, normals: Vec<DVec3>) -> Self {
+        assert_eq!(normals.len(), self.positions.len());
+        self.normals = Some(normals.into_iter().map(DVec3::normalize_or_zero).collect());
+        self
+    }
+
+    /// Set the texture coordinates of the vertices.
+    pub fn uvs(mut self, uvs: Vec<(f64, f64)>) -> Self {
+        assert_eq!(uvs.len(), self.positions.len());
+        self.uvs = Some(uvs);
+        self
+    }
+
+    /// Set the colors of the vertices.
+    pub fn colors(mut self, colors: Vec<Color>) -> Self {
+        assert_eq!(colors.len(), self.positions.len());
+        self.colors = Some(colors);
+        self
+    }
+
+    /// Get the file which the mesh is loaded from.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Get the number of triangles.
+    pub const fn len(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// Whether the mesh has no triangles, which is never the case.
+    pub const fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+
+    /// Get the number of vertices.
+    pub const fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Get the vertices of the triangle `i`.
+    pub fn triangle(&self, i: usize) -> [DPoint3; 3] {
+        self.triangles[i].map(|v| self.positions[v as usize])
+    }
+
+    /// Get the vertices of all triangles, e.g. to bake or scatter on the mesh.
+    pub fn triangles(&self) -> Vec<[DPoint3; 3]> {
+        (0..self.len()).map(|i| self.triangle(i)).collect()
+    }
+
+    /// Get the bounding box of the triangles from `first` to `last`.
+    fn bounds(&self, first: usize, last: usize) -> Aabb {
+        let p = self.positions[self.triangles[first][0] as usize];
+        self.triangles[first..last]
+            .iter()
+            .flatten()
+            .map(|&v| self.positions[v as usize])
+            .fold(Aabb::from_points(p, p), |b, p| {
+                Aabb::surrounding_box(&b, &Aabb::from_points(p, p))
+            })
+            .padding_to_minimal()
+    }
+
+    /// Append the subtree of the triangles from `first` to `last`, which are sorted in place,
+    /// and get its bounding box.
+    fn build(&mut self, first: usize, last: usize) -> Aabb {
+        let bbox = self.bounds(first, last);
+        if last - first <= LEAF_SIZE {
+            self.nodes.push(MeshNode::Leaf {
+                first: first as u32,
+                batch: self.batches.len() as u32,
+                bbox,
+            });
+            let triangles: Vec<_> = (first..last).map(|i| self.triangle(i)).collect();
+            self.batches.push(TriangleBatch::new(&triangles));
+            return bbox;
+        }
+        // The node is filled in after its children.
+        let index = self.nodes.len();
+        self.nodes.push(MeshNode::Node { right: 0, bbox });
+        // Split at the median centroid along the longest axis of the centroids.
+        let positions = &self.positions;
+        let centroid = |t: &[u32; 3]| t.iter().map(|&v| positions[v as usize]).sum::<DVec3>();
+        let (min, max) = self.triangles[first..last]
+            .iter()
+            .map(centroid)
+            .fold((DVec3::INFINITY, DVec3::NEG_INFINITY), |(min, max), c| {
+                (min.min(c), max.max(c))
+            });
+        let axis = (max - min).max_position();
+        let middle = (first + last) / 2;
+        self.triangles[first..last].select_nth_unstable_by(middle - first, |a, b| {
+            centroid(a)[axis].total_cmp(&centroid(b)[axis])
+        });
+        self.build(first, middle);
+        let right = self.nodes.len() as u32;
+        self.build(middle, last);
+        self.nodes[index] = MeshNode::Node { right, bbox };
+        bbox
+    }
+
+    /// Fill the record of the hit at `t` of the triangle `i` with the barycentric coordinates
+    /// (b1, b2).
+    fn record(&self, r: &Ray, t: f64, i: usize, (b1, b2): (f64, f64)) -> HitRecord {
+        let ids = self.triangles[i].map(|v| v as usize);
+        let vertices = ids.map(|v| self.positions[v]);
+        let (e1, e2) = (vertices[1] - vertices[0], vertices[2] - vertices[0]);
+        let weights = [1.0 - b1 - b2, b1, b2];
+        let mut rec = HitRecord {
+            t,
+            p: r.at(t),
+            u: b1,
+            v: b2,
+            tangent: e1,
+            barycentric: Some((b1, b2)),
+            ..Default::default()
+        };
+        rec.set_face_normal(r, e1.cross(e2).normalize());
+
+        if let Some(uvs) = &self.uvs {
+            let uv = ids.map(|v| uvs[v]);
+            rec.u = (0..3).map(|k| weights[k] * uv[k].0).sum();
+            rec.v = (0..3).map(|k| weights[k] * uv[k].1).sum();
+            // The derivative of position by u from the differences of the texture coordinates
+            // along the edges, which are degenerate for the triangles of a single texel.
+            let (du1, dv1) = (uv[1].0 - uv[0].0, uv[1].1 - uv[0].1);
+            let (du2, dv2) = (uv[2].0 - uv[0].0, uv[2].1 - uv[0].1);
+            let determinant = du1 * dv2 - dv1 * du2;
+            if determinant.abs() > 1e-12 {
+                rec.tangent = (dv2 * e1 - dv1 * e2) / determinant;
+            }
+        }
+        if let Some(colors) = &self.colors {
+            rec.color = Some((0..3).map(|k| weights[k] * colors[ids[k]]).sum());
+        }
+        if let Some(normals) = &self.normals {
+            let side = if rec.front_face { 1.0 } else { -1.0 };
+            let normals = ids.map(|v| normals[v] * side);
+            let shading = (0..3)
+                .map(|k| weights[k] * normals[k])
+                .sum::<DVec3>()
+                .normalize_or_zero();
+            if shading != DVec3::ZERO {
+                rec.normal = shading;
+            }
+            rec.shadow_origin = Some(terminator_origin(rec.p, vertices, normals, (b1, b2)));
+        }
+        rec
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let mut closest = None;
+        let mut t_max = ray_t.max;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bbox().intersect(r, Interval::new(ray_t.min, t_max)) {
+                continue;
+            }
+            match node {
+                MeshNode::Leaf { first, batch, .. } => {
+                    let triangles = &self.batches[*batch as usize];
+                    let hit = triangles.intersect(r.ori, r.dir, Interval::new(ray_t.min, t_max));
+                    if let Some(hit) = hit {
+                        t_max = hit.t;
+                        closest = Some((hit.t, *first as usize + hit.lane, (hit.b1, hit.b2)));
+                    }
+                }
+                MeshNode::Node { right, .. } => {
+                    stack.push(*right as usize);
+                    stack.push(index + 1);
+                }
+            }
+        }
+        closest.map(|(t, i, barycentric)| self.record(r, t, i, barycentric))
+    }
+}
+
+impl Bounded for TriangleMesh {
+    fn bbox(&self) -> Aabb {
+        *self.nodes[0].bbox()
+    }
+
+    fn memory(&self) -> usize {
+        size_of_val(self)
+            + self.positions.capacity() * size_of::<DPoint3>()
+            + self.normals.as_ref().map_or(0, Vec::capacity) * size_of::<DVec3>()
+            + self.uvs.as_ref().map_or(0, Vec::capacity) * size_of::<(f64, f64)>()
+            + self.colors.as_ref().map_or(0, Vec::capacity) * size_of::<Color>()
+            + self.triangles.capacity() * size_of::<[u32; 3]>()
+            + self.nodes.capacity() * size_of::<MeshNode>()
+            + self.batches.capacity() * size_of::<TriangleBatch<LEAF_SIZE>>()
+    }
+}
@@ -0,0 +1,151 @@
+use std::f64;
+
+use glam::DVec3;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::{
+    math::DPoint3,
+    shape::{displaced::DisplacedQuad, quad::Quad},
+};
+
+/// The gravitational acceleration in m/s², which sets the speed of waves.
+const GRAVITY: f64 = 9.81;
+
+/// One Gerstner wave of the ocean.
+#[derive(Clone, Copy, Debug)]
+pub struct Wave {
+    /// The normalized direction of travel in the xz plane.
+    pub direction: DVec3,
+
+    /// The wavenumber, i.e. 2π over the wavelength.
+    pub k: f64,
+
+    /// The angular frequency by the dispersion of deep water.
+    pub omega: f64,
+
+    /// The amplitude of the height.
+    pub amplitude: f64,
+
+    /// The initial phase.
+    pub phase: f64,
+}
+
+/// A procedural ocean of Gerstner waves in the xz plane with y up, whose wavelengths and
+/// directions are drawn from the Phillips spectrum of the wind and whose heights follow the
+/// fully developed sea of Pierson-Moskowitz. The surface is diced into displaced grids by
+/// `patch`, which are shaded with `Material::water`.
+/// References:
+/// Tessendorf, Simulating Ocean Water, SIGGRAPH 2001 course notes
+pub struct Ocean {
+    /// The waves summed up into the surface.
+    waves: Vec<Wave>,
+
+    /// The horizontal motion of the crests towards each other in [0, 1], where 1 makes the
+    /// sharpest crests without loops.
+    choppiness: f64,
+
+    /// The time of the surface in seconds.
+    time: f64,
+}
+
+impl Ocean {
+    /// Create the ocean of 64 waves under the wind of `speed` m/s blowing towards `direction`
+    /// in the xz plane, whose random numbers are seeded by `seed`.
+    pub fn new(speed: f64, direction: DVec3, seed: u64) -> Self {
+        Self::with_waves(speed, direction, 64, seed)
+    }
+
+    /// Create the ocean like `new` with `count` waves, the more the less repetitive.
+    pub fn with_waves(speed: f64, direction: DVec3, count: u32, seed: u64) -> Self {
+        let wind = DVec3::new(direction.x, 0.0, direction.z).normalize_or(DVec3::X);
+        let mut rng = StdRng::seed_from_u64(seed);
+        // The largest wave of the wind, where the Phillips spectrum peaks.
+        let length = speed * speed / GRAVITY;
+        let k_peak = 1.0 / (length * f64::consts::SQRT_2).max(1e-6);
+        let (k_min, k_max) = (k_peak / 2.0, k_peak * 16.0);
+        let mut waves: Vec<Wave> = (0..count)
+            .map(|_| {
+                // Log-uniform wavenumbers and directions around the wind, whose amplitudes are
+                // the square root of the spectrum over the measure of samples, i.e. P(k) k².
+                let k = k_min * (k_max / k_min).powf(rng.random::<f64>());
+                let angle = rng.random_range(-f64::consts::FRAC_PI_2..f64::consts::FRAC_PI_2);
+                let direction = DVec3::new(
+                    wind.x * angle.cos() - wind.z * angle.sin(),
+                    0.0,
+                    wind.x * angle.sin() + wind.z * angle.cos(),
+                );
+                let phillips =
+                    (-1.0 / (k * length).powi(2)).exp() / k.powi(4) * angle.cos().powi(2);
+                Wave {
+                    direction,
+                    k,
+                    omega: (GRAVITY * k).sqrt(),
+                    amplitude: (phillips * k * k).sqrt(),
+                    phase: rng.random_range(0.0..f64::consts::TAU),
+                }
+            })
+            .collect();
+        // Scale the waves to the significant height of Pierson-Moskowitz, which is four times
+        // the standard deviation of the surface.
+        let significant = 0.21 * speed * speed / GRAVITY;
+        let deviation = (waves.iter().map(|w| w.amplitude.powi(2) / 2.0).sum::<f64>()).sqrt();
+        if deviation > 0.0 {
+            for wave in &mut waves {
+                wave.amplitude *= significant / (4.0 * deviation);
+            }
+        }
+        Self::from_waves(waves)
+    }
+
+    /// Create the ocean of the given waves.
+    pub const fn from_waves(waves: Vec<Wave>) -> Self {
+        Self {
+            waves,
+            choppiness: 0.8,
+            time: 0.0,
+        }
+    }
+
+    /// Set the horizontal motion of the crests in [0, 1].
+    pub const fn choppiness(mut self, choppiness: f64) -> Self {
+        self.choppiness = choppiness.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Set the time of the surface in seconds, e.g. the frames of animation.
+    pub const fn time(mut self, time: f64) -> Self {
+        self.time = time;
+        self
+    }
+
+    /// Get the waves of the ocean.
+    pub fn waves(&self) -> &[Wave] {
+        &self.waves
+    }
+
+    /// Get the offset of the surface point above `(x, z)` at rest, whose y is the height.
+    pub fn displacement(&self, x: f64, z: f64) -> DVec3 {
+        let count = self.waves.len() as f64;
+        self.waves.iter().fold(DVec3::ZERO, |offset, wave| {
+            let theta = wave.k * (wave.direction.x * x + wave.direction.z * z)
+                - wave.omega * self.time
+                + wave.phase;
+            // The steepness is shared by the waves, so that their crests don't loop.
+            let steepness = self.choppiness / (wave.k * count);
+            let (sin, cos) = theta.sin_cos();
+            offset - wave.direction * steepness * sin + DVec3::Y * wave.amplitude * cos
+        })
+    }
+
+    /// Dice the square of `size` from `corner` at the minimal x and z into a grid of
+    /// `resolution` x `resolution` cells displaced by the waves. The texture coordinates run
+    /// along x and -z.
+    pub fn patch(&self, corner: DPoint3, size: f64, resolution: u32) -> DisplacedQuad {
+        let quad = Quad::new(
+            corner + DVec3::Z * size,
+            DVec3::X * size,
+            DVec3::NEG_Z * size,
+        );
+        DisplacedQuad::from_fn(quad, resolution, |_, _, p| self.displacement(p.x, p.z))
+    }
+}
@@ -7,7 +7,7 @@ use crate::{
     aabb::Aabb,
     interval::Interval,
     math::{DPoint3, Ray},
-    shape::{Bounded, HitRecord, Hittable},
+    shape::{Bounded, HitRecord, Hittable, Sampleable},
 };
 
 #[allow(non_snake_case)]
@@ -108,10 +108,13 @@ impl Hittable for Quad {
         rec.t = root;
         rec.p = r.at(root);
         rec.set_face_normal(r, self.normal);
+        rec.tangent = self.u;
 
         Some(rec)
     }
+}
 
+impl Sampleable for Quad {
     /// Get a random point from the quadrangle and also return the vector from the random point to `target` and the constant PDF.
     fn sample(
         &self,
@@ -126,6 +129,10 @@ impl Hittable for Quad {
         let pdf = 1.0 / (self.area);
         (p, self.normal, pdf)
     }
+
+    fn area(&self) -> f64 {
+        self.area
+    }
 }
 
 impl Bounded for Quad {
@@ -2,13 +2,14 @@ use std::f64;
 use std::f64::consts::PI;
 
 use glam::DVec3;
-use rand::rngs::StdRng;
+use rand::{Rng, rngs::StdRng};
 
 use crate::aabb::Aabb;
 use crate::interval::Interval;
-use crate::math::{DPoint3, Ray, vec::random_cosine_weight_on_hemisphere};
+use crate::math::{DPoint3, Ray};
 use crate::onb::ONB;
-use crate::shape::{Bounded, HitRecord, Hittable};
+use crate::sampling;
+use crate::shape::{Bounded, HitRecord, Hittable, Sampleable};
 
 pub struct Sphere {
     /// The center point of the sphere.
@@ -49,6 +50,17 @@ impl Sphere {
         }
     }
 
+    /// Get the center at time 0.0 and the center at time 1.0 if the sphere moves.
+    pub fn centers(&self) -> (DPoint3, Option<DPoint3>) {
+        let center_to = (self.center.dir != DVec3::ZERO).then(|| self.center.ori + self.center.dir);
+        (self.center.ori, center_to)
+    }
+
+    /// Get the radius of the sphere, which is negative for the inverted normal.
+    pub const fn radius(&self) -> f64 {
+        self.radius
+    }
+
     /// Transform 3D sphere coordinates into plane coordinates using polar angle and azimuth angle.
     pub fn get_sphere_uv(p: DPoint3) -> (f64, f64) {
         // Normalize to make UV mapping independent of radius length
@@ -85,16 +97,22 @@ impl Hittable for Sphere {
         let mut rec = HitRecord {
             t: root,
             p: r.at(root),
+            velocity: self.center.dir,
             ..Default::default()
         };
         // If radius is negative, the normal is inverted. Application: hollow glass sphere.
         let normal = (rec.p - current_center) / self.radius;
         rec.set_face_normal(r, normal);
         (rec.u, rec.v) = Self::get_sphere_uv(normal);
+        // The derivative of the azimuth of `get_sphere_uv` which spans 2π over u.
+        let offset = rec.p - current_center;
+        rec.tangent = 2.0 * PI * DVec3::new(offset.z, 0.0, -offset.x);
 
         Some(rec)
     }
+}
 
+impl Sampleable for Sphere {
     /// Get a random point from the sphere and also return the vector from the random point to `target` and the PDF based on MIS.
     fn sample(
         &self,
@@ -102,16 +120,20 @@ impl Hittable for Sphere {
         rng: &mut StdRng,
         shutter_time: f64,
     ) -> (DPoint3, DVec3, f64) {
-        let p = random_cosine_weight_on_hemisphere(rng);
+        let p = sampling::cosine_hemisphere(rng.random());
         let n = (target - self.center.at(shutter_time)).normalize();
         let world_onb = ONB::new(n);
         let world_p = world_onb.transform(p) * self.radius.abs() + self.center.at(shutter_time);
         (
             world_p,
             n,
-            p.z * f64::consts::FRAC_1_PI / (self.radius * self.radius),
+            sampling::cosine_hemisphere_pdf(p.z) / (self.radius * self.radius),
         ) // p.z = cosθ
     }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
 }
 
 impl Bounded for Sphere {
@@ -0,0 +1,242 @@
+use glam::DVec3;
+
+use crate::{interval::Interval, math::DPoint3};
+
+/// The implementation of the batched kernels which is selected for the running CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kernel {
+    /// The lanes are compiled for AVX2 and FMA of x86-64 CPUs which support them.
+    Avx2,
+
+    /// The lanes are compiled for NEON, which all AArch64 CPUs support, e.g. Apple Silicon.
+    Neon,
+
+    /// The lanes are compiled for the baseline of target, e.g. SSE2 of x86-64.
+    Scalar,
+}
+
+/// Get the kernel which the batched tests run on the current CPU.
+pub fn kernel() -> Kernel {
+    #[cfg(target_arch = "x86_64")]
+    if std::is_x86_feature_detected!("avx2") && std::is_x86_feature_detected!("fma") {
+        return Kernel::Avx2;
+    }
+    if cfg!(target_arch = "aarch64") {
+        Kernel::Neon
+    } else {
+        Kernel::Scalar
+    }
+}
+
+/// Whether the AVX2 kernel can run on the current CPU. The detection is cached by std.
+#[cfg(target_arch = "x86_64")]
+fn has_avx2() -> bool {
+    std::is_x86_feature_detected!("avx2") && std::is_x86_feature_detected!("fma")
+}
+
+/// The axis-aligned boxes tested together against one ray, stored per axis so that the lanes
+/// are contiguous. The unused lanes are the point at infinity which is never hit.
+#[derive(Clone, Copy)]
+pub struct BoxBatch<const N: usize> {
+    /// The minimal corners in x, y, z order.
+    pub min: [[f64; N]; 3],
+
+    /// The maximal corners in x, y, z order.
+    pub max: [[f64; N]; 3],
+}
+
+impl<const N: usize> Default for BoxBatch<N> {
+    fn default() -> Self {
+        Self {
+            min: [[f64::INFINITY; N]; 3],
+            max: [[f64::INFINITY; N]; 3],
+        }
+    }
+}
+
+impl<const N: usize> BoxBatch<N> {
+    /// Set the box of `lane` from its minimal and maximal corners.
+    pub fn set(&mut self, lane: usize, min: DPoint3, max: DPoint3) {
+        for axis in 0..3 {
+            self.min[axis][lane] = min[axis];
+            self.max[axis][lane] = max[axis];
+        }
+    }
+
+    /// Get the distances where the ray with origin `ori` and reciprocal direction `inv_dir`
+    /// enters each box within `ray_t`, infinity for the boxes which are missed. It agrees with
+    /// `Aabb::entry_distance`.
+    pub fn entry_distances(&self, ori: DPoint3, inv_dir: DVec3, ray_t: Interval) -> [f64; N] {
+        #[cfg(target_arch = "x86_64")]
+        if has_avx2() {
+            // SAFETY: AVX2 and FMA are supported by the running CPU as detected above.
+            return unsafe { slab_avx2(self, ori, inv_dir, ray_t) };
+        }
+        slab(self, ori, inv_dir, ray_t)
+    }
+}
+
+/// The slab test of all lanes, which is written without branches so that it's vectorized.
+#[inline(always)]
+fn slab<const N: usize>(
+    boxes: &BoxBatch<N>,
+    ori: DPoint3,
+    inv_dir: DVec3,
+    ray_t: Interval,
+) -> [f64; N] {
+    let mut t_near = [ray_t.min; N];
+    let mut t_far = [ray_t.max; N];
+    for axis in 0..3 {
+        let (o, inv_d) = (ori[axis], inv_dir[axis]);
+        for i in 0..N {
+            let t0 = (boxes.min[axis][i] - o) * inv_d;
+            let t1 = (boxes.max[axis][i] - o) * inv_d;
+            t_near[i] = t_near[i].max(t0.min(t1));
+            t_far[i] = t_far[i].min(t0.max(t1));
+        }
+    }
+    let mut entry = [f64::INFINITY; N];
+    for i in 0..N {
+        if t_near[i] < t_far[i] {
+            entry[i] = t_near[i];
+        }
+    }
+    entry
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+fn slab_avx2<const N: usize>(
+    boxes: &BoxBatch<N>,
+    ori: DPoint3,
+    inv_dir: DVec3,
+    ray_t: Interval,
+) -> [f64; N] {
+    slab(boxes, ori, inv_dir, ray_t)
+}
+
+/// The triangles tested together against one ray, stored per axis like `BoxBatch`. The unused
+/// lanes are degenerate triangles which are never hit.
+#[derive(Clone, Copy)]
+pub struct TriangleBatch<const N: usize> {
+    /// The first vertices in x, y, z order.
+    v0: [[f64; N]; 3],
+
+    /// The edges from the first to the second vertices.
+    e1: [[f64; N]; 3],
+
+    /// The edges from the first to the third vertices.
+    e2: [[f64; N]; 3],
+
+    /// The number of used lanes.
+    len: usize,
+}
+
+/// The closest hit of a ray in a `TriangleBatch`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TriangleHit {
+    /// The lane of the hit triangle.
+    pub lane: usize,
+
+    /// The distance along the ray.
+    pub t: f64,
+
+    /// The barycentric coordinates of the second and third vertices.
+    pub b1: f64,
+    pub b2: f64,
+}
+
+impl<const N: usize> TriangleBatch<N> {
+    /// Create a batch from at most `N` triangles.
+    pub fn new(triangles: &[[DPoint3; 3]]) -> Self {
+        assert!(triangles.len() <= N, "too many triangles for the batch");
+        let mut batch = Self {
+            v0: [[0.0; N]; 3],
+            e1: [[0.0; N]; 3],
+            e2: [[0.0; N]; 3],
+            len: triangles.len(),
+        };
+        for (lane, [a, b, c]) in triangles.iter().enumerate() {
+            for axis in 0..3 {
+                batch.v0[axis][lane] = a[axis];
+                batch.e1[axis][lane] = b[axis] - a[axis];
+                batch.e2[axis][lane] = c[axis] - a[axis];
+            }
+        }
+        batch
+    }
+
+    /// Get the number of triangles in the batch.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the batch has no triangles.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the closest hit of the ray with origin `ori` and direction `dir` within `ray_t`.
+    pub fn intersect(&self, ori: DPoint3, dir: DVec3, ray_t: Interval) -> Option<TriangleHit> {
+        #[cfg(target_arch = "x86_64")]
+        if has_avx2() {
+            // SAFETY: AVX2 and FMA are supported by the running CPU as detected above.
+            return unsafe { moller_trumbore_avx2(self, ori, dir, ray_t) };
+        }
+        moller_trumbore(self, ori, dir, ray_t)
+    }
+}
+
+/// The Möller–Trumbore test of all lanes followed by the closest hit.
+/// References:
+/// Möller and Trumbore 1997, Fast, Minimum Storage Ray/Triangle Intersection
+#[inline(always)]
+fn moller_trumbore<const N: usize>(
+    batch: &TriangleBatch<N>,
+    ori: DPoint3,
+    dir: DVec3,
+    ray_t: Interval,
+) -> Option<TriangleHit> {
+    let (mut ts, mut b1s, mut b2s) = ([f64::INFINITY; N], [0.0; N], [0.0; N]);
+    for i in 0..N {
+        let e1 = DVec3::new(batch.e1[0][i], batch.e1[1][i], batch.e1[2][i]);
+        let e2 = DVec3::new(batch.e2[0][i], batch.e2[1][i], batch.e2[2][i]);
+        let v0 = DVec3::new(batch.v0[0][i], batch.v0[1][i], batch.v0[2][i]);
+        let p = dir.cross(e2);
+        let det = e1.dot(p);
+        let inv_det = 1.0 / det;
+        let s = ori - v0;
+        let b1 = s.dot(p) * inv_det;
+        let q = s.cross(e1);
+        let b2 = dir.dot(q) * inv_det;
+        let t = e2.dot(q) * inv_det;
+        // Parallel rays and degenerate lanes have zero determinant.
+        let hit = det.abs() > 1e-12
+            && b1 >= 0.0
+            && b2 >= 0.0
+            && b1 + b2 <= 1.0
+            && t > ray_t.min
+            && t < ray_t.max;
+        if hit {
+            (ts[i], b1s[i], b2s[i]) = (t, b1, b2);
+        }
+    }
+    let lane = (0..batch.len).min_by(|&a, &b| ts[a].total_cmp(&ts[b]))?;
+    ts[lane].is_finite().then_some(TriangleHit {
+        lane,
+        t: ts[lane],
+        b1: b1s[lane],
+        b2: b2s[lane],
+    })
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+fn moller_trumbore_avx2<const N: usize>(
+    batch: &TriangleBatch<N>,
+    ori: DPoint3,
+    dir: DVec3,
+    ray_t: Interval,
+) -> Option<TriangleHit> {
+    moller_trumbore(batch, ori, dir, ray_t)
+}
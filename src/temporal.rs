@@ -0,0 +1,144 @@
+use rayon::prelude::*;
+
+use crate::{buffer::Buffer, color::Color, renderer::Renderer};
+
+/// The rendered frame of animation which seeds the accumulation of the next frame, see
+/// `Renderer::temporal_history`. Each pixel of the next frame finds its surface point in this
+/// frame by the motion of camera and objects, and starts from the color of this frame if the
+/// point was visible there.
+pub struct FrameHistory {
+    /// The width of frame.
+    width: u32,
+
+    /// The height of frame.
+    height: u32,
+
+    /// The average color of each pixel.
+    colors: Vec<Color>,
+
+    /// The number of samples of each pixel.
+    counts: Vec<u32>,
+
+    /// The planar depth of the surface through the center of each pixel, `None` for the
+    /// background.
+    depths: Vec<Option<f64>>,
+
+    /// The maximal motion in pixels of the reprojected pixels, beyond which the shading is
+    /// likely to change, e.g. the reflections and shadows on fast objects.
+    max_motion: f64,
+
+    /// The maximal difference of depth relative to the depth, beyond which the history pixel
+    /// belongs to another surface, i.e. the point was occluded in the previous frame.
+    depth_tolerance: f64,
+
+    /// The maximal number of samples taken from the history, which limits how long a change of
+    /// lighting lags behind.
+    max_samples: u32,
+}
+
+impl FrameHistory {
+    /// Capture the history from the `buffer` rendered by `renderer`, whose geometry is traced
+    /// through the center of each pixel.
+    pub fn capture(renderer: &Renderer, buffer: &Buffer) -> Self {
+        let (width, height) = buffer.dimensions();
+        let counts = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| buffer.sample_count(x, y))
+            .collect();
+        let depths = renderer.install(|| {
+            (0..width * height)
+                .into_par_iter()
+                .map(|index| {
+                    let (_, hit) = renderer.center_hit(index % width, index / width);
+                    hit.map(|hit| renderer.cam.depth(hit.p))
+                })
+                .collect()
+        });
+        Self {
+            width,
+            height,
+            colors: buffer.colors(),
+            counts,
+            depths,
+            max_motion: 16.0,
+            depth_tolerance: 0.02,
+            max_samples: 256,
+        }
+    }
+
+    /// Set the maximal motion in pixels of the reprojected pixels.
+    pub const fn max_motion(mut self, pixels: f64) -> Self {
+        self.max_motion = pixels;
+        self
+    }
+
+    /// Set the maximal difference of depth relative to the depth of the reprojected pixels.
+    pub const fn depth_tolerance(mut self, tolerance: f64) -> Self {
+        self.depth_tolerance = tolerance;
+        self
+    }
+
+    /// Set the maximal number of samples taken from the history of each pixel.
+    pub const fn max_samples(mut self, n: u32) -> Self {
+        self.max_samples = n;
+        self
+    }
+
+    /// Push the reprojected history as the first round of each pixel of `buffer` rendered by
+    /// `renderer`, and return the number of pixels whose history is valid. The history is
+    /// skipped if the size of frame changes.
+    pub fn reproject(&self, renderer: &Renderer, buffer: &mut Buffer) -> usize {
+        if buffer.dimensions() != (self.width, self.height) {
+            return 0;
+        }
+        let (width, height) = (self.width, self.height);
+        let rounds: Vec<Option<(Color, u32)>> = renderer.install(|| {
+            (0..width * height)
+                .into_par_iter()
+                .map(|index| self.lookup(renderer, index % width, index / width))
+                .collect()
+        });
+        let mut valid = 0;
+        for (index, round) in rounds.into_iter().enumerate() {
+            if let Some((color, count)) = round {
+                buffer.add_round(index as u32 % width, index as u32 / width, color, count);
+                valid += 1;
+            }
+        }
+        valid
+    }
+
+    /// Find the history of the pixel (x, y) of current frame, `None` if it's invalidated by
+    /// motion or depth.
+    fn lookup(&self, renderer: &Renderer, x: u32, y: u32) -> Option<(Color, u32)> {
+        let previous = renderer.previous_camera.as_ref().unwrap_or(&renderer.cam);
+        let (ray, hit) = renderer.center_hit(x, y);
+        let (point, expected_depth) = match &hit {
+            Some(hit) => {
+                let p = hit.p - hit.velocity;
+                (previous.project(p)?, Some(previous.depth(p)))
+            }
+            None => (previous.project_dir(ray.dir)?, None),
+        };
+        let (px, py) = (point.0 * self.width as f64, point.1 * self.height as f64);
+        let motion = (px - (x as f64 + 0.5)).hypot(py - (y as f64 + 0.5));
+        if motion > self.max_motion
+            || px < 0.0
+            || py < 0.0
+            || px >= self.width as f64
+            || py >= self.height as f64
+        {
+            return None;
+        }
+        let index = (py as u32 * self.width + px as u32) as usize;
+        let consistent = match (expected_depth, self.depths[index]) {
+            (Some(expected), Some(depth)) => {
+                (depth - expected).abs() <= self.depth_tolerance * expected.abs()
+            }
+            (None, None) => true,
+            _ => false,
+        };
+        let count = self.counts[index].min(self.max_samples);
+        (consistent && count > 0).then_some((self.colors[index], count))
+    }
+}
@@ -0,0 +1,37 @@
+use std::any::Any;
+
+use crate::{color::Color, shape::HitRecord};
+
+pub trait Texture: Send + Sync + Any {
+    /// Get the color of texture at the intersection.
+    fn value(&self, rec: &HitRecord) -> Color;
+}
+
+/// A texture with the same color everywhere.
+pub struct SolidColor(pub Color);
+
+impl Texture for SolidColor {
+    fn value(&self, _rec: &HitRecord) -> Color {
+        self.0
+    }
+}
+
+/// A texture which uses the interpolated vertex color of the intersection, e.g. the baked colors
+/// of scanned assets.
+pub struct VertexColorTexture {
+    /// The color used when the shape doesn't carry vertex colors.
+    pub fallback: Color,
+}
+
+impl VertexColorTexture {
+    /// Create a vertex color texture with the color for shapes without vertex colors.
+    pub const fn new(fallback: Color) -> Self {
+        Self { fallback }
+    }
+}
+
+impl Texture for VertexColorTexture {
+    fn value(&self, rec: &HitRecord) -> Color {
+        rec.color.unwrap_or(self.fallback)
+    }
+}
@@ -0,0 +1,175 @@
+use std::f64;
+
+use crate::buffer::Buffer;
+use crate::color::Color;
+
+/// The share of the tiles with the highest error which get twice the samples in the passes of
+/// `TileOrder::Adaptive`, within `Renderer::num_samples`.
+const BOOSTED_SHARE: f64 = 0.25;
+
+/// A rectangular region of image in pixels, which is the unit of work of render threads.
+#[derive(Clone, Copy, Debug)]
+pub struct Tile {
+    /// The left column of tile.
+    pub x: u32,
+
+    /// The top row of tile.
+    pub y: u32,
+
+    /// The width of tile, which is smaller than the tile size at the right border of image.
+    pub width: u32,
+
+    /// The height of tile, which is smaller than the tile size at the bottom border of image.
+    pub height: u32,
+}
+
+/// The pixels of a finished tile in one rendering pass.
+pub struct TileResult {
+    /// The region of the tile.
+    pub tile: Tile,
+
+    /// The number of samples per pixel of the pass.
+    pub iterations: u32,
+
+    /// The average colors of the samples inside each pixel in the pass, in row-major order of the
+    /// tile. They are not filtered since the neighboring tiles may be unfinished.
+    pub pixels: Vec<Color>,
+}
+
+/// The order in which tiles are dispatched to render threads.
+#[derive(Clone, Copy, Default)]
+pub enum TileOrder {
+    /// Row by row from the top left corner.
+    #[default]
+    Scanline,
+
+    /// Ring by ring from the center of image, so previews show the center region first.
+    Spiral,
+
+    /// Along the Hilbert curve, which keeps successive tiles close to each other.
+    Hilbert,
+
+    /// By the estimated noise of the samples so far from the highest, so that each progressive
+    /// pass refines caustics and penumbrae first. The noisiest tiles also get twice the
+    /// samples within `Renderer::num_samples`, and the tiles converged to
+    /// `Renderer::target_noise` are skipped. The first passes are in scanline order, see
+    /// `schedule_by_error`.
+    Adaptive,
+}
+
+/// Split the image into tiles of `size` x `size` pixels and sort them in `order`.
+pub fn tiles(width: u32, height: u32, size: u32, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let (nx, ny) = (width.div_ceil(size), height.div_ceil(size));
+    let mut grid: Vec<(u32, u32)> = (0..ny)
+        .flat_map(|ty| (0..nx).map(move |tx| (tx, ty)))
+        .collect();
+    match order {
+        TileOrder::Scanline | TileOrder::Adaptive => {}
+        TileOrder::Spiral => {
+            let (cx, cy) = ((nx as f64 - 1.0) / 2.0, (ny as f64 - 1.0) / 2.0);
+            let key = |&(tx, ty): &(u32, u32)| {
+                let (dx, dy) = (tx as f64 - cx, ty as f64 - cy);
+                // The ring is the Chebyshev distance, and tiles in one ring go counterclockwise.
+                let ring = dx.abs().max(dy.abs());
+                let angle = dy.atan2(dx).rem_euclid(f64::consts::TAU);
+                (ring, angle)
+            };
+            grid.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+        }
+        TileOrder::Hilbert => {
+            let n = nx.max(ny).next_power_of_two();
+            grid.sort_by_key(|&(tx, ty)| hilbert_index(n, tx, ty));
+        }
+    }
+    grid.into_iter()
+        .map(|(tx, ty)| Tile {
+            x: tx * size,
+            y: ty * size,
+            width: size.min(width - tx * size),
+            height: size.min(height - ty * size),
+        })
+        .collect()
+}
+
+/// Schedule the tiles of a progressive pass of `iterations` samples per pixel by the relative
+/// errors of their pixels in `buffer`, see `Buffer::relative_error`, and get the number of
+/// samples per pixel of each tile.
+/// The tiles whose pixels have no estimate yet come first in their order. The others are sorted
+/// by the error which one more sample per pixel removes from the highest, i.e. the mean of
+/// e^2 / n over the pixels of error e and n samples, so that the tiles which are noisy for
+/// their samples come first. The first `BOOSTED_SHARE` of them get twice the samples, and the
+/// tiles whose RMS error is below `target` are converged and skipped. The samples of the tiles
+/// are capped so that no pixel gets more than `max_samples` in total, and the tiles which reach
+/// it are skipped too.
+pub fn schedule_by_error(
+    tiles: Vec<Tile>,
+    buffer: &Buffer,
+    iterations: u32,
+    max_samples: u32,
+    target: Option<f64>,
+) -> Vec<(Tile, u32)> {
+    // The mean squared error and the mean error removed per sample of the pixels, `None` if
+    // any pixel has no estimate.
+    let error = |tile: &Tile| {
+        let (mut squared, mut gain) = (0.0, 0.0);
+        for y in tile.y..tile.y + tile.height {
+            for x in tile.x..tile.x + tile.width {
+                let e2 = buffer.relative_error(x, y)?.powi(2);
+                squared += e2;
+                gain += e2 / buffer.sample_count(x, y).max(1) as f64;
+            }
+        }
+        let pixels = (tile.width * tile.height) as f64;
+        Some((squared / pixels, gain / pixels))
+    };
+    let (mut unknown, mut known) = (Vec::new(), Vec::new());
+    for tile in tiles {
+        match error(&tile) {
+            None => unknown.push((tile, iterations)),
+            Some((squared, _)) if target.is_some_and(|target| squared.sqrt() <= target) => {}
+            Some((_, gain)) => known.push((gain, tile)),
+        }
+    }
+    known.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let boosted = (known.len() as f64 * BOOSTED_SHARE).ceil() as usize;
+    let known = known.into_iter().enumerate().filter_map(|(i, (_, tile))| {
+        let iterations = if i < boosted {
+            2 * iterations
+        } else {
+            iterations
+        };
+        let most = (tile.y..tile.y + tile.height)
+            .flat_map(|y| (tile.x..tile.x + tile.width).map(move |x| (x, y)))
+            .map(|(x, y)| buffer.sample_count(x, y))
+            .max()
+            .unwrap_or(0);
+        let iterations = iterations.min(max_samples.saturating_sub(most));
+        (iterations > 0).then_some((tile, iterations))
+    });
+    unknown.into_iter().chain(known).collect()
+}
+
+/// Get the distance along the Hilbert curve of a `n` x `n` grid to cell (x, y), where `n` is a
+/// power of two.
+/// References:
+/// https://en.wikipedia.org/wiki/Hilbert_curve
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u64;
+        let ry = (y & s > 0) as u64;
+        d += (s as u64).pow(2) * ((3 * rx) ^ ry);
+        // Rotate the quadrant to keep the curve continuous.
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
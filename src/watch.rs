@@ -0,0 +1,71 @@
+use std::{
+    fs, io,
+    path::Path,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::{buffer::Buffer, handle::RenderHandle, renderer::Renderer};
+
+/// The interval to poll the modification time of watched file.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The progress of `watch` which is reported to its callback.
+pub enum WatchEvent<'a> {
+    /// A pass of the preview is finished with the number of samples per pixel so far.
+    Pass(u32, &'a Buffer),
+
+    /// The file failed to build, and it's watched until the next change.
+    Error(io::Error),
+}
+
+/// Watch the scene or config file at `path` and render it progressively for quick look
+/// development. `build` creates the renderer from the file, which is rendered progressively up
+/// to `preview_samples` samples per pixel, and `callback` receives the buffer after each pass,
+/// e.g. to update a preview window. Whenever the file changes, the current render is cancelled
+/// and restarted.
+/// Errors of `build` are reported to `callback` and the file is watched until the next change.
+/// The function only returns when the file can't be accessed.
+pub fn watch<B, F>(path: &Path, preview_samples: u32, build: B, callback: F) -> io::Result<()>
+where
+    B: Fn(&Path) -> io::Result<Renderer>,
+    F: Fn(WatchEvent),
+{
+    loop {
+        let modified = modified_time(path)?;
+        match build(path) {
+            Ok(renderer) => {
+                let handle = RenderHandle::new();
+                let renderer = renderer.num_samples(preview_samples).handle(handle.clone());
+                // The finished render is kept until the file changes.
+                thread::scope(|s| {
+                    let watcher = s.spawn(|| {
+                        let result = wait_for_change(path, modified);
+                        handle.cancel();
+                        result
+                    });
+                    renderer
+                        .progressive_render(|spp, buffer| callback(WatchEvent::Pass(spp, buffer)));
+                    watcher.join().unwrap()
+                })?;
+            }
+            Err(err) => {
+                callback(WatchEvent::Error(err));
+                wait_for_change(path, modified)?;
+            }
+        }
+    }
+}
+
+/// Get the modification time of the file at `path`.
+fn modified_time(path: &Path) -> io::Result<SystemTime> {
+    fs::metadata(path)?.modified()
+}
+
+/// Block until the file at `path` is modified after `modified`.
+fn wait_for_change(path: &Path, modified: SystemTime) -> io::Result<()> {
+    while modified_time(path)? == modified {
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
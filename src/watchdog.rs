@@ -0,0 +1,180 @@
+use std::{fmt, sync::Mutex, time::Duration};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use glam::DVec3;
+
+use crate::{math::DPoint3, tile::Tile};
+
+/// The state of the ray whose path is stopped by the time limit of its tile.
+#[derive(Clone, Copy, Debug)]
+pub struct StalledRay {
+    /// The origin of the ray.
+    pub origin: DPoint3,
+
+    /// The direction of the ray.
+    pub dir: DVec3,
+
+    /// The number of bounces of the path before the ray.
+    pub depth: u32,
+}
+
+/// The diagnostics of a tile which runs over the time limit, see `Renderer::tile_time_limit`.
+#[derive(Clone, Copy, Debug)]
+pub struct StallReport {
+    /// The stalled tile.
+    pub tile: Tile,
+
+    /// The pixel being traced.
+    pub pixel: (u32, u32),
+
+    /// The index of the sample of the pixel being traced.
+    pub sample: u32,
+
+    /// The time spent on the tile so far.
+    pub elapsed: Duration,
+
+    /// The ray at which the path is stopped, `None` if the tile is found still running by the
+    /// watchdog, e.g. an intersection never returns.
+    pub ray: Option<StalledRay>,
+}
+
+impl fmt::Display for StallReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Tile {
+            x,
+            y,
+            width,
+            height,
+        } = self.tile;
+        write!(
+            f,
+            "Tile {width}x{height} at ({x}, {y}) stalled for {:.1}s at pixel ({}, {}) sample {}",
+            self.elapsed.as_secs_f64(),
+            self.pixel.0,
+            self.pixel.1,
+            self.sample
+        )?;
+        match &self.ray {
+            Some(ray) => write!(
+                f,
+                ", the path is stopped at depth {} on the ray from {} towards {}",
+                ray.depth, ray.origin, ray.dir
+            ),
+            None => write!(f, ", still running"),
+        }
+    }
+}
+
+/// The tiles in flight of a pass, which are checked against the time limit by a separate
+/// thread so that the tiles hung inside a single ray are reported as well.
+pub(crate) struct Watchdog {
+    /// The time limit of each tile.
+    limit: Duration,
+
+    /// The tiles in flight, whose slots are freed when they finish.
+    tiles: Mutex<Vec<Option<InFlight>>>,
+}
+
+/// The progress of a tile in flight.
+struct InFlight {
+    /// The tile being traced.
+    tile: Tile,
+
+    /// The time when the tile started.
+    start: Instant,
+
+    /// The pixel and the index of the sample being traced.
+    pixel: (u32, u32),
+    sample: u32,
+
+    /// Whether the tile has been reported, so that it is reported once.
+    reported: bool,
+}
+
+impl Watchdog {
+    /// Create a watchdog of tiles with the time limit.
+    pub(crate) fn new(limit: Duration) -> Self {
+        Self {
+            limit,
+            tiles: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Register the tile which starts now, and return its slot and its deadline.
+    pub(crate) fn start(&self, tile: Tile) -> (usize, Instant) {
+        let start = Instant::now();
+        let state = InFlight {
+            tile,
+            start,
+            pixel: (tile.x, tile.y),
+            sample: 0,
+            reported: false,
+        };
+        let mut tiles = self.tiles.lock().unwrap();
+        let slot = match tiles.iter().position(Option::is_none) {
+            Some(slot) => {
+                tiles[slot] = Some(state);
+                slot
+            }
+            None => {
+                tiles.push(Some(state));
+                tiles.len() - 1
+            }
+        };
+        (slot, start + self.limit)
+    }
+
+    /// Update the pixel and the sample being traced of the tile in `slot`.
+    pub(crate) fn progress(&self, slot: usize, pixel: (u32, u32), sample: u32) {
+        if let Some(state) = &mut self.tiles.lock().unwrap()[slot] {
+            state.pixel = pixel;
+            state.sample = sample;
+        }
+    }
+
+    /// Free the slot of the finished tile, and get the report of its stop at `ray` if any.
+    pub(crate) fn finish(&self, slot: usize, ray: Option<StalledRay>) -> Option<StallReport> {
+        let state = self.tiles.lock().unwrap()[slot].take()?;
+        ray.map(|ray| StallReport {
+            ray: Some(ray),
+            ..state.report()
+        })
+    }
+
+    /// Get the reports of the tiles in flight which run over the time limit and haven't been
+    /// reported.
+    pub(crate) fn check(&self) -> Vec<StallReport> {
+        let mut tiles = self.tiles.lock().unwrap();
+        tiles
+            .iter_mut()
+            .flatten()
+            .filter(|state| !state.reported && state.start.elapsed() >= self.limit)
+            .map(|state| {
+                state.reported = true;
+                state.report()
+            })
+            .collect()
+    }
+
+    /// Get the interval to poll the tiles in flight.
+    pub(crate) fn poll_interval(&self) -> Duration {
+        (self.limit / 4).clamp(Duration::from_millis(1), Duration::from_millis(100))
+    }
+}
+
+impl InFlight {
+    /// Get the report of the tile while it is running.
+    fn report(&self) -> StallReport {
+        StallReport {
+            tile: self.tile,
+            pixel: self.pixel,
+            sample: self.sample,
+            elapsed: self.start.elapsed(),
+            ray: None,
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use image::codecs::jpeg::JpegEncoder;
+
+use crate::{
+    buffer::Buffer,
+    netpbm,
+    openexr::{self, RenderMetadata},
+    post::PostProcess,
+};
+
+/// The quality of JPEG files chosen by the extension of path.
+const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+/// The file format of the output image of a render. The 8-bit formats are post-processed to
+/// the display colors, while the float formats keep the linear radiance for compositing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageWriter {
+    /// 8-bit PNG.
+    Png,
+
+    /// 8-bit JPEG of `quality` in [1, 100], the lossy image for previews and the web.
+    Jpeg { quality: u8 },
+
+    /// Binary PPM (P6) of 8 bits.
+    Ppm,
+
+    /// PFM of 32-bit float radiance.
+    Pfm,
+
+    /// OpenEXR of 32-bit float radiance with all AOVs and the render metadata, see
+    /// `openexr::save_exr`.
+    Exr,
+}
+
+impl ImageWriter {
+    /// Choose the format by the extension of `path` in any case: `png`, `jpg` or `jpeg`, `ppm`,
+    /// `pfm` and `exr`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("png") => Ok(Self::Png),
+            Some("jpg" | "jpeg") => Ok(Self::Jpeg {
+                quality: DEFAULT_JPEG_QUALITY,
+            }),
+            Some("ppm") => Ok(Self::Ppm),
+            Some("pfm") => Ok(Self::Pfm),
+            Some("exr") => Ok(Self::Exr),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Unknown image format of {}", path.display()),
+            )),
+        }
+    }
+
+    /// Whether the format keeps the linear HDR radiance instead of the post-processed colors.
+    pub const fn is_hdr(&self) -> bool {
+        matches!(self, Self::Pfm | Self::Exr)
+    }
+
+    /// Write the rendered `buffer` to the file at `path`. The 8-bit formats are converted by
+    /// `post`, and the metadata if any is written to the EXR header.
+    pub fn write<P: AsRef<Path>>(
+        &self,
+        buffer: &Buffer,
+        post: &PostProcess,
+        metadata: Option<&RenderMetadata>,
+        path: P,
+    ) -> io::Result<()> {
+        match *self {
+            Self::Png => post
+                .image(buffer)
+                .save_with_format(path, image::ImageFormat::Png)
+                .map_err(io::Error::other),
+            Self::Jpeg { quality } => {
+                let mut writer = BufWriter::new(File::create(path)?);
+                JpegEncoder::new_with_quality(&mut writer, quality.clamp(1, 100))
+                    .encode_image(&post.image(buffer))
+                    .map_err(io::Error::other)?;
+                writer.flush()
+            }
+            Self::Ppm => netpbm::save_ppm(&post.image(buffer), path),
+            Self::Pfm => netpbm::save_pfm(buffer, path),
+            Self::Exr => openexr::save_exr(buffer, metadata, path),
+        }
+    }
+}
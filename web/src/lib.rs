@@ -0,0 +1,175 @@
+use glam::DVec3;
+use simple_rpt::{
+    buffer::Buffer,
+    camera::controller::{CameraController, CameraInput},
+    color::{self, color_bytes},
+    light::Light,
+    material::Material,
+    object::Object,
+    renderer::Renderer,
+    scene::Scene,
+    shape::{quad::Quad, sphere::Sphere},
+};
+use wasm_bindgen::prelude::*;
+
+/// A progressive render of the demo scene which is driven by the page, whose camera is moved
+/// by the keys and the mouse.
+#[wasm_bindgen]
+pub struct Demo {
+    renderer: Renderer,
+    controller: CameraController,
+    buffer: Buffer,
+    samples: u32,
+}
+
+#[wasm_bindgen]
+impl Demo {
+    /// Create the demo with the size of canvas and the number of samples per pixel to stop at.
+    #[wasm_bindgen(constructor)]
+    pub fn new(width: u32, height: u32, num_samples: u32) -> Self {
+        let controller = CameraController::new(
+            DVec3::new(0.0, 1.0, 4.0),
+            DVec3::new(0.0, 1.0, 0.0),
+            DVec3::Y,
+            50.0,
+            width as f64 / height as f64,
+            0.0,
+            1.0,
+        );
+        let renderer = Renderer::new(controller.camera(), cornell_box())
+            .width(width)
+            .height(height)
+            .num_samples(num_samples)
+            .max_bounces(8);
+        Self {
+            renderer,
+            controller,
+            buffer: Buffer::new(width, height),
+            samples: 0,
+        }
+    }
+
+    /// Move the camera by a key of WASD, or Q/E for down/up, and return whether the render
+    /// restarts.
+    pub fn key(&mut self, key: char) -> bool {
+        CameraInput::from_key(key).is_some_and(|input| self.input(input))
+    }
+
+    /// Orbit the camera by the mouse drag in pixels, and return whether the render restarts.
+    pub fn orbit(&mut self, dx: f64, dy: f64) -> bool {
+        self.input(CameraInput::Orbit { dx, dy })
+    }
+
+    /// Zoom the camera by the steps of mouse wheel, and return whether the render restarts.
+    pub fn zoom(&mut self, steps: f64) -> bool {
+        self.input(CameraInput::Zoom(steps))
+    }
+
+    /// Render one more pass which doubles the samples per pixel, and return whether the render
+    /// is unfinished.
+    pub fn step(&mut self) -> bool {
+        if self.samples >= self.renderer.num_samples {
+            return false;
+        }
+        let step = self
+            .samples
+            .max(1)
+            .min(self.renderer.num_samples - self.samples);
+        self.renderer.sample(step, &mut self.buffer);
+        self.samples += step;
+        self.samples < self.renderer.num_samples
+    }
+
+    /// The number of samples per pixel rendered so far.
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// Get the pixels of current image in RGBA layout of canvas `ImageData`.
+    pub fn pixels(&self) -> Vec<u8> {
+        let mut pixels = Vec::new();
+        for y in 0..self.renderer.height {
+            for x in 0..self.renderer.width {
+                let color = if self.samples > 0 {
+                    self.buffer.get_color(x, y)
+                } else {
+                    color::BLACK
+                };
+                pixels.extend(color_bytes(color));
+                pixels.push(255);
+            }
+        }
+        pixels
+    }
+}
+
+impl Demo {
+    /// Apply the input to the camera. The samples of the previous camera are discarded, and
+    /// the passes restart from one sample per pixel.
+    fn input(&mut self, input: CameraInput) -> bool {
+        if !self.controller.input(input) {
+            return false;
+        }
+        self.renderer.cam = self.controller.camera();
+        self.buffer = Buffer::new(self.renderer.width, self.renderer.height);
+        self.samples = 0;
+        true
+    }
+}
+
+/// A small Cornell box with a glass and a metal sphere.
+fn cornell_box() -> Scene {
+    let wall = |origin: DVec3, u: DVec3, v: DVec3, color| {
+        Object::new(Quad::new(origin, u, v)).material(Material::diffuse(color))
+    };
+    let light = Light::area(
+        Quad::new(
+            DVec3::new(-0.5, 1.99, -0.5),
+            DVec3::new(1.0, 0.0, 0.0),
+            DVec3::new(0.0, 0.0, 1.0),
+        ),
+        color::WHITE,
+        15.0,
+    );
+    Scene::new()
+        .with_obj(wall(
+            DVec3::new(-2.0, 0.0, -2.0),
+            DVec3::new(4.0, 0.0, 0.0),
+            DVec3::new(0.0, 0.0, 4.0),
+            color::WHITE,
+        ))
+        .with_obj(wall(
+            DVec3::new(-2.0, 2.0, -2.0),
+            DVec3::new(0.0, 0.0, 4.0),
+            DVec3::new(4.0, 0.0, 0.0),
+            color::WHITE,
+        ))
+        .with_obj(wall(
+            DVec3::new(-2.0, 0.0, -2.0),
+            DVec3::new(0.0, 2.0, 0.0),
+            DVec3::new(0.0, 0.0, 4.0),
+            color::RED,
+        ))
+        .with_obj(wall(
+            DVec3::new(2.0, 0.0, -2.0),
+            DVec3::new(0.0, 0.0, 4.0),
+            DVec3::new(0.0, 2.0, 0.0),
+            color::GREEN,
+        ))
+        .with_obj(wall(
+            DVec3::new(-2.0, 0.0, -2.0),
+            DVec3::new(4.0, 0.0, 0.0),
+            DVec3::new(0.0, 2.0, 0.0),
+            color::WHITE,
+        ))
+        .with_obj(
+            Object::new(Sphere::new(DVec3::new(-0.8, 0.5, -0.5), None, 0.5))
+                .material(Material::clear(1.5, 0.0)),
+        )
+        .with_obj(
+            Object::new(Sphere::new(DVec3::new(0.8, 0.5, -0.8), None, 0.5))
+                .material(Material::metallic(color::WHITE, 0.2)),
+        )
+        .with_visible_light(light)
+        .build_bvh()
+}